@@ -5,14 +5,14 @@ _lal()
     local cur prev words cword
     _init_completion || return
 
-    local -r subcommands="build clean configure export fetch help init script run ls
+    local -r subcommands="add build clean configure export fetch help init script run ls
                           query remove rm shell stash save status update upgrade verify
                           publish env list-components list-supported-environments list-dependencies
-                          list-environments list-configurations propagate"
+                          list-environments list-configurations list-targets list-versions propagate outdated search dep-tree why validate lock sbom promote logs test"
 
     local has_sub
     for (( i=0; i < ${#words[@]}-1; i++ )); do
-        if [[ ${words[i]} == @(build|clean|configure|export|script|propagate|fetch|help|init|remove|rm|script|run|query|shell|stash|save|status|ls|update|upgrade|verify|publish|env) ]]; then
+        if [[ ${words[i]} == @(add|build|clean|configure|export|script|propagate|fetch|help|init|remove|rm|script|run|query|shell|stash|save|status|ls|update|upgrade|verify|publish|env|lock|logs|test) ]]; then
             has_sub=1
         fi
     done
@@ -36,7 +36,7 @@ _lal()
     # special subcommand completions
     local special i
     for (( i=0; i < ${#words[@]}-1; i++ )); do
-        if [[ ${words[i]} == @(build|remove|rm|propagate|export|init|update|script|run|status|ls|query|shell|publish|env|configure|help) ]]; then
+        if [[ ${words[i]} == @(add|build|remove|rm|propagate|export|init|update|script|run|status|ls|query|shell|publish|env|lock|logs|test|configure|help) ]]; then
             special=${words[i]}
         fi
     done
@@ -49,7 +49,7 @@ _lal()
                 local -r components=$(lal list-components)
                 if [[ $prev = "build" ]]; then
                     COMPREPLY=($(compgen -W "$components" -- "$cur"))
-                elif [[ $prev == @(--config|-c) ]]; then
+                elif [[ $prev == @(--config|-c|--target) ]]; then
                     # Identify which component is used (arg after build that's not a flag)
                     local build_component i
                     for (( i=2; i < ${#words[@]}-1; i++ )); do
@@ -57,11 +57,13 @@ _lal()
                             build_component=${words[i]}
                         fi
                     done
-                    local -r configs="$(lal list-configurations "${build_component}")"
+                    local listing=list-configurations
+                    [[ $prev == --target ]] && listing=list-targets
+                    local -r configs="$(lal $listing "${build_component}")"
                     COMPREPLY=($(compgen -W "$configs" -- "$cur"))
                 else
                     # suggest flags
-                    local -r build_flags="-r --release -f --force -c --config -h --help --X11 -X -n --net-host --print-only --simple-verify -s --env-var"
+                    local -r build_flags="-r --release -f --force -c --config --target -h --help --X11 -X -n --net-host --print-only --simple-verify -s --env-var --watch --debounce --timings"
                     COMPREPLY=($(compgen -W "$build_flags" -- "$cur"))
                 fi
                 ;;
@@ -71,11 +73,27 @@ _lal()
                 local -r components=$(lal list-components)
                 if [[ $prev = "publish" ]]; then
                     COMPREPLY=($(compgen -W "$components" -- "$cur"))
+                else
+                    COMPREPLY=($(compgen -W "--channel" -- "$cur"))
+                fi
+                ;;
+            lock)
+                if [[ $prev = "lock" ]]; then
+                    COMPREPLY=($(compgen -W "diff export help" -- "$cur"))
+                fi
+                ;;
+            test)
+                COMPREPLY=($(compgen -W "-p --privileged -n --net-host --print-only --env-var" -- "$cur"))
+                ;;
+            logs)
+                if [[ $prev = "logs" ]]; then
+                    local -r logs="$(ls .lal/logs 2> /dev/null)"
+                    COMPREPLY=($(compgen -W "$logs --last" -- "$cur"))
                 fi
                 ;;
             env)
                 [[ $in_lal_repo ]] || return 0
-                local -r env_subs="set reset update help -h --help"
+                local -r env_subs="list set reset sync update help -h --help"
                 if [[ $prev = "set" ]]; then
                     local -r envs="$(lal list-supported-environments)"
                     COMPREPLY=($(compgen -W "$envs" -- "$cur"))
@@ -94,7 +112,7 @@ _lal()
                 local -r ls_flags="-f --full -o --origin -t --time -h --help"
                 COMPREPLY=($(compgen -W "$ls_flags" -- "$cur"))
                 ;;
-            export|query)
+            add|export|query)
                 components=$(find "$HOME/.lal/cache/environments" -maxdepth 2 -mindepth 2 -type d -printf "%f " 2> /dev/null)
                 COMPREPLY=($(compgen -W "$components" -- "$cur"))
                 ;;
@@ -154,8 +172,8 @@ _lal()
                 ;;
             script|run)
                 [[ $in_lal_repo ]] || return 0
-                # locate the scripts in .lal/scripts
-                local -r scripts="$(find "$PWD/.lal/scripts/" -maxdepth 1 -type f -printf "%f " 2> /dev/null)"
+                # scripts in the manifest, and the deprecated ones in .lal/scripts
+                local -r scripts="$(lal list-scripts) $(find "$PWD/.lal/scripts/" -maxdepth 1 -type f -printf "%f " 2> /dev/null)"
                 local -r second_args="${scripts} -p --privileged --X11 -X -n --net-host --print-only --env-var"
 
                 if [[ $prev == @(script|run) ]] || [[ $prev == -* ]]; then
@@ -168,6 +186,7 @@ _lal()
                             run_script=${words[i]}
                         fi
                     done
+                    [[ -f "$PWD/.lal/scripts/$run_script" ]] || return 0
                     local -r comps=$(source "$PWD/.lal/scripts/$run_script"; completer)
                     COMPREPLY=($(compgen -W "$comps" -- "$cur"))
                 fi
@@ -1,10 +1,18 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::io::Write;
+use serde_json;
+use tar::Archive;
 
 use shell;
-use verify::verify;
-use super::{ensure_dir_exists_fresh, output, Lockfile, Manifest, Container, Config, LalResult,
-            CliError, DockerRunFlags, ShellModes};
+use logs;
+use storage::Backend;
+use verify::{verify, VerifyOptions};
+use hooks::{run_hook, HookContext};
+use sbom::{sbom_document, SbomFormat};
+use super::{ensure_dir_exists_fresh, input, output, Lockfile, Manifest, Container, Config,
+            LalResult, CliError, IoResultExt, DockerRunFlags, ShellModes, VerifyCheck,
+            BuildCache, BuildRecord, PhaseTimer, COMPRESSIONS, SilentReporter, unpack_safely};
 
 
 fn find_valid_build_script() -> LalResult<String> {
@@ -39,11 +47,14 @@ fn find_valid_build_script() -> LalResult<String> {
 
 
 /// Configurable build flags for `lal build`
+#[derive(Clone)]
 pub struct BuildOptions {
     /// Component to build if specified
     pub name: Option<String>,
     /// Configuration to use for the component if specified
     pub configuration: Option<String>,
+    /// Target to build the component for if specified (it must have a targets list)
+    pub target: Option<String>,
     /// Container to run the `./BUILD` script in
     pub container: Container,
     /// Create release tarball in `./ARTIFACT`
@@ -52,40 +63,306 @@ pub struct BuildOptions {
     pub version: Option<String>,
     /// An explicit sha changeset id to put in the lockfile
     pub sha: Option<String>,
-    /// Ignore verify failures
+    /// Ignore verify failures, and rebuild even if nothing changed since the last build
     pub force: bool,
     /// Use the `simple` verify algorithm
     pub simple_verify: bool,
+    /// Verify checks that only warn (ignored for release builds)
+    pub verify_warnings: Vec<VerifyCheck>,
+    /// Give every file in the release tarball the same mtime
+    pub normalize_mtimes: bool,
+    /// Print where the time went once the build is done
+    pub timings: bool,
 }
 
+// what a build starts from, to compare with the last successful build
+//
+// The OUTPUT digest is only known (and filled in) once the build succeeds.
+fn build_record(
+    cfg: &Config,
+    container: &Container,
+    opts: &BuildOptions,
+    target: Option<&String>,
+    modes: &ShellModes,
+) -> LalResult<BuildRecord> {
+    let parameters = format!("container={}@{} target={} release={} version={} sha={} \
+                              tarball={}{} vars={}",
+                             container,
+                             container.digest.clone().unwrap_or_default(),
+                             target.map_or("", |t| t.as_str()),
+                             opts.release,
+                             opts.version.clone().unwrap_or_default(),
+                             opts.sha.clone().unwrap_or_default(),
+                             cfg.compression.extension(),
+                             if opts.normalize_mtimes { " normalized" } else { "" },
+                             modes.env_vars.join(","));
+    let input = Path::new("./INPUT");
+    Ok(BuildRecord {
+        parameters: parameters,
+        input: if input.is_dir() { output::contents_digest(input)? } else { "none".into() },
+        sources: output::source_digest(Path::new("."))?,
+        output: String::new(),
+    })
+}
+
+// the part of OUTPUT a build records its lockfile in, and that its digest covers
+//
+// Debug builds for a target only own their `OUTPUT/<target>/<configuration>` directory,
+// as the rest of OUTPUT holds the builds of other targets and configurations.
+fn output_cell(target: Option<&String>, configuration: &str, release: bool) -> PathBuf {
+    match target {
+        Some(t) if !release => Path::new("./OUTPUT").join(t).join(configuration),
+        _ => Path::new("./OUTPUT").to_path_buf(),
+    }
+}
+
+// whether the last successful build started from the same record, and its results are intact
+fn unchanged_since_last_build(
+    cfg: &Config,
+    key: &str,
+    component: &str,
+    cell: (&str, Option<&String>),
+    record: &BuildRecord,
+    release: bool,
+) -> LalResult<bool> {
+    let last = match BuildCache::read()?.builds.get(key) {
+        Some(r) => r.clone(),
+        None => return Ok(false),
+    };
+    if last.parameters != record.parameters || last.input != record.input ||
+        last.sources != record.sources
+    {
+        debug!("Inputs of {} changed since the last build", key);
+        return Ok(false);
+    }
+    let outdir = output_cell(cell.1, cell.0, release);
+    // the lockfile is that of the last configuration and target built there
+    let built = Lockfile::from_path(&outdir.join("lockfile.json"), component)
+        .ok()
+        .map_or(false, |lf| lf.name == component && (&*lf.config, lf.target.as_ref()) == cell);
+    if !built || output::contents_digest(&outdir)? != last.output {
+        debug!("OUTPUT changed since the last build of {}", key);
+        return Ok(false);
+    }
+    let tarball = Path::new("./ARTIFACT").join(cfg.compression.tarball_name(component));
+    if release && !(Path::new("./ARTIFACT/lockfile.json").is_file() && tarball.is_file()) {
+        debug!("ARTIFACT is missing the release build of {}", key);
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+
+// remember a successful build so an unchanged tree is not built again
+fn record_build(key: String, mut record: BuildRecord, contents: String) -> LalResult<()> {
+    record.output = contents;
+    let mut cache = BuildCache::read()?;
+    cache.builds.insert(key, record);
+    cache.write()
+}
+
+// key of a build in the remote build cache, from everything that goes into it
+//
+// Builds with stashed or linked dependencies, or in a container without a known digest,
+// cannot be reproduced elsewhere, so they are not shared.
+fn remote_build_key(
+    manifest: &Manifest,
+    component: &str,
+    configuration: &str,
+    env: &str,
+    container: &Container,
+    record: &BuildRecord,
+) -> LalResult<Option<String>> {
+    use sha2::{Digest, Sha256};
+    if container.digest.is_none() {
+        debug!("No digest for {} - not using the remote build cache", container);
+        return Ok(None);
+    }
+    let mut sha = Sha256::default();
+    sha.input(serde_json::to_string(manifest)?.as_bytes());
+    sha.input(format!("\n{}\n{}\n{}\n{}\n", component, configuration, env, record.parameters)
+        .as_bytes());
+    for (name, lf) in input::read_lockfiles_shallow()? {
+        if lf.version.parse::<u32>().is_err() {
+            debug!("{} is at unpublished version {} - not using the remote build cache",
+                   name,
+                   lf.version);
+            return Ok(None);
+        }
+        sha.input(format!("{} {} {}\n", name, lf.version, lf.environment).as_bytes());
+    }
+    sha.input(format!("{}\n{}\n", record.input, record.sources).as_bytes());
+    Ok(Some(format!("{:x}", sha.result())))
+}
+
+// unpack the OUTPUT of an identical build from the remote build cache
+//
+// Returns false when there is none, or when it does not match the digest in its lockfile.
+fn fetch_build_output(backend: &Backend, key: &str, component: &str) -> LalResult<bool> {
+    let tmpdir = Path::new(&backend.get_cache_dir()).join("remote-output").join(key);
+    fs::create_dir_all(&tmpdir).context(format!("Failed to create {}", tmpdir.display()))?;
+    for c in &COMPRESSIONS {
+        let file = c.tarball_name("output");
+        let tarball = tmpdir.join(&file);
+        let _ = fs::remove_file(&tarball); // never resume an older download
+        let fetched = backend.get_build_output_location(key, &file)
+            .and_then(|loc| backend.raw_fetch(&loc, &tarball, &SilentReporter));
+        if let Err(e) = fetched {
+            debug!("No {} in the remote build cache ({})", file, e);
+            let _ = fs::remove_file(&tarball);
+            continue;
+        }
+        ensure_dir_exists_fresh("./OUTPUT")?;
+        let res = fs::File::open(&tarball)
+            .map_err(CliError::from)
+            .and_then(|f| c.decoder(f))
+            .and_then(|d| unpack_safely(&mut Archive::new(d), Path::new("./OUTPUT")));
+        let _ = fs::remove_file(&tarball);
+        let _ = fs::remove_dir(&tmpdir);
+        res?;
+        let lf = Lockfile::from_path(&Path::new("./OUTPUT").join("lockfile.json"), component)?;
+        if lf.contents != Some(output::contents_digest(Path::new("./OUTPUT"))?) {
+            warn!("Build output in the remote build cache does not match its lockfile");
+            return Ok(false);
+        }
+        return Ok(true);
+    }
+    let _ = fs::remove_dir(&tmpdir);
+    Ok(false)
+}
+
+// share OUTPUT with identical builds elsewhere through the remote build cache
+fn upload_build_output(cfg: &Config, backend: &Backend, key: &str) -> LalResult<()> {
+    let tmpdir = Path::new(&backend.get_cache_dir()).join("remote-output").join(key);
+    fs::create_dir_all(&tmpdir).context(format!("Failed to create {}", tmpdir.display()))?;
+    let tarball = tmpdir.join(cfg.compression.tarball_name("output"));
+    let res = output::tar(&tarball, &cfg.compression_options())
+        .and_then(|_| backend.upload_build_output(key, &tarball));
+    let _ = fs::remove_dir_all(&tmpdir);
+    res
+}
+
+// create the release tarball, lockfile and bill of materials in ARTIFACT from OUTPUT
+fn package_release(
+    cfg: &Config,
+    manifest: &Manifest,
+    opts: &BuildOptions,
+    component: &str,
+    lockfile: &Lockfile,
+) -> LalResult<()> {
+    if let Some(ref contract) = manifest.artifactContract {
+        output::verify_contract(contract)?;
+    }
+    trace!("Create ARTIFACT dir");
+    ensure_dir_exists_fresh("./ARTIFACT")?;
+    trace!("Copy lockfile to ARTIFACT dir");
+    fs::copy("./OUTPUT/lockfile.json", Path::new("./ARTIFACT/lockfile.json"))?;
+    let bom = sbom_document(lockfile, SbomFormat::CycloneDx)?;
+    let bompth = Path::new("./ARTIFACT/sbom.cdx.json");
+    fs::File::create(bompth)
+        .and_then(|mut f| f.write_all(bom.as_bytes()))
+        .context(format!("Failed to write {}", bompth.display()))?;
+
+    trace!("Tar up OUTPUT into the ARTIFACT tarball");
+    let tarpth = Path::new("./ARTIFACT").join(cfg.compression.tarball_name(component));
+    let mut copts = cfg.compression_options();
+    copts.normalize_mtimes = opts.normalize_mtimes;
+    output::tar(&tarpth, &copts)
+}
 
 /// Runs the `./BUILD` script in a container and packages artifacts.
 ///
 /// The function performs basic sanity checks, before shelling out to `docker run`
 /// to perform the actual execution of the containerized `./BUILD` script.
+/// The `pre-build` and `post-build` hooks run around it (except when only printing).
+///
+/// Successful builds are recorded in `.lal/buildcache.json` with digests of INPUT and
+/// the source tree. Building the same configuration in the same environment again is
+/// skipped while those, the build parameters, and the results are unchanged, unless
+/// `force` is set.
+///
+/// Components with `targets` in the manifest build for one target at a time, given to
+/// `./BUILD` after the configuration and as `BUILD_TARGET`. Their builds write to
+/// `OUTPUT/<target>/<configuration>` (as `BUILD_OUTPUT`), leaving the directories of other
+/// targets and configurations alone, except for release builds which start from an empty
+/// OUTPUT. Dependencies built for another target fail the build unless `force` is set.
+///
+/// The time spent in each phase of the build (verifying, pulling the image, running the
+/// script, packaging, ...) is recorded in `.lal/timings.json`, and printed with `timings`.
 ///
 pub fn build(
+    cfg: &Config,
+    manifest: &Manifest,
+    opts: &BuildOptions,
+    envname: String,
+    modes: ShellModes,
+) -> LalResult<()> {
+    run_build(cfg, manifest, opts, envname, modes, None)
+}
+
+/// Runs `build` sharing OUTPUT with identical builds through the remote build cache
+///
+/// Builds are keyed by the manifest, the resolved dependency versions, the container
+/// digest, the build parameters, and the digests of INPUT and the source tree. The OUTPUT
+/// of an earlier build with the same key is fetched from the backend instead of building
+/// (without running the build hooks), and successful builds upload their OUTPUT.
+/// `force` builds regardless, but still uploads the result. Release builds never use the
+/// remote build cache, as anyone able to upload to it could then change what is published.
+pub fn build_with_remote_cache(
+    cfg: &Config,
+    manifest: &Manifest,
+    opts: &BuildOptions,
+    envname: String,
+    modes: ShellModes,
+    backend: &Backend,
+) -> LalResult<()> {
+    run_build(cfg, manifest, opts, envname, modes, Some(backend))
+}
+
+fn run_build(
+    cfg: &Config,
+    manifest: &Manifest,
+    opts: &BuildOptions,
+    envname: String,
+    modes: ShellModes,
+    remote: Option<&Backend>,
+) -> LalResult<()> {
+    let printonly = modes.printonly;
+    let mut timer = PhaseTimer::start("build", &manifest.name);
+    let res = build_phases(cfg, manifest, opts, envname, modes, remote, &mut timer);
+    if !printonly {
+        let timings = timer.finish(res.is_ok());
+        if opts.timings {
+            timings.print();
+        }
+    }
+    res
+}
+
+// the build itself, entering the phases of the timer as it goes
+fn build_phases(
     cfg: &Config,
     manifest: &Manifest,
     opts: &BuildOptions,
     envname: String,
     _modes: ShellModes,
+    remote: Option<&Backend>,
+    timer: &mut PhaseTimer,
 ) -> LalResult<()> {
     let mut modes = _modes;
 
-    // have a better warning on first file-io operation
-    // if nfs mounts and stuff cause issues this usually catches it
-    ensure_dir_exists_fresh("./OUTPUT")
-        .map_err(|e| {
-            error!("Failed to clean out OUTPUT dir: {}", e);
-            e
-        })?;
-
     debug!("Version flag is {:?}", opts.version);
 
-    // Verify INPUT
+    timer.phase("verify");
+    // Verify INPUT - release builds enforce every check
     let mut verify_failed = false;
-    if let Some(e) = verify(manifest, &envname, opts.simple_verify).err() {
+    let vopts = VerifyOptions {
+        simple: opts.simple_verify,
+        allowed: if opts.release { vec![] } else { opts.verify_warnings.clone() },
+        ..Default::default()
+    };
+    let res = verify(manifest, &envname, &vopts);
+    if let Some(e) = res.err() {
         if !opts.force {
             return Err(e);
         }
@@ -114,38 +391,162 @@ pub fn build(
         let ename = format!("{} not found in configurations list", configuration_name);
         return Err(CliError::InvalidBuildConfiguration(ename));
     }
+    let target = component_settings.resolve_target(opts.target.clone())?;
+    if let Some(ref t) = target {
+        let deps = Lockfile::default().populate_from_input_shallow()?;
+        if let Err(e) = input::verify_target_consistency(&deps, Some(t)) {
+            if !opts.force {
+                return Err(e);
+            }
+            warn!("{} - building for {} regardless", e, t);
+        }
+    }
+    // pull the image (if missing or outdated) and pin the image the tag resolves to
+    timer.phase("docker pull");
+    let mut container = opts.container.clone();
+    if !modes.printonly {
+        super::env::check_for_update(cfg, &container, &envname)?;
+        shell::ensure_docker_image(cfg, &container)?;
+        container.digest = super::env::image_digest(cfg, &container)?;
+    }
+
+    // skip rebuilding unchanged trees
+    let cachekey = BuildCache::key(&component,
+                                   &configuration_name,
+                                   target.as_ref().map(|t| t.as_str()),
+                                   &envname);
+    timer.subject(&cachekey);
+    let mut record = None;
+    let mut remote_key = None;
+    if !modes.printonly {
+        timer.phase("build cache");
+        let r = build_record(cfg, &container, opts, target.as_ref(), &modes)?;
+        let cell = (&*configuration_name, target.as_ref());
+        let release = opts.release;
+        if !opts.force &&
+            unchanged_since_last_build(cfg, &cachekey, &component, cell, &r, release)?
+        {
+            info!("Nothing changed since the last build of {} - skipping it (--force rebuilds)",
+                  cachekey);
+            return Ok(());
+        }
+        // a failed build must not be mistaken for the last successful one
+        let mut cache = BuildCache::read()?;
+        if cache.builds.remove(&cachekey).is_some() {
+            cache.write()?;
+        }
+        // release builds are published, so they never use OUTPUT from a shared cache
+        // (and OUTPUT collecting several targets of debug builds is never shared)
+        let shared = target.is_none() && !opts.release;
+        if let (true, Some(backend)) = (shared, remote) {
+            remote_key = remote_build_key(manifest,
+                                          &component,
+                                          &configuration_name,
+                                          &envname,
+                                          &container,
+                                          &r)?;
+            if let (false, Some(key)) = (opts.force, remote_key.as_ref()) {
+                timer.phase("fetch");
+                if fetch_build_output(backend, key, &component)? {
+                    info!("Using OUTPUT of an identical build from the remote build cache");
+                    let lockpth = Path::new("./OUTPUT").join("lockfile.json");
+                    let lockfile = Lockfile::from_path(&lockpth, &component)?;
+                    let contents = lockfile.contents.clone().unwrap_or_default();
+                    return record_build(cachekey, r, contents);
+                }
+            }
+        }
+        record = Some(r);
+    }
+
+    timer.phase("prepare");
+    // every target and configuration gets a directory in OUTPUT, so that building them
+    // in turn does not clobber the others (release builds only ever hold the one built)
+    let outdir = match target {
+        Some(ref t) => format!("./OUTPUT/{}/{}", t, configuration_name),
+        None => "./OUTPUT".into(),
+    };
+    if target.is_some() && !opts.release {
+        fs::create_dir_all("./OUTPUT").context("Failed to create OUTPUT".to_string())?;
+    } else {
+        // have a better warning on first file-io operation
+        // if nfs mounts and stuff cause issues this usually catches it
+        ensure_dir_exists_fresh("./OUTPUT")
+            .map_err(|e| {
+                error!("Failed to clean out OUTPUT dir: {}", e);
+                e
+            })?;
+    }
+    if target.is_some() {
+        ensure_dir_exists_fresh(&outdir)?;
+    }
     let lockfile = Lockfile::new(&component,
-                                 &opts.container,
+                                 &container,
                                  &envname,
                                  opts.version.clone(),
                                  Some(&configuration_name))
+        .attach_target(target.clone())
         .set_default_env(manifest.environment.clone())
         .attach_revision_id(opts.sha.clone())
+        .attach_license(manifest.license.clone())
         .populate_from_input()?;
 
-    let lockpth = Path::new("./OUTPUT/lockfile.json");
-    lockfile.write(lockpth)?; // always put a lockfile in OUTPUT at the start of a build
+    let celldir = output_cell(target.as_ref(), &configuration_name, opts.release);
+    let lockpth = celldir.join("lockfile.json");
+    lockfile.write(&lockpth)?; // always put a lockfile in OUTPUT at the start of a build
+
+    // output of the build script is also captured into .lal/logs
+    let log = if modes.printonly {
+        None
+    } else {
+        let t = target.as_ref().map(|t| t.as_str());
+        Some(logs::new_build_log(&component, &configuration_name, t)?)
+    };
 
     let bpath = find_valid_build_script()?;
-    let cmd = vec![bpath, component.clone(), configuration_name];
+    let mut cmd = vec![bpath, component.clone(), configuration_name];
 
     if let Some(v) = opts.version.clone() {
         modes.env_vars.push(format!("BUILD_VERSION={}", v));
     }
+    if let Some(t) = target {
+        modes.env_vars.push(format!("BUILD_TARGET={}", t));
+        modes.env_vars.push(format!("BUILD_OUTPUT={}", outdir.trim_left_matches("./")));
+        cmd.push(t);
+    }
 
     debug!("Build script is {:?}", cmd);
     if !modes.printonly {
         info!("Running build script in {} container", envname);
     }
 
+    let hook_ctx = HookContext {
+        enabled: cfg.runHooks,
+        component: component.clone(),
+        version: opts.version.clone(),
+        environment: Some(envname.clone()),
+    };
+    if !modes.printonly {
+        timer.phase("hooks");
+        run_hook("pre-build", &hook_ctx)?;
+    }
+
     let run_flags = DockerRunFlags {
         interactive: cfg.interactive,
         privileged: false,
+        no_tty: false,
+        log: log,
     };
-    shell::docker_run(cfg, &opts.container, cmd, &run_flags, &modes)?;
+    timer.phase("script");
+    shell::docker_run(cfg, &container, cmd, &run_flags, &modes)?;
     if modes.printonly {
         return Ok(()); // nothing else worth doing - warnings are pointless
     }
+    timer.phase("packaging");
+    // record what was built so dependees can verify their INPUT against it
+    let contents = output::contents_digest(&celldir)?;
+    let lockfile = lockfile.attach_contents(contents.clone());
+    lockfile.write(&lockpth)?;
 
     // Extra info and warnings for people who missed the leading ones (build is spammy)
     if verify_failed {
@@ -160,14 +561,18 @@ pub fn build(
     }
 
     if opts.release && !modes.printonly {
-        trace!("Create ARTIFACT dir");
-        ensure_dir_exists_fresh("./ARTIFACT")?;
-        trace!("Copy lockfile to ARTIFACT dir");
-        fs::copy(&lockpth, Path::new("./ARTIFACT/lockfile.json"))?;
-
-        trace!("Tar up OUTPUT into ARTIFACT/component.tar.gz");
-        let tarpth = Path::new("./ARTIFACT").join([component, ".tar.gz".into()].concat());
-        output::tar(&tarpth)?;
+        package_release(cfg, manifest, opts, &component, &lockfile)?;
+    }
+    if let Some(r) = record {
+        record_build(cachekey, r, contents)?;
+    }
+    if let (Some(backend), Some(key)) = (remote, remote_key) {
+        timer.phase("upload");
+        if let Err(e) = upload_build_output(cfg, backend, &key) {
+            warn!("Failed to upload OUTPUT to the remote build cache: {}", e);
+        }
     }
+    timer.phase("hooks");
+    run_hook("post-build", &hook_ctx)?;
     Ok(())
 }
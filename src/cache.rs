@@ -0,0 +1,457 @@
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use libc;
+use serde_json;
+use walkdir::WalkDir;
+
+use storage::{Backend, CachedBackend};
+use super::{LalResult, CliError, IoResultExt, Config, SilentReporter, COMPRESSIONS, sha1_file,
+            sha256_file, signature_path, tarball_lockfile};
+
+// workers answering `serve` connections
+const WORKERS: usize = 8;
+// seconds a `serve` connection may wait on a read or write
+const TIMEOUT_SECS: u64 = 30;
+
+// copy a cache tree file by file, preserving hardlinks between stash blobs
+//
+// Files already present at the destination with the same size are skipped,
+// so an interrupted copy can be resumed by running the move again.
+fn copy_tree(src: &Path, dest: &Path) -> LalResult<()> {
+    use std::os::unix::fs::{symlink, MetadataExt};
+    let mut inodes: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    for e in WalkDir::new(src).into_iter() {
+        let e = e.map_err(|e| CliError::CacheMoveFailure(format!("{}", e)))?;
+        let rel = e.path().strip_prefix(src).unwrap();
+        let target = dest.join(rel);
+        let ft = e.file_type();
+        if ft.is_dir() {
+            fs::create_dir_all(&target)
+                .context(format!("Failed to create {}", target.display()))?;
+        } else if ft.is_symlink() {
+            if fs::symlink_metadata(&target).is_err() {
+                fs::read_link(e.path())
+                    .and_then(|l| symlink(l, &target))
+                    .context(format!("Failed to copy symlink {}", rel.display()))?;
+            }
+        } else {
+            let meta = e.metadata().map_err(|e| CliError::CacheMoveFailure(format!("{}", e)))?;
+            let key = (meta.dev(), meta.ino());
+            if let Some(first) = inodes.get(&key) {
+                if !target.exists() {
+                    trace!("Linking {} -> {}", target.display(), first.display());
+                    fs::hard_link(first, &target)
+                        .context(format!("Failed to link {}", target.display()))?;
+                }
+                continue;
+            }
+            let done = target.metadata().map(|m| m.len() == meta.len()).unwrap_or(false);
+            if !done {
+                trace!("Copying {}", rel.display());
+                fs::copy(e.path(), &target)
+                    .context(format!("Failed to copy {}", rel.display()))?;
+            }
+            if meta.nlink() > 1 {
+                inodes.insert(key, target);
+            }
+        }
+    }
+    Ok(())
+}
+
+// check that every file made it across, and that a few tarballs are intact
+fn verify_copy(src: &Path, dest: &Path) -> LalResult<()> {
+    let mut hashed = 0;
+    for e in WalkDir::new(src).into_iter().filter_map(|e| e.ok()) {
+        if !e.file_type().is_file() {
+            continue;
+        }
+        let rel = e.path().strip_prefix(src).unwrap();
+        let target = dest.join(rel);
+        let srclen = e.path()
+            .metadata()
+            .context(format!("Failed to stat {}", e.path().display()))?
+            .len();
+        let survived = target.metadata().map(|m| m.len() == srclen).unwrap_or(false);
+        if !survived {
+            return Err(CliError::CacheMoveFailure(format!("{} did not survive", rel.display())));
+        }
+        let file = rel.to_string_lossy();
+        if hashed < 5 && COMPRESSIONS.iter().any(|c| file.ends_with(c.extension())) {
+            if sha1_file(e.path())? != sha1_file(&target)? {
+                return Err(CliError::CacheMoveFailure(format!("{} is corrupt", rel.display())));
+            }
+            hashed += 1;
+        }
+    }
+    debug!("Verified cache copy ({} tarballs hashed)", hashed);
+    Ok(())
+}
+
+/// Move the cache directory to a new location and point the config at it
+///
+/// Tries a plain rename first, and falls back to copying across devices.
+/// The copy is verified, and the config rewritten, before the old cache is removed.
+/// Re-running an interrupted move picks up where it left off.
+pub fn relocate(cfg: &Config, newdir: &str) -> LalResult<()> {
+    let src = Path::new(&cfg.cache).to_path_buf();
+    let dest = env::current_dir()?.join(newdir);
+    if dest == src {
+        info!("Cache is already at {}", dest.display());
+        return Ok(());
+    }
+    if dest.starts_with(&src) {
+        return Err(CliError::CacheMoveFailure("destination is inside the cache".into()));
+    }
+    if dest.exists() && !dest.is_dir() {
+        return Err(CliError::CacheIsFile(dest.display().to_string()));
+    }
+
+    if src.is_dir() {
+        info!("Moving cache from {} to {}", src.display(), dest.display());
+        let renamed = if !dest.exists() {
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)
+                    .context(format!("Failed to create {}", parent.display()))?;
+            }
+            match fs::rename(&src, &dest) {
+                Ok(_) => true,
+                Err(ref e) if e.raw_os_error() == Some(libc::EXDEV) => false,
+                Err(e) => {
+                    let ctx = format!("Failed to move {} to {}", src.display(), dest.display());
+                    return Err(CliError::IoContext(ctx, e));
+                }
+            }
+        } else {
+            false
+        };
+        if !renamed {
+            debug!("Copying cache across devices");
+            copy_tree(&src, &dest)?;
+            verify_copy(&src, &dest)?;
+        }
+    } else if !dest.is_dir() {
+        return Err(CliError::Io(io::Error::new(io::ErrorKind::NotFound,
+                                               format!("no cache at {}", src.display()))));
+    } else {
+        // previous run removed (part of) the source already - just finish up
+        debug!("No cache at {} - assuming it already moved", src.display());
+    }
+
+    if src.is_dir() {
+        debug!("Removing old cache {}", src.display());
+        fs::remove_dir_all(&src)
+            .context(format!("Failed to remove old cache {}", src.display()))?;
+    }
+
+    // only point config at the new cache once the move is complete, so a failed
+    // move is resumed by running it again
+    let mut newcfg = cfg.clone();
+    newcfg.cache = dest.to_string_lossy().into_owned();
+    newcfg.write(false)
+}
+
+// artifactory storage api responses understood by `ArtifactoryBackend`
+#[derive(Serialize)]
+struct StorageChild {
+    uri: String,
+    folder: bool,
+}
+#[derive(Serialize)]
+struct StorageListing {
+    children: Vec<StorageChild>,
+}
+#[derive(Serialize)]
+struct FolderInfo {
+    created: String,
+    children: Vec<StorageChild>,
+}
+#[derive(Serialize)]
+struct FileChecksums {
+    sha1: String,
+    sha256: String,
+}
+#[derive(Serialize)]
+struct FileInfo {
+    checksums: FileChecksums,
+}
+
+// what a request to `serve` is answered with
+enum Reply {
+    Json(String),
+    File(PathBuf),
+}
+
+// tarballs a worker is fetching through the backend, so others wait rather than fetch them too
+#[derive(Default)]
+struct InFlight {
+    keys: Mutex<BTreeSet<String>>,
+    done: Condvar,
+}
+
+fn listing<I: Iterator<Item = String>>(names: I) -> LalResult<Option<Reply>> {
+    let children = names.map(|n| {
+            StorageChild {
+                uri: format!("/{}", n),
+                folder: true,
+            }
+        })
+        .collect();
+    let body = serde_json::to_string(&StorageListing { children: children })?;
+    Ok(Some(Reply::Json(body)))
+}
+
+// the cached tarball of a component, fetched through the backend when missing
+fn cached_tarball<T: Backend + ?Sized>(
+    backend: &T,
+    fetching: &InFlight,
+    env: &str,
+    name: &str,
+    version: &str,
+) -> LalResult<PathBuf> {
+    let v = version.parse::<u32>().map_err(|_| CliError::InvalidVersion(version.into()))?;
+    let key = format!("{}/{}/{}", env, name, v);
+    {
+        let mut keys = fetching.keys.lock().unwrap();
+        while keys.contains(&key) {
+            keys = fetching.done.wait(keys).unwrap();
+        }
+        keys.insert(key.clone());
+    }
+    let res = backend.retrieve_published_component(name, Some(v), env, &SilentReporter);
+    fetching.keys.lock().unwrap().remove(&key);
+    fetching.done.notify_all();
+    Ok(res?.0)
+}
+
+// answer a request path the way artifactory would (the repository names are ignored)
+//
+// Paths that name nothing are answered with None.
+fn route<T: Backend + ?Sized>(
+    backend: &T,
+    fetching: &InFlight,
+    path: &str,
+    properties: bool,
+) -> LalResult<Option<Reply>> {
+    let parts = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    match &parts[..] {
+        &["api", "storage", _, "env", env] => listing(backend.get_components(env)?.into_iter()),
+        &["api", "storage", _, "env", env, name] => {
+            listing(backend.get_versions(name, env)?.into_iter().map(|v| v.to_string()))
+        }
+        &["api", "storage", _, "env", env, name, version] if !properties => {
+            let v = version.parse::<u32>().map_err(|_| CliError::InvalidVersion(version.into()))?;
+            let created = match backend.get_publish_time(name, v, env)? {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+            // the tarball is listed so its compression can be told from the name
+            let location = backend.get_tarball_location(name, v, env)?;
+            let tarname = Path::new(&location).file_name().unwrap().to_string_lossy().into_owned();
+            let info = FolderInfo {
+                created: created.to_rfc3339(),
+                children: vec![StorageChild {
+                                   uri: format!("/{}", tarname),
+                                   folder: false,
+                               }],
+            };
+            Ok(Some(Reply::Json(serde_json::to_string(&info)?)))
+        }
+        &["api", "storage", _, "env", env, name, version, file] if !properties => {
+            let tarball = cached_tarball(backend, fetching, env, name, version)?;
+            if tarball.file_name().unwrap().to_string_lossy() != file {
+                return Ok(None);
+            }
+            let info = FileInfo {
+                checksums: FileChecksums {
+                    sha1: sha1_file(&tarball)?,
+                    sha256: sha256_file(&tarball)?,
+                },
+            };
+            Ok(Some(Reply::Json(serde_json::to_string(&info)?)))
+        }
+        &[_, "env", env, name, version, file] => {
+            let tarball = cached_tarball(backend, fetching, env, name, version)?;
+            let tarname = tarball.file_name().unwrap().to_string_lossy().into_owned();
+            if file == "lockfile.json" {
+                let lf = tarball_lockfile(&tarball)
+                    .ok_or_else(|| CliError::MissingLockfile(name.to_string()))?;
+                Ok(Some(Reply::Json(serde_json::to_string(&lf)?)))
+            } else if file == format!("{}.asc", tarname) {
+                Ok(Some(Reply::File(signature_path(&tarball))))
+            } else if file == tarname {
+                Ok(Some(Reply::File(tarball)))
+            } else {
+                Ok(None)
+            }
+        }
+        &[_, "deprecations.json"] => {
+            Ok(Some(Reply::Json(serde_json::to_string(&backend.get_deprecations()?)?)))
+        }
+        _ => Ok(None),
+    }
+}
+
+// whether a failure to answer means the component or file does not exist
+fn not_found(e: &CliError) -> bool {
+    match *e {
+        CliError::MissingComponent(_) |
+        CliError::MissingLockfile(_) |
+        CliError::MissingTarball |
+        CliError::InvalidVersion(_) |
+        CliError::NoMatchingVersion(..) => true,
+        ref e => e.is_missing_file(),
+    }
+}
+
+fn respond(out: &mut TcpStream, status: &str, len: u64) -> io::Result<()> {
+    write!(out,
+           "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+           status,
+           len)
+}
+
+// answer one connection - files are streamed from a separate thread
+fn handle<T: Backend + ?Sized>(
+    backend: &T,
+    fetching: &InFlight,
+    mut stream: TcpStream,
+) -> LalResult<()> {
+    let mut request = String::new();
+    {
+        let mut reader = BufReader::new(&stream);
+        reader.read_line(&mut request)?;
+        // headers are not needed (ranges are answered with the whole file)
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+            header.clear();
+        }
+    }
+    let parts = request.split_whitespace().collect::<Vec<_>>();
+    if parts.len() < 2 {
+        respond(&mut stream, "400 Bad Request", 0)?;
+        return Ok(());
+    }
+    let head = parts[0] == "HEAD";
+    if parts[0] != "GET" && !head {
+        respond(&mut stream, "405 Method Not Allowed", 0)?;
+        return Ok(());
+    }
+    let mut target = parts[1].splitn(2, '?');
+    let path = target.next().unwrap_or("");
+    let properties = target.next().map_or(false, |q| q.contains("properties"));
+    debug!("{} {}", parts[0], parts[1]);
+
+    match route(backend, fetching, path, properties) {
+        Ok(Some(Reply::Json(body))) => {
+            respond(&mut stream, "200 OK", body.len() as u64)?;
+            if !head {
+                stream.write_all(body.as_bytes())?;
+            }
+        }
+        Ok(Some(Reply::File(pth))) => {
+            let mut f = match fs::File::open(&pth) {
+                Ok(f) => f,
+                Err(_) => {
+                    respond(&mut stream, "404 Not Found", 0)?;
+                    return Ok(());
+                }
+            };
+            let len = f.metadata()?.len();
+            thread::spawn(move || {
+                let res = respond(&mut stream, "200 OK", len).and_then(|_| if head {
+                    Ok(0)
+                } else {
+                    io::copy(&mut f, &mut stream)
+                });
+                if let Err(e) = res {
+                    warn!("Failed to send {} ({})", pth.display(), e);
+                }
+            });
+        }
+        Ok(None) => {
+            debug!("Nothing at {}", path);
+            respond(&mut stream, "404 Not Found", 0)?;
+        }
+        Err(ref e) if not_found(e) => {
+            debug!("Nothing at {} ({})", path, e);
+            respond(&mut stream, "404 Not Found", 0)?;
+        }
+        Err(e) => {
+            warn!("Failed to answer {} ({})", path, e);
+            respond(&mut stream, "502 Bad Gateway", 0)?;
+        }
+    }
+    Ok(())
+}
+
+/// Listen for `serve` connections on an address
+///
+/// Binding port 0 picks a free port, which `local_addr` on the listener reports.
+pub fn bind(address: &str) -> LalResult<TcpListener> {
+    TcpListener::bind(address).context(format!("Failed to listen on {}", address))
+}
+
+/// Serve the cache as a read-only artifactory compatible backend
+///
+/// Build agents point `master` and `slave` of their artifactory backend config at
+/// the server. Components that are not cached yet are fetched through the backend
+/// first, so one warm cache box answers for the whole farm. Publishing is rejected.
+/// Connections are answered by a pool of workers, each with a backend from
+/// `make_backend`, so a slow upstream fetch only holds up the requests waiting for it.
+pub fn serve<F>(listener: TcpListener, make_backend: F) -> LalResult<()>
+where
+    F: Fn() -> Box<Backend> + Send + Sync + 'static,
+{
+    let address = listener.local_addr()?;
+    info!("Serving {} on http://{}", make_backend().get_cache_dir(), address);
+
+    let (tx, rx) = mpsc::channel::<TcpStream>();
+    let rx = Arc::new(Mutex::new(rx));
+    let make_backend = Arc::new(make_backend);
+    let fetching = Arc::new(InFlight::default());
+    for _ in 0..WORKERS {
+        let (rx, make_backend, fetching) = (rx.clone(), make_backend.clone(), fetching.clone());
+        thread::spawn(move || {
+            let backend = (*make_backend)();
+            loop {
+                let next = rx.lock().unwrap().recv();
+                let stream = match next {
+                    Ok(s) => s,
+                    Err(_) => break, // the listener is gone
+                };
+                if let Err(e) = handle(&*backend, &fetching, stream) {
+                    warn!("Failed to answer a request ({})", e);
+                }
+            }
+        });
+    }
+
+    // idle or stalled clients are dropped rather than holding a worker forever
+    let timeout = Some(Duration::from_secs(TIMEOUT_SECS));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Failed to accept a connection ({})", e);
+                continue;
+            }
+        };
+        if let Err(e) = stream.set_read_timeout(timeout)
+            .and_then(|_| stream.set_write_timeout(timeout)) {
+            warn!("Failed to set connection timeouts ({})", e);
+            continue;
+        }
+        if tx.send(stream).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -1,16 +1,51 @@
+use std::collections::HashSet;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use chrono::{DateTime, UTC, Duration, TimeZone};
 use filetime::FileTime;
 use walkdir::WalkDir;
 
-use super::LalResult;
+use super::{LalResult, cache_entry_users, clean_orphaned_objects};
 
-// helper for `lal::clean`
-fn clean_in_dir(cutoff: DateTime<UTC>, dirs: WalkDir) -> LalResult<()> {
+/// What `lal clean` should remove from the cache
+#[derive(Clone, Debug)]
+pub struct CleanOptions {
+    /// Remove cached artifacts not modified in this many days
+    pub days: i64,
+    /// Remove all stashed builds regardless of age
+    pub stash: bool,
+    /// Remove everything in the cache
+    pub all: bool,
+    /// Only print what would be removed and how much space it would reclaim
+    pub dry_run: bool,
+}
+
+impl Default for CleanOptions {
+    fn default() -> Self {
+        CleanOptions {
+            days: 14,
+            stash: false,
+            all: false,
+            dry_run: false,
+        }
+    }
+}
+
+/// What `lal clean` removed, or would remove on a dry run
+#[derive(Clone, Debug, Default)]
+pub struct CleanSummary {
+    /// Cache folders removed, with the bytes each of them used
+    pub folders: Vec<(PathBuf, u64)>,
+    /// Bytes reclaimed in total, including orphaned stash blobs
+    pub reclaimed: u64,
+}
+
+// helper for `lal::clean` - directories last modified before the cutoff
+fn expired_in_dir(cutoff: DateTime<UTC>, dirs: WalkDir) -> Vec<PathBuf> {
     let drs = dirs.into_iter().filter_map(|e| e.ok()).filter(|e| e.path().is_dir());
 
+    let mut expired = vec![];
     for d in drs {
         let pth = d.path();
         trace!("Checking {}", pth.to_str().unwrap());
@@ -20,29 +55,122 @@ fn clean_in_dir(cutoff: DateTime<UTC>, dirs: WalkDir) -> LalResult<()> {
 
         trace!("Found {} with mtime {}", pth.to_str().unwrap(), mtimedate);
         if mtimedate < cutoff {
-            debug!("Cleaning {}", pth.to_str().unwrap());
-            fs::remove_dir_all(pth)?;
+            expired.push(pth.to_path_buf());
         }
     }
-    Ok(())
+    expired
+}
+
+// helper for `lal::clean` - immediate subdirectories (all stashed components)
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    WalkDir::new(dir)
+        .min_depth(1)
+        .max_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.path().to_path_buf())
+        .collect()
+}
+
+// bytes used by files under a path (hardlinked files only counted once)
+fn disk_usage(pth: &Path, seen: &mut HashSet<(u64, u64)>) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    WalkDir::new(pth)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| seen.insert((m.dev(), m.ino())))
+        .map(|m| m.len())
+        .sum()
 }
 
 /// Clean old artifacts in cache directory
 ///
 /// This does the equivalent of find CACHEDIR -mindepth 3 -maxdepth 3 -type d
 /// With the correct mtime flags, then -exec deletes these folders.
-pub fn clean(cachedir: &str, days: i64) -> LalResult<()> {
+/// Stashed builds can be removed regardless of age with `stash`, and the whole
+/// cache with `all`. A `dry_run` only measures the folders and the space they use.
+pub fn clean(cachedir: &str, opts: &CleanOptions) -> LalResult<CleanSummary> {
+    let days = if opts.all { 0 } else { opts.days };
     let cutoff = UTC::now() - Duration::days(days);
     debug!("Cleaning all artifacts from before {}", cutoff);
 
+    let mut targets = vec![];
     // clean out environment subdirectories
     let edir = Path::new(&cachedir).join("environments");
-    let edirs = WalkDir::new(&edir).min_depth(3).max_depth(3);
-    clean_in_dir(cutoff, edirs)?;
+    targets.extend(expired_in_dir(cutoff, WalkDir::new(&edir).min_depth(3).max_depth(3)));
 
     // clean out stash
     let dirs = WalkDir::new(&cachedir).min_depth(3).max_depth(3);
-    clean_in_dir(cutoff, dirs)?;
+    let expired = expired_in_dir(cutoff, dirs)
+        .into_iter()
+        .filter(|d| !targets.contains(d))
+        .collect::<Vec<_>>();
+    targets.extend(expired);
+    let sdir = Path::new(&cachedir).join("stash");
+    if opts.stash || opts.all {
+        targets.extend(subdirs(&sdir).into_iter().filter(|d| !d.ends_with(".blobs")));
+    }
+
+    let mut seen = HashSet::new();
+    let mut summary = CleanSummary::default();
+    for pth in targets {
+        for user in cache_entry_users(&pth)? {
+            warn!("Cleaning {} which still backs {}", pth.display(), user);
+        }
+        let size = disk_usage(&pth, &mut seen);
+        summary.reclaimed += size;
+        if !opts.dry_run {
+            debug!("Cleaning {}", pth.to_str().unwrap());
+            fs::remove_dir_all(&pth)?;
+        }
+        summary.folders.push((pth, size));
+    }
+
+    // clean out stash blobs and INPUT objects no longer linked from anywhere
+    let blobdir = sdir.join(".blobs");
+    summary.reclaimed += orphaned_blob_usage(&blobdir, &mut seen)?;
+    if !opts.dry_run {
+        clean_orphaned_blobs(&blobdir)?;
+        clean_orphaned_objects(cachedir)?;
+        info!("Cleaned {} cache folders", summary.folders.len());
+    }
+
+    Ok(summary)
+}
 
+// helper for `lal::clean` - blobs with a single link are only referenced by themselves
+pub fn clean_orphaned_blobs(blobdir: &Path) -> LalResult<()> {
+    use std::os::unix::fs::MetadataExt;
+    if !blobdir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(blobdir)? {
+        let pth = entry?.path();
+        if pth.metadata()?.nlink() == 1 {
+            debug!("Cleaning orphaned stash blob {}", pth.display());
+            fs::remove_file(&pth)?;
+        }
+    }
     Ok(())
 }
+
+// helper for `lal::clean` - size of blobs that are orphaned
+//
+// Blobs orphaned by removing stash folders share inodes with them and are counted there.
+fn orphaned_blob_usage(blobdir: &Path, seen: &mut HashSet<(u64, u64)>) -> LalResult<u64> {
+    use std::os::unix::fs::MetadataExt;
+    if !blobdir.is_dir() {
+        return Ok(0);
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(blobdir)? {
+        let meta = entry?.path().metadata()?;
+        if meta.nlink() == 1 && seen.insert((meta.dev(), meta.ino())) {
+            size += meta.len();
+        }
+    }
+    Ok(size)
+}
@@ -1,10 +1,115 @@
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::env;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::io::Write;
 use semver::Version;
 
-use super::{LalResult, Config, ConfigDefaults, CliError, config_dir};
+use super::{LalResult, Config, ConfigDefaults, CliError, Credentials, config_dir};
+
+/// A source of artifactory credentials other than the plaintext config
+pub trait CredentialProvider {
+    /// Credentials for a url, if the provider has any
+    fn credentials(&self, url: &str) -> LalResult<Option<Credentials>>;
+}
+
+/// A git-credential style helper command
+///
+/// The command is run through the shell as `<command> get`, with the protocol and
+/// host of the url on stdin, and is expected to print `username=` and `password=`
+/// lines (or an `api_key=` line) on stdout.
+pub struct CredentialHelper {
+    /// Helper command line
+    pub command: String,
+}
+
+/// The OS keychain - `secret-tool` on linux and `security` on macOS
+///
+/// An artifactory API key is looked up with the service `lal` and the url's host
+/// (`secret-tool store --label=lal service lal host <host>` to add it on linux).
+pub struct Keychain;
+
+// split a url into its protocol and host
+fn protocol_and_host(url: &str) -> (String, String) {
+    let mut parts = url.splitn(2, "://");
+    let protocol = parts.next().unwrap_or("https").to_string();
+    let rest = parts.next().unwrap_or("");
+    let host = rest.split('/').next().unwrap_or("").to_string();
+    (protocol, host)
+}
+
+impl CredentialProvider for CredentialHelper {
+    fn credentials(&self, url: &str) -> LalResult<Option<Credentials>> {
+        let (protocol, host) = protocol_and_host(url);
+        debug!("Asking credential helper for {}", host);
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} get", self.command))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        write!(child.stdin.as_mut().unwrap(),
+               "protocol={}\nhost={}\n\n",
+               protocol,
+               host)?;
+        let out = child.wait_with_output()?;
+        if !out.status.success() {
+            return Err(CliError::CredentialHelperFailure(self.command.clone()));
+        }
+        let mut creds = Credentials::default();
+        for line in String::from_utf8_lossy(&out.stdout).lines() {
+            let mut kv = line.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some("username"), Some(v)) => creds.username = v.into(),
+                (Some("password"), Some(v)) => creds.password = v.into(),
+                (Some("api_key"), Some(v)) => creds.api_key = Some(v.into()),
+                _ => {}
+            }
+        }
+        if creds.username.is_empty() && creds.api_key.is_none() {
+            debug!("Credential helper had no credentials for {}", host);
+            return Ok(None);
+        }
+        Ok(Some(creds))
+    }
+}
+
+impl CredentialProvider for Keychain {
+    fn credentials(&self, url: &str) -> LalResult<Option<Credentials>> {
+        let (_, host) = protocol_and_host(url);
+        debug!("Looking up {} in the keychain", host);
+        let out = if cfg!(target_os = "macos") {
+            Command::new("security")
+                .args(&["find-generic-password", "-s", "lal", "-a", host.as_str(), "-w"])
+                .output()
+        } else {
+            Command::new("secret-tool")
+                .args(&["lookup", "service", "lal", "host", host.as_str()])
+                .output()
+        };
+        let out = out.map_err(|_| CliError::CredentialHelperFailure("keychain".into()))?;
+        let key = String::from_utf8_lossy(&out.stdout).trim().to_string();
+        if !out.status.success() || key.is_empty() {
+            debug!("No keychain entry for {}", host);
+            return Ok(None);
+        }
+        Ok(Some(Credentials {
+                    api_key: Some(key),
+                    ..Default::default()
+                }))
+    }
+}
+
+/// The credential provider chosen by `credentialHelper` in the config
+///
+/// The value `keychain` uses the OS keychain, anything else is a helper command.
+pub fn credential_provider(cfg: &Config) -> Option<Box<CredentialProvider>> {
+    match cfg.credentialHelper {
+        Some(ref h) if h == "keychain" => Some(Box::new(Keychain)),
+        Some(ref h) => Some(Box::new(CredentialHelper { command: h.clone() })),
+        None => None,
+    }
+}
 
 fn executable_on_path(exe: &str) -> LalResult<()> {
     trace!("Verifying executable {}", exe);
@@ -178,7 +283,7 @@ fn non_root_sanity() -> LalResult<()> {
 }
 
 fn create_lal_dir() -> LalResult<PathBuf> {
-    let laldir = config_dir();
+    let laldir = config_dir()?;
     if !laldir.is_dir() {
         fs::create_dir(&laldir)?;
     }
@@ -193,11 +298,18 @@ fn create_lal_dir() -> LalResult<PathBuf> {
 ///
 /// A boolean option to discard the output is supplied for tests.
 /// A defaults file must be supplied to seed the new config with defined environments
-pub fn configure(save: bool, interactive: bool, defaults: &str) -> LalResult<Config> {
+/// The `project` flag writes the defaults to `./.lal/config` instead, overriding the
+/// global config for this project only.
+/// A `runtime` in the defaults file (e.g. `podman`) is checked instead of docker.
+pub fn configure(save: bool, interactive: bool, defaults: &str, project: bool)
+    -> LalResult<Config> {
     let _ = create_lal_dir()?;
 
+    let def = ConfigDefaults::read(defaults)?;
+    let runtime = def.runtime.clone().unwrap_or_else(|| "docker".into());
+
     for exe in [
-        "docker",
+        runtime.as_str(),
         "tar",
         "touch",
         "id",
@@ -209,22 +321,25 @@ pub fn configure(save: bool, interactive: bool, defaults: &str) -> LalResult<Con
     {
         executable_on_path(exe)?;
     }
-    docker_sanity()?;
-    docker_version_check()?;
+    // the docker checks do not apply to other runtimes
+    if Path::new(&runtime).file_name().map_or(false, |f| f == "docker") {
+        docker_sanity()?;
+        docker_version_check()?;
+    }
     kernel_sanity()?;
     ssl_cert_sanity()?;
     non_root_sanity()?;
 
-    let def = ConfigDefaults::read(defaults)?;
-
     // Enforce minimum_lal version check here if it's set in the defaults file
     if let Some(minlal) = def.minimum_lal.clone() {
         lal_version_check(&minlal)?;
     }
 
-    let mut cfg = Config::new(def);
+    let mut cfg = Config::new(def)?;
     cfg.interactive = interactive; // need to override default for tests
-    if save {
+    if save && project {
+        cfg.write_project(false)?;
+    } else if save {
         cfg.write(false)?;
     }
     Ok(cfg)
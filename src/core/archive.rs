@@ -0,0 +1,113 @@
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, Entry, EntryType};
+
+use super::{CliError, LalResult};
+
+// whether a path only walks down from the extraction directory
+fn is_contained(pth: &Path) -> bool {
+    pth.components().all(|c| match c {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false,
+    })
+}
+
+// the path without `./` components, so that equal entry paths compare equal
+fn normalize(pth: &Path) -> PathBuf {
+    pth.components().filter(|c| *c != Component::CurDir).collect()
+}
+
+/// Checks tarball entries before they are extracted
+///
+/// Entry paths must stay inside the destination, symlinks and hardlinks must point inside
+/// it, nothing may be extracted through an earlier symlink, and device nodes are refused.
+/// Entries have to be checked in archive order as symlinks are tracked along the way.
+#[derive(Default)]
+pub struct EntryGuard {
+    symlinks: BTreeSet<PathBuf>,
+}
+
+impl EntryGuard {
+    // whether a relative symlink target resolves inside the root from where the link is
+    fn target_contained(&self, link: &Path, target: &Path) -> bool {
+        let mut at: Vec<OsString> = match link.parent() {
+            Some(p) => p.components().map(|c| c.as_os_str().to_owned()).collect(),
+            None => vec![],
+        };
+        for c in target.components() {
+            match c {
+                Component::Normal(n) => at.push(n.to_owned()),
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    // `..` out of another symlink goes wherever that symlink points
+                    if self.symlinks.contains(&at.iter().collect::<PathBuf>()) {
+                        return false;
+                    }
+                    if at.pop().is_none() {
+                        return false;
+                    }
+                }
+                _ => return false, // absolute
+            }
+        }
+        true
+    }
+
+    /// Check an entry, failing with `MaliciousArchive` if it must not be extracted
+    pub fn check<R: Read>(&mut self, entry: &Entry<R>) -> LalResult<()> {
+        let pth = entry.path()?.into_owned();
+        let reject = |reason: String| {
+            Err(CliError::MaliciousArchive(pth.display().to_string(), reason))
+        };
+        if !is_contained(&pth) {
+            return reject("escapes the extraction directory".into());
+        }
+        let pth = normalize(&pth);
+        if let Some(link) = self.symlinks.iter().find(|l| pth.starts_with(l) && pth != **l) {
+            return reject(format!("is extracted through the symlink {}", link.display()));
+        }
+        match entry.header().entry_type() {
+            EntryType::Symlink => {
+                let target = entry.link_name()?.map(|l| l.into_owned()).unwrap_or_default();
+                if !self.target_contained(&pth, &target) {
+                    return reject(format!("links to {} outside the extraction directory",
+                                          target.display()));
+                }
+                self.symlinks.insert(pth);
+            }
+            EntryType::Link => {
+                let target = entry.link_name()?.map(|l| l.into_owned()).unwrap_or_default();
+                if !is_contained(&target) {
+                    return reject(format!("hardlinks to {} outside the extraction directory",
+                                          target.display()));
+                }
+            }
+            EntryType::Char | EntryType::Block => return reject("is a device node".into()),
+            EntryType::Fifo => return reject("is a fifo".into()),
+            _ => {
+                // a regular entry replacing an earlier symlink is no longer one
+                self.symlinks.remove(&pth);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Extract a whole tarball into a directory, checking every entry with an `EntryGuard`
+///
+/// Mode bits (ignoring the umask), mtimes and symlinks are extracted as recorded.
+/// Fails on the first entry that is refused, leaving what was extracted before it.
+pub fn unpack_safely<R: Read>(archive: &mut Archive<R>, dest: &Path) -> LalResult<()> {
+    fs::create_dir_all(dest)?;
+    archive.set_preserve_permissions(true);
+    let mut guard = EntryGuard::default();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        guard.check(&entry)?;
+        entry.unpack_in(dest)?;
+    }
+    Ok(())
+}
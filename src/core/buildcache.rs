@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::prelude::{Read, Write};
+use std::path::Path;
+use serde_json;
+
+use super::LalResult;
+use manifest::create_lal_subdir;
+
+/// A successful build as recorded in `.lal/buildcache.json`
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct BuildRecord {
+    /// Everything else the build depended on (container, release flags, variables)
+    pub parameters: String,
+    /// Content digest of INPUT when the build started
+    pub input: String,
+    /// Content digest of the source tree when the build started
+    pub sources: String,
+    /// Content digest of the OUTPUT the build produced
+    pub output: String,
+}
+
+/// Representation of `.lal/buildcache.json`
+///
+/// Holds the last successful build of every component, configuration, target and environment,
+/// so that builds of unchanged trees can be skipped.
+#[derive(Serialize, Deserialize, Default)]
+pub struct BuildCache {
+    /// Builds by `component/configuration/environment` (`configuration:target` for targets)
+    pub builds: BTreeMap<String, BuildRecord>,
+}
+
+impl BuildCache {
+    /// Key of a build in the cache
+    pub fn key(
+        component: &str,
+        configuration: &str,
+        target: Option<&str>,
+        environment: &str,
+    ) -> String {
+        match target {
+            Some(t) => format!("{}/{}:{}/{}", component, configuration, t, environment),
+            None => format!("{}/{}/{}", component, configuration, environment),
+        }
+    }
+
+    /// Read `.lal/buildcache.json` (empty if it is missing or unreadable)
+    ///
+    /// A broken build cache only means the next build cannot be skipped.
+    pub fn read() -> LalResult<BuildCache> {
+        let pth = Path::new(".lal/buildcache.json");
+        if !pth.exists() {
+            return Ok(BuildCache::default());
+        }
+        let mut data = String::new();
+        fs::File::open(&pth)?.read_to_string(&mut data)?;
+        Ok(serde_json::from_str(&data).unwrap_or_else(|e| {
+            debug!("Ignoring unparseable {}: {}", pth.display(), e);
+            BuildCache::default()
+        }))
+    }
+
+    /// Overwrite `.lal/buildcache.json` with the current builds
+    pub fn write(&self) -> LalResult<()> {
+        let pwd = env::current_dir()?;
+        create_lal_subdir(&pwd)?;
+        let pth = Path::new(".lal/buildcache.json");
+        let encoded = serde_json::to_string_pretty(self)?;
+        let mut f = fs::File::create(&pth)?;
+        write!(f, "{}\n", encoded)?;
+        debug!("Wrote {}", pth.display());
+        Ok(())
+    }
+}
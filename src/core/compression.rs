@@ -0,0 +1,132 @@
+use std::env;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use flate2::read::GzDecoder;
+use xz2::read::XzDecoder;
+use zstd;
+
+use super::LalResult;
+
+/// How component tarballs are compressed
+///
+/// The compression of a tarball is told by its file extension, so components published
+/// with different compressions can be fetched side by side.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Compression {
+    /// `.tar.gz` (the default, as every lal version can read it)
+    #[serde(rename = "gzip")]
+    Gzip,
+    /// `.tar.zst`
+    #[serde(rename = "zstd")]
+    Zstd,
+    /// `.tar.xz`
+    #[serde(rename = "xz")]
+    Xz,
+}
+
+impl Default for Compression {
+    fn default() -> Self { Compression::Gzip }
+}
+
+/// Every supported compression, in the order tarballs are looked for
+pub const COMPRESSIONS: [Compression; 3] = [Compression::Gzip, Compression::Zstd, Compression::Xz];
+
+impl Compression {
+    /// File extension of tarballs with this compression
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Compression::Gzip => ".tar.gz",
+            Compression::Zstd => ".tar.zst",
+            Compression::Xz => ".tar.xz",
+        }
+    }
+
+    /// The tarball file name of a component
+    pub fn tarball_name(&self, name: &str) -> String { format!("{}{}", name, self.extension()) }
+
+    /// The compression of a tarball from its file extension (gzip if unknown)
+    pub fn from_path(pth: &Path) -> Compression {
+        let file = pth.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+        COMPRESSIONS.iter()
+            .cloned()
+            .find(|c| file.ends_with(c.extension()))
+            .unwrap_or_default()
+    }
+
+    /// The compression of a file if it is the tarball of a component
+    pub fn of_tarball(file: &str, name: &str) -> Option<Compression> {
+        COMPRESSIONS.iter().cloned().find(|c| file == c.tarball_name(name))
+    }
+
+    // the program (and arguments) `tar` compresses with
+    fn compressor(&self, level: Option<u32>, threads: Option<u32>) -> String {
+        // 0 threads means one per core for zstd and xz
+        let threads = threads.unwrap_or(0);
+        let mut cmd = match *self {
+            // gzip only ever uses one core, pigz uses every core by default
+            // gzip leaves the header timestamp unset on pipes, pigz needs `-n` for that
+            Compression::Gzip if !has_program("pigz") => "gzip".to_string(),
+            Compression::Gzip if threads > 0 => format!("pigz -n -p {}", threads),
+            Compression::Gzip => "pigz -n".to_string(),
+            Compression::Zstd => format!("zstd -T{}", threads),
+            Compression::Xz => format!("xz -T{}", threads),
+        };
+        if let Some(l) = level {
+            cmd.push_str(&format!(" -{}", l));
+        }
+        cmd
+    }
+
+    /// Decompress tarball data as it is read
+    pub fn decoder<'a, R: Read + 'a>(&self, data: R) -> LalResult<Box<Read + 'a>> {
+        let decoder: Box<Read + 'a> = match *self {
+            Compression::Gzip => Box::new(GzDecoder::new(data)?),
+            Compression::Zstd => Box::new(zstd::Decoder::new(data)?),
+            Compression::Xz => Box::new(XzDecoder::new(data)),
+        };
+        Ok(decoder)
+    }
+}
+
+/// How tarballs are compressed when they are created
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CompressionOptions {
+    /// Compression of created tarballs
+    pub compression: Compression,
+    /// Compression level (the compressor default if unset)
+    pub level: Option<u32>,
+    /// Threads compressing (every core if unset)
+    pub threads: Option<u32>,
+    /// Record the same mtime for every entry (for reproducible tarballs)
+    pub normalize_mtimes: bool,
+}
+
+impl CompressionOptions {
+    /// The tarball file name of a component with these options
+    pub fn tarball_name(&self, name: &str) -> String { self.compression.tarball_name(name) }
+
+    /// The `tar` argument compressing a tarball, by the compression its extension says
+    ///
+    /// Compression is multi-threaded, except for gzip when `pigz` is not installed.
+    pub fn tar_arg(&self, tarball: &Path) -> String {
+        let compression = Compression::from_path(tarball);
+        format!("--use-compress-program={}", compression.compressor(self.level, self.threads))
+    }
+}
+
+// whether an executable is on the PATH
+fn has_program(name: &str) -> bool {
+    env::var_os("PATH").map_or(false, |paths| {
+        env::split_paths(&paths).any(|dir| dir.join(name).is_file())
+    })
+}
+
+/// The tarball of a component in a directory, whatever its compression
+///
+/// Returns the gzip tarball path if there is none, so callers can report it missing.
+pub fn find_tarball(dir: &Path, name: &str) -> PathBuf {
+    COMPRESSIONS.iter()
+        .map(|c| dir.join(c.tarball_name(name)))
+        .find(|pth| pth.is_file())
+        .unwrap_or_else(|| dir.join(Compression::Gzip.tarball_name(name)))
+}
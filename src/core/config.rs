@@ -6,23 +6,139 @@ use std::vec::Vec;
 use std::io::prelude::*;
 use std::collections::BTreeMap;
 use std::env;
+use std::str::FromStr;
 
-use super::{Container, LalResult, CliError};
-use storage::BackendConfiguration;
+use serde_json::Value;
 
-fn find_home_dir() -> PathBuf {
+use super::{Container, Compression, CompressionOptions, LalResult, CliError, IoResultExt,
+            SignaturePolicy};
+use storage::{ArtifactoryConfig, BackendConfiguration, HttpOptions, LocalConfig};
+
+/// Resolve the users home directory from `HOME`
+///
+/// Fails with `CliError::MissingHome` rather than panicking when lal runs
+/// without `HOME` (systemd services, minimal containers).
+pub fn home_dir() -> LalResult<PathBuf> {
+    match env::var_os("HOME") {
+        Some(ref h) if !h.is_empty() => Ok(Path::new(h).to_owned()),
+        _ => Err(CliError::MissingHome),
+    }
+}
+
+fn find_home_dir() -> LalResult<PathBuf> {
     // Either we have LAL_CONFIG_HOME evar, or HOME
     if let Ok(lh) = env::var("LAL_CONFIG_HOME") {
-        Path::new(&lh).to_owned()
+        Ok(Path::new(&lh).to_owned())
     } else {
-        env::home_dir().unwrap()
+        home_dir()
     }
 }
 
 /// Master override for where the .lal config lives
-pub fn config_dir() -> PathBuf {
-    let home = find_home_dir();
-    Path::new(&home).join(".lal")
+pub fn config_dir() -> LalResult<PathBuf> {
+    let home = find_home_dir()?;
+    Ok(Path::new(&home).join(".lal"))
+}
+
+/// Location of a project config overriding the global one (`./.lal/config`)
+pub fn project_config_path() -> LalResult<PathBuf> {
+    Ok(env::current_dir()?.join(".lal").join("config"))
+}
+
+// the project config, unless it is missing or actually the global config (cwd is home)
+fn project_overrides() -> LalResult<Option<PathBuf>> {
+    let pth = project_config_path()?;
+    if !pth.is_file() {
+        return Ok(None);
+    }
+    let global = config_dir()?.join("config");
+    if global.is_file() && fs::canonicalize(&global)? == fs::canonicalize(&pth)? {
+        return Ok(None);
+    }
+    Ok(Some(pth))
+}
+
+fn read_json(pth: &Path) -> LalResult<Value> {
+    let mut data = String::new();
+    fs::File::open(pth)
+        .and_then(|mut f| f.read_to_string(&mut data))
+        .context(format!("Failed to read config {}", pth.display()))?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+// merge `over` into `base` - objects merge key by key, anything else is replaced
+fn merge_json(base: &mut Value, over: Value) {
+    match (base, over) {
+        (&mut Value::Object(ref mut b), Value::Object(o)) => {
+            for (k, v) in o {
+                if b.contains_key(&k) {
+                    merge_json(b.get_mut(&k).unwrap(), v);
+                } else {
+                    b.insert(k, v);
+                }
+            }
+        }
+        (b, o) => *b = o,
+    }
+}
+
+// Environment variables overriding the config, and the top level key each one sets
+const ENV_OVERRIDES: &'static [(&'static str, &'static str)] = &[
+    ("LAL_CACHE", "cache"),
+    ("LAL_BACKEND", "backend"),
+    ("LAL_ARTIFACTORY", "backend"),
+    ("LAL_ARTIFACTORY_RELEASE", "backend"),
+    ("LAL_ARTIFACTORY_VGROUP", "backend"),
+    ("LAL_LOCAL_PATH", "backend"),
+    ("LAL_CONTAINER", "environments"),
+    ("LAL_INTERACTIVE", "interactive"),
+    ("LAL_AUTOUPGRADE", "autoupgrade"),
+    ("LAL_CACHE_MAX_SIZE_GB", "cacheMaxSizeGb"),
+    ("LAL_CA_BUNDLE", "caBundle"),
+    ("LAL_CREDENTIAL_HELPER", "credentialHelper"),
+    ("LAL_IMAGE_UPDATES", "imageUpdates"),
+    ("LAL_RUNTIME", "runtime"),
+    ("LAL_ENV_PASSTHROUGH", "envPassthrough"),
+    ("LAL_VERIFY_WARNINGS", "verifyWarnings"),
+    ("LAL_REQUIRE_SIGNATURES", "requireSignatures"),
+    ("LAL_REMOTE_BUILD_CACHE", "remoteBuildCache"),
+    ("LAL_COMPRESSION", "compression"),
+    ("LAL_COMPRESSION_LEVEL", "compressionLevel"),
+    ("LAL_COMPRESSION_THREADS", "compressionThreads"),
+    ("LAL_SIGNING_KEY", "signingKey"),
+    ("LAL_PUBLIC_KEYS", "publicKeys"),
+    ("LAL_RETENTION_KEEP_LAST", "retention"),
+    ("LAL_RETENTION_KEEP_CHANNELS", "retention"),
+    ("LAL_RUN_HOOKS", "runHooks"),
+    ("LAL_MOUNTS", "mounts"),
+    ("LAL_MINIMUM_LAL", "minimum_lal"),
+];
+
+fn env_override(name: &str) -> Option<String> { env::var(name).ok().filter(|v| !v.is_empty()) }
+
+fn parse_bool_override(name: &str, val: &str) -> LalResult<bool> {
+    match val {
+        "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" => Ok(false),
+        _ => Err(CliError::InvalidConfigOverride(name.into(), val.into())),
+    }
+}
+
+fn parse_num_override<T: FromStr>(name: &str, val: &str) -> LalResult<T> {
+    val.parse::<T>().map_err(|_| CliError::InvalidConfigOverride(name.into(), val.into()))
+}
+
+fn split_list_override(val: &str) -> Vec<String> {
+    val.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect()
+}
+
+// Cache location for new configs - `LAL_CACHE_HOME` overrides `config_dir()/cache`
+fn default_cache_dir() -> LalResult<PathBuf> {
+    if let Ok(ch) = env::var("LAL_CACHE_HOME") {
+        Ok(Path::new(&ch).to_owned())
+    } else {
+        Ok(config_dir()?.join("cache"))
+    }
 }
 
 /// Docker volume mount representation
@@ -36,6 +152,80 @@ pub struct Mount {
     pub readonly: bool,
 }
 
+/// What builds do when an environment's container was updated in the registry
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum ImageUpdatePolicy {
+    /// Do not check (the default, as checking needs the registry)
+    #[serde(rename = "off")]
+    Off,
+    /// Warn when the local image is stale
+    #[serde(rename = "warn")]
+    Warn,
+    /// Pull the updated image before building
+    #[serde(rename = "pull")]
+    Pull,
+}
+
+impl Default for ImageUpdatePolicy {
+    fn default() -> Self { ImageUpdatePolicy::Off }
+}
+
+/// A verify check that can be downgraded to a warning outside of release builds
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum VerifyCheck {
+    /// Dependencies in INPUT that are not the published versions in the manifest
+    NonGlobalDependencies,
+    /// Dependencies built in a different environment (or several environments)
+    EnvironmentMismatch,
+    /// Dependencies used at several versions in the dependency tree
+    MultipleVersions,
+    /// Dependencies built for different targets
+    TargetMismatch,
+}
+
+impl VerifyCheck {
+    /// Names of all the checks as used in the config and on the command line
+    pub fn names() -> &'static [&'static str] {
+        &["NonGlobalDependencies", "EnvironmentMismatch", "MultipleVersions", "TargetMismatch"]
+    }
+
+    /// Parse a check from its name
+    pub fn from_name(name: &str) -> Option<VerifyCheck> {
+        match name {
+            "NonGlobalDependencies" => Some(VerifyCheck::NonGlobalDependencies),
+            "EnvironmentMismatch" => Some(VerifyCheck::EnvironmentMismatch),
+            "MultipleVersions" => Some(VerifyCheck::MultipleVersions),
+            "TargetMismatch" => Some(VerifyCheck::TargetMismatch),
+            _ => None,
+        }
+    }
+
+    /// Whether a verify failure is one this check covers
+    pub fn covers(&self, err: &CliError) -> bool {
+        match (*self, err) {
+            (VerifyCheck::NonGlobalDependencies, &CliError::NonGlobalDependencies(_)) |
+            (VerifyCheck::EnvironmentMismatch, &CliError::EnvironmentMismatch(..)) |
+            (VerifyCheck::EnvironmentMismatch, &CliError::MultipleEnvironments(_)) |
+            (VerifyCheck::MultipleVersions, &CliError::MultipleVersions(_)) |
+            (VerifyCheck::TargetMismatch, &CliError::TargetMismatch(..)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Published versions that `lal retention apply` keeps
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RetentionPolicy {
+    /// Number of newest versions kept of every component in every environment
+    pub keepLast: usize,
+    /// Release channels whose versions are kept regardless of age
+    #[serde(default = "default_keep_channels")]
+    pub keepChannels: Vec<String>,
+}
+
+fn default_keep_channels() -> Vec<String> { vec!["stable".into()] }
+
 /// Representation of `~/.lal/config`
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Clone)]
@@ -56,6 +246,54 @@ pub struct Config {
     pub interactive: bool,
     /// Minimum version restriction of lal enforced by this config
     pub minimum_lal: Option<String>,
+    /// Size cap for cached components before least recently used ones are evicted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cacheMaxSizeGb: Option<u64>,
+    /// PEM bundle of extra certificate authorities to trust for https requests (OpenSSL only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caBundle: Option<String>,
+    /// Credential helper command (or `keychain`) used instead of plaintext credentials
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentialHelper: Option<String>,
+    /// Whether builds check the registry for updated containers first
+    #[serde(default)]
+    pub imageUpdates: ImageUpdatePolicy,
+    /// Container runtime binary - `docker` (default), `podman`, or a docker compatible cli
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<String>,
+    /// Host environment variables forwarded into containers when set (e.g. `SSH_AUTH_SOCK`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub envPassthrough: Vec<String>,
+    /// Verify checks that only warn, except in release builds and `lal verify --release`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub verifyWarnings: Vec<VerifyCheck>,
+    /// Gpg key that `lal publish` signs release tarballs with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signingKey: Option<String>,
+    /// Armored gpg public key files trusted to sign published artifacts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub publicKeys: Vec<String>,
+    /// Reject fetched artifacts without a valid signature from `publicKeys`
+    #[serde(default)]
+    pub requireSignatures: bool,
+    /// Which published versions `lal retention apply` keeps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<RetentionPolicy>,
+    /// Compression of tarballs created by builds, stashes and imports
+    #[serde(default)]
+    pub compression: Compression,
+    /// Compression level of created tarballs (the compressor default if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressionLevel: Option<u32>,
+    /// Threads compressing created tarballs (every core if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressionThreads: Option<u32>,
+    /// Share build outputs through the backend between identical builds
+    #[serde(default)]
+    pub remoteBuildCache: bool,
+    /// Run the `.lal/hooks` of components around fetch, build, stash and publish
+    #[serde(default)]
+    pub runHooks: bool,
 }
 
 /// Representation of a configuration defaults file
@@ -71,6 +309,12 @@ pub struct ConfigDefaults {
     pub mounts: Vec<Mount>,
     /// Optional minimum version restriction of lal
     pub minimum_lal: Option<String>,
+    /// Optional container runtime binary (docker if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<String>,
+    /// Host environment variables to forward into containers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub envPassthrough: Vec<String>,
 }
 
 impl ConfigDefaults {
@@ -88,9 +332,9 @@ impl ConfigDefaults {
     }
 }
 
-fn check_mount(name: &str) -> LalResult<String> {
+fn check_mount(name: &str, runtime: &str) -> LalResult<String> {
     // See if it's a path first:
-    let home = find_home_dir();
+    let home = find_home_dir()?;
     let src = name.to_string().replace("~", &home.to_string_lossy());
     let mount_path = Path::new(&src);
     if mount_path.exists() {
@@ -101,7 +345,7 @@ fn check_mount(name: &str) -> LalResult<String> {
     // Otherwise, if it does not contain a slash
     if !name.contains("/") {
         use std::process::Command;
-        let volume_output = Command::new("docker").args(vec!["volume", "ls", "-q"]).output()?;
+        let volume_output = Command::new(runtime).args(vec!["volume", "ls", "-q"]).output()?;
         let volstr = String::from_utf8_lossy(&volume_output.stdout);
         // If it exists, do nothing:
         if volstr.contains(name) {
@@ -123,18 +367,19 @@ impl Config {
     ///
     /// This will locate you homedir, and set last update check 2 days in the past.
     /// Thus, with a blank default config, you will always trigger an upgrade check.
-    pub fn new(defaults: ConfigDefaults) -> Config {
-        let cachepath = config_dir().join("cache");
+    pub fn new(defaults: ConfigDefaults) -> LalResult<Config> {
+        let cachepath = default_cache_dir()?;
         let cachedir = cachepath.as_path().to_str().unwrap();
 
         // reset last update time
         let time = UTC::now();
 
         // scan default mounts
+        let runtime = defaults.runtime.clone().unwrap_or_else(|| "docker".into());
         let mut mounts = vec![];
         for mount in defaults.mounts {
             // Check src for pathiness or prepare a docker volume
-            match check_mount(&mount.src) {
+            match check_mount(&mount.src, &runtime) {
                 Ok(src) => {
                     let mut mountnew = mount.clone();
                     mountnew.src = src; // update potentially mapped source
@@ -144,7 +389,7 @@ impl Config {
             }
         }
 
-        Config {
+        Ok(Config {
             cache: cachedir.into(),
             mounts: mounts, // the filtered defaults
             lastUpgrade: time.to_rfc3339(),
@@ -152,29 +397,296 @@ impl Config {
             environments: defaults.environments,
             backend: defaults.backend,
             minimum_lal: defaults.minimum_lal,
+            cacheMaxSizeGb: None,
+            caBundle: None,
+            credentialHelper: None,
+            imageUpdates: ImageUpdatePolicy::default(),
+            runtime: defaults.runtime,
+            envPassthrough: defaults.envPassthrough,
+            verifyWarnings: vec![],
+            signingKey: None,
+            publicKeys: vec![],
+            requireSignatures: false,
+            retention: None,
+            compression: Compression::default(),
+            compressionLevel: None,
+            compressionThreads: None,
+            remoteBuildCache: false,
+            runHooks: false,
             interactive: true,
+        })
+    }
+
+    /// The container runtime binary to invoke
+    pub fn runtime(&self) -> &str { self.runtime.as_ref().map_or("docker", |r| r.as_str()) }
+
+    /// Whether the runtime is podman, which runs containers rootless in a user namespace
+    pub fn is_podman(&self) -> bool {
+        Path::new(self.runtime()).file_name().map_or(false, |f| f == "podman")
+    }
+
+    /// The cache size cap in bytes (if any)
+    pub fn cache_max_bytes(&self) -> Option<u64> {
+        self.cacheMaxSizeGb.map(|gb| gb * 1024 * 1024 * 1024)
+    }
+
+    /// Signing and signature requirements for the backend
+    pub fn signature_policy(&self) -> SignaturePolicy {
+        SignaturePolicy {
+            key: self.signingKey.clone(),
+            public_keys: self.publicKeys.clone(),
+            required: self.requireSignatures,
+        }
+    }
+
+    /// How builds and the backend compress the tarballs they create
+    pub fn compression_options(&self) -> CompressionOptions {
+        CompressionOptions {
+            compression: self.compression,
+            level: self.compressionLevel,
+            threads: self.compressionThreads,
+            normalize_mtimes: false,
+        }
+    }
+
+    /// Transport settings for requests that do not go through the backend
+    pub fn http_options(&self) -> HttpOptions {
+        HttpOptions {
+            ca_bundle: self.caBundle.clone(),
+            ..Default::default()
         }
     }
 
     /// Read and deserialize a Config from ~/.lal/config
+    ///
+    /// A project config in `./.lal/config` is merged over it. Settings in the project
+    /// config take precedence; nested objects like `environments` are merged key by
+    /// key, while anything else (e.g. `mounts`) is replaced entirely.
+    /// Finally, `LAL_*` environment variables take precedence over both files
+    /// (see `apply_env_overrides`).
     pub fn read() -> LalResult<Config> {
-        let cfg_path = config_dir().join("config");
+        let cfg_path = config_dir()?.join("config");
         if !cfg_path.exists() {
             return Err(CliError::MissingConfig);
         }
-        let mut f = fs::File::open(&cfg_path)?;
-        let mut cfg_str = String::new();
-        f.read_to_string(&mut cfg_str)?;
-        let res: Config = serde_json::from_str(&cfg_str)?;
+        let mut cfg = read_json(&cfg_path)?;
+        if let Some(pth) = project_overrides()? {
+            debug!("Merging project config {}", pth.display());
+            merge_json(&mut cfg, read_json(&pth)?);
+        }
+        let mut res: Config = serde_json::from_value(cfg)?;
+        res.apply_env_overrides()?;
         Ok(res)
     }
 
+    /// Override config settings from `LAL_*` environment variables
+    ///
+    /// - `LAL_CACHE`: the cache directory
+    /// - `LAL_BACKEND`: `artifactory` or `local` (switching starts from a blank backend)
+    /// - `LAL_ARTIFACTORY`: artifactory url used as both master and slave
+    /// - `LAL_ARTIFACTORY_RELEASE` and `LAL_ARTIFACTORY_VGROUP`: artifactory repositories
+    /// - `LAL_LOCAL_PATH`: the directory of a local backend
+    /// - `LAL_CONTAINER`: comma separated `<environment>=<image>[:<tag>]` entries
+    /// - `LAL_INTERACTIVE` and `LAL_AUTOUPGRADE`: `true` or `false`
+    /// - `LAL_IMAGE_UPDATES`: `off`, `warn` or `pull`
+    /// - `LAL_RUNTIME`: the container runtime binary
+    /// - `LAL_ENV_PASSTHROUGH`: comma separated variables to forward into containers
+    /// - `LAL_CACHE_MAX_SIZE_GB`, `LAL_CA_BUNDLE` and `LAL_CREDENTIAL_HELPER`
+    /// - `LAL_VERIFY_WARNINGS`: comma separated verify checks that only warn
+    /// - `LAL_REQUIRE_SIGNATURES`, `LAL_REMOTE_BUILD_CACHE` and `LAL_RUN_HOOKS`: `true`
+    ///   or `false`
+    /// - `LAL_COMPRESSION`: `gzip`, `zstd` or `xz`
+    /// - `LAL_COMPRESSION_LEVEL` and `LAL_COMPRESSION_THREADS`: numbers
+    /// - `LAL_SIGNING_KEY`: the gpg key to sign published tarballs with
+    /// - `LAL_PUBLIC_KEYS`: comma separated public key files
+    /// - `LAL_RETENTION_KEEP_LAST`: versions kept by retention (channels default to `stable`)
+    /// - `LAL_RETENTION_KEEP_CHANNELS`: comma separated channels kept by a retention policy
+    /// - `LAL_MOUNTS`: comma separated `<src>:<dest>[:ro]` entries replacing the mounts
+    /// - `LAL_MINIMUM_LAL`: the minimum lal version
+    ///
+    /// Empty variables are ignored.
+    pub fn apply_env_overrides(&mut self) -> LalResult<()> {
+        if let Some(cache) = env_override("LAL_CACHE") {
+            self.cache = cache;
+        }
+        if let Some(b) = env_override("LAL_BACKEND") {
+            self.backend = match (b.as_str(), self.backend.clone()) {
+                ("artifactory", art @ BackendConfiguration::Artifactory(_)) => art,
+                ("local", local @ BackendConfiguration::Local(_)) => local,
+                ("artifactory", _) => {
+                    BackendConfiguration::Artifactory(ArtifactoryConfig::default())
+                }
+                ("local", _) => BackendConfiguration::Local(LocalConfig::default()),
+                _ => return Err(CliError::InvalidConfigOverride("LAL_BACKEND".into(), b)),
+            };
+        }
+        match self.backend {
+            BackendConfiguration::Artifactory(ref mut art) => {
+                if let Some(url) = env_override("LAL_ARTIFACTORY") {
+                    art.master = url.clone();
+                    art.slave = url;
+                }
+                if let Some(release) = env_override("LAL_ARTIFACTORY_RELEASE") {
+                    art.release = release;
+                }
+                if let Some(vgroup) = env_override("LAL_ARTIFACTORY_VGROUP") {
+                    art.vgroup = vgroup;
+                }
+            }
+            BackendConfiguration::Local(ref mut local) => {
+                if let Some(pth) = env_override("LAL_LOCAL_PATH") {
+                    local.path = Some(pth);
+                }
+            }
+        }
+        if let Some(containers) = env_override("LAL_CONTAINER") {
+            for entry in containers.split(',') {
+                let mut kv = entry.splitn(2, '=');
+                match (kv.next().map(str::trim), kv.next().map(str::trim)) {
+                    (Some(env), Some(image)) if !env.is_empty() && !image.is_empty() => {
+                        self.environments.insert(env.into(), Container::new(image));
+                    }
+                    _ => {
+                        return Err(CliError::InvalidConfigOverride("LAL_CONTAINER".into(),
+                                                                   containers.clone()))
+                    }
+                }
+            }
+        }
+        if let Some(i) = env_override("LAL_INTERACTIVE") {
+            self.interactive = parse_bool_override("LAL_INTERACTIVE", &i)?;
+        }
+        if let Some(a) = env_override("LAL_AUTOUPGRADE") {
+            self.autoupgrade = parse_bool_override("LAL_AUTOUPGRADE", &a)?;
+        }
+        if let Some(gb) = env_override("LAL_CACHE_MAX_SIZE_GB") {
+            self.cacheMaxSizeGb = Some(parse_num_override("LAL_CACHE_MAX_SIZE_GB", &gb)?);
+        }
+        if let Some(bundle) = env_override("LAL_CA_BUNDLE") {
+            self.caBundle = Some(bundle);
+        }
+        if let Some(helper) = env_override("LAL_CREDENTIAL_HELPER") {
+            self.credentialHelper = Some(helper);
+        }
+        if let Some(runtime) = env_override("LAL_RUNTIME") {
+            self.runtime = Some(runtime);
+        }
+        if let Some(vars) = env_override("LAL_ENV_PASSTHROUGH") {
+            self.envPassthrough = split_list_override(&vars);
+        }
+        if let Some(checks) = env_override("LAL_VERIFY_WARNINGS") {
+            let mut warnings = vec![];
+            for name in checks.split(',').map(|c| c.trim()).filter(|c| !c.is_empty()) {
+                let check = VerifyCheck::from_name(name)
+                    .ok_or_else(|| {
+                        CliError::InvalidConfigOverride("LAL_VERIFY_WARNINGS".into(), name.into())
+                    })?;
+                warnings.push(check);
+            }
+            self.verifyWarnings = warnings;
+        }
+        if let Some(r) = env_override("LAL_REQUIRE_SIGNATURES") {
+            self.requireSignatures = parse_bool_override("LAL_REQUIRE_SIGNATURES", &r)?;
+        }
+        if let Some(r) = env_override("LAL_REMOTE_BUILD_CACHE") {
+            self.remoteBuildCache = parse_bool_override("LAL_REMOTE_BUILD_CACHE", &r)?;
+        }
+        if let Some(policy) = env_override("LAL_IMAGE_UPDATES") {
+            self.imageUpdates = serde_json::from_value(Value::String(policy.clone()))
+                .map_err(|_| CliError::InvalidConfigOverride("LAL_IMAGE_UPDATES".into(), policy))?;
+        }
+        if let Some(c) = env_override("LAL_COMPRESSION") {
+            self.compression = serde_json::from_value(Value::String(c.clone()))
+                .map_err(|_| CliError::InvalidConfigOverride("LAL_COMPRESSION".into(), c))?;
+        }
+        if let Some(level) = env_override("LAL_COMPRESSION_LEVEL") {
+            self.compressionLevel = Some(parse_num_override("LAL_COMPRESSION_LEVEL", &level)?);
+        }
+        if let Some(threads) = env_override("LAL_COMPRESSION_THREADS") {
+            self.compressionThreads =
+                Some(parse_num_override("LAL_COMPRESSION_THREADS", &threads)?);
+        }
+        if let Some(key) = env_override("LAL_SIGNING_KEY") {
+            self.signingKey = Some(key);
+        }
+        if let Some(keys) = env_override("LAL_PUBLIC_KEYS") {
+            self.publicKeys = split_list_override(&keys);
+        }
+        if let Some(n) = env_override("LAL_RETENTION_KEEP_LAST") {
+            let keep = parse_num_override("LAL_RETENTION_KEEP_LAST", &n)?;
+            match self.retention {
+                Some(ref mut policy) => policy.keepLast = keep,
+                None => {
+                    self.retention = Some(RetentionPolicy {
+                        keepLast: keep,
+                        keepChannels: default_keep_channels(),
+                    })
+                }
+            }
+        }
+        if let Some(channels) = env_override("LAL_RETENTION_KEEP_CHANNELS") {
+            match self.retention {
+                Some(ref mut policy) => policy.keepChannels = split_list_override(&channels),
+                // channels alone are not a policy - keepLast has no sensible default
+                None => {
+                    let var = "LAL_RETENTION_KEEP_CHANNELS".into();
+                    return Err(CliError::InvalidConfigOverride(var, channels));
+                }
+            }
+        }
+        if let Some(r) = env_override("LAL_RUN_HOOKS") {
+            self.runHooks = parse_bool_override("LAL_RUN_HOOKS", &r)?;
+        }
+        if let Some(mounts) = env_override("LAL_MOUNTS") {
+            let mut res = vec![];
+            for entry in split_list_override(&mounts) {
+                let invalid = || {
+                    CliError::InvalidConfigOverride("LAL_MOUNTS".into(), entry.clone())
+                };
+                let parts = entry.split(':').collect::<Vec<_>>();
+                let readonly = match (parts.len(), parts.get(2).cloned()) {
+                    (2, None) | (3, Some("rw")) => false,
+                    (3, Some("ro")) => true,
+                    _ => return Err(invalid()),
+                };
+                if parts[0].is_empty() || parts[1].is_empty() {
+                    return Err(invalid());
+                }
+                res.push(Mount {
+                    src: parts[0].into(),
+                    dest: parts[1].into(),
+                    readonly: readonly,
+                });
+            }
+            self.mounts = res;
+        }
+        if let Some(version) = env_override("LAL_MINIMUM_LAL") {
+            self.minimum_lal = Some(version);
+        }
+        Ok(())
+    }
+
     /// Checks if it is time to perform an upgrade check
+    ///
+    /// A malformed `lastUpgrade` value, or one in the future (clock skew, restored
+    /// snapshots), is treated as expired so the check runs and rewrites the field.
     #[cfg(feature = "upgrade")]
     pub fn upgrade_check_time(&self) -> bool {
         use chrono::{Duration, DateTime};
-        let last = self.lastUpgrade.parse::<DateTime<UTC>>().unwrap();
-        let cutoff = UTC::now() - Duration::days(1);
+        let now = UTC::now();
+        let last = match self.lastUpgrade.parse::<DateTime<UTC>>() {
+            Ok(t) => t,
+            Err(e) => {
+                warn!("Malformed lastUpgrade '{}' in config ({})", self.lastUpgrade, e);
+                warn!("Performing upgrade check now to reset it");
+                return true;
+            }
+        };
+        if last > now {
+            warn!("lastUpgrade {} is in the future - assuming clock skew", last);
+            return true;
+        }
+        let cutoff = now - Duration::days(1);
         last < cutoff
     }
     /// Update the lastUpgrade time to avoid triggering it for another day
@@ -185,9 +697,33 @@ impl Config {
     }
 
     /// Overwrite `~/.lal/config` with serialized data from this struct
+    ///
+    /// Settings overridden by a project config or the environment keep their global
+    /// value, so that overrides never leak into the global config.
     pub fn write(&self, silent: bool) -> LalResult<()> {
-        let cfg_path = config_dir().join("config");
-        let encoded = serde_json::to_string_pretty(self)?;
+        let cfg_path = config_dir()?.join("config");
+        let mut data = serde_json::to_value(self)?;
+        let mut overridden = ENV_OVERRIDES.iter()
+            .filter(|&&(var, _)| env_override(var).is_some())
+            .map(|&(_, key)| key.to_string())
+            .collect::<Vec<_>>();
+        if let Some(pth) = project_overrides()? {
+            if let Value::Object(o) = read_json(&pth)? {
+                overridden.extend(o.keys().cloned());
+            }
+        }
+        if !overridden.is_empty() && cfg_path.is_file() {
+            let global = read_json(&cfg_path)?;
+            if let Value::Object(ref mut d) = data {
+                for k in &overridden {
+                    match global.get(k) {
+                        Some(v) => d.insert(k.clone(), v.clone()),
+                        None => d.remove(k),
+                    };
+                }
+            }
+        }
+        let encoded = serde_json::to_string_pretty(&data)?;
 
         let mut f = fs::File::create(&cfg_path)?;
         write!(f, "{}\n", encoded)?;
@@ -198,6 +734,38 @@ impl Config {
         Ok(())
     }
 
+    /// Write the settings a defaults file controls to the project config `./.lal/config`
+    ///
+    /// Only the settings a defaults file has (backend, environments, mounts, runtime,
+    /// env passthrough and minimum lal version) are written, so user specific settings
+    /// like the cache keep coming from the global `~/.lal/config`.
+    pub fn write_project(&self, silent: bool) -> LalResult<()> {
+        let cfg_path = project_config_path()?;
+        fs::create_dir_all(cfg_path.parent().unwrap())?;
+        let mut data = BTreeMap::new();
+        data.insert("backend", serde_json::to_value(&self.backend)?);
+        data.insert("environments", serde_json::to_value(&self.environments)?);
+        data.insert("mounts", serde_json::to_value(&self.mounts)?);
+        if let Some(ref minlal) = self.minimum_lal {
+            data.insert("minimum_lal", Value::String(minlal.clone()));
+        }
+        if let Some(ref runtime) = self.runtime {
+            data.insert("runtime", Value::String(runtime.clone()));
+        }
+        if !self.envPassthrough.is_empty() {
+            data.insert("envPassthrough", serde_json::to_value(&self.envPassthrough)?);
+        }
+        let encoded = serde_json::to_string_pretty(&data)?;
+
+        let mut f = fs::File::create(&cfg_path)?;
+        write!(f, "{}\n", encoded)?;
+        if !silent {
+            info!("Wrote project config to {}", cfg_path.display());
+        }
+        debug!("Wrote project config \n{}", encoded);
+        Ok(())
+    }
+
     /// Resolve an arbitrary container shorthand
     pub fn get_container(&self, env: String) -> LalResult<Container> {
         if let Some(container) = self.environments.get(&env) {
@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+
+/// A deprecation notice for a published component
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Deprecation {
+    /// Deprecated versions (every version when empty)
+    #[serde(default)]
+    pub versions: Vec<u32>,
+    /// Component that should be used instead
+    pub replacement: Option<String>,
+    /// Free form explanation
+    pub reason: Option<String>,
+}
+
+/// Representation of `deprecations.json` at the root of a backend
+///
+/// Backends without this document simply have no deprecations.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Deprecations {
+    /// Component name -> deprecation notice
+    #[serde(default)]
+    pub components: BTreeMap<String, Deprecation>,
+}
+
+impl Deprecations {
+    /// Find the notice covering a component at a version, if any
+    pub fn lookup(&self, name: &str, version: u32) -> Option<&Deprecation> {
+        self.components
+            .get(name)
+            .and_then(|d| if d.versions.is_empty() || d.versions.contains(&version) {
+                Some(d)
+            } else {
+                None
+            })
+    }
+
+    /// Human readable warning for a deprecated component at a version
+    pub fn notice(&self, name: &str, version: u32) -> Option<String> {
+        self.lookup(name, version).map(|d| {
+            let mut msg = format!("{} {} is deprecated", name, version);
+            if let Some(ref r) = d.reason {
+                msg.push_str(&format!(" ({})", r));
+            }
+            if let Some(ref r) = d.replacement {
+                msg.push_str(&format!(" - use {} instead", r));
+            }
+            msg
+        })
+    }
+}
@@ -1,5 +1,7 @@
 use std::fmt;
 use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
 use hyper;
 use serde_json;
 
@@ -12,6 +14,8 @@ use serde_json;
 pub enum CliError {
     /// Errors propagated from `std::fs`
     Io(io::Error),
+    /// Errors propagated from `std::fs` along with the operation that failed
+    IoContext(String, io::Error),
     /// Errors propagated from `serde_json`
     Parse(serde_json::error::Error),
     /// Errors propagated from `hyper`
@@ -22,12 +26,32 @@ pub enum CliError {
     MissingManifest,
     /// Config not found in ~/.lal
     MissingConfig,
+    /// Neither HOME nor LAL_CONFIG_HOME is set
+    MissingHome,
+    /// A LAL_* environment variable overriding the config has an invalid value
+    InvalidConfigOverride(String, String),
     /// Component not found in manifest
     MissingComponent(String),
+    /// Component to add is already in a dependency map of the manifest
+    DependencyExists(String, String),
     /// Value in manifest is not lowercase
     InvalidComponentName(String),
     /// Manifest cannot be overwritten without forcing
     ManifestExists,
+    /// No `workspace.json` in the current directory or its parents
+    MissingWorkspace,
+    /// Workspace members depend on each other in a cycle
+    WorkspaceCycle(String),
+    /// Manifest failed schema validation with this many problems
+    InvalidManifest(usize),
+    /// Init template is neither built in nor in `~/.lal/templates`
+    UnknownTemplate(String),
+    /// Dependency group references a component that is not a dependency
+    InvalidGroup(String, String),
+    /// Dependency group requested that is not in the manifest
+    MissingGroup(String),
+    /// Manifest keys were changed on disk and by us since it was read
+    ManifestConflict(String),
     /// Executable we shell out to is missing
     ExecutableMissing(String),
     /// lal version required by config is too old
@@ -46,6 +70,16 @@ pub enum CliError {
     DependencyCycle(String),
     /// Dependency present at wrong version
     InvalidVersion(String),
+    /// Dependency version range could not be parsed
+    InvalidVersionRange(String),
+    /// Release channel name with characters other than letters, digits, `-` and `_`
+    InvalidChannel(String),
+    /// Publish metadata that is not `key=value` with a valid key
+    InvalidMetadata(String),
+    /// No `retention` section in the config
+    MissingRetentionPolicy,
+    /// Tarball given to `lal import` that cannot be used as a component
+    InvalidImport(String),
     /// Extraneous dependencies in INPUT
     ExtraneousDependencies(String),
     /// No lockfile found for a component in INPUT
@@ -56,6 +90,10 @@ pub enum CliError {
     MultipleEnvironments(String),
     /// Environment for a component did not match our expected environment
     EnvironmentMismatch(String, String),
+    /// Component built for a different target than the rest of the tree
+    TargetMismatch(String, String, String),
+    /// Components were built with different images behind the same container tag
+    MultipleContainerDigests(String),
     /// Custom versions are stashed in INPUT which will not fly on Jenkins
     NonGlobalDependencies(String),
     /// No supported environments in the manifest
@@ -72,41 +110,101 @@ pub enum CliError {
     // build errors
     /// Build configurations does not match manifest or user input
     InvalidBuildConfiguration(String),
+    /// Build target not found in manifest.components
+    InvalidBuildTarget(String),
     /// BUILD script not executable
     BuildScriptNotExecutable(String),
     /// BUILD script not found
     MissingBuildScript,
 
     // script errors
-    /// Script not found in local .lal/scripts/ directory
+    /// Script not found in the manifest scripts (or the deprecated .lal/scripts/ directory)
     MissingScript(String),
 
     // cache errors
+    /// Cache directory path exists but is not a directory
+    CacheIsFile(String),
+    /// Cache directory could not be created
+    UnwritableCache(String),
+    /// Cache relocation could not be completed
+    CacheMoveFailure(String),
+    /// Another lal process holds the workspace or cache lock
+    ConcurrentInvocation(String, String),
     /// Failed to find a tarball after fetching from artifactory
     MissingTarball,
+    /// Downloaded tarball does not match the checksum recorded by the backend
+    ChecksumMismatch(String),
+    /// Published artifact has no signature but signatures are required
+    UnsignedArtifact(String),
+    /// Published artifact signature does not verify against the trusted keys
+    InvalidSignature(String),
+    /// Nothing stored in the remote build cache under a key
+    MissingBuildOutput(String),
+    /// Tarball entry that would be extracted outside its destination, and why
+    MaliciousArchive(String, String),
     /// Failed to find build artifacts in OUTPUT after a build or before stashing
     MissingBuild,
+    /// OUTPUT does not satisfy the manifest `artifactContract`
+    BrokenArtifactContract(String),
 
     // stash errors
     /// Invalid integer name used with lal stash
     InvalidStashName(u32),
+    /// Name used with lal stash is not usable as a directory name
+    InvalidStashLabel(String),
     /// Failed to find stashed artifact in the lal cache
     MissingStashArtifact(String),
+    /// Build log missing from .lal/logs
+    MissingLog(String),
+    /// Build log name that is not a file directly in .lal/logs
+    InvalidLogName(String),
 
-    /// Shell errors from docker subprocess
-    SubprocessFailure(i32),
+    /// Shell errors from docker subprocess (with the log of its output if captured)
+    SubprocessFailure(i32, Option<String>),
+    /// Gpg failed to sign an artifact or import a key
+    SigningFailure(String),
     /// Docker permission gate
     DockerPermissionSafety(String, u32, u32),
     /// Docker image not found
     DockerImageNotFound(String),
+    /// A pre hook in `.lal/hooks` failed
+    HookFailure(String, i32),
+    /// Test cases failed in the reports of `lal test`
+    TestFailures(u32),
+    /// Unknown subcommand without a `lal-<name>` plugin on the PATH
+    MissingPlugin(String),
 
     // fetch/update failures
     /// Unspecified install failure
     InstallFailure,
     /// Fetch failure related to backend
     BackendFailure(String),
+    /// A file requested from the backend does not exist there
+    RemoteNotFound(String),
+    /// The configured CA bundle could not be loaded
+    InvalidCaBundle(String),
+    /// The credential helper or keychain lookup failed
+    CredentialHelperFailure(String),
     /// No version found at same version across `supportedEnvironments`
     NoIntersectedVersion(String),
+    /// No published version satisfies a dependency's version range
+    NoMatchingVersion(String, String),
+    /// A version was required to be in a release channel it has not been promoted to
+    NotInChannel(String, String),
+    /// Search regex could not be parsed
+    InvalidSearchPattern(String),
+    /// Deprecated dependencies resolved while deprecations are denied
+    DeprecatedDependencies(String),
+    /// Stashed components in INPUT would be replaced without an explicit policy
+    StashedConflict(String),
+    /// Lockfile refers to a stashed build that only exists in someone's cache
+    UnreproducibleStash(String, String),
+    /// Packing OUTPUT twice gave tarballs with different digests
+    UnreproducibleArchive(String, String),
+    /// INPUT does not match the dependency tree recorded in a lockfile
+    LockfileDivergence(String),
+    /// INPUT contents of a component differ from the digest recorded in its lockfile
+    ContentsMismatch(String),
 
     // publish errors
     /// Missing release build
@@ -115,6 +213,12 @@ pub enum CliError {
     MissingBackendCredentials,
     /// Failed upload request to the backend
     UploadFailure(String),
+    /// Uploaded files did not match what the backend recorded before promotion
+    UnverifiedUpload(String),
+    /// A version allocated for publishing was published by someone else first
+    VersionTaken(String, u32),
+    /// Could not allocate a version to publish within the retry limit
+    VersionAllocationFailure(String),
 
     // upgrade error
     /// Failing to write to our current install prefix
@@ -123,6 +227,133 @@ pub enum CliError {
     UpgradeValidationFailure(String),
 }
 
+impl CliError {
+    /// Process exit code for the category of this error
+    ///
+    /// These are stable so that scripts can branch on the type of failure:
+    ///
+    /// - `1` unclassified failures
+    /// - `2` manifest, config and usage errors
+    /// - `3` verify failures (INPUT does not satisfy the manifest)
+    /// - `4` network and backend failures
+    /// - `5` docker and build script failures
+    /// - `6` local filesystem and cache failures
+    pub fn code(&self) -> i32 {
+        match *self {
+            CliError::Parse(_) |
+            CliError::MissingManifest |
+            CliError::MissingConfig |
+            CliError::MissingHome |
+            CliError::InvalidConfigOverride(..) |
+            CliError::MissingPlugin(_) |
+            CliError::MissingComponent(_) |
+            CliError::DependencyExists(..) |
+            CliError::InvalidComponentName(_) |
+            CliError::ManifestExists |
+            CliError::UnknownTemplate(_) |
+            CliError::InvalidManifest(_) |
+            CliError::MissingWorkspace |
+            CliError::WorkspaceCycle(_) |
+            CliError::InvalidGroup(..) |
+            CliError::MissingGroup(_) |
+            CliError::ManifestConflict(_) |
+            CliError::OutdatedLal(..) |
+            CliError::MissingMount(_) |
+            CliError::InvalidVersionRange(_) |
+            CliError::InvalidChannel(_) |
+            CliError::InvalidMetadata(_) |
+            CliError::MissingRetentionPolicy |
+            CliError::InvalidImport(_) |
+            CliError::NoSupportedEnvironments |
+            CliError::UnsupportedEnvironment |
+            CliError::MissingEnvironment(_) |
+            CliError::EnvironmentUnspecified |
+            CliError::InvalidBuildConfiguration(_) |
+            CliError::InvalidBuildTarget(_) |
+            CliError::InvalidStashName(_) |
+            CliError::InvalidStashLabel(_) |
+            CliError::InvalidLogName(_) |
+            CliError::InvalidSearchPattern(_) => 2,
+
+            CliError::MissingDependencies |
+            CliError::DependencyCycle(_) |
+            CliError::InvalidVersion(_) |
+            CliError::ExtraneousDependencies(_) |
+            CliError::MissingLockfile(_) |
+            CliError::MultipleVersions(_) |
+            CliError::MultipleEnvironments(_) |
+            CliError::EnvironmentMismatch(..) |
+            CliError::TargetMismatch(..) |
+            CliError::MultipleContainerDigests(_) |
+            CliError::NonGlobalDependencies(_) |
+            CliError::BrokenArtifactContract(_) |
+            CliError::DeprecatedDependencies(_) |
+            CliError::StashedConflict(_) |
+            CliError::UnreproducibleStash(..) |
+            CliError::UnreproducibleArchive(..) |
+            CliError::LockfileDivergence(_) |
+            CliError::ContentsMismatch(_) => 3,
+
+            CliError::Hype(_) |
+            CliError::MissingSslCerts |
+            CliError::MissingTarball |
+            CliError::ChecksumMismatch(_) |
+            CliError::UnsignedArtifact(_) |
+            CliError::InvalidSignature(_) |
+            CliError::MissingBuildOutput(_) |
+            CliError::InstallFailure |
+            CliError::BackendFailure(_) |
+            CliError::RemoteNotFound(_) |
+            CliError::InvalidCaBundle(_) |
+            CliError::CredentialHelperFailure(_) |
+            CliError::NoIntersectedVersion(_) |
+            CliError::NoMatchingVersion(..) |
+            CliError::NotInChannel(..) |
+            CliError::MissingBackendCredentials |
+            CliError::UploadFailure(_) |
+            CliError::UnverifiedUpload(_) |
+            CliError::VersionTaken(..) |
+            CliError::VersionAllocationFailure(_) |
+            CliError::UpgradeValidationFailure(_) => 4,
+
+            CliError::ExecutableMissing(_) |
+            CliError::UnmappableRootUser |
+            CliError::BuildScriptNotExecutable(_) |
+            CliError::MissingBuildScript |
+            CliError::MissingScript(_) |
+            CliError::MissingBuild |
+            CliError::MissingReleaseBuild |
+            CliError::SubprocessFailure(..) |
+            CliError::SigningFailure(_) |
+            CliError::DockerPermissionSafety(..) |
+            CliError::DockerImageNotFound(_) |
+            CliError::HookFailure(..) |
+            CliError::TestFailures(_) => 5,
+
+            CliError::Io(_) |
+            CliError::IoContext(..) |
+            CliError::CacheIsFile(_) |
+            CliError::UnwritableCache(_) |
+            CliError::MaliciousArchive(..) |
+            CliError::CacheMoveFailure(_) |
+            CliError::ConcurrentInvocation(..) |
+            CliError::MissingStashArtifact(_) |
+            CliError::MissingLog(_) |
+            CliError::MissingPrefixPermissions(_) => 6,
+        }
+    }
+
+    /// Whether a fetch failed as the file it asked for does not exist
+    pub fn is_missing_file(&self) -> bool {
+        match *self {
+            CliError::RemoteNotFound(_) => true,
+            CliError::Io(ref e) |
+            CliError::IoContext(_, ref e) => e.kind() == io::ErrorKind::NotFound,
+            _ => false,
+        }
+    }
+}
+
 // Format implementation used when printing an error
 impl fmt::Display for CliError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -135,6 +366,7 @@ impl fmt::Display for CliError {
                 }
                 err.fmt(f)
             }
+            CliError::IoContext(ref ctx, ref err) => write!(f, "{}: {}", ctx, err),
             CliError::Parse(ref err) => err.fmt(f),
             CliError::Hype(ref err) => err.fmt(f),
             CliError::MissingManifest => {
@@ -156,13 +388,46 @@ impl fmt::Display for CliError {
             CliError::UnmappableRootUser => write!(f, "Root user is not supported for lal builds"),
             CliError::MissingMount(ref s) => write!(f, "Missing mount {}", s),
             CliError::MissingConfig => write!(f, "No ~/.lal/config found"),
+            CliError::MissingHome => {
+                write!(f,
+                       "Could not find a home directory - set HOME or LAL_CONFIG_HOME (and \
+                        optionally LAL_CACHE_HOME)")
+            }
+            CliError::InvalidConfigOverride(ref var, ref val) => {
+                write!(f, "Invalid value '{}' for {}", val, var)
+            }
             CliError::MissingComponent(ref s) => {
                 write!(f, "Component '{}' not found in manifest", s)
             }
+            CliError::DependencyExists(ref s, ref map) => {
+                write!(f, "{} is already in {} (use `lal update` to change it)", s, map)
+            }
             CliError::InvalidComponentName(ref s) => {
                 write!(f, "Invalid component name {} - not lowercase", s)
             }
             CliError::ManifestExists => write!(f, "Manifest already exists (use -f to force)"),
+            CliError::MissingWorkspace => {
+                write!(f, "No workspace.json found in the current directory or its parents")
+            }
+            CliError::WorkspaceCycle(ref s) => {
+                write!(f, "Workspace members depend on each other in a cycle: {}", s)
+            }
+            CliError::InvalidManifest(n) => write!(f, "Manifest has {} schema problems", n),
+            CliError::UnknownTemplate(ref s) => {
+                write!(f, "Unknown template {} - not built in or in ~/.lal/templates", s)
+            }
+            CliError::InvalidGroup(ref g, ref c) => {
+                write!(f,
+                       "Group {} contains {} which is not in dependencies or devDependencies",
+                       g,
+                       c)
+            }
+            CliError::MissingGroup(ref g) => write!(f, "Group {} not found in the manifest", g),
+            CliError::ManifestConflict(ref s) => {
+                write!(f,
+                       "Manifest was changed concurrently - conflicting keys: {}",
+                       s)
+            }
             CliError::MissingDependencies => {
                 write!(f,
                        "Core dependencies missing in INPUT - try `lal fetch` first")
@@ -173,6 +438,13 @@ impl fmt::Display for CliError {
             CliError::InvalidVersion(ref s) => {
                 write!(f, "Dependency {} using incorrect version", s)
             }
+            CliError::InvalidVersionRange(ref s) => write!(f, "Invalid version range '{}'", s),
+            CliError::InvalidChannel(ref s) => write!(f, "Invalid release channel '{}'", s),
+            CliError::InvalidMetadata(ref s) => write!(f, "Invalid publish metadata '{}'", s),
+            CliError::MissingRetentionPolicy => {
+                write!(f, "No retention policy - add a retention section to the config")
+            }
+            CliError::InvalidImport(ref s) => write!(f, "Cannot import tarball: {}", s),
             CliError::ExtraneousDependencies(ref s) => {
                 write!(f, "Extraneous dependencies in INPUT ({})", s)
             }
@@ -186,6 +458,12 @@ impl fmt::Display for CliError {
             CliError::EnvironmentMismatch(ref dep, ref env) => {
                 write!(f, "Environment mismatch for {} - built in {}", dep, env)
             }
+            CliError::TargetMismatch(ref dep, ref target, ref expected) => {
+                write!(f, "Target mismatch for {} - built for {}, not {}", dep, target, expected)
+            }
+            CliError::MultipleContainerDigests(ref s) => {
+                write!(f, "Dependencies were built with different images of {}", s)
+            }
             CliError::NonGlobalDependencies(ref s) => {
                 write!(f,
                        "Depending on a custom version of {} (use -s to allow stashed versions)",
@@ -206,12 +484,54 @@ impl fmt::Display for CliError {
             CliError::InvalidBuildConfiguration(ref s) => {
                 write!(f, "Invalid build configuration - {}", s)
             }
+            CliError::InvalidBuildTarget(ref s) => write!(f, "Invalid build target - {}", s),
             CliError::BuildScriptNotExecutable(ref s) => {
                 write!(f, "BUILD script at {} is not executable", s)
             }
             CliError::MissingBuildScript => write!(f, "No `BUILD` script found"),
             CliError::MissingScript(ref s) => {
-                write!(f, "Missing script '{}' in local folder .lal/scripts/", s)
+                write!(f, "Missing script '{}' in the scripts of the manifest", s)
+            }
+            CliError::CacheIsFile(ref s) => {
+                write!(f,
+                       "Cache path {} is a file - remove it or change `cache` in ~/.lal/config",
+                       s)
+            }
+            CliError::BrokenArtifactContract(ref s) => {
+                write!(f, "OUTPUT does not satisfy the artifactContract: {}", s)
+            }
+            CliError::ChecksumMismatch(ref s) => {
+                write!(f, "Checksum mismatch for downloaded {} - not caching it", s)
+            }
+            CliError::UnsignedArtifact(ref s) => {
+                write!(f, "No signature published for {} - requireSignatures is set", s)
+            }
+            CliError::InvalidSignature(ref s) => {
+                write!(f, "Signature of {} does not verify against the trusted publicKeys", s)
+            }
+            CliError::MissingBuildOutput(ref key) => {
+                write!(f, "No build output in the remote build cache for {}", key)
+            }
+            CliError::MaliciousArchive(ref entry, ref reason) => {
+                write!(f, "Refusing to extract {} from the tarball: it {}", entry, reason)
+            }
+            CliError::CacheMoveFailure(ref s) => {
+                write!(f,
+                       "Failed to move cache: {} - the old cache is kept, rerun to resume",
+                       s)
+            }
+            CliError::ConcurrentInvocation(ref lock, ref pid) => {
+                let pid = if pid.is_empty() { "unknown" } else { &pid[..] };
+                write!(f,
+                       "Another lal is already running (pid {} holds {}) - wait for it to finish",
+                       pid,
+                       lock)
+            }
+            CliError::UnwritableCache(ref s) => {
+                write!(f,
+                       "Failed to create cache directory {} - check permissions or `cache` in \
+                        ~/.lal/config",
+                       s)
             }
             CliError::MissingTarball => write!(f, "Tarball missing in PWD"),
             CliError::MissingBuild => write!(f, "No build found in OUTPUT"),
@@ -220,10 +540,29 @@ impl fmt::Display for CliError {
                        "Invalid name '{}' to stash under - must not be an integer",
                        n)
             }
+            CliError::InvalidStashLabel(ref s) => {
+                write!(f,
+                       "Invalid name '{}' to stash under - must be non-empty, without \
+                        slashes or a leading dot",
+                       s)
+            }
+            CliError::MissingLog(ref s) => write!(f, "No build log {} in .lal/logs", s),
+            CliError::InvalidLogName(ref s) => {
+                write!(f, "Invalid build log name '{}' - must not contain a path", s)
+            }
             CliError::MissingStashArtifact(ref s) => {
                 write!(f, "No stashed artifact '{}' found in ~/.lal/cache/stash", s)
             }
-            CliError::SubprocessFailure(n) => write!(f, "Process exited with {}", n),
+            CliError::SubprocessFailure(n, None) => write!(f, "Process exited with {}", n),
+            CliError::SubprocessFailure(n, Some(ref log)) => {
+                write!(f, "Process exited with {} - output is in {}", n, log)
+            }
+            CliError::SigningFailure(ref s) => write!(f, "Gpg failed {}", s),
+            CliError::HookFailure(ref hook, n) => write!(f, "The {} hook exited with {}", hook, n),
+            CliError::TestFailures(n) => write!(f, "{} tests failed", n),
+            CliError::MissingPlugin(ref s) => {
+                write!(f, "Unknown subcommand {} (and no lal-{} on the PATH)", s, s)
+            }
             CliError::DockerPermissionSafety(ref s, u, g) => {
                 write!(f,
                        "ID mismatch inside and outside docker - {}; UID and GID are {}:{}",
@@ -234,12 +573,50 @@ impl fmt::Display for CliError {
             CliError::DockerImageNotFound(ref s) => write!(f, "Could not find docker image {}", s),
             CliError::InstallFailure => write!(f, "Install failed"),
             CliError::BackendFailure(ref s) => write!(f, "Backend - {}", s),
+            CliError::RemoteNotFound(ref url) => write!(f, "Backend - {} not found", url),
+            CliError::InvalidCaBundle(ref s) => write!(f, "Could not load CA bundle {}", s),
+            CliError::CredentialHelperFailure(ref s) => {
+                write!(f, "Credential helper `{}` failed", s)
+            }
             CliError::NoIntersectedVersion(ref s) => {
                 write!(f, "No version of {} found across all environments", s)
             }
+            CliError::NoMatchingVersion(ref n, ref r) => {
+                write!(f, "No published version of {} satisfies {}", n, r)
+            }
+            CliError::NotInChannel(ref c, ref ch) => {
+                write!(f, "{} has not been promoted to the {} channel", c, ch)
+            }
+            CliError::InvalidSearchPattern(ref s) => write!(f, "Invalid search pattern: {}", s),
+            CliError::DeprecatedDependencies(ref s) => {
+                write!(f, "Deprecated dependencies used: {}", s)
+            }
+            CliError::UnreproducibleArchive(ref a, ref b) => {
+                write!(f, "Packing OUTPUT twice gave different tarballs ({} and {})", a, b)
+            }
+            CliError::UnreproducibleStash(ref n, ref v) => {
+                write!(f,
+                       "{} at version {} came from a stash which cannot be fetched remotely",
+                       n,
+                       v)
+            }
+            CliError::LockfileDivergence(ref s) => {
+                write!(f, "INPUT does not match the dependency tree recorded in {}", s)
+            }
+            CliError::ContentsMismatch(ref s) => {
+                write!(f, "INPUT/{} does not match the contents recorded in its lockfile", s)
+            }
+            CliError::StashedConflict(ref s) => {
+                write!(f,
+                       "Stashed components in INPUT would be replaced: {} - use \
+                        --stashed=keep or --stashed=overwrite",
+                       s)
+            }
             CliError::MissingReleaseBuild => write!(f, "Missing release build"),
             CliError::MissingBackendCredentials => {
-                write!(f, "Missing backend credentials in ~/.lal/config")
+                write!(f,
+                       "Missing backend credentials in ~/.lal/config or LAL_ARTIFACTORY_* \
+                        environment variables")
             }
             CliError::MissingPrefixPermissions(ref s) => {
                 write!(f,
@@ -253,6 +630,15 @@ impl fmt::Display for CliError {
                        s)
             }
             CliError::UploadFailure(ref up) => write!(f, "Upload failure: {}", up),
+            CliError::UnverifiedUpload(ref s) => {
+                write!(f, "Backend checksums do not match the uploaded {} - nothing published", s)
+            }
+            CliError::VersionTaken(ref n, v) => write!(f, "{}={} is already published", n, v),
+            CliError::VersionAllocationFailure(ref n) => {
+                write!(f,
+                       "Could not allocate a new version of {} - too many concurrent publishes",
+                       n)
+            }
         }
     }
 }
@@ -277,3 +663,26 @@ impl From<serde_json::error::Error> for CliError {
 /// the many different error types that can arise from using curl, json serializers,
 /// file IO, user errors, and potential logic bugs.
 pub type LalResult<T> = Result<T, CliError>;
+
+/// Attach the failing operation to IO errors
+///
+/// Used as `File::open(&pth).context(format!("Failed to open {}", pth.display()))?`
+/// so that errors name the file involved rather than just the OS error.
+pub trait IoResultExt<T> {
+    /// Convert an IO error into a `CliError::IoContext` with the given description
+    fn context<S: Into<String>>(self, ctx: S) -> LalResult<T>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn context<S: Into<String>>(self, ctx: S) -> LalResult<T> {
+        self.map_err(|e| CliError::IoContext(ctx.into(), e))
+    }
+}
+
+/// Exit code of a finished subprocess, for `CliError::SubprocessFailure`
+///
+/// Processes killed by a signal have no exit code, and get 128 + the signal number
+/// like they do in a shell, so that `lal` can exit with it.
+pub fn exit_code(s: &ExitStatus) -> i32 {
+    s.code().unwrap_or_else(|| 128 + s.signal().unwrap_or(0))
+}
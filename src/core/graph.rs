@@ -0,0 +1,136 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::Lockfile;
+
+/// The dependency graph of a lockfile tree
+///
+/// Components are nodes keyed by name, with an edge to every component they were built
+/// against at any version. The shortest path from the root to each version of a
+/// component is kept so that conflicts can be traced back to their source.
+#[derive(Debug)]
+pub struct DependencyGraph {
+    /// Name of the root component
+    pub root: String,
+    // component name -> names of its direct dependencies
+    edges: BTreeMap<String, BTreeSet<String>>,
+    // component name -> version -> shortest path from the root to it
+    paths: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+    // longest path from the root to a leaf
+    deepest: Vec<String>,
+}
+
+impl DependencyGraph {
+    /// Build the graph for a populated lockfile
+    pub fn new(lf: &Lockfile) -> Self {
+        let mut graph = DependencyGraph {
+            root: lf.name.clone(),
+            edges: BTreeMap::new(),
+            paths: BTreeMap::new(),
+            deepest: vec![lf.name.clone()],
+        };
+        let mut path = vec![lf.name.clone()];
+        graph.walk(&lf.name, lf, &mut path);
+        graph
+    }
+
+    fn walk(&mut self, parent: &str, lf: &Lockfile, path: &mut Vec<String>) {
+        if path.len() > self.deepest.len() {
+            self.deepest = path.clone();
+        }
+        for (name, dep) in &lf.dependencies {
+            self.edges.entry(parent.into()).or_insert_with(BTreeSet::new).insert(name.clone());
+            path.push(format!("{}@{}", name, dep.version));
+            {
+                let shortest = self.paths
+                    .entry(name.clone())
+                    .or_insert_with(BTreeMap::new)
+                    .entry(dep.version.clone())
+                    .or_insert_with(|| path.clone());
+                if path.len() < shortest.len() {
+                    *shortest = path.clone();
+                }
+            }
+            self.walk(name, dep, path);
+            path.pop();
+        }
+    }
+
+    /// Number of dependency levels below the root
+    pub fn max_depth(&self) -> usize { self.deepest.len() - 1 }
+
+    /// A longest path from the root, ending at a component without dependencies
+    pub fn deepest_path(&self) -> &[String] { &self.deepest }
+
+    /// Every version of a component used anywhere in the tree
+    pub fn versions_of(&self, component: &str) -> BTreeSet<String> {
+        self.paths.get(component).map_or_else(BTreeSet::new, |v| v.keys().cloned().collect())
+    }
+
+    /// Components used at more than one version in the tree
+    pub fn conflicts(&self) -> BTreeSet<String> {
+        self.paths
+            .iter()
+            .filter(|&(_, vers)| vers.len() > 1)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// The shortest path from the root to each version of a component
+    pub fn conflict_paths(&self, component: &str) -> Vec<Vec<String>> {
+        self.paths.get(component).map_or_else(Vec::new, |v| v.values().cloned().collect())
+    }
+
+    /// Describe where each version of a component comes from
+    ///
+    /// E.g. `libc (app -> ssl@2 -> libc@1, app -> libc@3)`.
+    pub fn describe_conflict(&self, component: &str) -> String {
+        let paths = self.conflict_paths(component)
+            .iter()
+            .map(|p| p.join(" -> "))
+            .collect::<Vec<_>>();
+        format!("{} ({})", component, paths.join(", "))
+    }
+
+    /// Find a dependency cycle between components
+    ///
+    /// Returns the names along the cycle, starting and ending at the same component.
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        let mut done = BTreeSet::new();
+        let mut stack = vec![];
+        let mut starts = vec![self.root.clone()];
+        starts.extend(self.edges.keys().cloned());
+        for start in starts {
+            if let Some(c) = self.visit(&start, &mut stack, &mut done) {
+                return Some(c);
+            }
+        }
+        None
+    }
+
+    // depth first search keeping the names on the current path in `stack`
+    fn visit(&self,
+             name: &str,
+             stack: &mut Vec<String>,
+             done: &mut BTreeSet<String>)
+             -> Option<Vec<String>> {
+        if let Some(i) = stack.iter().position(|n| n == name) {
+            let mut cycle = stack[i..].to_vec();
+            cycle.push(name.into());
+            return Some(cycle);
+        }
+        if done.contains(name) {
+            return None;
+        }
+        stack.push(name.into());
+        if let Some(deps) = self.edges.get(name) {
+            for d in deps {
+                if let Some(c) = self.visit(d, stack, done) {
+                    return Some(c);
+                }
+            }
+        }
+        stack.pop();
+        done.insert(name.into());
+        None
+    }
+}
@@ -1,56 +1,131 @@
 #![allow(missing_docs)]
 
-use std::io::prelude::*;
-use std::fs::File;
-use std::path::Path;
-use std::collections::BTreeMap;
-use serde_json;
+use std::cmp;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use std::thread;
 
 use walkdir::WalkDir;
 
-use super::{Manifest, Lockfile, CliError, LalResult};
+use super::{Manifest, Lockfile, DependencyGraph, CliError, LalResult, IoResultExt, output};
 
-#[derive(Deserialize)]
-struct PartialLock {
-    pub version: String,
-}
-fn read_partial_lockfile(component: &str) -> LalResult<PartialLock> {
-    let lock_path = Path::new("./INPUT").join(component).join("lockfile.json");
-    if !lock_path.exists() {
-        return Err(CliError::MissingLockfile(component.to_string()));
-    }
-    let mut lock_str = String::new();
-    trace!("Deserializing lockfile for {}", component);
-    File::open(&lock_path)?.read_to_string(&mut lock_str)?;
-    Ok(serde_json::from_str(&lock_str)?)
-}
+// threads used to parse the lockfiles in INPUT
+const PARSE_THREADS: usize = 8;
 
 pub fn present() -> bool {
     Path::new("./INPUT").is_dir()
 }
 
-/// Simple INPUT analyzer for the lockfile generator and `analyze_full`
-pub fn analyze() -> LalResult<BTreeMap<String, String>> {
-    let input = Path::new("./INPUT");
+// dot directories in INPUT (like the staging area) are not components
+fn is_hidden(pth: &Path) -> bool {
+    pth.file_name().map_or(false, |n| n.to_string_lossy().starts_with('.'))
+}
 
-    let mut deps = BTreeMap::new();
-    if !input.is_dir() {
-        return Ok(deps);
+/// Where a component is assembled before being moved into `./INPUT`
+///
+/// Any leftovers from an interrupted fetch are removed, so this is always empty.
+pub fn staging_dir(component: &str) -> LalResult<PathBuf> {
+    let staging = Path::new("./INPUT").join(".staging").join(component);
+    if staging.exists() {
+        fs::remove_dir_all(&staging)
+            .context(format!("Failed to clear {}", staging.display()))?;
     }
-    let dirs = WalkDir::new("INPUT")
+    fs::create_dir_all(&staging).context(format!("Failed to create {}", staging.display()))?;
+    Ok(staging)
+}
+
+/// Move a completely staged component into `./INPUT`
+///
+/// The old version is moved aside before the new one is renamed into place, so
+/// `INPUT/<component>` is always either the old or the new version in full.
+pub fn commit_staged(component: &str) -> LalResult<()> {
+    let staging = Path::new("./INPUT").join(".staging");
+    let dest = Path::new("./INPUT").join(component);
+    let old = staging.join(format!("{}.old", component));
+    if dest.exists() {
+        if old.exists() {
+            fs::remove_dir_all(&old).context(format!("Failed to clear {}", old.display()))?;
+        }
+        fs::rename(&dest, &old)
+            .context(format!("Failed to move {} aside", dest.display()))?;
+    }
+    fs::rename(staging.join(component), &dest)
+        .context(format!("Failed to move staged {} into INPUT", component))?;
+    if old.exists() {
+        fs::remove_dir_all(&old).context(format!("Failed to remove {}", old.display()))?;
+    }
+    // leave no empty staging area behind
+    let _ = fs::remove_dir(&staging);
+    Ok(())
+}
+
+// names of the components in INPUT
+fn component_names() -> Vec<String> {
+    WalkDir::new("INPUT")
         .min_depth(1)
         .max_depth(1)
         .into_iter()
         .filter_map(|e| e.ok())
-        .filter(|e| e.path().is_dir());
+        .filter(|e| e.path().is_dir() && !is_hidden(e.path()))
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect()
+}
 
-    for d in dirs {
-        let pth = d.path().strip_prefix("INPUT").unwrap();
-        let component = pth.to_str().unwrap();
-        let lck = read_partial_lockfile(component)?;
-        deps.insert(component.to_string(), lck.version);
+fn read_lockfile(component: &str, shallow: bool) -> LalResult<Lockfile> {
+    trace!("Deserializing lockfile for {}", component);
+    let lock_path = Path::new("./INPUT").join(component).join("lockfile.json");
+    if shallow {
+        Lockfile::from_path_shallow(&lock_path, component)
+    } else {
+        Lockfile::from_path(&lock_path, component)
     }
-    Ok(deps)
+}
+
+fn read_all_lockfiles(shallow: bool) -> LalResult<BTreeMap<String, Lockfile>> {
+    let mut res = BTreeMap::new();
+    if !present() {
+        return Ok(res);
+    }
+    let names = component_names();
+    let chunk_size = cmp::max(1, (names.len() + PARSE_THREADS - 1) / PARSE_THREADS);
+    let handles = names.chunks(chunk_size)
+        .map(|chunk| {
+            let chunk = chunk.to_vec();
+            thread::spawn(move || {
+                chunk.into_iter()
+                    .map(|name| {
+                        let lf = read_lockfile(&name, shallow);
+                        (name, lf)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+    for h in handles {
+        for (name, lf) in h.join().expect("lockfile parsing thread panicked") {
+            res.insert(name, lf?);
+        }
+    }
+    Ok(res)
+}
+
+/// Read the lockfile of every component in `./INPUT`
+///
+/// Every lockfile is parsed exactly once. Lockfiles of large components embed their
+/// whole dependency tree, so parsing is spread over a few threads.
+pub fn read_lockfiles() -> LalResult<BTreeMap<String, Lockfile>> { read_all_lockfiles(false) }
+
+/// Read the top level of the lockfile of every component in `./INPUT`
+///
+/// The returned lockfiles have no dependencies. Use this when recursion is not needed.
+pub fn read_lockfiles_shallow() -> LalResult<BTreeMap<String, Lockfile>> {
+    read_all_lockfiles(true)
+}
+
+/// Simple INPUT analyzer returning the version of every component
+pub fn analyze() -> LalResult<BTreeMap<String, String>> {
+    Ok(read_lockfiles_shallow()?.into_iter().map(|(name, lf)| (name, lf.version)).collect())
 }
 
 #[derive(Debug)]
@@ -66,10 +141,15 @@ pub struct InputDependency {
 pub type InputMap = BTreeMap<String, InputDependency>;
 
 /// Helper for `lal::status`
-pub fn analyze_full(manifest: &Manifest) -> LalResult<InputMap> {
+///
+/// Takes the lockfile populated from INPUT so nothing in INPUT is read again.
+pub fn analyze_full(manifest: &Manifest, lf: &Lockfile) -> LalResult<InputMap> {
     let input = Path::new("./INPUT");
 
-    let deps = analyze()?;
+    let deps = lf.dependencies
+        .iter()
+        .map(|(name, dep)| (name.clone(), dep.version.clone()))
+        .collect::<BTreeMap<_, _>>();
     let saved_deps = manifest.all_dependencies();
 
     let mut depmap = InputMap::new();
@@ -116,7 +196,9 @@ pub fn analyze_full(manifest: &Manifest) -> LalResult<InputMap> {
 }
 
 /// Basic part of input verifier - checks that everything is at least present
-pub fn verify_dependencies_present(m: &Manifest) -> LalResult<()> {
+///
+/// Components in `optional` (unrequested group members) may be absent.
+pub fn verify_dependencies_present(m: &Manifest, optional: &BTreeSet<String>) -> LalResult<()> {
     let mut error = None;
     let mut deps = vec![];
     let dirs = WalkDir::new("INPUT")
@@ -124,7 +206,7 @@ pub fn verify_dependencies_present(m: &Manifest) -> LalResult<()> {
         .max_depth(1)
         .into_iter()
         .filter_map(|e| e.ok())
-        .filter(|e| e.path().is_dir());
+        .filter(|e| e.path().is_dir() && !is_hidden(e.path()));
     for entry in dirs {
         let pth = entry.path().strip_prefix("INPUT").unwrap();
         debug!("-> {}", pth.display());
@@ -137,7 +219,9 @@ pub fn verify_dependencies_present(m: &Manifest) -> LalResult<()> {
     // if INPUT folders are missing at the start of a build (forgot to fetch)
     for (d, v) in &m.dependencies {
         trace!("Verifying dependency from manifest: {}@{}", d, v);
-        if !deps.contains(d) {
+        if !deps.contains(d) && optional.contains(d) {
+            debug!("Group member {} not fetched", d);
+        } else if !deps.contains(d) {
             warn!("Dependency {} not found in INPUT", d);
             error = Some(CliError::MissingDependencies);
         }
@@ -146,6 +230,8 @@ pub fn verify_dependencies_present(m: &Manifest) -> LalResult<()> {
 }
 
 /// Optional part of input verifier - checks that all versions use correct versions
+///
+/// Dependencies with a version range only need a version within the range.
 pub fn verify_global_versions(lf: &Lockfile, m: &Manifest) -> LalResult<()> {
     let all_deps = m.all_dependencies();
     for (name, dep) in &lf.dependencies {
@@ -162,11 +248,15 @@ pub fn verify_global_versions(lf: &Lockfile, m: &Manifest) -> LalResult<()> {
                 // This is a first level dependency - it should be in the manifest
                 CliError::ExtraneousDependencies(name.clone())
             })?;
-        if v != vreq {
+        let allowed = match m.version_range(name) {
+            Some(range) => range.matches(v),
+            None => v == vreq,
+        };
+        if !allowed {
             warn!("Dependency {} has version {}, but manifest requires {}",
                   name,
                   v,
-                  vreq);
+                  m.version_range(name).map_or(vreq.to_string(), |r| r.to_string()));
             return Err(CliError::InvalidVersion(name.clone()));
         }
         // Prevent Cycles (enough to stop it at one manifest level)
@@ -177,18 +267,87 @@ pub fn verify_global_versions(lf: &Lockfile, m: &Manifest) -> LalResult<()> {
     Ok(())
 }
 
+/// Requirement for verifier - no component depends on itself through the tree
+pub fn verify_acyclic(graph: &DependencyGraph) -> LalResult<()> {
+    if let Some(cycle) = graph.find_cycle() {
+        warn!("Dependency cycle found: {}", cycle.join(" -> "));
+        return Err(CliError::DependencyCycle(cycle.join(" -> ")));
+    }
+    Ok(())
+}
+
 /// Strict requirement for verifier - dependency tree must be flat-equivalent
-pub fn verify_consistent_dependency_versions(lf: &Lockfile, m: &Manifest) -> LalResult<()> {
-    for (name, vers) in lf.find_all_dependency_versions() {
-        debug!("Found version(s) for {} as {:?}", name, vers);
-        assert!(!vers.is_empty(), "found versions");
-        if vers.len() != 1 && m.dependencies.contains_key(&name) {
-            warn!("Multiple version requirements on {} found in lockfile",
-                  name.clone());
+///
+/// The error names the paths from the root that pull in each conflicting version.
+pub fn verify_consistent_dependency_versions(graph: &DependencyGraph,
+                                             m: &Manifest)
+                                             -> LalResult<()> {
+    for name in graph.conflicts() {
+        debug!("Found versions for {} as {:?}", name, graph.versions_of(&name));
+        if m.dependencies.contains_key(&name) {
+            warn!("Multiple version requirements on {} found in lockfile", name);
+            for p in graph.conflict_paths(&name) {
+                warn!("{} is pulled in by {}", p.last().unwrap(), p.join(" -> "));
+            }
             warn!("If you are trying to propagate {0} into the tree, \
                     you need to follow `lal propagate {0}`",
                   name);
-            return Err(CliError::MultipleVersions(name.clone()));
+            return Err(CliError::MultipleVersions(graph.describe_conflict(&name)));
+        }
+    }
+    Ok(())
+}
+
+/// Strict verify requirement - all deps built with a container tag used the same image
+///
+/// Tags move when images are rebuilt, so the same environment can still differ.
+pub fn verify_container_digests(lf: &Lockfile) -> LalResult<()> {
+    for (container, digests) in lf.find_all_container_digests() {
+        debug!("Found digest(s) for {} as {:?}", container, digests);
+        if digests.len() > 1 {
+            warn!("Multiple images of {} used: {:?}", container, digests);
+            return Err(CliError::MultipleContainerDigests(container));
+        }
+    }
+    Ok(())
+}
+
+/// Strict verify requirement - INPUT contents match the digests in their lockfiles
+///
+/// Components built before digests were recorded are skipped.
+pub fn verify_contents(lf: &Lockfile) -> LalResult<()> {
+    for (name, dep) in &lf.dependencies {
+        let expected = match dep.contents {
+            Some(ref c) => c,
+            None => {
+                debug!("No contents digest recorded for {} - not checking it", name);
+                continue;
+            }
+        };
+        let found = output::contents_digest(&Path::new("./INPUT").join(name))?;
+        if &found != expected {
+            warn!("INPUT/{} has digest {} but its lockfile records {}", name, found, expected);
+            return Err(CliError::ContentsMismatch(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Requirement for verifier - all deps built for a target must be built for the same one
+///
+/// Target independent components fit any target. When a `target` is given (as when
+/// building for one) the deps must be built for that target.
+pub fn verify_target_consistency(lf: &Lockfile, target: Option<&str>) -> LalResult<()> {
+    let mut expected = target.map(String::from);
+    for (name, targets) in lf.find_all_targets() {
+        debug!("Found target(s) for {} as {:?}", name, targets);
+        for t in targets.into_iter().filter(|t| !t.is_empty()) {
+            if expected.is_none() {
+                expected = Some(t.clone());
+            }
+            if expected.as_ref() != Some(&t) {
+                return Err(CliError::TargetMismatch(name, t, expected.unwrap()));
+            }
         }
     }
     Ok(())
@@ -0,0 +1,74 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::{Read, Write};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use libc;
+
+use super::{CliError, LalResult, IoResultExt};
+use manifest::create_lal_subdir;
+
+/// An advisory lock on a workspace or cache directory
+///
+/// Taken by commands that write to `./INPUT` or the cache, so that two concurrent
+/// invocations of lal fail instead of corrupting each other. The lock is released
+/// when this is dropped, or when the process exits.
+pub struct LalLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl LalLock {
+    /// Lock the current workspace via `.lal/lock`
+    pub fn project() -> LalResult<LalLock> {
+        let pwd = env::current_dir()?;
+        create_lal_subdir(&pwd)?;
+        LalLock::acquire(&pwd.join(".lal").join("lock"))
+    }
+
+    /// Lock a cache directory via `.lock` inside it
+    pub fn cache(cache: &str) -> LalResult<LalLock> {
+        let dir = Path::new(cache);
+        fs::create_dir_all(dir).context(format!("Failed to create cache {}", dir.display()))?;
+        LalLock::acquire(&dir.join(".lock"))
+    }
+
+    fn acquire(pth: &Path) -> LalResult<LalLock> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(pth)
+            .context(format!("Failed to open lock {}", pth.display()))?;
+        let res = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if res != 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                return Err(CliError::IoContext(format!("Failed to lock {}", pth.display()), err));
+            }
+            // the holder writes its pid into the lock file
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            return Err(CliError::ConcurrentInvocation(pth.display().to_string(),
+                                                      pid.trim().to_string()));
+        }
+        file.set_len(0)
+            .and_then(|_| write!(file, "{}\n", process::id()))
+            .context(format!("Failed to write lock {}", pth.display()))?;
+        debug!("Locked {}", pth.display());
+        Ok(LalLock {
+            file: file,
+            path: pth.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for LalLock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+        unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+        debug!("Unlocked {}", self.path.display());
+    }
+}
@@ -1,6 +1,7 @@
 use serde_json;
 use chrono::UTC;
 use rand;
+use libc;
 
 use std::path::{Path, PathBuf};
 use std::fs::File;
@@ -10,7 +11,7 @@ use std::collections::{HashMap, BTreeMap};
 use std::collections::BTreeSet;
 use std::fmt;
 
-use super::{CliError, LalResult, input};
+use super::{CliError, LalResult, IoResultExt, input};
 
 /// Representation of a docker container image
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -19,6 +20,9 @@ pub struct Container {
     pub name: String,
     /// The tag to use
     pub tag: String,
+    /// The image digest (`sha256:...`) the tag resolved to when building
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
 }
 
 impl Container {
@@ -27,6 +31,7 @@ impl Container {
         Container {
             name: name.into(),
             tag: "latest".into(),
+            digest: None,
         }
     }
 }
@@ -42,6 +47,7 @@ impl Default for Container {
         Container {
             name: "ubuntu".into(),
             tag: "xenial".into(),
+            digest: None,
         }
     }
 }
@@ -58,18 +64,61 @@ impl Container {
         Container {
             name: cname.into(),
             tag: tag.into(),
+            digest: None,
         }
     }
+
+    /// The image reference pinned by digest (`name@sha256:...`) if the digest is known
+    pub fn pinned(&self) -> Option<String> {
+        self.digest.as_ref().map(|d| format!("{}@{}", self.name, d))
+    }
+}
+
+/// The backend location of components built in an environment for a target
+///
+/// Components built for a target are published next to their environment as
+/// `<env>+<target>`, so that every target has its own versions, URLs and cache entries.
+/// Target independent components are published in the environment itself.
+pub fn target_location(env: &str, target: Option<&str>) -> String {
+    match target {
+        Some(t) => format!("{}+{}", env, t),
+        None => env.into(),
+    }
+}
+
+/// Format version of lockfiles written by this version of lal
+pub const LOCKFILE_VERSION: u32 = 2;
+
+// lockfiles without a version are from before checksums were recorded
+fn legacy_lockfile_version() -> u32 { 1 }
+
+// identity of the user building: `user@host`
+fn builder_identity() -> Option<String> {
+    use std::env;
+    let user = env::var("USER").or_else(|_| env::var("LOGNAME")).ok()?;
+    let mut buf = [0u8; 256];
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if res != 0 {
+        return Some(user);
+    }
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    Some(format!("{}@{}", user, String::from_utf8_lossy(&buf[..len])))
 }
 
 /// Representation of `lockfile.json`
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Lockfile {
+    /// Format version of the lockfile
+    #[serde(default = "legacy_lockfile_version")]
+    pub lockfileVersion: u32,
     /// Name of the component built
     pub name: String,
     /// Build configuration used
     pub config: String,
+    /// Target architecture built for (none if the component is target independent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
     /// Container and tag used to build
     pub container: Container,
     /// Name of the environment for the container at the time
@@ -84,10 +133,66 @@ pub struct Lockfile {
     pub tool: String,
     /// Built timestamp
     pub built: Option<String>,
+    /// Identity of the builder as `user@host`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub builder: Option<String>,
+    /// Digest of the built OUTPUT (`sha256:...`) recorded after a successful build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contents: Option<String>,
+    /// SPDX license expression from the manifest of the component
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
     /// Recursive map of dependencies used
     pub dependencies: BTreeMap<String, Lockfile>,
 }
 
+// `Lockfile` without its dependency tree, which serde skips over without allocating
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+struct ShallowLockfile {
+    #[serde(default = "legacy_lockfile_version")]
+    lockfileVersion: u32,
+    name: String,
+    config: String,
+    #[serde(default)]
+    target: Option<String>,
+    container: Container,
+    environment: String,
+    defaultEnv: Option<String>,
+    sha: Option<String>,
+    version: String,
+    tool: String,
+    built: Option<String>,
+    #[serde(default)]
+    builder: Option<String>,
+    #[serde(default)]
+    contents: Option<String>,
+    #[serde(default)]
+    license: Option<String>,
+}
+
+impl From<ShallowLockfile> for Lockfile {
+    fn from(s: ShallowLockfile) -> Self {
+        Lockfile {
+            lockfileVersion: s.lockfileVersion,
+            name: s.name,
+            config: s.config,
+            target: s.target,
+            container: s.container,
+            environment: s.environment,
+            defaultEnv: s.defaultEnv,
+            sha: s.sha,
+            version: s.version,
+            tool: s.tool,
+            built: s.built,
+            builder: s.builder,
+            contents: s.contents,
+            license: s.license,
+            dependencies: BTreeMap::new(),
+        }
+    }
+}
+
 /// Generates a temporary empty lockfile for internal analysis
 impl Default for Lockfile {
     fn default() -> Self { Lockfile::new("templock", &Container::default(), "none", None, None) }
@@ -107,12 +212,17 @@ impl Lockfile {
         let def_version = format!("EXPERIMENTAL-{:x}", rand::random::<u64>());
         let time = UTC::now();
         Lockfile {
+            lockfileVersion: LOCKFILE_VERSION,
             name: name.to_string(),
             version: v.unwrap_or(def_version),
             config: build_cfg.unwrap_or("release").to_string(),
+            target: None,
             container: container.clone(),
             tool: env!("CARGO_PKG_VERSION").to_string(),
             built: Some(time.format("%Y-%m-%d %H:%M:%S").to_string()),
+            builder: builder_identity(),
+            contents: None,
+            license: None,
             defaultEnv: Some(env.into()),
             environment: env.into(),
             dependencies: BTreeMap::new(),
@@ -120,41 +230,54 @@ impl Lockfile {
         }
     }
 
-    /// Opened lockfile at a path
-    pub fn from_path(lock_path: &PathBuf, name: &str) -> LalResult<Self> {
+    // contents of a lockfile at a path
+    fn read_path(lock_path: &PathBuf, name: &str) -> LalResult<String> {
         if !lock_path.exists() {
             return Err(CliError::MissingLockfile(name.to_string()));
         }
         let mut lock_str = String::new();
-        File::open(lock_path)?.read_to_string(&mut lock_str)?;
+        File::open(lock_path)
+            .and_then(|mut f| f.read_to_string(&mut lock_str))
+            .context(format!("Failed to read lockfile {}", lock_path.display()))?;
+        Ok(lock_str)
+    }
+
+    /// Opened lockfile at a path
+    pub fn from_path(lock_path: &PathBuf, name: &str) -> LalResult<Self> {
+        let lock_str = Lockfile::read_path(lock_path, name)?;
         Ok(serde_json::from_str(&lock_str)?)
     }
 
+    /// Opened lockfile at a path without its dependency tree
+    ///
+    /// Only the top level fields are deserialized and `dependencies` is left empty,
+    /// which is much cheaper for components with large trees.
+    pub fn from_path_shallow(lock_path: &PathBuf, name: &str) -> LalResult<Self> {
+        let lock_str = Lockfile::read_path(lock_path, name)?;
+        let shallow: ShallowLockfile = serde_json::from_str(&lock_str)?;
+        Ok(shallow.into())
+    }
+
     /// A reader from ARTIFACT directory
     pub fn release_build() -> LalResult<Self> {
         let lpath = Path::new("ARTIFACT").join("lockfile.json");
         Ok(Lockfile::from_path(&lpath, "release build")?)
     }
 
-    // Helper constructor for input populator below
-    fn from_input_component(component: &str) -> LalResult<Self> {
-        let lock_path = Path::new("./INPUT").join(component).join("lockfile.json");
-        Ok(Lockfile::from_path(&lock_path, component)?)
-    }
-
-
     /// Read all the lockfiles in INPUT to generate the full lockfile
-    ///
-    /// NB: This currently reads all the lockfiles partially in `analyze`,
-    /// the re-reads them fully in `read_lockfile_from_component` so can be sped up.
     pub fn populate_from_input(mut self) -> LalResult<Self> {
         debug!("Reading all lockfiles");
-        let deps = input::analyze()?;
-        for name in deps.keys() {
-            trace!("Populating lockfile with {}", name);
-            let deplock = Lockfile::from_input_component(name)?;
-            self.dependencies.insert(name.clone(), deplock);
-        }
+        self.dependencies.extend(input::read_lockfiles()?);
+        Ok(self)
+    }
+
+    /// Read the top level of all the lockfiles in INPUT
+    ///
+    /// Like `populate_from_input`, but the dependencies of INPUT components are left
+    /// empty, for when only their names, versions and environments are needed.
+    pub fn populate_from_input_shallow(mut self) -> LalResult<Self> {
+        debug!("Reading top level of all lockfiles");
+        self.dependencies.extend(input::read_lockfiles_shallow()?);
         Ok(self)
     }
 
@@ -170,6 +293,29 @@ impl Lockfile {
         self
     }
 
+    /// Attach the digest of the built OUTPUT
+    pub fn attach_contents(mut self, digest: String) -> Self {
+        self.contents = Some(digest);
+        self
+    }
+
+    /// Where the component is published (its environment and target)
+    pub fn location(&self) -> String {
+        target_location(&self.environment, self.target.as_ref().map(|t| t.as_str()))
+    }
+
+    /// Attach the target the component is built for
+    pub fn attach_target(mut self, target: Option<String>) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Attach the license of the component
+    pub fn attach_license(mut self, license: Option<String>) -> Self {
+        self.license = license;
+        self
+    }
+
     /// Attach a name to the lockfile
     pub fn set_name(mut self, name: &str) -> Self {
         self.name = name.into();
@@ -179,8 +325,9 @@ impl Lockfile {
     /// Write the current `Lockfile` struct to a Path
     pub fn write(&self, pth: &Path) -> LalResult<()> {
         let encoded = serde_json::to_string_pretty(self)?;
-        let mut f = File::create(pth)?;
-        write!(f, "{}\n", encoded)?;
+        File::create(pth)
+            .and_then(|mut f| write!(f, "{}\n", encoded))
+            .context(format!("Failed to write lockfile {}", pth.display()))?;
         debug!("Wrote lockfile {}: \n{}", pth.display(), encoded);
         Ok(())
     }
@@ -190,6 +337,19 @@ impl Lockfile {
 // name of component -> (value1, value2, ..)
 pub type ValueUsage = HashMap<String, BTreeSet<String>>;
 
+/// A component in a flattened dependency tree
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FlatDependency {
+    /// Component name
+    pub name: String,
+    /// Version used
+    pub version: String,
+    /// Environment it was built in
+    pub environment: String,
+    /// Digest of its contents if recorded (`sha256:...`)
+    pub checksum: Option<String>,
+}
+
 // The hardcore dependency analysis parts
 impl Lockfile {
     // helper to extract specific keys out of a struct
@@ -198,11 +358,31 @@ impl Lockfile {
             self.version.clone()
         } else if key == "environment" {
             self.environment.clone()
+        } else if key == "target" {
+            self.target.clone().unwrap_or_default()
         } else {
             unreachable!("Only using get_value internally");
         }
     }
 
+    /// Find every digest used to build the tree, keyed by container tag
+    ///
+    /// Components built before digests were recorded are skipped.
+    pub fn find_all_container_digests(&self) -> ValueUsage {
+        let mut acc: ValueUsage = HashMap::new();
+        for dep in self.dependencies.values() {
+            if let Some(ref digest) = dep.container.digest {
+                acc.entry(dep.container.to_string())
+                    .or_insert_with(BTreeSet::new)
+                    .insert(digest.clone());
+            }
+            for (tag, digests) in dep.find_all_container_digests() {
+                acc.entry(tag).or_insert_with(BTreeSet::new).extend(digests);
+            }
+        }
+        acc
+    }
+
     /// Recursive function to check for multiple version/environment (key) use
     fn find_all_values(&self, key: &str) -> ValueUsage {
         let mut acc = HashMap::new();
@@ -246,6 +426,30 @@ impl Lockfile {
     /// List all used environments used of each dependency
     pub fn find_all_environments(&self) -> ValueUsage { self.find_all_values("environment") }
 
+    /// List all targets used of each dependency (empty for target independent ones)
+    pub fn find_all_targets(&self) -> ValueUsage { self.find_all_values("target") }
+
+    /// Collapse the dependency tree into a deduplicated list sorted by name
+    ///
+    /// Components used at several versions or environments appear once for each.
+    pub fn flatten(&self) -> Vec<FlatDependency> {
+        let mut acc = BTreeSet::new();
+        self.collect_flat(&mut acc);
+        acc.into_iter().collect()
+    }
+
+    fn collect_flat(&self, acc: &mut BTreeSet<FlatDependency>) {
+        for (name, dep) in &self.dependencies {
+            acc.insert(FlatDependency {
+                name: name.clone(),
+                version: dep.version.clone(),
+                environment: dep.environment.clone(),
+                checksum: dep.contents.clone(),
+            });
+            dep.collect_flat(acc);
+        }
+    }
+
     /// List all dependency names used by each dependency (not transitively)
     pub fn find_all_dependency_names(&self) -> ValueUsage {
         let mut acc = HashMap::new();
@@ -1,11 +1,51 @@
 use std::io::prelude::*;
 use std::fs::{self, File};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::vec::Vec;
 use serde_json;
+use serde_json::Value;
 use std::path::{Path, PathBuf};
 
-use super::{CliError, LalResult};
+use super::{CliError, LalResult, VersionRange};
+
+// three way merge of json values - `ours` wins where only we changed something
+//
+// Objects are merged key by key so that unrelated changes to different keys
+// (like two dependency bumps) combine. Keys changed differently by both sides
+// are pushed onto `conflicts`.
+fn merge3(
+    base: &Value,
+    ours: &Value,
+    theirs: &Value,
+    path: &str,
+    conflicts: &mut Vec<String>,
+) -> Value {
+    if ours == theirs || theirs == base {
+        return ours.clone();
+    }
+    if ours == base {
+        return theirs.clone();
+    }
+    if let (Some(o), Some(t)) = (ours.as_object(), theirs.as_object()) {
+        let empty = serde_json::Map::new();
+        let b = base.as_object().unwrap_or(&empty);
+        let mut merged = serde_json::Map::new();
+        for k in b.keys().chain(o.keys()).chain(t.keys()) {
+            if merged.contains_key(k) {
+                continue;
+            }
+            let get = |m: &serde_json::Map<String, Value>| m.get(k).cloned().unwrap_or(Value::Null);
+            let sub = if path.is_empty() { k.clone() } else { format!("{}.{}", path, k) };
+            let v = merge3(&get(b), &get(o), &get(t), &sub, conflicts);
+            if !v.is_null() {
+                merged.insert(k.clone(), v);
+            }
+        }
+        return Value::Object(merged);
+    }
+    conflicts.push(path.to_string());
+    ours.clone()
+}
 
 /// A startup helper used in a few places
 pub fn create_lal_subdir(pwd: &PathBuf) -> LalResult<()> {
@@ -24,6 +64,12 @@ pub struct ComponentConfiguration {
     pub defaultConfig: String,
     /// List of allowed configurations (must contain defaultConfig)
     pub configurations: Vec<String>,
+    /// Target architectures every configuration builds for (none if target independent)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+    /// The target to use if not passed in - the first of `targets` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub defaultTarget: Option<String>,
 }
 
 impl Default for ComponentConfiguration {
@@ -31,10 +77,48 @@ impl Default for ComponentConfiguration {
         ComponentConfiguration {
             configurations: vec!["release".to_string()],
             defaultConfig: "release".to_string(),
+            targets: vec![],
+            defaultTarget: None,
+        }
+    }
+}
+
+impl ComponentConfiguration {
+    /// The target to build for, from an explicit one or the default
+    ///
+    /// Components without `targets` build for no target and reject an explicit one.
+    pub fn resolve_target(&self, target: Option<String>) -> LalResult<Option<String>> {
+        if self.targets.is_empty() {
+            return match target {
+                Some(t) => {
+                    let ename = format!("{} requested without a targets list", t);
+                    Err(CliError::InvalidBuildTarget(ename))
+                }
+                None => Ok(None),
+            };
+        }
+        let t = target.or_else(|| self.defaultTarget.clone())
+            .unwrap_or_else(|| self.targets[0].clone());
+        if !self.targets.contains(&t) {
+            return Err(CliError::InvalidBuildTarget(format!("{} not found in targets list", t)));
         }
+        Ok(Some(t))
     }
 }
 
+/// Patterns that published OUTPUT must, and must not, contain
+///
+/// Patterns are globs relative to OUTPUT using `output::glob_match` semantics.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct ArtifactContract {
+    /// Patterns that must each match at least one path in OUTPUT
+    #[serde(default)]
+    pub require: Vec<String>,
+    /// Patterns that must not match any path in OUTPUT
+    #[serde(default)]
+    pub forbid: Vec<String>,
+}
+
 /// Representation of `manifest.json`
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Clone, Default)]
@@ -51,10 +135,41 @@ pub struct Manifest {
     pub dependencies: BTreeMap<String, u32>,
     /// Development dependencies
     pub devDependencies: BTreeMap<String, u32>,
+    /// Named groups of dependencies only fetched on demand
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// Remove stale INPUT components on every fetch (as if `--sync` was passed)
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pruneExtraneous: bool,
+    /// Optional contract for what OUTPUT must contain before packaging
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifactContract: Option<ArtifactContract>,
+    /// Dependency versions to use instead in specific environments
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub environmentOverrides: BTreeMap<String, BTreeMap<String, u32>>,
+    /// SPDX license expression for the component, recorded in its lockfile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    /// Properties recorded on every published version (`$VAR` values come from the environment)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub publishMetadata: BTreeMap<String, String>,
+    /// Named commands `lal run` executes in the container (e.g. `test`, `lint`, `bench`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scripts: BTreeMap<String, String>,
+
+    /// Dependencies given as version ranges rather than exact versions
+    ///
+    /// These are written as strings in the dependency maps on disk, where the maps
+    /// hold the lowest version in the range until `fetch` resolves them.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub ranges: BTreeMap<String, VersionRange>,
 
     /// Internal path of this manifest
     #[serde(skip_serializing, skip_deserializing)]
     location: String,
+    /// File contents at the time of reading (for conflict detection on write)
+    #[serde(skip_serializing, skip_deserializing)]
+    original: Option<String>,
 }
 
 /// An enum to clarify intent
@@ -97,6 +212,9 @@ impl ManifestLocation {
     }
 }
 
+// avoids writing out default booleans
+#[cfg_attr(feature = "cargo-clippy", allow(trivially_copy_pass_by_ref))]
+fn is_false(b: &bool) -> bool { !*b }
 
 impl Manifest {
     /// Initialize a manifest struct based on a name
@@ -123,6 +241,87 @@ impl Manifest {
         }
         deps
     }
+    /// The dependency group a component belongs to (if any)
+    pub fn group_of(&self, component: &str) -> Option<&str> {
+        self.groups.iter().find(|&(_, m)| m.iter().any(|c| c == component)).map(|(g, _)| &g[..])
+    }
+    /// Members of groups that were not requested
+    ///
+    /// These are skipped by `fetch` and may be absent from INPUT.
+    pub fn excluded_group_members(&self, requested: &[String], all: bool) -> BTreeSet<String> {
+        if all {
+            return BTreeSet::new();
+        }
+        self.groups
+            .iter()
+            .filter(|&(g, _)| !requested.contains(g))
+            .flat_map(|(_, m)| m.iter().cloned())
+            .collect()
+    }
+    /// The manifest as seen from an environment
+    ///
+    /// Versions in `environmentOverrides` for the environment replace those in
+    /// `dependencies` and `devDependencies` (including any version range). Overrides
+    /// for components that are not dependencies are ignored.
+    pub fn for_environment(&self, env: &str) -> Manifest {
+        let mut res = self.clone();
+        if let Some(overrides) = self.environmentOverrides.get(env) {
+            for (name, &v) in overrides {
+                let dep = res.dependencies.get_mut(name).or(res.devDependencies.get_mut(name));
+                if let Some(dv) = dep {
+                    debug!("Using {} {} in {}", name, v, env);
+                    *dv = v;
+                    res.ranges.remove(name);
+                }
+            }
+        }
+        res
+    }
+    /// The version range of a dependency, if it is not pinned to an exact version
+    pub fn version_range(&self, component: &str) -> Option<&VersionRange> {
+        self.ranges.get(component)
+    }
+
+    // deserialize, moving string versions in the dependency maps into `ranges`
+    fn from_json(mut value: Value) -> LalResult<Manifest> {
+        let mut ranges = BTreeMap::new();
+        for key in &["dependencies", "devDependencies"] {
+            if let Some(deps) = value.get_mut(*key).and_then(|d| d.as_object_mut()) {
+                for (name, v) in deps.iter_mut() {
+                    let spec = match *v {
+                        Value::String(ref s) => s.clone(),
+                        _ => continue,
+                    };
+                    if let Ok(n) = spec.trim().parse::<u32>() {
+                        *v = Value::from(n);
+                        continue;
+                    }
+                    let range = VersionRange::parse(&spec)?;
+                    *v = Value::from(range.min);
+                    ranges.insert(name.clone(), range);
+                }
+            }
+        }
+        let mut res: Manifest = serde_json::from_value(value)?;
+        res.ranges = ranges;
+        Ok(res)
+    }
+
+    // serialize, writing `ranges` back as strings in the dependency maps
+    fn to_json(&self) -> LalResult<Value> {
+        let mut value = serde_json::to_value(self)?;
+        for key in &["dependencies", "devDependencies"] {
+            if let Some(deps) = value.get_mut(*key).and_then(|d| d.as_object_mut()) {
+                for (name, range) in &self.ranges {
+                    if let Some(v) = deps.get_mut(name) {
+                        *v = Value::String(range.spec.clone());
+                    }
+                }
+            }
+        }
+        Ok(value)
+    }
+
     /// Read a manifest file in PWD
     pub fn read() -> LalResult<Manifest> { Ok(Manifest::read_from(&Path::new(".").to_path_buf())?) }
 
@@ -133,18 +332,64 @@ impl Manifest {
         let mut f = File::open(&mpath)?;
         let mut data = String::new();
         f.read_to_string(&mut data)?;
-        let mut res: Manifest = serde_json::from_str(&data)?;
-        // store the location internally (not serialized to disk)
+        let mut res = Manifest::from_json(serde_json::from_str(&data)?)?;
+        // store the location and contents internally (not serialized to disk)
         res.location = mpath.to_string_lossy().into();
+        res.original = Some(data);
+        Ok(res)
+    }
+
+    // merge our changes onto a manifest someone else wrote after we read ours
+    fn rebase(&self, original: &str, current: &str) -> LalResult<Manifest> {
+        let base: Value = serde_json::from_str(original)?;
+        let theirs: Value = serde_json::from_str(current)?;
+        let ours = self.to_json()?;
+        let mut conflicts = vec![];
+        let merged = merge3(&base, &ours, &theirs, "", &mut conflicts);
+        if !conflicts.is_empty() {
+            return Err(CliError::ManifestConflict(conflicts.join(", ")));
+        }
+        let mut res = Manifest::from_json(merged)?;
+        res.location = self.location.clone();
         Ok(res)
     }
 
     /// Update the manifest file in the current folder
+    ///
+    /// If the file changed on disk since this manifest was read, the changes made to
+    /// this manifest are re-applied on top of the new file contents. Only keys changed
+    /// differently by both cause a `ManifestConflict`.
     pub fn write(&self) -> LalResult<()> {
-        let encoded = serde_json::to_string_pretty(self)?;
+        if let Some(ref original) = self.original {
+            let mut current = String::new();
+            if let Ok(mut f) = File::open(&self.location) {
+                f.read_to_string(&mut current)?;
+                if &current != original {
+                    warn!("{} changed since it was read - merging changes", self.location);
+                    let merged = self.rebase(original, &current)?;
+                    return merged.write_unchecked();
+                }
+            }
+        }
+        self.write_unchecked()
+    }
+
+    // the previous manifest is kept as `manifest.json.bak`, and the new one is
+    // renamed into place so the manifest is never half written
+    fn write_unchecked(&self) -> LalResult<()> {
+        let encoded = serde_json::to_string_pretty(&self.to_json()?)?;
         trace!("Writing manifest in {}", self.location);
-        let mut f = File::create(&self.location)?;
-        write!(f, "{}\n", encoded)?;
+        let pth = Path::new(&self.location);
+        if pth.is_file() {
+            fs::copy(pth, format!("{}.bak", self.location))?;
+        }
+        let tmp = format!("{}.tmp", self.location);
+        {
+            let mut f = File::create(&tmp)?;
+            write!(f, "{}\n", encoded)?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp, pth)?;
         debug!("Wrote manifest in {}: \n{}", self.location, encoded);
         Ok(())
     }
@@ -162,6 +407,19 @@ impl Manifest {
                                     conf.defaultConfig);
                 return Err(CliError::InvalidBuildConfiguration(ename));
             }
+            // targets are part of backend locations
+            let valid = |t: &String| {
+                !t.is_empty() && t.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            };
+            if let Some(t) = conf.targets.iter().find(|t| !valid(t)) {
+                return Err(CliError::InvalidBuildTarget(format!("'{}' is not a valid name", t)));
+            }
+            if let Some(ref t) = conf.defaultTarget {
+                if !conf.targets.contains(t) {
+                    let ename = format!("default target '{}' not found in targets list", t);
+                    return Err(CliError::InvalidBuildTarget(ename));
+                }
+            }
         }
         for (name, _) in &self.dependencies {
             if &name.to_lowercase() != name {
@@ -173,6 +431,14 @@ impl Manifest {
                 return Err(CliError::InvalidComponentName(name.clone()));
             }
         }
+        let all_deps = self.all_dependencies();
+        for (group, members) in &self.groups {
+            for m in members {
+                if !all_deps.contains_key(m) {
+                    return Err(CliError::InvalidGroup(group.clone(), m.clone()));
+                }
+            }
+        }
         if self.supportedEnvironments.is_empty() {
             return Err(CliError::NoSupportedEnvironments);
         }
@@ -1,15 +1,40 @@
-pub use self::errors::{CliError, LalResult};
-pub use self::manifest::{Manifest, ComponentConfiguration, ManifestLocation};
-pub use self::lockfile::{Lockfile, Container};
-pub use self::config::{Config, ConfigDefaults, Mount, config_dir};
+pub use self::errors::{CliError, LalResult, IoResultExt, exit_code};
+pub use self::manifest::{Manifest, ComponentConfiguration, ManifestLocation, ArtifactContract};
+pub use self::lockfile::{Lockfile, Container, FlatDependency, LOCKFILE_VERSION,
+                         target_location};
+pub use self::graph::DependencyGraph;
+pub use self::config::{Config, ConfigDefaults, ImageUpdatePolicy, Mount, RetentionPolicy,
+                       VerifyCheck, config_dir, home_dir, project_config_path};
 pub use self::sticky::StickyOptions;
+pub use self::buildcache::{BuildCache, BuildRecord};
+pub use self::timings::{Timings, CommandTimings, PhaseTiming, PhaseTimer};
+pub use self::signing::{SignaturePolicy, sign_file, signature_path, verify_signature};
+pub use self::range::{VersionRange, is_valid_channel};
 pub use self::ensure::ensure_dir_exists_fresh;
+pub use self::archive::{EntryGuard, unpack_safely};
+pub use self::compression::{Compression, CompressionOptions, COMPRESSIONS, find_tarball};
+pub use self::lock::LalLock;
+pub use self::deprecations::{Deprecation, Deprecations};
+pub use self::sources::{InputSources, InputSource, cache_entry_users, forget_source};
+pub use self::reporter::{ProgressReporter, ProgressEvent, SilentReporter, RecordingReporter,
+                         TerminalReporter};
 
 mod config;
 mod errors;
 mod lockfile;
+mod graph;
 mod sticky;
+mod buildcache;
+mod timings;
+mod signing;
 mod ensure;
+mod compression;
+mod archive;
+mod reporter;
+mod sources;
+mod deprecations;
+mod range;
+mod lock;
 
 /// Manifest module can be used directly
 pub mod manifest;
@@ -1,35 +1,220 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::os::unix::fs::PermissionsExt;
 use std::process::Command;
 use std::path::Path;
+use walkdir::WalkDir;
+use sha2::{Digest, Sha256};
 
-use super::{CliError, LalResult};
+use super::{CliError, CompressionOptions, LalResult, IoResultExt, ArtifactContract,
+            exit_code};
+
+// match a single path segment against a pattern segment with `*` and `?`
+fn segment_match(pat: &[u8], s: &[u8]) -> bool {
+    match (pat.first(), s.first()) {
+        (None, None) => true,
+        (Some(&b'*'), _) => {
+            segment_match(&pat[1..], s) || (!s.is_empty() && segment_match(pat, &s[1..]))
+        }
+        (Some(&b'?'), Some(_)) => segment_match(&pat[1..], &s[1..]),
+        (Some(p), Some(c)) if p == c => segment_match(&pat[1..], &s[1..]),
+        _ => false,
+    }
+}
+
+fn segments_match(pat: &[&str], path: &[&str]) -> bool {
+    match pat.first() {
+        None => path.is_empty(),
+        Some(&"**") => (0..path.len() + 1).any(|i| segments_match(&pat[1..], &path[i..])),
+        Some(p) => {
+            !path.is_empty() && segment_match(p.as_bytes(), path[0].as_bytes()) &&
+                segments_match(&pat[1..], &path[1..])
+        }
+    }
+}
+
+/// Match a relative path against a glob pattern
+///
+/// `*` and `?` match within a path segment, and `**` matches any number of segments.
+/// Leading and trailing slashes in the pattern are ignored.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pat = pattern.trim_matches('/').split('/').collect::<Vec<_>>();
+    let pth = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    segments_match(&pat, &pth)
+}
+
+/// Check OUTPUT against an `ArtifactContract`
+///
+/// Every path in OUTPUT (files, links and directories) is considered.
+/// All unmet requirements and forbidden matches are reported together.
+pub fn verify_contract(contract: &ArtifactContract) -> LalResult<()> {
+    let paths = WalkDir::new("OUTPUT")
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path().strip_prefix("OUTPUT").unwrap().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+
+    let mut unmet = vec![];
+    for pat in &contract.require {
+        if !paths.iter().any(|p| glob_match(pat, p)) {
+            warn!("OUTPUT is missing required {}", pat);
+            unmet.push(format!("missing {}", pat));
+        }
+    }
+    for pat in &contract.forbid {
+        for p in paths.iter().filter(|p| glob_match(pat, p)) {
+            warn!("OUTPUT contains forbidden {} (matching {})", p, pat);
+            unmet.push(format!("forbidden {}", p));
+        }
+    }
+    if !unmet.is_empty() {
+        return Err(CliError::BrokenArtifactContract(unmet.join(", ")));
+    }
+    debug!("OUTPUT satisfies the artifact contract");
+    Ok(())
+}
+
+/// Content digest (`sha256:...`) of a built component directory
+///
+/// Covers the relative path, executable bit and contents of every file, and the target
+/// of every link, in path order. Only files and links are packaged, so directories are
+/// ignored, as is the top level `lockfile.json` that records the digest.
+pub fn contents_digest(dir: &Path) -> LalResult<String> {
+    tree_digest(dir, &["lockfile.json"])
+}
+
+/// Paths in a component that are not sources, relative to its root
+///
+/// These are what lal writes (`INPUT`, `OUTPUT`, `ARTIFACT`, and under `.lal` the project
+/// lock, sticky options, input sources, build cache, timings, logs and the manifest backup)
+/// along with the `.git` directory. The lock alone changes on every command.
+pub const SOURCE_EXCLUDES: &'static [&'static str] = &["INPUT",
+                                                      "OUTPUT",
+                                                      "ARTIFACT",
+                                                      ".git",
+                                                      ".lal/lock",
+                                                      ".lal/opts",
+                                                      ".lal/sources.json",
+                                                      ".lal/buildcache.json",
+                                                      ".lal/timings.json",
+                                                      ".lal/logs",
+                                                      "manifest.json.bak",
+                                                      ".lal/manifest.json.bak"];
+
+/// Content digest (`sha256:...`) of the sources of a component in a directory
+///
+/// Like `contents_digest`, but leaves out everything in `SOURCE_EXCLUDES`.
+pub fn source_digest(dir: &Path) -> LalResult<String> { tree_digest(dir, SOURCE_EXCLUDES) }
+
+// digest of the files and links in a directory, leaving out relative paths in `skip`
+fn tree_digest(dir: &Path, skip: &[&str]) -> LalResult<String> {
+    let mut entries = WalkDir::new(dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| {
+            let rel = e.path().strip_prefix(dir).unwrap();
+            !skip.iter().any(|s| rel == Path::new(s))
+        })
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_type().is_dir())
+        .map(|e| e.path().to_path_buf())
+        .collect::<Vec<_>>();
+    entries.sort();
+
+    let mut sha = Sha256::default();
+    for pth in entries {
+        let rel = pth.strip_prefix(dir).unwrap();
+        sha.input(rel.to_string_lossy().as_bytes());
+        let meta = fs::symlink_metadata(&pth)?;
+        if meta.file_type().is_symlink() {
+            sha.input(b"\0l\0");
+            sha.input(fs::read_link(&pth)?.to_string_lossy().as_bytes());
+        } else {
+            let exec = meta.permissions().mode() & 0o111 != 0;
+            sha.input(if exec { b"\0x\0" } else { b"\0f\0" });
+            let ctx = || format!("Failed to read {}", pth.display());
+            let mut f = File::open(&pth).context(ctx())?;
+            let mut buffer = [0; 1024 * 64];
+            loop {
+                let read = f.read(&mut buffer).context(ctx())?;
+                if read == 0 {
+                    break;
+                }
+                sha.input(&buffer[0..read]);
+            }
+        }
+        sha.input(b"\n");
+    }
+    Ok(format!("sha256:{:x}", sha.result()))
+}
 
 /// Helper for stash and build
-pub fn tar(tarball: &Path) -> LalResult<()> {
+pub fn tar(tarball: &Path, opts: &CompressionOptions) -> LalResult<()> {
     info!("Taring OUTPUT");
+    tar_dir(Path::new("OUTPUT"), tarball, opts)
+}
+
+// `tar` arguments making entries independent of by whom OUTPUT was built
+//
+// Entries are always owned by root in the plain gnu format (pax headers record atimes).
+// Mtimes later than `SOURCE_DATE_EPOCH` are clamped to it, and normalized mtimes are
+// all set to it (or to the epoch when it is unset).
+fn metadata_args(opts: &CompressionOptions) -> Vec<String> {
+    let mut args: Vec<String> = vec![
+        "--format=gnu".into(),
+        "--owner=0".into(),
+        "--group=0".into(),
+        "--numeric-owner".into(),
+    ];
+    let epoch = env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.parse::<u64>().ok());
+    if opts.normalize_mtimes {
+        args.push(format!("--mtime=@{}", epoch.unwrap_or(0)));
+    } else if let Some(e) = epoch {
+        args.push(format!("--mtime=@{}", e));
+        args.push("--clamp-mtime".into());
+    }
+    args
+}
+
+/// Tar up the files and links in a directory with paths relative to it
+///
+/// The tarball is compressed according to its file extension, with the level and
+/// number of threads in `opts`. Mode bits and mtimes of files are recorded, and links
+/// are stored as links rather than what they point to. Entries are sorted by path and
+/// owned by root, so the same files with the same mtimes always give the same tarball.
+pub fn tar_dir(dir: &Path, tarball: &Path, opts: &CompressionOptions) -> LalResult<()> {
     let mut args: Vec<String> = vec![
-        "czf".into(),
+        "cf".into(),
         tarball.to_str().unwrap().into(), // path created internally - always valid unicode
-        "--transform=s,^OUTPUT/,,".into(), // remove leading OUTPUT
+        opts.tar_arg(tarball),
+        "-C".into(),
+        dir.to_str().unwrap().into(),
+        "--transform=s,^\\./,,".into(), // remove leading ./
     ];
+    args.extend(metadata_args(opts));
 
     // Avoid depending on wildcards (which would also hide hidden files)
     // All links, hidden files, and regular files should go into the tarball.
-    let findargs = vec!["OUTPUT/", "-type", "f", "-o", "-type", "l"];
+    let findargs = vec![".", "-type", "f", "-o", "-type", "l"];
     debug!("find {}", findargs.join(" "));
-    let find_output = Command::new("find").args(&findargs).output()?;
+    let find_output = Command::new("find").args(&findargs).current_dir(dir).output()?;
     let find_str = String::from_utf8_lossy(&find_output.stdout);
 
     // append each file as an arg to the main tar process
-    for f in find_str.trim().split('\n') {
+    let mut files = find_str.trim().split('\n').collect::<Vec<_>>();
+    files.sort();
+    for f in files {
         args.push(f.into())
     }
 
-    // basically `tar czf component.tar.gz --transform.. $(find OUTPUT -type f -o -type l)`:
+    // basically `tar cf component.tar.gz -I pigz -C dir --transform.. $(cd dir && find . -type f)`:
     debug!("tar {}", args.join(" "));
     let s = Command::new("tar").args(&args).status()?;
 
     if !s.success() {
-        return Err(CliError::SubprocessFailure(s.code().unwrap_or(1001)));
+        return Err(CliError::SubprocessFailure(exit_code(&s), None));
     }
     Ok(())
 }
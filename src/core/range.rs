@@ -0,0 +1,90 @@
+use std::fmt;
+
+use super::{CliError, LalResult};
+
+/// A range of acceptable versions for a dependency
+///
+/// Ranges are comma separated comparisons on integer versions like `>=120, <130`.
+/// A caret or tilde (`^120`) allows anything from that version up, and `*` allows
+/// any version. A release channel like `@stable` only allows versions promoted to
+/// that channel, and can be combined with comparisons (`@stable, >=120`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct VersionRange {
+    /// Lowest allowed version
+    pub min: u32,
+    /// First version above the range (unbounded if unset)
+    pub max: Option<u32>,
+    /// Release channel the versions must be promoted to (if pinned to one)
+    pub channel: Option<String>,
+    /// The range as it was written
+    pub spec: String,
+}
+
+impl VersionRange {
+    /// Parse a range from its manifest representation
+    pub fn parse(spec: &str) -> LalResult<VersionRange> {
+        let invalid = || CliError::InvalidVersionRange(spec.into());
+        let mut range = VersionRange {
+            min: 0,
+            max: None,
+            channel: None,
+            spec: spec.trim().into(),
+        };
+        for part in spec.split(',').map(|p| p.trim()) {
+            if part == "*" {
+                continue;
+            }
+            if part.starts_with('@') {
+                let channel = &part[1..];
+                if !is_valid_channel(channel) || range.channel.is_some() {
+                    return Err(invalid());
+                }
+                range.channel = Some(channel.into());
+                continue;
+            }
+            let (op, num) = match part.find(|c: char| c.is_digit(10)) {
+                Some(i) => part.split_at(i),
+                None => return Err(invalid()),
+            };
+            let v = num.trim().parse::<u32>().map_err(|_| invalid())?;
+            let above = v.saturating_add(1);
+            let cap = |max: Option<u32>, m: u32| Some(max.map_or(m, |x| x.min(m)));
+            match op.trim() {
+                ">=" | "^" | "~" => range.min = range.min.max(v),
+                ">" => range.min = range.min.max(above),
+                "<" => range.max = cap(range.max, v),
+                "<=" => range.max = cap(range.max, above),
+                "=" | "" => {
+                    range.min = range.min.max(v);
+                    range.max = cap(range.max, above);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        if range.max.map_or(false, |m| m <= range.min) {
+            return Err(invalid());
+        }
+        Ok(range)
+    }
+
+    /// Whether a version is in the range
+    ///
+    /// Channel membership is not known offline, so it is checked when resolving.
+    pub fn matches(&self, version: u32) -> bool {
+        version >= self.min && self.max.map_or(true, |m| version < m)
+    }
+
+    /// The highest of the given versions in the range
+    pub fn highest(&self, versions: &[u32]) -> Option<u32> {
+        versions.iter().cloned().filter(|&v| self.matches(v)).max()
+    }
+}
+
+/// Whether a release channel name is usable (letters, digits, `-` and `_`)
+pub fn is_valid_channel(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", self.spec) }
+}
@@ -0,0 +1,247 @@
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+use chrono::UTC;
+use libc;
+
+#[cfg(feature = "progress")]
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Presentation hooks for long running operations
+///
+/// The library reports what it is doing through this trait, and the caller decides
+/// how to show it. The lal binary uses `TerminalReporter`, while tests and other
+/// embedders can use `SilentReporter` or `RecordingReporter`.
+pub trait ProgressReporter {
+    /// A component is about to be retrieved or unpacked
+    fn start_component(&self, name: &str, version: &str);
+    /// Bytes processed so far for the current component, and the total if known
+    fn bytes(&self, done: u64, total: Option<u64>);
+    /// The current component is done
+    fn finish_component(&self, name: &str);
+    /// A named phase of the current operation started (e.g. "downloading")
+    fn start_phase(&self, phase: &str);
+    /// The current phase is done
+    fn finish_phase(&self, phase: &str);
+    /// A non-fatal problem worth surfacing
+    fn warn(&self, msg: &str);
+}
+
+/// A reporter that discards everything
+#[derive(Default)]
+pub struct SilentReporter;
+
+impl ProgressReporter for SilentReporter {
+    fn start_component(&self, _: &str, _: &str) {}
+    fn bytes(&self, _: u64, _: Option<u64>) {}
+    fn finish_component(&self, _: &str) {}
+    fn start_phase(&self, _: &str) {}
+    fn finish_phase(&self, _: &str) {}
+    fn warn(&self, _: &str) {}
+}
+
+/// A single event seen by a `RecordingReporter`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+    /// Component name and version started
+    Start(String, String),
+    /// Bytes done out of an optional total
+    Bytes(u64, Option<u64>),
+    /// Component name finished
+    Finish(String),
+    /// Phase started
+    Phase(String),
+    /// Phase finished
+    PhaseDone(String),
+    /// Warning message
+    Warn(String),
+}
+
+/// A reporter that records every event for later inspection
+#[derive(Default)]
+pub struct RecordingReporter {
+    /// Events in the order they were reported
+    pub events: RefCell<Vec<ProgressEvent>>,
+}
+
+impl ProgressReporter for RecordingReporter {
+    fn start_component(&self, name: &str, version: &str) {
+        self.events.borrow_mut().push(ProgressEvent::Start(name.into(), version.into()));
+    }
+    fn bytes(&self, done: u64, total: Option<u64>) {
+        self.events.borrow_mut().push(ProgressEvent::Bytes(done, total));
+    }
+    fn finish_component(&self, name: &str) {
+        self.events.borrow_mut().push(ProgressEvent::Finish(name.into()));
+    }
+    fn start_phase(&self, phase: &str) {
+        self.events.borrow_mut().push(ProgressEvent::Phase(phase.into()));
+    }
+    fn finish_phase(&self, phase: &str) {
+        self.events.borrow_mut().push(ProgressEvent::PhaseDone(phase.into()));
+    }
+    fn warn(&self, msg: &str) { self.events.borrow_mut().push(ProgressEvent::Warn(msg.into())); }
+}
+
+/// Reporter for interactive terminal use
+///
+/// Warnings go to the log, and download progress is drawn as a progress bar per
+/// component when the `progress` feature is enabled. Without a terminal (e.g. in CI
+/// logs), download progress is logged as timestamped lines at every tenth instead,
+/// so it is always visible whether lal is working or hung.
+///
+/// A reporter created `with_phases` also prints a status line per phase with its
+/// elapsed time, and reports byte progress for every phase rather than just downloads.
+#[derive(Default)]
+pub struct TerminalReporter {
+    #[cfg(feature = "progress")]
+    bar: RefCell<Option<ProgressBar>>,
+    phases: bool,
+    component: RefCell<Option<String>>,
+    phase: RefCell<Option<(String, Instant)>>,
+    last_tenth: Cell<u64>,
+}
+
+fn secs(d: Duration) -> f64 { d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9 }
+
+fn megabytes(b: u64) -> String { format!("{:.1} MB", b as f64 / 1_048_576.0) }
+
+impl TerminalReporter {
+    /// A terminal reporter that also prints phase status lines
+    pub fn with_phases() -> TerminalReporter {
+        TerminalReporter { phases: true, ..Default::default() }
+    }
+
+    // whether stderr is a terminal
+    fn is_tty(&self) -> bool { unsafe { libc::isatty(2) == 1 } }
+
+    // print a status line, in place on a terminal and timestamped otherwise
+    fn status(&self, msg: &str, done: bool) {
+        let stderr = io::stderr();
+        let mut err = stderr.lock();
+        let _ = if !self.is_tty() {
+            writeln!(err, "[{}] {}", UTC::now().format("%H:%M:%S"), msg)
+        } else if done {
+            writeln!(err, "\r\x1b[K{}", msg)
+        } else {
+            write!(err, "\r\x1b[K{}", msg).and_then(|_| err.flush())
+        };
+    }
+
+    fn phase_name(&self) -> Option<String> { self.phase.borrow().as_ref().map(|p| p.0.clone()) }
+
+    // what the current byte progress belongs to, if it should be shown
+    //
+    // Without a terminal only downloads are logged unless phases were asked for.
+    fn progress_label(&self) -> Option<String> {
+        let shown = |p: &str| self.phases || p == "downloading" || self.is_tty();
+        let phase = self.phase_name();
+        let component = self.component.borrow().clone();
+        match (phase, component) {
+            (Some(ref p), Some(ref c)) if shown(p) => Some(format!("{}: {}", c, p)),
+            (Some(ref p), None) if shown(p) => Some(p.clone()),
+            (None, c) => Some(c.unwrap_or_default()),
+            _ => None,
+        }
+    }
+
+    fn progress_line(label: &str, done: u64, total: Option<u64>) -> String {
+        let line = match total {
+            Some(t) if t > 0 => {
+                format!("{} {}% ({} of {})",
+                        label,
+                        done * 100 / t,
+                        megabytes(done),
+                        megabytes(t))
+            }
+            _ => format!("{} ({})", label, megabytes(done)),
+        };
+        line.trim_left().to_string()
+    }
+
+    // timestamped lines at every tenth (or every 10MB without a total)
+    fn log_bytes(&self, label: &str, done: u64, total: Option<u64>) {
+        let tenth = match total {
+            Some(t) if t > 0 => done * 10 / t,
+            _ => done / (10 * 1_048_576),
+        };
+        if tenth > self.last_tenth.get() {
+            self.last_tenth.set(tenth);
+            self.status(&TerminalReporter::progress_line(label, done, total), true);
+        }
+    }
+
+    #[cfg(feature = "progress")]
+    fn draw_bytes(&self, label: &str, done: u64, total: Option<u64>) {
+        let mut bar = self.bar.borrow_mut();
+        if bar.is_none() {
+            if let Some(t) = total {
+                let pb = ProgressBar::new(t);
+                pb.set_style(ProgressStyle::default_bar()
+                    .template("{msg} {bar:40.yellow/black} {bytes}/{total_bytes} ({eta})"));
+                pb.set_message(label);
+                *bar = Some(pb);
+            }
+        }
+        if let Some(ref pb) = *bar {
+            pb.set_position(done);
+        }
+    }
+    #[cfg(not(feature = "progress"))]
+    fn draw_bytes(&self, label: &str, done: u64, total: Option<u64>) {
+        self.status(&TerminalReporter::progress_line(label, done, total), false);
+    }
+
+    fn finish_bar(&self) {
+        #[cfg(feature = "progress")]
+        {
+            if let Some(pb) = self.bar.borrow_mut().take() {
+                pb.finish();
+            }
+        }
+        self.last_tenth.set(0);
+    }
+}
+
+impl ProgressReporter for TerminalReporter {
+    fn start_component(&self, name: &str, version: &str) {
+        debug!("Starting {} {}", name, version);
+        *self.component.borrow_mut() = Some(format!("{}={}", name, version));
+        self.last_tenth.set(0);
+    }
+
+    fn bytes(&self, done: u64, total: Option<u64>) {
+        if let Some(label) = self.progress_label() {
+            if self.is_tty() {
+                self.draw_bytes(&label, done, total);
+            } else {
+                self.log_bytes(&label, done, total);
+            }
+        }
+    }
+
+    fn finish_component(&self, name: &str) {
+        self.finish_bar();
+        *self.component.borrow_mut() = None;
+        debug!("Finished {}", name);
+    }
+
+    fn start_phase(&self, phase: &str) {
+        self.finish_bar();
+        *self.phase.borrow_mut() = Some((phase.into(), Instant::now()));
+        if self.phases {
+            self.status(phase, false);
+        }
+    }
+
+    fn finish_phase(&self, phase: &str) {
+        self.finish_bar();
+        if let Some((_, start)) = self.phase.borrow_mut().take() {
+            if self.phases {
+                self.status(&format!("{} done in {:.1}s", phase, secs(start.elapsed())), true);
+            }
+        }
+    }
+
+    fn warn(&self, msg: &str) { warn!("{}", msg); }
+}
@@ -0,0 +1,98 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use rand;
+
+use super::{CliError, LalResult, IoResultExt};
+
+/// Signing and signature requirements for published artifacts
+///
+/// Signatures are armored detached gpg signatures stored next to the tarball.
+#[derive(Clone, Debug, Default)]
+pub struct SignaturePolicy {
+    /// Gpg key (id, fingerprint or email) that `lal publish` signs with
+    pub key: Option<String>,
+    /// Armored public key files that signatures are verified against
+    pub public_keys: Vec<String>,
+    /// Reject published artifacts without a valid signature
+    pub required: bool,
+}
+
+impl SignaturePolicy {
+    /// Whether downloads need their signature checked
+    pub fn verifies(&self) -> bool { self.required || !self.public_keys.is_empty() }
+}
+
+/// Location of the detached signature for a file
+pub fn signature_path(pth: &Path) -> PathBuf {
+    let mut name = pth.file_name().unwrap().to_os_string();
+    name.push(".asc");
+    pth.with_file_name(name)
+}
+
+// run gpg, returning its output if it succeeded
+fn gpg(args: &[&str], what: &str) -> LalResult<Output> {
+    trace!("gpg {}", args.join(" "));
+    let out = Command::new("gpg")
+        .arg("--batch")
+        .args(args)
+        .output()
+        .map_err(|e| CliError::SigningFailure(format!("{} (could not run gpg: {})", what, e)))?;
+    if !out.status.success() {
+        debug!("gpg: {}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(out)
+}
+
+/// Create an armored detached signature next to a file with a gpg key
+pub fn sign_file(key: &str, pth: &Path) -> LalResult<PathBuf> {
+    let sig = signature_path(pth);
+    let (p, s) = (pth.to_string_lossy(), sig.to_string_lossy());
+    let args = ["--yes", "--armor", "--local-user", key, "--output", &*s, "--detach-sign", &*p];
+    let out = gpg(&args, &format!("signing {}", p))?;
+    if !out.status.success() {
+        let err = String::from_utf8_lossy(&out.stderr).trim().to_string();
+        return Err(CliError::SigningFailure(format!("signing {} with {}: {}", p, key, err)));
+    }
+    info!("Signed {} with {}", p, key);
+    Ok(sig)
+}
+
+/// Verify a detached signature against a set of trusted public key files
+///
+/// The keys are imported into a throwaway keyring, so the user's keyring and
+/// its trust settings play no part in what is accepted.
+pub fn verify_signature(public_keys: &[String], pth: &Path, sig: &Path) -> LalResult<()> {
+    let name = pth.file_name().unwrap().to_string_lossy().into_owned();
+    if public_keys.is_empty() {
+        warn!("No publicKeys configured to verify the signature of {}", name);
+        return Err(CliError::InvalidSignature(name));
+    }
+    let home = env::temp_dir().join(format!("lal-gpg-{:08x}", rand::random::<u32>()));
+    fs::create_dir_all(&home).context(format!("Failed to create {}", home.display()))?;
+    let res = verify_in(&home, public_keys, pth, sig, &name);
+    let _ = fs::remove_dir_all(&home);
+    res
+}
+
+fn verify_in(home: &Path, keys: &[String], pth: &Path, sig: &Path, name: &str) -> LalResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+    // gpg refuses to use a homedir readable by others
+    fs::set_permissions(home, fs::Permissions::from_mode(0o700))?;
+    let home = home.to_string_lossy();
+    for k in keys {
+        let out = gpg(&["--homedir", &*home, "--import", k.as_str()], &format!("importing {}", k))?;
+        if !out.status.success() {
+            let err = String::from_utf8_lossy(&out.stderr).trim().to_string();
+            return Err(CliError::SigningFailure(format!("importing {}: {}", k, err)));
+        }
+    }
+    let (p, s) = (pth.to_string_lossy(), sig.to_string_lossy());
+    let args = ["--homedir", &*home, "--trust-model", "always", "--verify", &*s, &*p];
+    if !gpg(&args, &format!("verifying {}", name))?.status.success() {
+        return Err(CliError::InvalidSignature(name.into()));
+    }
+    debug!("Verified signature of {}", name);
+    Ok(())
+}
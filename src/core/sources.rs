@@ -0,0 +1,132 @@
+use std::io::prelude::*;
+use std::fs::{File, OpenOptions};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::env;
+use serde_json;
+
+use super::LalResult;
+use manifest::create_lal_subdir;
+
+/// The cache entry an INPUT component was extracted from
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InputSource {
+    /// Path of the cached tarball that was extracted
+    pub path: String,
+    /// SHA1 digest of the cached tarball at extraction time
+    pub sha1: String,
+}
+
+/// Representation of `.lal/sources.json`
+///
+/// Records where each INPUT component came from, so `lal status --paths` can show it,
+/// and `lal clean` can warn before deleting cache entries backing a workspace.
+#[derive(Serialize, Deserialize, Default)]
+pub struct InputSources {
+    /// Component name -> source
+    pub components: BTreeMap<String, InputSource>,
+}
+
+impl InputSources {
+    /// Read `.lal/sources.json` in an arbitrary workspace (empty if missing)
+    pub fn read_from(pwd: &Path) -> LalResult<InputSources> {
+        let pth = pwd.join(".lal").join("sources.json");
+        if !pth.exists() {
+            return Ok(InputSources::default());
+        }
+        let mut data = String::new();
+        File::open(&pth)?.read_to_string(&mut data)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    /// Read `.lal/sources.json` in the current workspace
+    pub fn read() -> LalResult<InputSources> { InputSources::read_from(&env::current_dir()?) }
+
+    /// Overwrite `.lal/sources.json` in the current workspace
+    pub fn write(&self) -> LalResult<()> {
+        let pwd = env::current_dir()?;
+        create_lal_subdir(&pwd)?;
+        let pth = pwd.join(".lal").join("sources.json");
+        let encoded = serde_json::to_string_pretty(self)?;
+        let mut f = File::create(&pth)?;
+        write!(f, "{}\n", encoded)?;
+        debug!("Wrote {}: \n{}", pth.display(), encoded);
+        Ok(())
+    }
+
+    /// Record the source of a freshly extracted component
+    ///
+    /// Also registers the current workspace next to the cache entry so the
+    /// cache can find workspaces still referencing it.
+    pub fn record(component: &str, tarball: &Path, sha1: &str) -> LalResult<()> {
+        let mut sources = InputSources::read()?;
+        sources.components.insert(component.into(),
+                                  InputSource {
+                                      path: tarball.to_string_lossy().into(),
+                                      sha1: sha1.into(),
+                                  });
+        sources.write()?;
+        if let Some(entry) = tarball.parent() {
+            register_workspace(entry, &env::current_dir()?)?;
+        }
+        Ok(())
+    }
+}
+
+fn workspaces_file(entry: &Path) -> PathBuf { entry.join("workspaces") }
+
+// append a workspace to the list of workspaces using a cache entry
+fn register_workspace(entry: &Path, pwd: &Path) -> LalResult<()> {
+    let pwdstr = pwd.to_string_lossy().into_owned();
+    if cache_entry_workspaces(entry)?.contains(&pwdstr) {
+        return Ok(());
+    }
+    let mut f = OpenOptions::new().create(true).append(true).open(workspaces_file(entry))?;
+    write!(f, "{}\n", pwdstr)?;
+    Ok(())
+}
+
+/// Workspaces that have extracted a cache entry at some point
+pub fn cache_entry_workspaces(entry: &Path) -> LalResult<Vec<String>> {
+    let pth = workspaces_file(entry);
+    if !pth.is_file() {
+        return Ok(vec![]);
+    }
+    let f = BufReader::new(File::open(&pth)?);
+    let mut res = vec![];
+    for l in f.lines() {
+        res.push(l?);
+    }
+    Ok(res)
+}
+
+/// Workspace INPUT components still backed by a cache entry directory
+///
+/// Returns `workspace/INPUT/component` strings for workspaces whose
+/// `.lal/sources.json` still point into the entry.
+pub fn cache_entry_users(entry: &Path) -> LalResult<Vec<String>> {
+    let mut res = vec![];
+    for ws in cache_entry_workspaces(entry)? {
+        let wspath = Path::new(&ws);
+        if !wspath.is_dir() {
+            continue;
+        }
+        let sources = InputSources::read_from(wspath).unwrap_or_default();
+        for (name, src) in sources.components {
+            if Path::new(&src.path).starts_with(entry) {
+                res.push(format!("{}/INPUT/{}", ws, name));
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Remove a component from `.lal/sources.json` if it is recorded
+pub fn forget_source(component: &str) -> LalResult<()> {
+    let mut sources = InputSources::read()?;
+    if sources.components.remove(component).is_some() {
+        sources.write()?;
+    }
+    Ok(())
+}
@@ -14,6 +14,9 @@ use manifest::create_lal_subdir;
 pub struct StickyOptions {
     /// Environment to be used implicitally instead of the default
     pub env: Option<String>,
+    /// Dependency groups requested by the last `lal fetch`
+    #[serde(default)]
+    pub groups: Vec<String>,
 }
 
 impl StickyOptions {
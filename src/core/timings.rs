@@ -0,0 +1,160 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::prelude::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use chrono::UTC;
+use serde_json;
+
+use super::LalResult;
+use manifest::create_lal_subdir;
+
+/// Time spent in one phase of a command
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PhaseTiming {
+    /// Name of the phase (e.g. `verify`, `docker pull`, `script`, `packaging`)
+    pub name: String,
+    /// Seconds spent in it (summed over every time the command entered it)
+    pub seconds: f64,
+}
+
+/// Timings of the last run of a command
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct CommandTimings {
+    /// When the command started (UTC)
+    pub started: String,
+    /// What the command worked on (e.g. the build cache key of a build)
+    pub subject: String,
+    /// Whether the command succeeded
+    pub success: bool,
+    /// Seconds from start to finish
+    pub total: f64,
+    /// Phases in the order they were first entered
+    pub phases: Vec<PhaseTiming>,
+}
+
+impl CommandTimings {
+    /// Print a breakdown of where the time went
+    pub fn print(&self) {
+        let width = cmp::max(self.phases.iter().map(|p| p.name.len()).max().unwrap_or(0), 5);
+        println!("Timings for {}:", self.subject);
+        for p in &self.phases {
+            let share = if self.total > 0.0 { 100.0 * p.seconds / self.total } else { 0.0 };
+            println!("  {:width$}  {:>8.1}s  {:>3.0}%",
+                     p.name,
+                     p.seconds,
+                     share,
+                     width = width);
+        }
+        println!("  {:width$}  {:>8.1}s", "total", self.total, width = width);
+    }
+}
+
+/// Representation of `.lal/timings.json`
+///
+/// Holds the timings of the last run of each timed command (`build` and `fetch`).
+#[derive(Serialize, Deserialize, Default)]
+pub struct Timings {
+    /// Timings by command
+    pub commands: BTreeMap<String, CommandTimings>,
+}
+
+impl Timings {
+    /// Read `.lal/timings.json` (empty if it is missing or unreadable)
+    pub fn read() -> LalResult<Timings> {
+        let pth = Path::new(".lal/timings.json");
+        if !pth.exists() {
+            return Ok(Timings::default());
+        }
+        let mut data = String::new();
+        fs::File::open(&pth)?.read_to_string(&mut data)?;
+        Ok(serde_json::from_str(&data).unwrap_or_else(|e| {
+            debug!("Ignoring unparseable {}: {}", pth.display(), e);
+            Timings::default()
+        }))
+    }
+
+    /// Overwrite `.lal/timings.json` with the current timings
+    pub fn write(&self) -> LalResult<()> {
+        let pwd = env::current_dir()?;
+        create_lal_subdir(&pwd)?;
+        let pth = Path::new(".lal/timings.json");
+        let encoded = serde_json::to_string_pretty(self)?;
+        let mut f = fs::File::create(&pth)?;
+        write!(f, "{}\n", encoded)?;
+        debug!("Wrote {}", pth.display());
+        Ok(())
+    }
+}
+
+// seconds with millisecond precision
+fn seconds(d: Duration) -> f64 {
+    let ms = d.as_secs() * 1000 + u64::from(d.subsec_nanos() / 1_000_000);
+    ms as f64 / 1000.0
+}
+
+/// Times the phases of a command as it moves through them
+///
+/// Entering a phase ends the previous one, and phases entered again add to their time.
+pub struct PhaseTimer {
+    command: String,
+    timings: CommandTimings,
+    start: Instant,
+    current: Option<(String, Instant)>,
+}
+
+impl PhaseTimer {
+    /// Start timing a command
+    pub fn start(command: &str, subject: &str) -> PhaseTimer {
+        PhaseTimer {
+            command: command.into(),
+            timings: CommandTimings {
+                started: UTC::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                subject: subject.into(),
+                ..CommandTimings::default()
+            },
+            start: Instant::now(),
+            current: None,
+        }
+    }
+
+    /// Name what the command works on once it is known
+    pub fn subject(&mut self, subject: &str) { self.timings.subject = subject.into(); }
+
+    /// Enter a phase, ending the current one
+    pub fn phase(&mut self, name: &str) {
+        self.end_phase();
+        self.current = Some((name.into(), Instant::now()));
+    }
+
+    fn end_phase(&mut self) {
+        if let Some((name, since)) = self.current.take() {
+            let spent = seconds(since.elapsed());
+            trace!("{} took {}s", name, spent);
+            if let Some(p) = self.timings.phases.iter_mut().find(|p| p.name == name) {
+                p.seconds += spent;
+                return;
+            }
+            self.timings.phases.push(PhaseTiming { name: name, seconds: spent });
+        }
+    }
+
+    /// Stop timing and record the timings of the command in `.lal/timings.json`
+    ///
+    /// Failing to record them is only warned about, as it must not fail the command.
+    pub fn finish(mut self, success: bool) -> CommandTimings {
+        self.end_phase();
+        self.timings.success = success;
+        self.timings.total = seconds(self.start.elapsed());
+        let res = Timings::read().and_then(|mut t| {
+            t.commands.insert(self.command.clone(), self.timings.clone());
+            t.write()
+        });
+        if let Err(e) = res {
+            warn!("Failed to record timings in .lal/timings.json ({})", e);
+        }
+        self.timings
+    }
+}
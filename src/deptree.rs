@@ -0,0 +1,126 @@
+use std::collections::BTreeSet;
+use ansi_term::Colour;
+
+use super::{CliError, DependencyGraph, LalResult, Lockfile, Manifest};
+
+// ascii tree of a lockfile's dependencies, with conflicting versions in red
+fn print_tree(lf: &Lockfile, conflicts: &BTreeSet<String>, indent: &str) {
+    let len = lf.dependencies.len();
+    for (i, (name, sub)) in lf.dependencies.iter().enumerate() {
+        let is_last = i == len - 1;
+        let label = format!("{} {}", name, sub.version);
+        let label = if conflicts.contains(name) {
+            Colour::Red.paint(format!("{} (conflict)", label)).to_string()
+        } else {
+            label
+        };
+        println!("{}{}── {}", indent, if is_last { "└" } else { "├" }, label);
+        let next = format!("{}{}", indent, if is_last { "    " } else { "│   " });
+        print_tree(sub, conflicts, &next);
+    }
+}
+
+// graphviz edges with one node per component version
+fn dot_edges(lf: &Lockfile, conflicts: &BTreeSet<String>, lines: &mut BTreeSet<String>) {
+    let from = format!("{}@{}", lf.name, lf.version);
+    for (name, sub) in &lf.dependencies {
+        let to = format!("{}@{}", name, sub.version);
+        let colour = if conflicts.contains(name) { ", color=red" } else { "" };
+        lines.insert(format!("  \"{}\" [label=\"{}\\n{}\"{}];", to, name, sub.version, colour));
+        lines.insert(format!("  \"{}\" -> \"{}\";", from, to));
+        dot_edges(sub, conflicts, lines);
+    }
+}
+
+/// Render the dependency tree of `./INPUT` in graphviz dot format
+///
+/// Components used at more than one version are drawn in red.
+pub fn dep_tree_dot(lf: &Lockfile) -> String {
+    let conflicts = conflicting(lf);
+    let mut lines = BTreeSet::new();
+    dot_edges(lf, &conflicts, &mut lines);
+    let mut out = format!("digraph \"{}\" {{\n", lf.name);
+    let root = format!("{}@{}", lf.name, lf.version);
+    out.push_str(&format!("  \"{}\" [label=\"{}\", shape=box];\n", root, lf.name));
+    for l in lines {
+        out.push_str(&l);
+        out.push('\n');
+    }
+    out.push_str("}\n");
+    out
+}
+
+// components depended on at more than one version
+fn conflicting(lf: &Lockfile) -> BTreeSet<String> { DependencyGraph::new(lf).conflicts() }
+
+/// Prints the full dependency tree of `./INPUT`
+///
+/// This uses the lockfiles of every component in INPUT, and highlights components
+/// that are used at more than one version in the tree. The `dot` flag prints it
+/// for graphviz instead (e.g. `lal dep-tree --dot | dot -Tsvg > deps.svg`).
+pub fn dep_tree(manifest: &Manifest, dot: bool) -> LalResult<()> {
+    let lf = Lockfile::default().set_name(&manifest.name).populate_from_input()?;
+    if dot {
+        print!("{}", dep_tree_dot(&lf));
+        return Ok(());
+    }
+    let conflicts = conflicting(&lf);
+    println!("{}", lf.name);
+    print_tree(&lf, &conflicts, "");
+    if !conflicts.is_empty() {
+        let names = conflicts.into_iter().collect::<Vec<_>>();
+        warn!("Multiple versions used of {}", names.join(", "));
+    }
+    Ok(())
+}
+
+// collect every path from the root lockfile down to the component
+fn collect_paths(lf: &Lockfile,
+                 component: &str,
+                 version: Option<&str>,
+                 path: &mut Vec<String>,
+                 res: &mut Vec<Vec<String>>) {
+    for (name, sub) in &lf.dependencies {
+        path.push(format!("{}@{}", name, sub.version));
+        if name == component && version.map_or(true, |v| v == sub.version) {
+            res.push(path.clone());
+        }
+        collect_paths(sub, component, version, path, res);
+        path.pop();
+    }
+}
+
+/// Find every dependency path leading to a component in a lockfile tree
+///
+/// Each path starts at the root component and ends at `component` (at `version` if given).
+pub fn dependency_paths(lf: &Lockfile,
+                        component: &str,
+                        version: Option<&str>)
+                        -> Vec<Vec<String>> {
+    let mut res = vec![];
+    let mut path = vec![lf.name.clone()];
+    collect_paths(lf, component, version, &mut path, &mut res);
+    res
+}
+
+/// Explain why a component is in `./INPUT`
+///
+/// Prints every chain of parents that pulls in the component (at `version` if given),
+/// so that the source of multiple versions reported by `verify` can be tracked down.
+pub fn why(manifest: &Manifest, component: &str, version: Option<&str>) -> LalResult<()> {
+    let lf = Lockfile::default().set_name(&manifest.name).populate_from_input()?;
+    let paths = dependency_paths(&lf, component, version);
+    if paths.is_empty() {
+        return Err(CliError::MissingComponent(component.into()));
+    }
+    let mut versions = BTreeSet::new();
+    for p in &paths {
+        println!("{}", p.join(" -> "));
+        versions.insert(p.last().unwrap().clone());
+    }
+    if versions.len() > 1 {
+        let vers = versions.into_iter().collect::<Vec<_>>();
+        warn!("Multiple versions of {} are used: {}", component, vers.join(", "));
+    }
+    Ok(())
+}
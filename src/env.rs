@@ -1,17 +1,125 @@
 use std::process::Command;
 use std::vec::Vec;
 
-use super::{StickyOptions, LalResult, CliError, Container, Config};
+use super::{StickyOptions, LalResult, CliError, Container, Config, ImageUpdatePolicy,
+            exit_code};
 
-/// Pull the current environment from docker
-pub fn update(container: &Container, env: &str) -> LalResult<()> {
+/// Pull the current environment with the container runtime (`lal env sync`)
+pub fn update(cfg: &Config, container: &Container, env: &str) -> LalResult<()> {
     info!("Updating {} container", env);
     let args: Vec<String> = vec!["pull".into(), format!("{}", container)];
-    trace!("Docker pull {}", container);
-    let s = Command::new("docker").args(&args).status()?;
-    trace!("Exited docker");
+    trace!("{} pull {}", cfg.runtime(), container);
+    let s = Command::new(cfg.runtime()).args(&args).status()?;
+    trace!("Exited {}", cfg.runtime());
     if !s.success() {
-        return Err(CliError::SubprocessFailure(s.code().unwrap_or(1001)));
+        return Err(CliError::SubprocessFailure(exit_code(&s), None));
+    }
+    Ok(())
+}
+
+// digests recorded for the local image of a container (empty if never pulled)
+fn local_digests(cfg: &Config, container: &Container) -> LalResult<Vec<String>> {
+    let out = Command::new(cfg.runtime())
+        .args(&["image", "inspect", "--format", "{{join .RepoDigests \" \"}}"])
+        .arg(container.to_string())
+        .output()?;
+    if !out.status.success() {
+        return Ok(vec![]);
+    }
+    Ok(String::from_utf8_lossy(&out.stdout)
+           .split_whitespace()
+           .filter_map(|d| d.split('@').nth(1))
+           .map(String::from)
+           .collect())
+}
+
+/// The digest of the local image of a container (if it was pulled from a registry)
+pub fn image_digest(cfg: &Config, container: &Container) -> LalResult<Option<String>> {
+    Ok(local_digests(cfg, container)?.into_iter().next())
+}
+
+// digest of a container in its registry
+//
+// Docker can look up the manifest via buildx, podman hosts use skopeo instead.
+// Both report the digest of the manifest as the registry serves it, which is
+// what the local image records in its repo digests.
+fn remote_digest(cfg: &Config, container: &Container) -> Option<String> {
+    let out = if cfg.is_podman() {
+        Command::new("skopeo")
+            .args(&["inspect", "--format", "{{.Digest}}"])
+            .arg(format!("docker://{}", container))
+            .output()
+    } else {
+        Command::new(cfg.runtime())
+            .args(&["buildx", "imagetools", "inspect", "--format", "{{.Manifest.Digest}}"])
+            .arg(container.to_string())
+            .output()
+    };
+    let out = out.ok()?;
+    if !out.status.success() {
+        debug!("Could not inspect {} in its registry: {}",
+               container,
+               String::from_utf8_lossy(&out.stderr).trim());
+        return None;
+    }
+    let digest = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    if !digest.starts_with("sha256:") {
+        debug!("Unexpected registry digest for {}: {}", container, digest);
+        return None;
+    }
+    Some(digest)
+}
+
+/// Whether the registry has a different image than the local one for a container
+///
+/// This compares the digest of the image in the registry against the digests of
+/// the local image. It is `None` when either is unknown (e.g. the image was never
+/// pulled, or the registry is unreachable).
+pub fn image_is_stale(cfg: &Config, container: &Container) -> LalResult<Option<bool>> {
+    let local = local_digests(cfg, container)?;
+    if local.is_empty() {
+        debug!("No local digest for {}", container);
+        return Ok(None);
+    }
+    Ok(remote_digest(cfg, container).map(|remote| !local.contains(&remote)))
+}
+
+/// Pull the current environment if its container was updated (`lal env update`)
+///
+/// Pulls unconditionally when staleness cannot be determined.
+pub fn update_if_stale(cfg: &Config, container: &Container, env: &str) -> LalResult<()> {
+    if image_is_stale(cfg, container)? == Some(false) {
+        info!("{} container {} is up to date", env, container);
+        return Ok(());
+    }
+    update(cfg, container, env)
+}
+
+/// Check for an updated container before a build according to `imageUpdates`
+pub fn check_for_update(cfg: &Config, container: &Container, env: &str) -> LalResult<()> {
+    let policy = cfg.imageUpdates;
+    if policy == ImageUpdatePolicy::Off || image_is_stale(cfg, container)? != Some(true) {
+        return Ok(());
+    }
+    match policy {
+        ImageUpdatePolicy::Pull => update(cfg, container, env),
+        _ => {
+            warn!("{} container {} was updated in its registry", env, container);
+            warn!("Run `lal env update` to use the new image");
+            Ok(())
+        }
+    }
+}
+
+/// List the configured environments and their containers
+///
+/// The environment currently in use (from `--env`, `.lal/opts` or the manifest)
+/// is marked with a `*`.
+pub fn list(cfg: &Config, current: &str) -> LalResult<()> {
+    let width = cfg.environments.keys().map(|k| k.len()).max().unwrap_or(0);
+    for (name, container) in &cfg.environments {
+        let marker = if name == current { "*" } else { " " };
+        println!("{} {:width$}  {}", marker, name, container, width = width);
     }
     Ok(())
 }
@@ -1,30 +1,37 @@
 use std::fs;
 use std::path::Path;
+use std::time::Instant;
 
 use storage::CachedBackend;
-use super::{LalResult, CliError};
+use super::{LalResult, CliError, Compression, Manifest, ProgressReporter};
+
+// the environment export needs to be told explicitly
+fn explicit_env(env: Option<&str>) -> LalResult<&str> {
+    env.ok_or_else(|| {
+        error!("export is no longer allowed without an explicit environment");
+        CliError::EnvironmentUnspecified
+    })
+}
 
 /// Export a specific component from the storage backend
+///
+/// Progress is reported per phase (resolving, downloading, storing in cache and
+/// copying to destination), and the total duration is logged at the end.
 pub fn export<T: CachedBackend + ?Sized>(
     backend: &T,
     comp: &str,
     output: Option<&str>,
     _env: Option<&str>,
+    reporter: &ProgressReporter,
 ) -> LalResult<()> {
-    let env = match _env {
-        None => {
-            error!("export is no longer allowed without an explicit environment");
-            return Err(CliError::EnvironmentUnspecified)
-        },
-        Some(e) => e
-    };
-
+    let env = explicit_env(_env)?;
     if comp.to_lowercase() != comp {
         return Err(CliError::InvalidComponentName(comp.into()));
     }
 
     let dir = output.unwrap_or(".");
     info!("Export {} {} to {}", env, comp, dir);
+    let start = Instant::now();
 
     let mut component_name = comp; // this is only correct if no =version suffix
     let tarname = if comp.contains('=') {
@@ -32,7 +39,7 @@ pub fn export<T: CachedBackend + ?Sized>(
         if let Ok(n) = pair[1].parse::<u32>() {
             // standard fetch with an integer version
             component_name = pair[0]; // save so we have sensible tarball names
-            backend.retrieve_published_component(pair[0], Some(n), env)?.0
+            backend.retrieve_published_component(pair[0], Some(n), env, reporter)?.0
         } else {
             // string version -> stash
             component_name = pair[0]; // save so we have sensible tarball names
@@ -40,12 +47,62 @@ pub fn export<T: CachedBackend + ?Sized>(
         }
     } else {
         // fetch without a specific version (latest)
-        backend.retrieve_published_component(comp, None, env)?.0
+        backend.retrieve_published_component(comp, None, env, reporter)?.0
     };
 
-    let dest = Path::new(dir).join(format!("{}.tar.gz", component_name));
+    let compression = Compression::from_path(&tarname);
+    let dest = Path::new(dir).join(compression.tarball_name(component_name));
     debug!("Copying {:?} to {:?}", tarname, dest);
 
-    fs::copy(tarname, dest)?;
+    reporter.start_phase("copying to destination");
+    fs::copy(tarname, &dest)?;
+    reporter.finish_phase("copying to destination");
+    let took = start.elapsed();
+    info!("Exported {} to {} in {}.{:01}s",
+          comp,
+          dest.display(),
+          took.as_secs(),
+          took.subsec_nanos() / 100_000_000);
+    Ok(())
+}
+
+/// Export several components from the storage backend
+///
+/// Each `comp[=version]` is exported as with `export`, into a directory that is
+/// created when missing. Stops at the first component that cannot be exported.
+pub fn export_all<T: CachedBackend + ?Sized>(
+    backend: &T,
+    comps: &[&str],
+    output: Option<&str>,
+    env: Option<&str>,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    let env = explicit_env(env)?;
+    let dir = output.unwrap_or(".");
+    fs::create_dir_all(dir)?;
+    for comp in comps {
+        export(backend, comp, Some(dir), Some(env), reporter)?;
+    }
+    Ok(())
+}
+
+/// Export every dependency of a manifest at its pinned version
+///
+/// Dependencies and devDependencies are exported at the versions the manifest
+/// has for the environment (the manifest's default environment when not given),
+/// e.g. to put together an offline installer.
+pub fn export_manifest<T: CachedBackend + ?Sized>(
+    backend: &T,
+    manifest: &Manifest,
+    output: Option<&str>,
+    env: Option<&str>,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    let env = env.unwrap_or(&manifest.environment);
+    let deps = manifest.for_environment(env).all_dependencies();
+    let comps = deps.iter().map(|(name, v)| format!("{}={}", name, v)).collect::<Vec<_>>();
+    let refs = comps.iter().map(|c| c.as_str()).collect::<Vec<_>>();
+    export_all(backend, &refs, output, Some(env), reporter)?;
+    info!("Exported {} dependencies of {} for {}", refs.len(), manifest.name, env);
     Ok(())
 }
@@ -1,8 +1,202 @@
 use std::fs;
-use std::path::Path;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+use libc;
+use serde_json;
 
-use storage::CachedBackend;
-use super::{CliError, LalResult, Lockfile, Manifest};
+use storage::{Backend, CachedBackend};
+use input;
+use hooks::{run_hook, HookContext};
+use workspace;
+use super::{CliError, LalResult, Lockfile, Manifest, InputSources, ProgressReporter,
+            StickyOptions, PhaseTimer, forget_source, target_location};
+
+/// What to do when an operation would replace a stashed component in INPUT
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StashedPolicy {
+    /// Ask per component on a terminal, otherwise behave like `Fail`
+    Prompt,
+    /// Leave the stashed component in place
+    Keep,
+    /// Replace the stashed component
+    Overwrite,
+    /// Refuse to touch INPUT and list the stashed components
+    Fail,
+}
+
+impl Default for StashedPolicy {
+    fn default() -> Self { StashedPolicy::Prompt }
+}
+
+/// Options shared by `fetch`, `update` and `update_all`
+#[derive(Clone, Default, Debug)]
+pub struct FetchOptions {
+    /// Only fetch core dependencies (`fetch` only)
+    pub core: bool,
+    /// What to do with stashed components in INPUT that would be replaced
+    pub stashed: StashedPolicy,
+    /// Fail rather than warn when a resolved dependency is deprecated
+    pub deny_deprecated: bool,
+    /// Path to write a `FetchReport` to (`fetch` only)
+    pub report: Option<String>,
+    /// Workspace to reuse matching INPUT components from (`fetch` only)
+    pub reuse_from: Option<String>,
+    /// Dependency groups to fetch, or to save updated components into
+    pub groups: Vec<String>,
+    /// Fetch every dependency group (`fetch` only)
+    pub all_groups: bool,
+    /// Remove components in INPUT that are no longer in the manifest (`fetch` only)
+    pub sync: bool,
+    /// Also remove stale stashed components when syncing (`fetch` only)
+    pub include_stashed: bool,
+    /// Save the successfully updated components even if others failed (`update` only)
+    pub partial: bool,
+    /// OUTPUT of workspace members to link dependencies from (`fetch` only)
+    pub siblings: BTreeMap<String, PathBuf>,
+    /// Target to fetch dependencies for (the default target of the component if unset)
+    pub target: Option<String>,
+    /// Run the `pre-fetch` and `post-fetch` hooks (the `runHooks` config setting)
+    pub hooks: bool,
+}
+
+/// The target dependencies are fetched for
+///
+/// This is the target in the options, or else the default target of the main component
+/// if it has a targets list.
+pub fn fetch_target(manifest: &Manifest, opts: &FetchOptions) -> LalResult<Option<String>> {
+    match manifest.components.get(&manifest.name) {
+        Some(c) => c.resolve_target(opts.target.clone()),
+        None => Ok(opts.target.clone()),
+    }
+}
+
+/// Components published for a target in its `<env>+<target>` location
+///
+/// Empty without a target, or when nothing was published for the target yet. Failing to
+/// list the location is an error, so that targeted builds never silently fall back to
+/// the components of the environment. Listing it once is enough for `component_location`.
+pub fn target_components<T: Backend + ?Sized>(
+    backend: &T,
+    env: &str,
+    target: Option<&str>,
+) -> LalResult<BTreeSet<String>> {
+    match target {
+        Some(t) => {
+            let loc = target_location(env, Some(t));
+            Ok(backend.get_components(&loc)?.into_iter().collect())
+        }
+        None => Ok(BTreeSet::new()),
+    }
+}
+
+/// Where a dependency is published for a target
+///
+/// Components published for the target (see `target_components`) are found in its
+/// location, and target independent components (never published for it) in the
+/// environment.
+pub fn component_location(
+    published: &BTreeSet<String>,
+    name: &str,
+    env: &str,
+    target: Option<&str>,
+) -> String {
+    if let Some(t) = target {
+        if published.contains(name) {
+            return target_location(env, Some(t));
+        }
+        debug!("{} is not published for {} - using {}", name, t, env);
+    }
+    env.into()
+}
+
+/// Warn about deprecated components among resolved name/version pairs
+///
+/// With `deny` set, any deprecated component is an error listing all of them.
+pub fn check_deprecations<T: Backend + ?Sized>(
+    backend: &T,
+    resolved: &[(String, u32)],
+    deny: bool,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    if resolved.is_empty() {
+        return Ok(());
+    }
+    let deprecations = backend.get_deprecations()?;
+    let mut deprecated = vec![];
+    for &(ref name, version) in resolved {
+        if let Some(msg) = deprecations.notice(name, version) {
+            reporter.warn(&msg);
+            deprecated.push(format!("{}={}", name, version));
+        }
+    }
+    if deny && !deprecated.is_empty() {
+        return Err(CliError::DeprecatedDependencies(deprecated.join(", ")));
+    }
+    Ok(())
+}
+
+// whether stdin is a terminal we can ask questions on
+fn stdin_is_tty() -> bool { unsafe { libc::isatty(0) == 1 } }
+
+fn confirm_overwrite(name: &str, version: &str) -> LalResult<bool> {
+    print!("Replace stashed {} ({})? [y/N] ", name, version);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_lowercase().starts_with('y'))
+}
+
+/// Stashed components currently in INPUT mapped to their stash names
+pub fn stashed_in_input() -> LalResult<BTreeMap<String, String>> {
+    let lf = Lockfile::default().populate_from_input_shallow()?;
+    Ok(lf.dependencies
+        .into_iter()
+        .filter(|&(_, ref d)| d.version.parse::<u32>().is_err())
+        .map(|(n, d)| (n, d.version))
+        .collect())
+}
+
+/// Decide which stashed components to keep among those about to be replaced
+///
+/// Returns the names of the components that must be left alone.
+pub fn resolve_stashed(
+    conflicts: &BTreeMap<String, String>,
+    policy: StashedPolicy,
+) -> LalResult<BTreeSet<String>> {
+    let policy = if policy == StashedPolicy::Prompt && !stdin_is_tty() {
+        debug!("Not on a terminal - refusing to replace stashed components");
+        StashedPolicy::Fail
+    } else {
+        policy
+    };
+    let mut kept = BTreeSet::new();
+    if conflicts.is_empty() {
+        return Ok(kept);
+    }
+    if policy == StashedPolicy::Fail {
+        for (name, ver) in conflicts {
+            warn!("INPUT/{} is stashed ({}) and would be replaced", name, ver);
+        }
+        let names = conflicts.keys().cloned().collect::<Vec<_>>();
+        return Err(CliError::StashedConflict(names.join(", ")));
+    }
+    for (name, ver) in conflicts {
+        let keep = match policy {
+            StashedPolicy::Keep => true,
+            StashedPolicy::Prompt => !confirm_overwrite(name, ver)?,
+            _ => false,
+        };
+        if keep {
+            info!("Keeping stashed {} ({})", name, ver);
+            kept.insert(name.clone());
+        } else {
+            info!("Overwriting stashed {} ({})", name, ver);
+        }
+    }
+    Ok(kept)
+}
 
 fn clean_input() {
     let input = Path::new("./INPUT");
@@ -11,95 +205,543 @@ fn clean_input() {
     }
 }
 
+/// A single dependency in a `FetchReport`
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FetchReportEntry {
+    /// Component name
+    pub name: String,
+    /// Version requested by the manifest
+    pub requested: Option<u32>,
+    /// Version (or stash name) present in INPUT afterwards
+    pub resolved: Option<String>,
+    /// Environment the component was fetched for
+    pub environment: String,
+    /// One of `fetched`, `reused`, `linked`, `sibling`, `kept` or `failed`
+    pub status: String,
+    /// SHA1 of the tarball that was extracted (if known)
+    pub sha1: Option<String>,
+    /// Bytes downloaded from the backend (zero on cache hits)
+    pub bytes: u64,
+    /// Whether the tarball was already in the cache (only set when fetched)
+    pub cacheHit: Option<bool>,
+    /// Time spent retrieving and extracting in milliseconds
+    pub durationMs: u64,
+    /// Error string when the component failed
+    pub error: Option<String>,
+}
+
+/// Representation of the report written by `lal fetch --report`
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FetchReport {
+    /// Version of lal performing the fetch
+    pub lalVersion: String,
+    /// Name of the component in the manifest
+    pub manifest: String,
+    /// Environment fetched for
+    pub environment: String,
+    /// Target fetched for (if any)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Whether the fetch as a whole succeeded
+    pub success: bool,
+    /// One entry per dependency
+    pub components: Vec<FetchReportEntry>,
+}
+
+impl FetchReport {
+    fn new(manifest: &Manifest, env: &str) -> FetchReport {
+        FetchReport {
+            lalVersion: env!("CARGO_PKG_VERSION").into(),
+            manifest: manifest.name.clone(),
+            environment: env.into(),
+            target: None,
+            success: false,
+            components: vec![],
+        }
+    }
+
+    fn entry(&self, name: &str, requested: Option<u32>, status: &str) -> FetchReportEntry {
+        FetchReportEntry {
+            name: name.into(),
+            requested: requested,
+            resolved: None,
+            environment: self.environment.clone(),
+            status: status.into(),
+            sha1: None,
+            bytes: 0,
+            cacheHit: None,
+            durationMs: 0,
+            error: None,
+        }
+    }
+
+    /// Write the report to a path
+    pub fn write(&self, pth: &Path) -> LalResult<()> {
+        let encoded = serde_json::to_string_pretty(self)?;
+        let mut f = fs::File::create(pth)?;
+        write!(f, "{}\n", encoded)?;
+        debug!("Wrote fetch report {}", pth.display());
+        Ok(())
+    }
+}
+
+/// Hardlink a directory tree into place, copying when linking is impossible
+pub fn link_tree(src: &Path, dest: &Path) -> LalResult<()> {
+    use walkdir::WalkDir;
+    use std::os::unix::fs::symlink;
+    for e in WalkDir::new(src) {
+        let e = e.map_err(|e| CliError::Io(e.into()))?;
+        let target = dest.join(e.path().strip_prefix(src).unwrap());
+        let ft = e.file_type();
+        if ft.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if ft.is_symlink() {
+            symlink(fs::read_link(e.path())?, &target)?;
+        } else if fs::hard_link(e.path(), &target).is_err() {
+            fs::copy(e.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+// link components from another workspace's INPUT when they match what we need
+//
+// Returns the names of the components that were linked. The other workspace
+// is only ever read from.
+fn reuse_from_workspace(
+    other: &Path,
+    deps: &BTreeMap<String, u32>,
+    env: &str,
+    target: Option<&str>,
+    rep: &mut FetchReport,
+) -> LalResult<Vec<String>> {
+    let mut linked = vec![];
+    if !other.join("INPUT").is_dir() {
+        warn!("No INPUT in {} to reuse", other.display());
+        return Ok(linked);
+    }
+    if fs::canonicalize(other)? == fs::canonicalize(".")? {
+        warn!("Not reusing INPUT from the current workspace");
+        return Ok(linked);
+    }
+    let sources = InputSources::read_from(other)?;
+    for (name, &v) in deps {
+        let srcdir = other.join("INPUT").join(name);
+        let lf = match Lockfile::from_path_shallow(&srcdir.join("lockfile.json"), name) {
+            Ok(lf) => lf,
+            Err(_) => continue,
+        };
+        if lf.version.parse::<u32>().ok() != Some(v) || lf.environment != env ||
+            !fits_target(&lf, target)
+        {
+            debug!("Not reusing {} {} ({}) from {}",
+                   name,
+                   lf.version,
+                   lf.environment,
+                   other.display());
+            continue;
+        }
+        let start = Instant::now();
+        let staging = input::staging_dir(name)?;
+        if let Err(e) = link_tree(&srcdir, &staging).and_then(|_| input::commit_staged(name)) {
+            warn!("Failed to reuse {} from {} ({})", name, other.display(), e);
+            let _ = fs::remove_dir_all(&staging);
+            continue;
+        }
+        info!("Reuse {} {} {} from {}", env, name, v, other.display());
+        let mut entry = rep.entry(name, Some(v), "linked");
+        entry.resolved = Some(v.to_string());
+        match sources.components.get(name) {
+            Some(src) => {
+                InputSources::record(name, Path::new(&src.path), &src.sha1)?;
+                entry.sha1 = Some(src.sha1.clone());
+            }
+            None => forget_source(name)?,
+        }
+        entry.durationMs = millis(start.elapsed());
+        rep.components.push(entry);
+        linked.push(name.clone());
+    }
+    Ok(linked)
+}
+
+// whether a component in INPUT was built for the target (or for no target at all)
+fn fits_target(lf: &Lockfile, target: Option<&str>) -> bool {
+    lf.target.is_none() || lf.target.as_ref().map(|t| t.as_str()) == target
+}
+
+fn millis(d: Duration) -> u64 { d.as_secs() * 1000 + (d.subsec_nanos() / 1_000_000) as u64 }
+
 /// Fetch all dependencies from `manifest.json`
 ///
 /// This will read, and HTTP GET all the dependencies at the specified versions.
-/// If the `core` bool is set, then `devDependencies` are not installed.
-pub fn fetch<T: CachedBackend + ?Sized>(
+/// If the `core` option is set, then `devDependencies` are not installed.
+/// Members of dependency groups are only installed when their group is requested,
+/// and the requested groups are remembered in `.lal/opts` for `verify` and `status`.
+///
+/// Dependencies are fetched for the target from `fetch_target`, falling back to the
+/// environment for dependencies that are not published for it.
+///
+/// Components in INPUT that are no longer in the manifest are listed as stale.
+/// They are removed when `sync` is set (or `pruneExtraneous` is set in the manifest),
+/// and stale stashed components additionally need `include_stashed`.
+/// Stashed components that would be replaced or removed are handled by `stashed`,
+/// and deprecated dependencies are warned about (or denied). Kept stashed components
+/// are checked against their lockfile to warn when built in another environment.
+///
+/// If a `report` path is given, a `FetchReport` is written there even if the fetch
+/// fails. Failing to write the report only warns.
+///
+/// The `pre-fetch` and `post-fetch` hooks run around a fetch, the latter only on success.
+/// How long the fetch and its hooks took is recorded in `.lal/timings.json`.
+pub fn fetch<T: CachedBackend + Backend + ?Sized>(
+    manifest: &Manifest,
+    backend: &T,
+    opts: &FetchOptions,
+    env: &str,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    let ctx = HookContext {
+        enabled: opts.hooks,
+        component: manifest.name.clone(),
+        environment: Some(env.into()),
+        ..Default::default()
+    };
+    let mut timer = PhaseTimer::start("fetch", &format!("{}/{}", manifest.name, env));
+    timer.phase("hooks");
+    run_hook("pre-fetch", &ctx)?;
+    let mut rep = FetchReport::new(manifest, env);
+    timer.phase("fetch");
+    let res = fetch_input(manifest, backend, opts, env, reporter, &mut rep);
+    if let Some(ref pth) = opts.report {
+        rep.success = res.is_ok();
+        rep.components.sort_by(|a, b| a.name.cmp(&b.name));
+        let _ = rep.write(Path::new(pth)).map_err(|e| {
+            warn!("Failed to write fetch report {} ({})", pth, e);
+        });
+    }
+    let res = res.and_then(|_| {
+        timer.phase("hooks");
+        run_hook("post-fetch", &ctx)
+    });
+    timer.finish(res.is_ok());
+    res
+}
+
+fn fetch_input<T: CachedBackend + Backend + ?Sized>(
     manifest: &Manifest,
     backend: &T,
-    core: bool,
+    opts: &FetchOptions,
     env: &str,
+    reporter: &ProgressReporter,
+    rep: &mut FetchReport,
 ) -> LalResult<()> {
     // first ensure manifest is sane:
     manifest.verify()?;
+    let target = fetch_target(manifest, opts)?;
+    rep.target = target.clone();
+    let target = target.as_ref().map(|t| t.as_str());
+    let published = target_components(backend, env, target)?;
+    // resolve versions for this environment
+    let env_manifest = manifest.for_environment(env);
+    let manifest = &env_manifest;
 
     debug!("Installing dependencies{}",
-           if !core { " and devDependencies" } else { "" });
+           if !opts.core { " and devDependencies" } else { "" });
 
     // create the joined hashmap of dependencies and possibly devdependencies
     let mut deps = manifest.dependencies.clone();
-    if !core {
+    if !opts.core {
         for (k, v) in &manifest.devDependencies {
             deps.insert(k.clone(), *v);
         }
     }
-    let mut extraneous = vec![]; // stuff we should remove
+    // skip members of groups that were not asked for
+    for g in &opts.groups {
+        if !manifest.groups.contains_key(g) {
+            return Err(CliError::MissingGroup(g.clone()));
+        }
+    }
+    let excluded = manifest.excluded_group_members(&opts.groups, opts.all_groups);
+    for name in &excluded {
+        if deps.remove(name).is_some() {
+            debug!("Skipping {} in group {}", name, manifest.group_of(name).unwrap());
+        }
+    }
+    // workspace members are linked from their OUTPUT rather than fetched
+    let from_siblings = deps.keys()
+        .filter(|n| opts.siblings.contains_key(*n))
+        .cloned()
+        .collect::<Vec<_>>();
+    for name in &from_siblings {
+        deps.remove(name);
+    }
+    // pick the highest published version for dependencies given as ranges
+    for (name, v) in deps.iter_mut() {
+        if let Some(range) = manifest.version_range(name) {
+            let loc = component_location(&published, name, env, target);
+            let versions = match range.channel {
+                Some(ref c) => backend.get_channel_versions(name, c, &loc)?,
+                None => backend.get_versions(name, &loc)?,
+            };
+            *v = range.highest(&versions)
+                .ok_or_else(|| CliError::NoMatchingVersion(name.clone(), range.to_string()))?;
+            info!("Resolved {} {} to {}", name, range, v);
+        }
+    }
+    let mut sticky = StickyOptions::read()?;
+    sticky.groups = if opts.all_groups {
+        manifest.groups.keys().cloned().collect()
+    } else {
+        opts.groups.clone()
+    };
+    sticky.write()?;
+
+    let resolved = deps.iter().map(|(k, v)| (k.clone(), *v)).collect::<Vec<_>>();
+    check_deprecations(backend, &resolved, opts.deny_deprecated, reporter)?;
+    let prune = opts.sync || manifest.pruneExtraneous;
+    let prune_stashed = prune && opts.include_stashed;
+    let all_deps = manifest.all_dependencies();
+    let mut stale = vec![]; // published things no longer in the manifest
+    let mut stale_stashed = vec![]; // stashed things no longer in the manifest
 
     // figure out what we have already
     let lf = Lockfile::default()
-        .populate_from_input()
+        .populate_from_input_shallow()
         .map_err(|e| {
             // Guide users a bit if they did something dumb - see #77
             warn!("Populating INPUT data failed - your INPUT may be corrupt");
-            warn!("This can happen if you CTRL-C during `lal fetch`");
             warn!("Try to `rm -rf INPUT` and `lal fetch` again.");
             e
         })?;
+    // stashed components we are about to replace or remove need a decision
+    let conflicts: BTreeMap<String, String> = lf.dependencies
+        .iter()
+        .filter(|&(_, d)| d.version.parse::<u32>().is_err())
+        .filter(|&(n, _)| deps.contains_key(n) || (prune_stashed && !all_deps.contains_key(n)))
+        .map(|(n, d)| (n.clone(), d.version.clone()))
+        .collect();
+    let kept = resolve_stashed(&conflicts, opts.stashed)?;
+    let sources = InputSources::read()?;
+
     // filter out what we already have (being careful to examine env)
     for (name, d) in lf.dependencies {
+        if excluded.contains(&name) || from_siblings.contains(&name) {
+            continue; // unrequested group members are left as they are
+        }
+        if kept.contains(&name) {
+            if d.environment != env {
+                warn!("Stashed {} was built in {} rather than {} - verify will fail",
+                      name,
+                      d.environment,
+                      env);
+            }
+            let mut entry = rep.entry(&name, deps.remove(&name), "kept");
+            entry.resolved = Some(d.version.clone());
+            entry.sha1 = sources.components.get(&name).map(|s| s.sha1.clone());
+            rep.components.push(entry);
+            continue;
+        }
         // if d.name at d.version in d.environment matches something in deps
         if let Some(&cand) = deps.get(&name) {
             // version found in manifest
             // ignore non-integer versions (stashed things must be overwritten)
             if let Ok(n) = d.version.parse::<u32>() {
-                if n == cand && d.environment == env {
+                if n == cand && d.environment == env && fits_target(&d, target) {
                     info!("Reuse {} {} {}", env, name, n);
                     deps.remove(&name);
+                    let mut entry = rep.entry(&name, Some(n), "reused");
+                    entry.resolved = Some(n.to_string());
+                    entry.sha1 = sources.components.get(&name).map(|s| s.sha1.clone());
+                    rep.components.push(entry);
                 }
             }
+        } else if all_deps.contains_key(&name) {
+            debug!("Leaving {} in INPUT (not requested in this fetch)", name);
+        } else if d.version.parse::<u32>().is_err() {
+            stale_stashed.push(name.clone());
         } else {
-            extraneous.push(name.clone());
+            stale.push(name.clone());
+        }
+    }
+
+    if let Some(ref other) = opts.reuse_from {
+        fs::create_dir_all("./INPUT")?;
+        let linked = reuse_from_workspace(Path::new(other), &deps, env, target, rep)?;
+        info!("Reused {} components from {}, fetching {}",
+              linked.len(),
+              other,
+              deps.len() - linked.len());
+        for name in linked {
+            deps.remove(&name);
         }
     }
 
+    for name in workspace::link_siblings(&from_siblings, &opts.siblings)? {
+        let mut entry = rep.entry(&name, all_deps.get(&name).cloned(), "sibling");
+        let lockpth = Path::new("./INPUT").join(&name).join("lockfile.json");
+        entry.resolved = Lockfile::from_path_shallow(&lockpth, &name).ok().map(|lf| lf.version);
+        rep.components.push(entry);
+    }
+
     let mut err = None;
     for (k, v) in deps {
-        info!("Fetch {} {} {}", env, k, v);
-
-        // first kill the folders we actually need to fetch:
-        let cmponent_dir = Path::new("./INPUT").join(&k);
-        if cmponent_dir.is_dir() {
-            // Don't think this can fail, but we are dealing with NFS
-            fs::remove_dir_all(&cmponent_dir)
-                .map_err(|e| {
-                    warn!("Failed to remove INPUT/{} - {}", k, e);
-                    warn!("Please clean out your INPUT folder yourself to avoid corruption");
-                    e
-                })?;
-        }
-
-        let _ = backend.unpack_published_component(&k, Some(v), env).map_err(|e| {
-            warn!("Failed to completely install {} ({})", k, e);
-            // likely symlinks inside tarball that are being dodgy
-            // this is why we clean_input
-            err = Some(e);
-        });
+        let loc = component_location(&published, &k, env, target);
+        info!("Fetch {} {} {}", loc, k, v);
+
+        // NB: the old INPUT/k is replaced only once the new one is fully unpacked
+        let mut entry = rep.entry(&k, Some(v), "fetched");
+        let hit = backend.is_published_cached(&k, v, &loc);
+        let start = Instant::now();
+        reporter.start_component(&k, &v.to_string());
+        match backend.unpack_published_component(&k, Some(v), &loc, reporter) {
+            Ok(c) => {
+                entry.resolved = Some(c.version.to_string());
+                entry.cacheHit = Some(hit);
+                if let Some(src) = InputSources::read()?.components.get(&k) {
+                    entry.sha1 = Some(src.sha1.clone());
+                    if !hit {
+                        entry.bytes = fs::metadata(&src.path).map(|m| m.len()).unwrap_or(0);
+                    }
+                }
+            }
+            Err(e) => {
+                reporter.warn(&format!("Failed to completely install {} ({})", k, e));
+                // likely symlinks inside tarball that are being dodgy
+                // this is why we clean_input
+                entry.status = "failed".into();
+                entry.error = Some(e.to_string());
+                err = Some(e);
+            }
+        }
+        reporter.finish_component(&k);
+        entry.durationMs = millis(start.elapsed());
+        rep.components.push(entry);
+    }
+
+    prune_stale(&stale, prune, "")?;
+    if prune && !prune_stashed && !stale_stashed.is_empty() {
+        warn!("Stale stashed components: {}", stale_stashed.join(", "));
+        warn!("Use `lal fetch --sync --include-stashed` to remove them as well");
+    } else {
+        prune_stale(&stale_stashed, prune_stashed, "stashed ")?;
     }
 
-    // remove extraneous deps
-    for name in extraneous {
-        info!("Remove {}", name);
-        let pth = Path::new("./INPUT").join(&name);
+    if err.is_some() {
+        warn!("Cleaning potentially broken INPUT");
+        clean_input(); // don't want to risk having users in corrupted states
+        return Err(CliError::InstallFailure);
+    }
+    Ok(())
+}
+
+// remove (or just list) components in INPUT that are no longer in the manifest
+fn prune_stale(names: &[String], remove: bool, kind: &str) -> LalResult<()> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    if !remove {
+        warn!("Stale {}components in INPUT: {}", kind, names.join(", "));
+        warn!("These are no longer in the manifest - use `lal fetch --sync` to remove them");
+        return Ok(());
+    }
+    for name in names {
+        debug!("Remove {}", name);
+        let pth = Path::new("./INPUT").join(name);
         if pth.is_dir() {
             fs::remove_dir_all(&pth)?;
         }
+        forget_source(name)?;
+    }
+    info!("Pruned {} stale {}components: {}", names.len(), kind, names.join(", "));
+    Ok(())
+}
+
+// collect differences between a recorded dependency tree and what is in INPUT
+fn diverging(expected: &Lockfile, actual: &Lockfile, path: &str, out: &mut Vec<String>) {
+    for (name, exp) in &expected.dependencies {
+        let here = format!("{}{}", path, name);
+        match actual.dependencies.get(name) {
+            None => out.push(format!("{} is missing", here)),
+            Some(act) => {
+                if act.version != exp.version || act.location() != exp.location() {
+                    out.push(format!("{} is {} ({}) but {} ({}) was recorded",
+                                     here,
+                                     act.version,
+                                     act.location(),
+                                     exp.version,
+                                     exp.location()));
+                }
+                diverging(exp, act, &format!("{} -> ", here), out);
+            }
+        }
+    }
+    for name in actual.dependencies.keys() {
+        if !expected.dependencies.contains_key(name) {
+            out.push(format!("{}{} was not recorded", path, name));
+        }
+    }
+}
+
+/// Fetch the first level dependencies of a lockfile into INPUT
+///
+/// This ignores the manifest and reproduces INPUT exactly as recorded in `lockpath`,
+/// fetching each dependency at its recorded version, environment and target. Anything else
+/// in INPUT is removed. The resulting tree is verified against the full recorded tree.
+///
+/// Stashed (non-numeric) versions cannot be reproduced and are rejected up front.
+pub fn fetch_lockfile<T: CachedBackend + Backend + ?Sized>(
+    backend: &T,
+    lockpath: &str,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    let lock = Lockfile::from_path(&Path::new(lockpath).to_path_buf(), lockpath)?;
+
+    let mut deps = BTreeMap::new();
+    for (name, dep) in &lock.dependencies {
+        let v = dep.version.parse::<u32>().map_err(|_| {
+            CliError::UnreproducibleStash(name.clone(), dep.version.clone())
+        })?;
+        deps.insert(name.clone(), (v, dep.location()));
+    }
+    let resolved = deps.iter().map(|(k, &(v, _))| (k.clone(), v)).collect::<Vec<_>>();
+    check_deprecations(backend, &resolved, false, reporter)?;
+
+    // clear out everything not in the lockfile
+    for name in Lockfile::default().populate_from_input_shallow()?.dependencies.keys() {
+        if !deps.contains_key(name) {
+            info!("Remove {}", name);
+            fs::remove_dir_all(Path::new("./INPUT").join(name))?;
+            forget_source(name)?;
+        }
     }
 
+    let mut err = None;
+    for (name, &(v, ref loc)) in &deps {
+        info!("Fetch {} {} {}", loc, name, v);
+        reporter.start_component(name, &v.to_string());
+        let _ = backend.unpack_published_component(name, Some(v), loc, reporter).map_err(|e| {
+            reporter.warn(&format!("Failed to completely install {} ({})", name, e));
+            err = Some(e);
+        });
+        reporter.finish_component(name);
+    }
     if err.is_some() {
         warn!("Cleaning potentially broken INPUT");
-        clean_input(); // don't want to risk having users in corrupted states
+        clean_input();
         return Err(CliError::InstallFailure);
     }
+
+    let actual = Lockfile::default().populate_from_input()?;
+    let mut diffs = vec![];
+    diverging(&lock, &actual, "", &mut diffs);
+    for d in &diffs {
+        warn!("{}", d);
+    }
+    if !diffs.is_empty() {
+        return Err(CliError::LockfileDivergence(lockpath.into()));
+    }
     Ok(())
 }
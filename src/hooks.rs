@@ -0,0 +1,66 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+use super::{CliError, LalResult, IoResultExt, exit_code};
+
+/// Values passed to a hook through environment variables
+///
+/// Hooks get `LAL_HOOK` and `LAL_COMPONENT`, and `LAL_VERSION` and `LAL_ENVIRONMENT`
+/// when they are known for the command.
+#[derive(Default)]
+pub struct HookContext {
+    /// Whether hooks run at all (the `runHooks` config setting)
+    pub enabled: bool,
+    /// Component the command runs for
+    pub component: String,
+    /// Version built, stashed or published
+    pub version: Option<String>,
+    /// Environment the command runs in
+    pub environment: Option<String>,
+}
+
+/// Run the hook `.lal/hooks/<name>` if it exists
+///
+/// Hooks are executables run from the component directory around `fetch`, `build`,
+/// `stash` and `publish` (e.g. `post-fetch` or `pre-publish`). A failing `pre-` hook
+/// aborts the command, while a failing `post-` hook only warns as the work is done.
+/// Hook files that are not executable are skipped with a warning.
+///
+/// Hooks come with the repository but run on the host, so they are skipped with a
+/// warning unless `runHooks` is enabled in the config.
+pub fn run_hook(name: &str, ctx: &HookContext) -> LalResult<()> {
+    let pth = Path::new(".lal").join("hooks").join(name);
+    if !pth.is_file() {
+        return Ok(());
+    }
+    if !ctx.enabled {
+        warn!("Skipping hook {} as runHooks is not enabled in the config", pth.display());
+        return Ok(());
+    }
+    let mode = pth.metadata().context(format!("Failed to read hook {}", pth.display()))?
+        .permissions()
+        .mode();
+    if mode & 0o111 == 0 {
+        warn!("Skipping hook {} as it is not executable", pth.display());
+        return Ok(());
+    }
+    info!("Running {} hook", name);
+    let mut cmd = Command::new(&pth);
+    cmd.env("LAL_HOOK", name).env("LAL_COMPONENT", &ctx.component);
+    if let Some(ref v) = ctx.version {
+        cmd.env("LAL_VERSION", v);
+    }
+    if let Some(ref e) = ctx.environment {
+        cmd.env("LAL_ENVIRONMENT", e);
+    }
+    let s = cmd.status().context(format!("Failed to run hook {}", pth.display()))?;
+    if !s.success() {
+        let code = exit_code(&s);
+        if name.starts_with("pre-") {
+            return Err(CliError::HookFailure(name.into(), code));
+        }
+        warn!("The {} hook exited with {}", name, code);
+    }
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use tar::Archive;
+
+// Need both the struct and the trait
+use storage::{Backend, CachedBackend};
+use publish::{upload, PublishOptions};
+use validate::is_canonical_name;
+use super::{LalResult, CliError, IoResultExt, Lockfile, Compression, Container, output,
+            unpack_safely};
+
+// unpack a tarball into a directory (compressed as its extension says)
+fn unpack(tarball: &Path, dest: &Path) -> LalResult<()> {
+    let file = fs::File::open(tarball).context(format!("Failed to open {}", tarball.display()))?;
+    let compression = Compression::from_path(tarball);
+    let decoder = compression.decoder(file).map_err(|_| {
+        let ext = compression.extension();
+        CliError::InvalidImport(format!("{} is not a {} file", tarball.display(), ext))
+    })?;
+    unpack_safely(&mut Archive::new(decoder), dest).map_err(|e| match e {
+        e @ CliError::MaliciousArchive(..) => e,
+        e => {
+            let reason = format!("{} is not a valid tarball ({})", tarball.display(), e);
+            CliError::InvalidImport(reason)
+        }
+    })
+}
+
+// the directory holding the component: a lone top level directory (like `sdk-1.2/`) is stripped
+fn content_root(dir: &Path) -> LalResult<PathBuf> {
+    if dir.join("lockfile.json").is_file() {
+        return Ok(dir.to_path_buf());
+    }
+    let entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    if entries.is_empty() {
+        return Err(CliError::InvalidImport("the tarball is empty".into()));
+    }
+    if entries.len() == 1 && entries[0].file_type()?.is_dir() {
+        debug!("Stripping top level directory {}", entries[0].file_name().to_string_lossy());
+        return Ok(entries[0].path());
+    }
+    Ok(dir.to_path_buf())
+}
+
+// write the lockfile of the imported component, synthesizing one if the tarball has none
+fn stamp_lockfile(root: &Path, name: &str, version: u32, env: &str) -> LalResult<Lockfile> {
+    let lfpth = root.join("lockfile.json");
+    let mut lf = if lfpth.is_file() {
+        let lf = Lockfile::from_path(&lfpth, name)?;
+        if lf.name != name {
+            return Err(CliError::InvalidImport(format!("it has a lockfile for {}", lf.name)));
+        }
+        if lf.version != version.to_string() || lf.environment != env {
+            warn!("Restamping lockfile of {} {} in {} as {} in {}",
+                  name,
+                  lf.version,
+                  lf.environment,
+                  version,
+                  env);
+        }
+        fs::remove_file(&lfpth).context(format!("Failed to remove {}", lfpth.display()))?;
+        lf
+    } else {
+        info!("Synthesizing a lockfile for {}", name);
+        Lockfile::new(name, &Container::default(), env, None, None)
+    };
+    lf.version = version.to_string();
+    lf.environment = env.into();
+    let lf = lf.attach_contents(output::contents_digest(root)?);
+    lf.write(&lfpth)?;
+    Ok(lf)
+}
+
+// publish the repacked tarball through an ARTIFACT dir assembled for it
+fn publish_import<T: Backend + ?Sized>(
+    backend: &T,
+    name: &str,
+    version: u32,
+    lock: &Lockfile,
+    tarball: &Path,
+) -> LalResult<()> {
+    let artdir = Path::new("./ARTIFACT");
+    if artdir.exists() {
+        return Err(CliError::InvalidImport("ARTIFACT would be overwritten - run lal clean".into()));
+    }
+    fs::create_dir_all(artdir).context(format!("Failed to create {}", artdir.display()))?;
+    let dest = artdir.join(tarball.file_name().unwrap());
+    let res = fs::copy(tarball, &dest)
+        .context(format!("Failed to copy {} to ARTIFACT", tarball.display()))
+        .and_then(|_| lock.write(&artdir.join("lockfile.json")))
+        .and_then(|_| {
+            upload(name,
+                   version,
+                   lock,
+                   backend,
+                   &PublishOptions::default(),
+                   &dest,
+                   true)
+        });
+    let _ = fs::remove_dir_all(artdir);
+    res
+}
+
+/// Import a prebuilt tarball from outside lal as a published component
+///
+/// The tarball (`.tar.gz`, `.tar.zst` or `.tar.xz`) is unpacked, a lone top level directory
+/// is stripped, and a lockfile is synthesized unless the tarball has one for the component
+/// already. The lockfile is stamped with the version and environment before repacking the
+/// component into the cache, so that it can be fetched as `name=version`.
+///
+/// With `publish` it is also published, failing if that version already exists.
+pub fn import<T: CachedBackend + Backend + ?Sized>(
+    backend: &T,
+    tarball: &Path,
+    name: &str,
+    version: u32,
+    env: &str,
+    publish: bool,
+) -> LalResult<Lockfile> {
+    // the name is used in paths inside the cache
+    if !is_canonical_name(name) {
+        return Err(CliError::InvalidComponentName(name.into()));
+    }
+    if !tarball.is_file() {
+        return Err(CliError::InvalidImport(format!("{} does not exist", tarball.display())));
+    }
+    // work inside the cache so the result can be moved into it
+    let workdir = Path::new(&backend.get_cache_dir())
+        .join("import")
+        .join(format!("{}-{}-{}", env, name, version));
+    if workdir.is_dir() {
+        fs::remove_dir_all(&workdir)?;
+    }
+    let contents = workdir.join("contents");
+    fs::create_dir_all(&contents)
+        .context(format!("Failed to create import directory {}", contents.display()))?;
+
+    let res = unpack(tarball, &contents)
+        .and_then(|_| content_root(&contents))
+        .and_then(|root| {
+            let lock = stamp_lockfile(&root, name, version, env)?;
+            let repacked = workdir.join(backend.get_compression().tarball_name(name));
+            output::tar_dir(&root, &repacked, backend.get_compression())?;
+            if publish {
+                publish_import(backend, name, version, &lock, &repacked)?;
+            }
+            let cached = backend.store_published_component(name, version, env, &repacked)?;
+            info!("Imported {} {} in {} to {}", name, version, env, cached.display());
+            Ok(lock)
+        });
+    let _ = fs::remove_dir_all(&workdir);
+    res
+}
@@ -1,8 +1,179 @@
 use std::env;
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+use serde_json;
 
-use super::{Config, CliError, LalResult};
+use super::{config_dir, Config, CliError, LalResult, IoResultExt};
 use core::manifest::*;
 
+/// A file scaffolded by a built in template, relative to the component root
+struct TemplateFile {
+    path: &'static str,
+    contents: &'static str,
+    executable: bool,
+}
+
+/// A built in `lal init` template
+struct Template {
+    configurations: &'static [&'static str],
+    files: &'static [TemplateFile],
+    /// Scripts for the manifest by name
+    scripts: &'static [(&'static str, &'static str)],
+}
+
+const CPP_LIB_BUILD: &'static str = r#"#!/bin/bash
+set -eo pipefail
+
+# called by lal build as: BUILD <component> <configuration>
+main() {
+  local component="$1" configuration="$2" buildtype
+  case "$configuration" in
+    release) buildtype=Release ;;
+    debug) buildtype=Debug ;;
+    *) echo "Unknown configuration $configuration" >&2; exit 1 ;;
+  esac
+  mkdir -p build OUTPUT/lib OUTPUT/include
+  cmake -H. -Bbuild -DCMAKE_BUILD_TYPE="$buildtype" -DCMAKE_INSTALL_PREFIX="$PWD/OUTPUT"
+  cmake --build build -- -j"$(nproc)"
+  cmake --build build --target install
+}
+
+main "$@"
+"#;
+
+const CPP_BIN_BUILD: &'static str = r#"#!/bin/bash
+set -eo pipefail
+
+# called by lal build as: BUILD <component> <configuration>
+main() {
+  local component="$1" configuration="$2" buildtype
+  case "$configuration" in
+    release) buildtype=Release ;;
+    debug) buildtype=Debug ;;
+    *) echo "Unknown configuration $configuration" >&2; exit 1 ;;
+  esac
+  mkdir -p build OUTPUT/bin
+  cmake -H. -Bbuild -DCMAKE_BUILD_TYPE="$buildtype" -DCMAKE_INSTALL_PREFIX="$PWD/OUTPUT"
+  cmake --build build -- -j"$(nproc)"
+  cmake --build build --target install
+}
+
+main "$@"
+"#;
+
+// scripts get the arguments of `lal run` appended
+const CPP_TEST_SCRIPT: &'static str = "cmake --build build -- -j\"$(nproc)\" && \
+                                       cd build && ctest --output-on-failure";
+
+const DOCS_SERVE_SCRIPT: &'static str = "cd _build/html && python3 -m http.server";
+
+const DOCS_BUILD: &'static str = r#"#!/bin/bash
+set -eo pipefail
+
+# called by lal build as: BUILD <component> <configuration>
+main() {
+  local component="$1" configuration="$2"
+  make "$configuration"
+  mkdir -p OUTPUT
+  cp -r "_build/$configuration/." OUTPUT/
+}
+
+main "$@"
+"#;
+
+fn builtin_template(name: &str) -> Option<Template> {
+    match name {
+        "cpp-lib" => Some(Template {
+            configurations: &["release", "debug"],
+            files: &[
+                TemplateFile { path: ".lal/BUILD", contents: CPP_LIB_BUILD, executable: true },
+            ],
+            scripts: &[("test", CPP_TEST_SCRIPT)],
+        }),
+        "cpp-bin" => Some(Template {
+            configurations: &["release", "debug"],
+            files: &[
+                TemplateFile { path: ".lal/BUILD", contents: CPP_BIN_BUILD, executable: true },
+            ],
+            scripts: &[("test", CPP_TEST_SCRIPT)],
+        }),
+        "docs" => Some(Template {
+            configurations: &["html"],
+            files: &[
+                TemplateFile { path: ".lal/BUILD", contents: DOCS_BUILD, executable: true },
+            ],
+            scripts: &[("serve", DOCS_SERVE_SCRIPT)],
+        }),
+        _ => None,
+    }
+}
+
+fn write_template_file(pwd: &Path, f: &TemplateFile) -> LalResult<()> {
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    let pth = pwd.join(f.path);
+    fs::create_dir_all(pth.parent().unwrap())?;
+    fs::File::create(&pth)
+        .and_then(|mut fh| fh.write_all(f.contents.as_bytes()))
+        .context(format!("Failed to write {}", pth.display()))?;
+    if f.executable {
+        fs::set_permissions(&pth, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+// copy a user template directory into the component, returning its configurations
+//
+// Everything but an optional `template.json` (a components entry for the manifest)
+// is copied as is, so executable bits on BUILD scripts are kept.
+fn apply_user_template(tpl: &Path, pwd: &Path) -> LalResult<Option<ComponentConfiguration>> {
+    let mut conf = None;
+    for e in WalkDir::new(tpl).min_depth(1).into_iter().filter_map(|e| e.ok()) {
+        let rel = e.path().strip_prefix(tpl).unwrap();
+        if rel == Path::new("template.json") {
+            let data = fs::File::open(e.path())?;
+            conf = Some(serde_json::from_reader(data)?);
+            continue;
+        }
+        let dest = pwd.join(rel);
+        if e.file_type().is_dir() {
+            fs::create_dir_all(&dest)?;
+        } else {
+            trace!("Copying template file {}", rel.display());
+            fs::copy(e.path(), &dest).context(format!("Failed to write {}", dest.display()))?;
+        }
+    }
+    Ok(conf)
+}
+
+// scaffold a template into the component and its manifest
+//
+// Templates in `~/.lal/templates/<name>` take precedence over the built in ones.
+fn scaffold(name: &str, pwd: &Path, manifest: &mut Manifest) -> LalResult<()> {
+    let component = manifest.name.clone();
+    let user_tpl = config_dir()?.join("templates").join(name);
+    if user_tpl.is_dir() {
+        debug!("Using template from {}", user_tpl.display());
+        let conf = apply_user_template(&user_tpl, pwd)?;
+        manifest.components.insert(component, conf.unwrap_or_default());
+        return Ok(());
+    }
+    let tpl = builtin_template(name).ok_or_else(|| CliError::UnknownTemplate(name.into()))?;
+    for f in tpl.files {
+        write_template_file(pwd, f)?;
+    }
+    for &(script, command) in tpl.scripts {
+        manifest.scripts.insert(script.into(), command.into());
+    }
+    let conf = ComponentConfiguration {
+        defaultConfig: tpl.configurations[0].into(),
+        configurations: tpl.configurations.iter().map(|c| c.to_string()).collect(),
+        ..ComponentConfiguration::default()
+    };
+    manifest.components.insert(component, conf);
+    Ok(())
+}
 
 /// Generates a blank manifest in the current directory
 ///
@@ -11,7 +182,10 @@ use core::manifest::*;
 ///
 /// The function will not overwrite an existing `manifest.json`,
 /// unless the `force` bool is set.
-pub fn init(cfg: &Config, force: bool, env: &str) -> LalResult<()> {
+///
+/// A `template` (`cpp-lib`, `cpp-bin`, `docs`, or a directory in `~/.lal/templates`)
+/// additionally scaffolds `.lal/BUILD`, the component configurations and `scripts`.
+pub fn init(cfg: &Config, force: bool, env: &str, template: Option<&str>) -> LalResult<()> {
     cfg.get_container(env.into())?;
 
     let pwd = env::current_dir()?;
@@ -26,7 +200,12 @@ pub fn init(cfg: &Config, force: bool, env: &str) -> LalResult<()> {
     // we are allowed to overwrite or write a new manifest if we are here
     // always create new manifests in new default location
     create_lal_subdir(&pwd)?; // create the `.lal` subdir if it's not there already
-    Manifest::new(dirname, env, ManifestLocation::default().as_path(&pwd)).write()?;
+    let mut manifest = Manifest::new(dirname, env, ManifestLocation::default().as_path(&pwd));
+    if let Some(t) = template {
+        scaffold(t, &pwd, &mut manifest)?;
+        info!("Scaffolded {} from the {} template", dirname, t);
+    }
+    manifest.write()?;
 
     // if the manifest already existed, warn about this now being placed elsewhere
     if let Ok(ManifestLocation::RepoRoot) = mpath {
@@ -20,6 +20,7 @@
 #[macro_use]
 extern crate hyper;
 extern crate hyper_native_tls;
+extern crate native_tls;
 extern crate openssl_probe;
 #[macro_use]
 extern crate serde_derive;
@@ -27,14 +28,19 @@ extern crate serde_json;
 extern crate regex;
 extern crate tar;
 extern crate flate2;
+extern crate xz2;
+extern crate zstd;
 extern crate ansi_term;
 extern crate sha1;
+extern crate sha2;
 #[macro_use]
 extern crate log;
 extern crate walkdir;
+extern crate xml;
 extern crate chrono;
 extern crate filetime;
 extern crate rand;
+extern crate libc;
 extern crate semver;
 #[cfg(feature = "progress")]
 extern crate indicatif;
@@ -48,6 +54,8 @@ pub use storage::*;
 
 /// Env module for env subcommand (which has further subcommands)
 pub mod env;
+/// Cache module for the cache subcommand (which has further subcommands)
+pub mod cache;
 /// List module for all the list-* subcommands
 pub mod list;
 /// Propagation module with all structs describing the steps
@@ -56,35 +64,70 @@ pub mod propagate;
 
 // lift most other pub functions into our libraries main scope
 // this avoids having to type lal::build::build in tests and main.rs
-pub use build::{build, BuildOptions};
-pub use configure::configure;
+pub use build::{build, build_with_remote_cache, BuildOptions};
+pub use configure::{configure, credential_provider, CredentialProvider, CredentialHelper,
+                    Keychain};
 pub use init::init;
 pub use shell::{shell, docker_run, script, DockerRunFlags, ShellModes};
-pub use fetch::fetch;
-pub use update::{update, update_all};
-pub use remove::remove;
-pub use export::export;
-pub use status::status;
-pub use verify::verify;
-pub use stash::stash;
-pub use clean::clean;
-pub use query::query;
-pub use publish::publish;
+pub use fetch::{fetch, fetch_lockfile, fetch_target, component_location, target_components,
+                FetchOptions, StashedPolicy, FetchReport, FetchReportEntry};
+pub use update::{add, update, update_all, UpdatePolicy};
+pub use remove::{remove, rm};
+pub use export::{export, export_all, export_manifest};
+pub use status::{status, status_rows, status_table, StatusOptions, TableRow};
+pub use deptree::{dep_tree, dep_tree_dot, dependency_paths, why};
+pub use lockdiff::{lock_diff, diff_lockfiles, read_lock_reference, LockChange, LockChangeKind};
+pub use lockexport::{lock_export, flat_csv, LockExportFormat};
+pub use sbom::{sbom, sbom_document, SbomFormat};
+pub use verify::{verify, verify_output, VerifyOptions};
+pub use validate::{validate, validate_manifest};
+pub use stash::{stash, stash_list, stash_remove, stash_push, stash_pull, StashMetadata};
+pub use clean::{clean, CleanOptions, CleanSummary};
+pub use query::{query, query_metadata, list_versions, search};
+pub use outdated::{outdated, outdated_dependencies, OutdatedEntry};
+pub use publish::{publish, publish_bump, publish_dry_run, publish_preflight, publish_metadata,
+                  PublishOptions, PreflightReport, PreflightCheck, PreflightStatus};
+pub use promote::{promote, parse_component_version};
+pub use retention::{retention_apply, retention_plan, RetentionPlan};
+pub use import::import;
+pub use package::package;
+pub use hooks::{run_hook, HookContext};
+pub use plugin::{find_plugin, run_plugin, PluginGlobals};
+pub use workspace::{Workspace, WorkspaceMember, link_siblings, fetch_all, build_all, status_all};
+pub use watch::{watch, SourceWatcher};
+pub use logs::{logs, list_logs, new_build_log};
+pub use test::{test, collect_results, TestSummary, TEST_RESULTS};
 
 mod configure;
 mod init;
 mod shell;
 mod build;
 mod query;
+mod outdated;
 mod update;
 mod fetch;
 mod remove;
 mod export;
 mod clean;
 mod verify;
+mod validate;
 mod stash;
 mod status;
+mod deptree;
+mod lockdiff;
+mod lockexport;
+mod sbom;
 mod publish;
+mod promote;
+mod retention;
+mod import;
+mod package;
+mod hooks;
+mod plugin;
+mod workspace;
+mod watch;
+mod logs;
+mod test;
 
 #[cfg(feature = "upgrade")]
 pub use upgrade::upgrade;
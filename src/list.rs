@@ -31,6 +31,26 @@ pub fn configurations(component: &str, manifest: &Manifest) -> LalResult<()> {
     Ok(())
 }
 
+/// Print the available targets for a buildable Component
+pub fn targets(component: &str, manifest: &Manifest) -> LalResult<()> {
+    let component_settings = match manifest.components.get(component) {
+        Some(c) => c,
+        None => return Ok(()), // invalid component - but this is for completion
+    };
+    for t in &component_settings.targets {
+        println!("{}", t);
+    }
+    Ok(())
+}
+
+/// Print the names of the scripts in the manifest
+pub fn scripts(manifest: &Manifest) -> LalResult<()> {
+    for name in manifest.scripts.keys() {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
 /// Print the configured environments from the config
 pub fn environments(cfg: &Config) -> LalResult<()> {
     for k in cfg.environments.keys() {
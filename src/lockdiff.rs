@@ -0,0 +1,170 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use ansi_term::Colour;
+use serde_json;
+
+use storage::{CachedBackend, tarball_lockfile};
+use super::{CliError, IoResultExt, LalResult, Lockfile, ProgressReporter};
+
+/// How a dependency differs between two lockfile trees
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LockChangeKind {
+    /// Only in the new tree
+    Added,
+    /// Only in the old tree
+    Removed,
+    /// In both trees with different versions or environments
+    Changed,
+}
+
+/// A dependency that differs between two lockfile trees
+#[derive(Serialize, Clone, Debug)]
+pub struct LockChange {
+    /// Component name
+    pub name: String,
+    /// Kind of change
+    pub kind: LockChangeKind,
+    /// Versions used in the old tree
+    pub from: Option<String>,
+    /// Versions used in the new tree
+    pub to: Option<String>,
+    /// Environments used in the old tree
+    pub from_environment: Option<String>,
+    /// Environments used in the new tree
+    pub to_environment: Option<String>,
+}
+
+// comma separated values, sorted as they are in the set
+fn joined(xs: Option<&BTreeSet<String>>) -> Option<String> {
+    xs.map(|x| x.iter().cloned().collect::<Vec<_>>().join(", "))
+}
+
+/// Compare the full dependency trees of two lockfiles
+///
+/// Every component anywhere in either tree is considered, so a component whose
+/// version did not change but was rebuilt in another environment is also reported.
+pub fn diff_lockfiles(old: &Lockfile, new: &Lockfile) -> Vec<LockChange> {
+    let (old_vers, new_vers) = (old.find_all_dependency_versions(),
+                                new.find_all_dependency_versions());
+    let (old_envs, new_envs) = (old.find_all_environments(), new.find_all_environments());
+    let names = old_vers.keys().chain(new_vers.keys()).collect::<BTreeSet<_>>();
+
+    let mut res = vec![];
+    for name in names {
+        let kind = match (old_vers.get(name), new_vers.get(name)) {
+            (None, Some(_)) => LockChangeKind::Added,
+            (Some(_), None) => LockChangeKind::Removed,
+            (o, n) if o != n || old_envs.get(name) != new_envs.get(name) => {
+                LockChangeKind::Changed
+            }
+            _ => continue,
+        };
+        res.push(LockChange {
+            name: name.clone(),
+            kind: kind,
+            from: joined(old_vers.get(name)),
+            to: joined(new_vers.get(name)),
+            from_environment: joined(old_envs.get(name)),
+            to_environment: joined(new_envs.get(name)),
+        });
+    }
+    res
+}
+
+/// Read a lockfile tree from a reference
+///
+/// A reference is either `INPUT` for the tree currently in `./INPUT`, a path to a saved
+/// lockfile, or `component=version` for the lockfile inside a published tarball.
+/// Published tarballs are fetched into the cache from the backend, and need `env`.
+pub fn read_lock_reference<T: CachedBackend + ?Sized>(
+    backend: &T,
+    reference: &str,
+    env: Option<&str>,
+    reporter: &ProgressReporter,
+) -> LalResult<Lockfile> {
+    if reference == "INPUT" {
+        return Lockfile::default().set_name("INPUT").populate_from_input();
+    }
+    let pth = Path::new(reference);
+    if pth.is_file() || !reference.contains('=') {
+        return Lockfile::from_path(&pth.to_path_buf(), reference);
+    }
+    let pair = reference.splitn(2, '=').collect::<Vec<_>>();
+    let version = pair[1].parse::<u32>()
+        .map_err(|_| CliError::InvalidVersion(pair[1].into()))?;
+    let env = env.ok_or(CliError::EnvironmentUnspecified)?;
+    let (tarball, _) = backend.retrieve_published_component(pair[0], Some(version), env, reporter)?;
+    tarball_lockfile(&tarball).ok_or_else(|| CliError::MissingLockfile(reference.into()))
+}
+
+/// Write a document read from lock references to `output` if given, otherwise to stdout
+pub fn write_output(data: &str, output: Option<&str>) -> LalResult<()> {
+    match output {
+        Some(pth) => {
+            File::create(pth)
+                .and_then(|mut f| f.write_all(data.as_bytes()))
+                .context(format!("Failed to write {}", pth))?
+        }
+        None => print!("{}", data),
+    }
+    Ok(())
+}
+
+/// Print what changed between two lockfile trees
+///
+/// Compares `from` against `to` (or the current `./INPUT` if absent), printing added,
+/// removed and changed dependencies along with any change of environment.
+pub fn lock_diff<T: CachedBackend + ?Sized>(
+    backend: &T,
+    from: &str,
+    to: Option<&str>,
+    env: Option<&str>,
+    json: bool,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    let old = read_lock_reference(backend, from, env, reporter)?;
+    let new = read_lock_reference(backend, to.unwrap_or("INPUT"), env, reporter)?;
+    let changes = diff_lockfiles(&old, &new);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&changes)?);
+        return Ok(());
+    }
+    for c in &changes {
+        let none = || "-".to_string();
+        match c.kind {
+            LockChangeKind::Added => {
+                let line = format!("+ {} {} ({})",
+                                   c.name,
+                                   c.to.clone().unwrap_or_else(&none),
+                                   c.to_environment.clone().unwrap_or_else(&none));
+                println!("{}", Colour::Green.paint(line));
+            }
+            LockChangeKind::Removed => {
+                let line = format!("- {} {}", c.name, c.from.clone().unwrap_or_else(&none));
+                println!("{}", Colour::Red.paint(line));
+            }
+            LockChangeKind::Changed => {
+                let mut line = format!("~ {}", c.name);
+                if c.from != c.to {
+                    line.push_str(&format!(" {} -> {}",
+                                           c.from.clone().unwrap_or_else(&none),
+                                           c.to.clone().unwrap_or_else(&none)));
+                }
+                if c.from_environment != c.to_environment {
+                    line.push_str(&format!(" (environment {} -> {})",
+                                           c.from_environment.clone().unwrap_or_else(&none),
+                                           c.to_environment.clone().unwrap_or_else(&none)));
+                }
+                println!("{}", Colour::Yellow.paint(line));
+            }
+        }
+    }
+    info!("{} dependencies differ between {} and {}",
+          changes.len(),
+          from,
+          to.unwrap_or("INPUT"));
+    Ok(())
+}
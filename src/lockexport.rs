@@ -0,0 +1,71 @@
+use serde_json;
+
+use storage::CachedBackend;
+use lockdiff::{read_lock_reference, write_output};
+use super::{LalResult, FlatDependency, ProgressReporter};
+
+/// Output formats for `lal lock export`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LockExportFormat {
+    /// Pretty printed json
+    Json,
+    /// Comma separated values with a header row (flat export only)
+    Csv,
+}
+
+// quote a csv field if it needs it
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.into()
+    }
+}
+
+/// Render a flattened dependency list as csv
+pub fn flat_csv(deps: &[FlatDependency]) -> String {
+    let mut res = String::from("name,version,environment,checksum\n");
+    for d in deps {
+        let checksum = d.checksum.clone().unwrap_or_default();
+        let row = vec![&d.name, &d.version, &d.environment, &checksum]
+            .into_iter()
+            .map(|f| csv_field(f))
+            .collect::<Vec<_>>();
+        res.push_str(&row.join(","));
+        res.push('\n');
+    }
+    res
+}
+
+/// Export a lockfile tree for external tooling
+///
+/// The tree is read from a reference as in `lal lock diff` (`INPUT` by default).
+/// With `flat` the recursive tree is collapsed into a deduplicated list of name,
+/// version, environment and checksum, which can also be written as csv.
+/// The result is written to `output` if given, otherwise to stdout.
+pub fn lock_export<T: CachedBackend + ?Sized>(
+    backend: &T,
+    reference: Option<&str>,
+    env: Option<&str>,
+    flat: bool,
+    format: LockExportFormat,
+    output: Option<&str>,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    let lf = read_lock_reference(backend, reference.unwrap_or("INPUT"), env, reporter)?;
+    let data = if flat {
+        let deps = lf.flatten();
+        debug!("Flattened {} into {} entries", lf.name, deps.len());
+        match format {
+            LockExportFormat::Json => format!("{}\n", serde_json::to_string_pretty(&deps)?),
+            LockExportFormat::Csv => flat_csv(&deps),
+        }
+    } else {
+        format!("{}\n", serde_json::to_string_pretty(&lf)?)
+    };
+    write_output(&data, output)?;
+    if let Some(pth) = output {
+        info!("Exported lockfile tree to {}", pth);
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{self, Path, PathBuf};
+use chrono::{Timelike, UTC};
+
+use super::{CliError, LalResult};
+
+// builds logged before the oldest ones are removed
+const MAX_LOGS: usize = 20;
+
+fn logs_dir() -> PathBuf { Path::new(".").join(".lal").join("logs") }
+
+/// Logs of earlier builds in `.lal/logs`, oldest first
+pub fn list_logs() -> LalResult<Vec<PathBuf>> {
+    let dir = logs_dir();
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut logs = fs::read_dir(&dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().map_or(false, |x| x == "log"))
+        .collect::<Vec<_>>();
+    logs.sort(); // names start with the time the build started
+    Ok(logs)
+}
+
+/// Path of the log for a build starting now
+///
+/// Logs are named `<UTC time>-<component>-<configuration>[-<target>].log` so they sort
+/// by time. Only the last 20 logs are kept; older ones are removed here.
+pub fn new_build_log(
+    component: &str,
+    configuration: &str,
+    target: Option<&str>,
+) -> LalResult<PathBuf> {
+    let dir = logs_dir();
+    fs::create_dir_all(&dir)?;
+    let existing = list_logs()?;
+    if existing.len() >= MAX_LOGS {
+        for old in &existing[..existing.len() + 1 - MAX_LOGS] {
+            trace!("Removing old build log {}", old.display());
+            let _ = fs::remove_file(old);
+        }
+    }
+    let now = UTC::now();
+    let mut name = format!("{}.{:03}Z-{}-{}",
+                           now.format("%Y%m%dT%H%M%S"),
+                           now.nanosecond() / 1_000_000,
+                           component,
+                           configuration);
+    if let Some(t) = target {
+        name.push_str(&format!("-{}", t));
+    }
+    Ok(dir.join(format!("{}.log", name)))
+}
+
+/// Show logs of earlier builds
+///
+/// Lists the logs in `.lal/logs` (newest first) unless `last` or a log name is given,
+/// in which case that log is printed as it was captured.
+pub fn logs(name: Option<&str>, last: bool) -> LalResult<()> {
+    let logs = list_logs()?;
+    let pth = match name {
+        Some(n) => {
+            // only logs directly inside the logs directory can be shown
+            if n.is_empty() || n.contains('/') || n.contains(path::MAIN_SEPARATOR) ||
+               n.starts_with('.') {
+                return Err(CliError::InvalidLogName(n.into()));
+            }
+            let pth = logs_dir().join(n);
+            if !pth.is_file() {
+                return Err(CliError::MissingLog(n.into()));
+            }
+            pth
+        }
+        None if last => {
+            match logs.last() {
+                Some(pth) => pth.clone(),
+                None => return Err(CliError::MissingLog("recorded".into())),
+            }
+        }
+        None => {
+            for pth in logs.iter().rev() {
+                let size = pth.metadata()?.len();
+                println!("{} ({} bytes)", pth.file_name().unwrap().to_string_lossy(), size);
+            }
+            return Ok(());
+        }
+    };
+    debug!("Showing {}", pth.display());
+    let mut f = fs::File::open(&pth)?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    io::copy(&mut f, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
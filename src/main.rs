@@ -7,9 +7,11 @@ extern crate openssl_probe;
 
 extern crate lal;
 use lal::*;
-use clap::{Arg, App, AppSettings, SubCommand, ArgMatches};
+use clap::{Arg, App, AppSettings, SubCommand, ArgMatches, ArgGroup};
 use std::process;
 use std::ops::Deref;
+use std::path::Path;
+use std::time::Duration;
 
 fn is_integer(v: String) -> Result<(), String> {
     if v.parse::<u32>().is_ok() {
@@ -23,7 +25,7 @@ fn result_exit<T>(name: &str, x: LalResult<T>) {
         println!(""); // add a separator
         error!("{} error: {}", name, e);
         debug!("{}: {:?}", name, e); // in the off-chance that Debug is useful
-        process::exit(1);
+        process::exit(e.code());
     });
     process::exit(0);
 }
@@ -35,20 +37,151 @@ fn handle_manifest_agnostic_cmds(
     backend: &Backend,
     explicit_env: Option<&str>,
 ) {
+    let reporter = TerminalReporter::with_phases();
     let res = if let Some(a) = args.subcommand_matches("export") {
-        lal::export(backend,
-                    a.value_of("component").unwrap(),
-                    a.value_of("output"),
-                    explicit_env)
+        if a.is_present("manifest") {
+            Manifest::read().and_then(|mf| {
+                lal::export_manifest(backend, &mf, a.value_of("output"), explicit_env, &reporter)
+            })
+        } else {
+            let comps = a.values_of("component").unwrap().collect::<Vec<_>>();
+            lal::export_all(backend, &comps, a.value_of("output"), explicit_env, &reporter)
+        }
+    } else if let Some(a) = args.subcommand_matches("query")
+        .and_then(|a| a.subcommand_matches("meta")) {
+        lal::query_metadata(backend,
+                            explicit_env,
+                            a.value_of("component").unwrap(),
+                            a.is_present("json"))
     } else if let Some(a) = args.subcommand_matches("query") {
         lal::query(backend,
                    explicit_env,
                    a.value_of("component").unwrap(),
-                   a.is_present("latest"))
+                   a.is_present("latest"),
+                   a.is_present("json"))
+    } else if let Some(a) = args.subcommand_matches("search") {
+        let envs = match explicit_env {
+            Some(e) => vec![e.to_string()],
+            None => cfg.environments.keys().cloned().collect(),
+        };
+        lal::search(backend,
+                    &envs,
+                    a.value_of("term").unwrap(),
+                    a.is_present("regex"),
+                    a.is_present("json"))
+    } else if let Some(a) = args.subcommand_matches("list-versions") {
+        let envs = match explicit_env {
+            Some(e) => vec![e.to_string()],
+            None => cfg.environments.keys().cloned().collect(),
+        };
+        let last = a.value_of("last").map(|n| n.parse().unwrap());
+        lal::list_versions(backend,
+                           &envs,
+                           a.value_of("component").unwrap(),
+                           last,
+                           a.is_present("json"))
+    } else if let Some(a) = args.subcommand_matches("lock")
+        .and_then(|a| a.subcommand_matches("diff")) {
+        lal::lock_diff(backend,
+                       a.value_of("from").unwrap(),
+                       a.value_of("to"),
+                       explicit_env,
+                       a.is_present("json"),
+                       &reporter)
+    } else if let Some(a) = args.subcommand_matches("lock")
+        .and_then(|a| a.subcommand_matches("export")) {
+        let format = match a.value_of("format") {
+            Some("csv") => LockExportFormat::Csv,
+            _ => LockExportFormat::Json,
+        };
+        lal::lock_export(backend,
+                         a.value_of("lockfile"),
+                         explicit_env,
+                         a.is_present("flat"),
+                         format,
+                         a.value_of("output"),
+                         &reporter)
+    } else if let Some(a) = args.subcommand_matches("sbom") {
+        let format = match a.value_of("format") {
+            Some("spdx") => SbomFormat::Spdx,
+            _ => SbomFormat::CycloneDx,
+        };
+        lal::sbom(backend,
+                  a.value_of("lockfile"),
+                  explicit_env,
+                  format,
+                  a.value_of("output"),
+                  &reporter)
     } else if let Some(a) = args.subcommand_matches("publish") {
-        lal::publish(a.value_of("component").unwrap(), backend)
+        let component = a.value_of("component").unwrap();
+        let flags = a.values_of("meta").map(|vs| vs.collect()).unwrap_or_else(Vec::new);
+        // the manifest is optional here but adds its publishMetadata when present
+        let mf = match Manifest::read() {
+            Err(CliError::MissingManifest) => Ok(None),
+            res => res.map(Some),
+        };
+        mf.and_then(|mf| lal::publish_metadata(mf.as_ref(), &flags)).and_then(|metadata| {
+            let opts = PublishOptions {
+                channel: a.value_of("channel").map(String::from),
+                metadata: metadata,
+                hooks: cfg.runHooks,
+            };
+            if a.is_present("dry-run") {
+                lal::publish_dry_run(component, backend, &opts)
+            } else if a.is_present("bump") {
+                lal::publish_bump(component, backend, &opts).map(|v| {
+                    println!("{}", v);
+                })
+            } else {
+                lal::publish(component, backend, &opts)
+            }
+        })
+    } else if let Some(a) = args.subcommand_matches("promote") {
+        lal::parse_component_version(a.value_of("component").unwrap()).and_then(|(c, v)| {
+            let env = explicit_env.ok_or(CliError::EnvironmentUnspecified)?;
+            lal::promote(backend, &c, v, env, a.value_of("to").unwrap(), a.value_of("from"))
+        })
+    } else if let Some(a) = args.subcommand_matches("import") {
+        // the version is validated as an integer by clap
+        let version = a.value_of("version").unwrap().parse().unwrap();
+        a.value_of("env")
+            .or(explicit_env)
+            .ok_or(CliError::EnvironmentUnspecified)
+            .and_then(|env| {
+                lal::import(backend,
+                            Path::new(a.value_of("tarball").unwrap()),
+                            a.value_of("name").unwrap(),
+                            version,
+                            env,
+                            a.is_present("publish"))
+            })
+            .map(|_| ())
+    } else if let Some(a) = args.subcommand_matches("retention")
+        .and_then(|a| a.subcommand_matches("apply")) {
+        let envs = match explicit_env {
+            Some(e) => vec![e.to_string()],
+            None => cfg.environments.keys().cloned().collect(),
+        };
+        let dry_run = a.is_present("dry-run");
+        cfg.retention
+            .as_ref()
+            .ok_or(CliError::MissingRetentionPolicy)
+            .and_then(|policy| lal::retention_apply(backend, policy, &envs, dry_run))
     } else if args.subcommand_matches("list-environments").is_some() {
         lal::list::environments(cfg)
+    } else if let Some(a) = args.subcommand_matches("stash")
+        .and_then(|a| a.subcommand_matches("list")) {
+        lal::stash_list(&cfg.cache, a.value_of("component"))
+    } else if let Some(a) = args.subcommand_matches("stash")
+        .and_then(|a| a.subcommand_matches("rm")) {
+        // component is only absent with --all
+        lal::stash_remove(&cfg.cache, a.value_of("component"), a.value_of("name"))
+    } else if let Some(a) = args.subcommand_matches("stash")
+        .and_then(|a| a.subcommand_matches("pull")) {
+        lal::stash_pull(backend, a.value_of("stash").unwrap(), &reporter).map(|_| ())
+    } else if let Some(lf) = args.subcommand_matches("fetch")
+        .and_then(|a| a.value_of("from-lockfile")) {
+        lal::fetch_lockfile(backend, lf, &reporter)
     } else {
         return ();
     };
@@ -56,46 +189,322 @@ fn handle_manifest_agnostic_cmds(
 }
 
 // functions that need a manifest, but do not depend on environment values
-fn handle_environment_agnostic_cmds(args: &ArgMatches, mf: &Manifest, backend: &Backend) {
+fn handle_environment_agnostic_cmds(
+    args: &ArgMatches,
+    cfg: &Config,
+    mf: &Manifest,
+    backend: &Backend,
+) {
     let res = if let Some(a) = args.subcommand_matches("status") {
-        lal::status(mf,
-                    a.is_present("full"),
-                    a.is_present("origin"),
-                    a.is_present("time"))
+        if a.is_present("table") || a.is_present("machine") {
+            return (); // latest versions depend on the environment
+        }
+        lal::status(mf, &status_options(a))
     } else if args.subcommand_matches("list-components").is_some() {
         lal::list::buildables(mf)
     } else if args.subcommand_matches("list-supported-environments").is_some() {
         lal::list::supported_environments(mf)
     } else if let Some(a) = args.subcommand_matches("list-configurations") {
         lal::list::configurations(a.value_of("component").unwrap(), mf)
+    } else if let Some(a) = args.subcommand_matches("list-targets") {
+        lal::list::targets(a.value_of("component").unwrap(), mf)
+    } else if args.subcommand_matches("list-scripts").is_some() {
+        lal::list::scripts(mf)
     } else if let Some(a) = args.subcommand_matches("list-dependencies") {
         lal::list::dependencies(mf, a.is_present("core"))
     } else if let Some(a) = args.subcommand_matches("remove") {
         let xs = a.values_of("components").unwrap().map(String::from).collect::<Vec<_>>();
         lal::remove(mf, xs, a.is_present("save"), a.is_present("savedev"))
+    } else if let Some(a) = args.subcommand_matches("rm") {
+        let xs = a.values_of("components").unwrap().map(String::from).collect::<Vec<_>>();
+        lal::rm(mf, xs)
+    } else if let Some(a) = args.subcommand_matches("stash")
+        .and_then(|a| a.subcommand_matches("push")) {
+        lal::stash_push(backend, &mf.name, a.value_of("name").unwrap())
     } else if let Some(a) = args.subcommand_matches("stash") {
-        lal::stash(backend, mf, a.value_of("name").unwrap())
+        lal::stash(backend, mf, a.value_of("name").unwrap(), cfg.runHooks)
+    } else if let Some(a) = args.subcommand_matches("package") {
+        lal::package(backend, mf, a.is_present("check-reproducible")).map(|digest| {
+            println!("{}", digest);
+        })
     } else if let Some(a) = args.subcommand_matches("propagate") {
         lal::propagate::print(mf, a.value_of("component").unwrap(), a.is_present("json"))
+    } else if let Some(a) = args.subcommand_matches("dep-tree") {
+        lal::dep_tree(mf, a.is_present("dot"))
+    } else if let Some(a) = args.subcommand_matches("why") {
+        lal::why(mf, a.value_of("component").unwrap(), a.value_of("version"))
     } else {
         return ();
     };
     result_exit(args.subcommand_name().unwrap(), res);
 }
 
-fn handle_network_cmds(args: &ArgMatches, mf: &Manifest, backend: &Backend, env: &str) {
-    let res = if let Some(a) = args.subcommand_matches("update") {
+// commands writing to INPUT or the cache hold advisory locks until lal exits
+//
+// builds only lock the workspace so builds in separate checkouts can run concurrently.
+fn acquire_locks(args: &ArgMatches, cfg: &Config) -> Vec<LalLock> {
+    let (project, cache) = match args.subcommand() {
+        // workspace members are locked one at a time
+        ("fetch", Some(a)) if a.is_present("all") => (false, true),
+        ("build", Some(a)) | ("status", Some(a)) if a.is_present("all") => (false, false),
+        ("fetch", _) | ("add", _) | ("update", _) | ("update-all", _) => (true, true),
+        ("stash", Some(a)) if a.subcommand_matches("list").is_some() => (false, false),
+        ("stash", Some(a)) if a.subcommand_matches("rm").is_some() => (false, true),
+        ("stash", Some(a)) if a.subcommand_matches("pull").is_some() => (false, true),
+        ("stash", Some(a)) if a.subcommand_matches("push").is_some() => (false, false),
+        ("stash", _) => (true, true),
+        ("build", _) | ("test", _) | ("package", _) | ("remove", _) | ("rm", _) => (true, false),
+        _ => (false, false),
+    };
+    let mut locks = vec![];
+    let res = (|| -> LalResult<()> {
+        if project {
+            locks.push(LalLock::project()?);
+        }
+        if cache {
+            locks.push(LalLock::cache(&cfg.cache)?);
+        }
+        Ok(())
+    })();
+    if let Err(e) = res {
+        error!("{}", e);
+        process::exit(e.code());
+    }
+    locks
+}
+
+// shared by every command that can replace stashed components in INPUT
+fn stashed_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("stashed")
+        .long("stashed")
+        .takes_value(true)
+        .possible_values(&["keep", "overwrite", "fail"])
+        .help("What to do with stashed components in INPUT (prompts on a terminal, \
+               otherwise fails)")
+}
+
+// shared by every command that fetches published components into INPUT
+fn fetch_target_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("target")
+        .long("target")
+        .takes_value(true)
+        .help("Fetch dependencies built for a target (else the defaultTarget of the component)")
+}
+
+// shared by every command with a machine readable output mode
+fn json_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("json")
+        .short("j")
+        .long("json")
+        .help("Produce machine readable json output")
+}
+
+fn keep_stashed_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("keep-stashed")
+        .long("keep-stashed")
+        .conflicts_with("stashed")
+        .help("Keep stashed components in INPUT (same as --stashed keep)")
+}
+
+fn deny_deprecated_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("deny-deprecated")
+        .long("deny-deprecated")
+        .help("Fail instead of warning when dependencies are deprecated")
+}
+
+// shared by the commands creating release, package and stash tarballs
+fn normalize_mtimes_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("normalize-mtimes")
+        .long("normalize-mtimes")
+        .help("Give every file in the tarball the same mtime (SOURCE_DATE_EPOCH or 1970) \
+               so identical builds give identical tarballs")
+}
+
+// shared by commands that can run over every member of a workspace
+fn workspace_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("all")
+        .long("all")
+        .short("a")
+        .help("Run for every member of the workspace.json in this or a parent directory")
+}
+
+// only meaningful when updated versions are saved in the manifest
+fn partial_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("partial")
+        .long("partial")
+        .help("Save the components that updated even if others failed")
+}
+
+// repeatable, and also takes comma separated lists (`--with test,bench`)
+fn group_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("group")
+        .long("group")
+        .alias("with")
+        .short("g")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+}
+
+fn fetch_options(args: &ArgMatches, cfg: &Config) -> FetchOptions {
+    let stashed = match args.value_of("stashed") {
+        _ if args.is_present("keep-stashed") => StashedPolicy::Keep,
+        Some("keep") => StashedPolicy::Keep,
+        Some("overwrite") => StashedPolicy::Overwrite,
+        Some("fail") => StashedPolicy::Fail,
+        _ => StashedPolicy::Prompt,
+    };
+    FetchOptions {
+        core: args.is_present("core"),
+        stashed: stashed,
+        deny_deprecated: args.is_present("deny-deprecated"),
+        report: args.value_of("report").map(String::from),
+        reuse_from: args.value_of("reuse-from").map(String::from),
+        groups: args.values_of("group")
+            .map(|xs| {
+                xs.flat_map(|x| x.split(','))
+                    .filter(|g| !g.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        all_groups: args.is_present("all-groups"),
+        sync: args.is_present("sync"),
+        include_stashed: args.is_present("include-stashed"),
+        partial: args.is_present("partial"),
+        siblings: Default::default(),
+        target: args.value_of("target").map(String::from),
+        hooks: cfg.runHooks,
+    }
+}
+
+fn status_options(args: &ArgMatches) -> StatusOptions {
+    StatusOptions {
+        full: args.is_present("full"),
+        versions: args.is_present("origin"),
+        time: args.is_present("time"),
+        paths: args.is_present("paths"),
+        json: args.is_present("json"),
+    }
+}
+
+// verify checks downgraded to warnings by the config and `--warn` flags
+fn verify_warnings(args: &ArgMatches, cfg: &Config) -> Vec<VerifyCheck> {
+    let mut checks = cfg.verifyWarnings.clone();
+    if let Some(names) = args.values_of("warn") {
+        checks.extend(names.filter_map(VerifyCheck::from_name)); // validated by clap
+    }
+    checks.sort();
+    checks.dedup();
+    checks
+}
+
+fn verify_warn_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("warn")
+        .long("warn")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .possible_values(VerifyCheck::names())
+        .help("Only warn when this verify check fails (not for release builds)")
+}
+
+fn build_options(args: &ArgMatches, cfg: &Config, container: &Container) -> BuildOptions {
+    BuildOptions {
+        name: args.value_of("component").map(String::from),
+        configuration: args.value_of("configuration").map(String::from),
+        target: args.value_of("target").map(String::from),
+        release: args.is_present("release"),
+        version: args.value_of("with-version").map(String::from),
+        sha: args.value_of("with-sha").map(String::from),
+        container: container.clone(),
+        force: args.is_present("force"),
+        simple_verify: args.is_present("simple-verify"),
+        verify_warnings: verify_warnings(args, cfg),
+        normalize_mtimes: args.is_present("normalize-mtimes"),
+        timings: args.is_present("timings"),
+    }
+}
+
+// fetch, build and status over every workspace member (these need no manifest here)
+fn handle_workspace_cmds(args: &ArgMatches,
+                         cfg: &Config,
+                         backend: &Backend,
+                         explicit_env: Option<&str>) {
+    let (name, a) = match args.subcommand() {
+        (name @ "fetch", Some(a)) |
+        (name @ "build", Some(a)) |
+        (name @ "status", Some(a)) if a.is_present("all") => (name, a),
+        _ => return,
+    };
+    let res = Workspace::find().and_then(|ws| match name {
+        "fetch" => {
+            let reporter = TerminalReporter::default();
+            lal::fetch_all(&ws, backend, &fetch_options(a, cfg), explicit_env, &reporter)
+        }
+        "build" => {
+            let modes = ShellModes {
+                printonly: a.is_present("print"),
+                x11_forwarding: a.is_present("x11"),
+                host_networking: a.is_present("net-host"),
+                env_vars: values_t!(a.values_of("env-var"), String).unwrap_or(vec![]),
+            };
+            // the container is resolved for each member
+            let bopts = build_options(a, cfg, &Container::default());
+            lal::build_all(cfg, &ws, &bopts, explicit_env, &modes)
+        }
+        _ => {
+            lal::status_all(&ws, &status_options(a))
+        }
+    });
+    result_exit(name, res);
+}
+
+fn handle_network_cmds(
+    args: &ArgMatches,
+    cfg: &Config,
+    mf: &Manifest,
+    backend: &Backend,
+    env: &str,
+) {
+    // fetch handles many components, so only update commands show phase lines
+    let reporter = if args.subcommand_matches("fetch").is_some() {
+        TerminalReporter::default()
+    } else {
+        TerminalReporter::with_phases()
+    };
+    let res = if let Some(a) = args.subcommand_matches("add") {
+        let xs = a.values_of("components").unwrap().map(String::from).collect::<Vec<_>>();
+        lal::add(mf, backend, xs, a.is_present("dev"), &fetch_options(a, cfg), env, &reporter)
+    } else if let Some(a) = args.subcommand_matches("update") {
         let xs = a.values_of("components").unwrap().map(String::from).collect::<Vec<_>>();
         lal::update(mf,
                     backend,
                     xs,
                     a.is_present("save"),
                     a.is_present("savedev"),
-                    env)
+                    &fetch_options(a, cfg),
+                    env,
+                    &reporter)
     } else if let Some(a) = args.subcommand_matches("update-all") {
-        lal::update_all(mf, backend, a.is_present("save"), a.is_present("dev"), env)
+        let policy = match a.value_of("policy") {
+            Some("newer") => UpdatePolicy::Newer,
+            _ => UpdatePolicy::Latest,
+        };
+        lal::update_all(mf,
+                        backend,
+                        a.is_present("save"),
+                        a.is_present("dev"),
+                        policy,
+                        &fetch_options(a, cfg),
+                        env,
+                        &reporter)
     } else if let Some(a) = args.subcommand_matches("fetch") {
-        lal::fetch(mf, backend, a.is_present("core"), env)
+        lal::fetch(mf, backend, &fetch_options(a, cfg), env, &reporter)
+    } else if let Some(a) = args.subcommand_matches("outdated") {
+        lal::outdated(mf, backend, env, a.is_present("json"))
+    } else if let Some(a) = args.subcommand_matches("status") {
+        lal::status_table(mf, backend, env, a.is_present("machine"))
     } else {
         return (); // not a network cmnd
     };
@@ -114,14 +523,18 @@ fn handle_env_command(
         .map_err(|e| {
             error!("Environment error: {}", e);
             println!("Ensure that manifest.environment has a corresponding entry in ~/.lal/config");
-            process::exit(1);
+            process::exit(e.code());
         })
         .unwrap();
 
     // resolve env updates and sticky options before main subcommands
     if let Some(a) = args.subcommand_matches("env") {
-        if a.subcommand_matches("update").is_some() {
-            result_exit("env update", lal::env::update(&container, env))
+        if a.subcommand_matches("sync").is_some() {
+            result_exit("env sync", lal::env::update(cfg, &container, env))
+        } else if a.subcommand_matches("update").is_some() {
+            result_exit("env update", lal::env::update_if_stale(cfg, &container, env))
+        } else if a.subcommand_matches("list").is_some() {
+            result_exit("env list", lal::env::list(cfg, env))
         } else if a.subcommand_matches("reset").is_some() {
             // NB: if .lal/opts.env points at an environment not in config
             // reset will fail.. possible to fix, but complects this file too much
@@ -142,6 +555,52 @@ fn handle_env_command(
     container
 }
 
+// create a storage backend (something that implements storage/traits.rs)
+fn make_backend(config: &Config, compression: CompressionOptions) -> Box<Backend> {
+    match &config.backend {
+        &BackendConfiguration::Artifactory(ref art_cfg) => {
+            let mut art = ArtifactoryBackend::new(&art_cfg, &config.cache);
+            art.cache_max_size = config.cache_max_bytes();
+            art.signatures = config.signature_policy();
+            art.compression = compression;
+            art.ca_bundle = config.caBundle.clone();
+            art.credential_provider = lal::credential_provider(&config);
+            Box::new(art)
+        }
+        &BackendConfiguration::Local(ref local_cfg) => {
+            let mut local = LocalBackend::new(&local_cfg, &config.cache);
+            local.cache_max_size = config.cache_max_bytes();
+            local.signatures = config.signature_policy();
+            local.compression = compression;
+            Box::new(local)
+        }
+    }
+}
+
+// run `lal-<name>` from the PATH for subcommands we do not know
+fn handle_plugin(args: &ArgMatches, builtins: &App, cfg: &Config) {
+    let (name, sub) = match args.subcommand() {
+        (name, Some(sub)) => (name, sub),
+        _ => return,
+    };
+    // builtin subcommands (and aliases) print help here, external ones parse fine
+    let probe = builtins.clone().get_matches_from_safe(vec!["lal", name, "--help"]);
+    if probe.is_err() {
+        return;
+    }
+    let pargs = sub.values_of("").map(|v| v.collect::<Vec<_>>()).unwrap_or_default();
+    let globals = PluginGlobals {
+        verbosity: args.occurrences_of("verbose"),
+        debug: args.is_present("debug"),
+        environment: args.value_of("environment").map(String::from),
+    };
+    match lal::run_plugin(name, &pargs, cfg, &globals) {
+        // plugins exit with their own status
+        Err(CliError::SubprocessFailure(code, _)) => process::exit(code),
+        res => result_exit(name, res),
+    }
+}
+
 #[cfg(feature = "upgrade")]
 fn handle_upgrade(args: &ArgMatches, cfg: &Config) {
     // we have a subcommand because SubcommandRequiredElseHelp
@@ -149,7 +608,7 @@ fn handle_upgrade(args: &ArgMatches, cfg: &Config) {
 
     // Allow lal upgrade without manifest
     if args.subcommand_matches("upgrade").is_some() {
-        result_exit("upgrade", lal::upgrade(false)); // explicit, verbose check
+        result_exit("upgrade", lal::upgrade(false, &cfg.http_options())); // explicit, verbose check
     }
 
     // Autoupgrade if enabled - runs once daily if enabled
@@ -158,7 +617,7 @@ fn handle_upgrade(args: &ArgMatches, cfg: &Config) {
         cfg.upgrade_check_time()
     {
         debug!("Performing daily upgrade check");
-        let _ = lal::upgrade(false).map_err(|e| {
+        let _ = lal::upgrade(false, &cfg.http_options()).map_err(|e| {
             error!("Daily upgrade check failed: {}", e);
             // don't halt here if this ever happens as it could break it for users
         });
@@ -178,29 +637,44 @@ fn handle_docker_cmds(
     cfg: &Config,
     env: &str,
     container: &Container,
+    backend: &Backend,
 ) {
     let res = if let Some(a) = args.subcommand_matches("verify") {
         // not really a docker related command, but it needs
         // the resolved env to verify consistent dependency usage
-        lal::verify(mf, env, a.is_present("simple"))
-    } else if let Some(a) = args.subcommand_matches("build") {
-        let bopts = BuildOptions {
-            name: a.value_of("component").map(String::from),
-            configuration: a.value_of("configuration").map(String::from),
-            release: a.is_present("release"),
-            version: a.value_of("with-version").map(String::from),
-            sha: a.value_of("with-sha").map(String::from),
-            container: container.clone(),
-            force: a.is_present("force"),
-            simple_verify: a.is_present("simple-verify"),
+        // release verification enforces every check
+        let release = a.is_present("release");
+        let vopts = VerifyOptions {
+            simple: a.is_present("simple"),
+            strict: a.is_present("strict") || release,
+            json: a.is_present("json"),
+            allowed: if release { vec![] } else { verify_warnings(a, cfg) },
         };
+        lal::verify(mf, env, &vopts)
+            .and_then(|_| if a.is_present("output") {
+                lal::verify_output(mf)
+            } else {
+                Ok(())
+            })
+    } else if let Some(a) = args.subcommand_matches("build") {
+        let bopts = build_options(a, cfg, container);
         let modes = ShellModes {
             printonly: a.is_present("print"),
             x11_forwarding: a.is_present("x11"),
             host_networking: a.is_present("net-host"),
             env_vars: values_t!(a.values_of("env-var"), String).unwrap_or(vec![]),
         };
-        lal::build(cfg, mf, &bopts, env.into(), modes)
+        let run_build = || if cfg.remoteBuildCache {
+            lal::build_with_remote_cache(cfg, mf, &bopts, env.into(), modes.clone(), backend)
+        } else {
+            lal::build(cfg, mf, &bopts, env.into(), modes.clone())
+        };
+        if a.is_present("watch") {
+            let debounce = a.value_of("debounce").map_or(300, |ms| ms.parse().unwrap());
+            lal::watch(Duration::from_millis(debounce), run_build)
+        } else {
+            run_build()
+        }
     } else if let Some(a) = args.subcommand_matches("shell") {
         let xs = if a.is_present("cmd") {
             Some(a.values_of("cmd").unwrap().collect::<Vec<_>>())
@@ -213,7 +687,13 @@ fn handle_docker_cmds(
             host_networking: a.is_present("net-host"),
             env_vars: values_t!(a.values_of("env-var"), String).unwrap_or(vec![]),
         };
-        lal::shell(cfg, container, &modes, xs, a.is_present("privileged"))
+        let one_off = xs.is_some();
+        let res = lal::shell(cfg, container, &modes, xs, a.is_present("privileged"));
+        if let (true, &Err(CliError::SubprocessFailure(code, _))) = (one_off, &res) {
+            // one-off commands exit with the status of the command
+            process::exit(code);
+        }
+        res
     } else if let Some(a) = args.subcommand_matches("run") {
         let xs = if a.is_present("parameters") {
             a.values_of("parameters").unwrap().collect::<Vec<_>>()
@@ -228,10 +708,24 @@ fn handle_docker_cmds(
         };
         lal::script(cfg,
                     container,
+                    mf,
                     a.value_of("script").unwrap(),
                     xs,
                     &modes,
                     a.is_present("privileged"))
+    } else if let Some(a) = args.subcommand_matches("test") {
+        let xs = if a.is_present("parameters") {
+            a.values_of("parameters").unwrap().collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+        let modes = ShellModes {
+            printonly: a.is_present("print"),
+            x11_forwarding: false,
+            host_networking: a.is_present("net-host"),
+            env_vars: values_t!(a.values_of("env-var"), String).unwrap_or(vec![]),
+        };
+        lal::test(cfg, container, mf, xs, &modes, a.is_present("privileged"))
     } else {
         return (); // no valid docker related command found
     };
@@ -243,10 +737,19 @@ fn main() {
         .version(crate_version!())
         .setting(AppSettings::VersionlessSubcommands)
         .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::AllowExternalSubcommands)
         .setting(AppSettings::ColoredHelp)
         .setting(AppSettings::DeriveDisplayOrder)
         .global_settings(&[AppSettings::ColoredHelp])
         .about("lal dependency manager")
+        .after_help("EXIT CODES:\n    \
+                     0  success\n    \
+                     1  unclassified failure\n    \
+                     2  manifest, config or usage error\n    \
+                     3  verify failure\n    \
+                     4  network or backend failure\n    \
+                     5  docker or build failure\n    \
+                     6  filesystem or cache failure")
         .arg(Arg::with_name("environment")
             .short("e")
             .long("env")
@@ -265,7 +768,39 @@ fn main() {
             .arg(Arg::with_name("core")
                 .long("core")
                 .short("c")
-                .help("Only fetch core dependencies")))
+                .help("Only fetch core dependencies"))
+            .arg(Arg::with_name("report")
+                .long("report")
+                .takes_value(true)
+                .help("Write a JSON report of what was fetched to this file"))
+            .arg(Arg::with_name("reuse-from")
+                .long("reuse-from")
+                .takes_value(true)
+                .help("Hardlink matching INPUT components from another workspace"))
+            .arg(group_arg().help("Also fetch the members of these dependency groups"))
+            .arg(Arg::with_name("all-groups")
+                .long("all-groups")
+                .conflicts_with("group")
+                .help("Fetch the members of every dependency group"))
+            .arg(Arg::with_name("sync")
+                .long("sync")
+                .help("Remove components in INPUT that are no longer in the manifest"))
+            .arg(Arg::with_name("include-stashed")
+                .long("include-stashed")
+                .requires("sync")
+                .help("Also remove stale stashed components when using --sync"))
+            .arg(Arg::with_name("from-lockfile")
+                .long("from-lockfile")
+                .takes_value(true)
+                .conflicts_with_all(&["core", "report", "stashed", "reuse-from", "group",
+                                      "all-groups", "sync", "include-stashed"])
+                .help("Fetch exactly the dependencies recorded in a lockfile, ignoring the \
+                       manifest"))
+            .arg(workspace_arg().conflicts_with_all(&["from-lockfile", "reuse-from", "report"]))
+            .arg(stashed_arg())
+            .arg(keep_stashed_arg())
+            .arg(fetch_target_arg())
+            .arg(deny_deprecated_arg()))
         .subcommand(SubCommand::with_name("build")
             .about("Runs BUILD script in current directory in the configured container")
             .arg(Arg::with_name("component")
@@ -275,14 +810,20 @@ fn main() {
                 .short("c")
                 .takes_value(true)
                 .help("Build using a specific configuration (else will use defaultConfig)"))
+            .arg(Arg::with_name("target")
+                .long("target")
+                .takes_value(true)
+                .help("Build for a specific target (else will use defaultTarget)"))
             .arg(Arg::with_name("simple-verify")
                 .short("s")
                 .long("simple-verify")
                 .help("Use verify --simple to check INPUT (allows stashed dependencies)"))
+            .arg(verify_warn_arg())
             .arg(Arg::with_name("force")
                 .long("force")
                 .short("f")
-                .help("Ignore verify errors when using custom dependencies"))
+                .help("Ignore verify errors, and rebuild even if nothing changed since the \
+                       last build"))
             .arg(Arg::with_name("release")
                 .long("release")
                 .short("r")
@@ -297,6 +838,7 @@ fn main() {
                 .takes_value(true)
                 .requires("release")
                 .help("Configure lockfiles with an explicit sha"))
+            .arg(normalize_mtimes_arg().requires("release"))
             .arg(Arg::with_name("x11")
                 .short("X")
                 .long("X11")
@@ -314,7 +856,36 @@ fn main() {
             .arg(Arg::with_name("print")
                 .long("print-only")
                 .conflicts_with("release")
-                .help("Only print the docker run command and exit")))
+                .help("Only print the docker run command and exit"))
+            .arg(Arg::with_name("timings")
+                .long("timings")
+                .help("Print how long each phase of the build took"))
+            .arg(Arg::with_name("watch")
+                .long("watch")
+                .conflicts_with_all(&["release", "print", "all"])
+                .help("Rebuild whenever the sources change, until interrupted"))
+            .arg(Arg::with_name("debounce")
+                .long("debounce")
+                .takes_value(true)
+                .validator(is_integer)
+                .requires("watch")
+                .help("Milliseconds the sources must be left alone before rebuilding (300)"))
+            .arg(workspace_arg().conflicts_with("component")))
+        .subcommand(SubCommand::with_name("add")
+            .about("Add new dependencies to the manifest and fetch them into INPUT")
+            .arg(Arg::with_name("components")
+                .help("Components to add, optionally as component=version (else latest)")
+                .required(true)
+                .multiple(true))
+            .arg(Arg::with_name("dev")
+                .short("D")
+                .long("dev")
+                .help("Add the components to devDependencies"))
+            .arg(group_arg().help("Also add the components to this dependency group"))
+            .arg(stashed_arg())
+            .arg(keep_stashed_arg())
+            .arg(fetch_target_arg())
+            .arg(deny_deprecated_arg()))
         .subcommand(SubCommand::with_name("update")
             .about("Update arbitrary dependencies into INPUT")
             .arg(Arg::with_name("components")
@@ -330,13 +901,47 @@ fn main() {
                 .short("D")
                 .long("save-dev")
                 .conflicts_with("save")
-                .help("Save updated versions in devDependencies in the manifest")))
+                .help("Save updated versions in devDependencies in the manifest"))
+            .group(ArgGroup::with_name("saving").args(&["save", "savedev"]))
+            .arg(group_arg().help("Add saved components to this dependency group"))
+            .arg(partial_arg().requires("saving"))
+            .arg(stashed_arg())
+            .arg(keep_stashed_arg())
+            .arg(fetch_target_arg())
+            .arg(deny_deprecated_arg()))
         .subcommand(SubCommand::with_name("verify")
             .arg(Arg::with_name("simple")
                 .short("s")
                 .long("simple")
                 .help("Allow stashed versions in this simpler verify algorithm"))
+            .arg(Arg::with_name("strict")
+                .long("strict")
+                .conflicts_with("simple")
+                .help("Also require identical images and INPUT matching lockfile digests"))
+            .arg(Arg::with_name("release")
+                .long("release")
+                .conflicts_with_all(&["simple", "warn"])
+                .help("Verify like a release build: strict, and every check is enforced"))
+            .arg(verify_warn_arg())
+            .arg(Arg::with_name("output")
+                .long("output")
+                .help("Also verify OUTPUT against the artifactContract in the manifest"))
+            .arg(json_arg())
             .about("verify consistency of INPUT"))
+        .subcommand(SubCommand::with_name("validate")
+            .about("Validate the manifest against its schema and list every problem"))
+        .subcommand(SubCommand::with_name("dep-tree")
+            .about("Print the dependency tree of INPUT with version conflicts")
+            .arg(Arg::with_name("dot")
+                .long("dot")
+                .help("Print the tree in graphviz dot format")))
+        .subcommand(SubCommand::with_name("why")
+            .about("Print the dependency paths that pull a component into INPUT")
+            .arg(Arg::with_name("component")
+                .required(true)
+                .help("Component to explain"))
+            .arg(Arg::with_name("version")
+                .help("Only show paths to this version")))
         .subcommand(SubCommand::with_name("status")
             .alias("ls")
             .arg(Arg::with_name("full")
@@ -351,6 +956,20 @@ fn main() {
                 .short("o")
                 .long("origin")
                 .help("Print version and environment origin of artifact"))
+            .arg(Arg::with_name("paths")
+                .short("p")
+                .long("paths")
+                .help("Print the cache path and digest each dependency was extracted from"))
+            .arg(Arg::with_name("table")
+                .long("table")
+                .conflicts_with_all(&["json", "full", "all"])
+                .help("Print a table of versions and drift from the manifest and backend"))
+            .arg(Arg::with_name("machine")
+                .long("machine")
+                .conflicts_with_all(&["json", "full", "all"])
+                .help("Print the table as tab separated values"))
+            .arg(json_arg())
+            .arg(workspace_arg().conflicts_with("json"))
             .about("Prints current dependencies and their status"))
         .subcommand(SubCommand::with_name("shell")
             .about("Enters the configured container mounting the current directory")
@@ -377,12 +996,37 @@ fn main() {
                 .long("print-only")
                 .help("Only print the docker run command and exit"))
             .setting(AppSettings::TrailingVarArg)
-            .arg(Arg::with_name("cmd").multiple(true)))
+            .arg(Arg::with_name("cmd")
+                .multiple(true)
+                .help("Command to run instead of a shell (e.g. lal shell -- make -j4)")))
+        .subcommand(SubCommand::with_name("test")
+            .about("Runs the test script from the manifest and summarizes its JUnit results")
+            .arg(Arg::with_name("net-host")
+                .short("n")
+                .long("net-host")
+                .help("Enable host networking"))
+            .arg(Arg::with_name("env-var")
+                .long("env-var")
+                .help("Set environment variables in the container")
+                .multiple(true)
+                .takes_value(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("print")
+                .long("print-only")
+                .help("Only print the docker run command and exit"))
+            .arg(Arg::with_name("privileged")
+                .short("p")
+                .long("privileged")
+                .help("Run docker in privileged mode"))
+            .setting(AppSettings::TrailingVarArg)
+            .arg(Arg::with_name("parameters")
+                .multiple(true)
+                .help("Parameters to pass on to the test script")))
         .subcommand(SubCommand::with_name("run")
-            .about("Runs scripts from .lal/scripts in the configured container")
+            .about("Runs scripts from the manifest in the configured container")
             .alias("script")
             .arg(Arg::with_name("script")
-                .help("Name of the script file to be run")
+                .help("Name of the script in the manifest to be run")
                 .required(true))
             .arg(Arg::with_name("x11")
                 .short("X")
@@ -416,22 +1060,126 @@ fn main() {
                 .help("Environment to build this component in"))
             .arg(Arg::with_name("force")
                 .short("f")
-                .help("overwrites manifest if necessary")))
+                .help("overwrites manifest if necessary"))
+            .arg(Arg::with_name("template")
+                .long("template")
+                .short("t")
+                .takes_value(true)
+                .help("Scaffold BUILD and scripts from a template (cpp-lib, cpp-bin, docs, \
+                       or one in ~/.lal/templates)")))
         .subcommand(SubCommand::with_name("configure")
             .about("Creates a default lal config ~/.lal/ from a defaults file")
             .arg(Arg::with_name("file")
                 .required(true)
-                .help("An environments file to seed the config with")))
+                .help("An environments file to seed the config with"))
+            .arg(Arg::with_name("project")
+                .long("project")
+                .help("Write a project config in ./.lal/ overriding the global one")))
         .subcommand(SubCommand::with_name("export")
-            .about("Fetch a raw tarball from artifactory")
+            .about("Fetch raw tarballs from artifactory")
             .arg(Arg::with_name("component")
-                .help("The component to export")
-                .required(true))
+                .help("The components to export as name or name=version")
+                .multiple(true)
+                .required_unless("manifest"))
+            .arg(Arg::with_name("manifest")
+                .long("manifest")
+                .conflicts_with("component")
+                .help("Export every dependency in the manifest at its pinned version"))
             .arg(Arg::with_name("output")
                 .short("o")
                 .long("output")
                 .takes_value(true)
                 .help("Output directory to save to")))
+        .subcommand(SubCommand::with_name("retention")
+            .about("Enforce the retention policy on published versions")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("apply")
+                .about("Delete published versions outside the retention policy \
+                        (in every environment without -e)")
+                .arg(Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .short("n")
+                    .help("Only print the versions that would be deleted"))))
+        .subcommand(SubCommand::with_name("lock")
+            .about("Inspect lockfile trees")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("diff")
+                .about("Compare the dependencies of two lockfile trees")
+                .arg(Arg::with_name("from")
+                    .required(true)
+                    .help("Lockfile path, component=version, or INPUT to compare from"))
+                .arg(Arg::with_name("to")
+                    .help("Lockfile path, component=version, or INPUT (default) to compare to"))
+                .arg(json_arg()))
+            .subcommand(SubCommand::with_name("export")
+                .about("Export a lockfile tree for other tooling")
+                .arg(Arg::with_name("lockfile")
+                    .help("Lockfile path, component=version, or INPUT (default) to export"))
+                .arg(Arg::with_name("flat")
+                    .long("flat")
+                    .help("Collapse the tree into a deduplicated list of dependencies"))
+                .arg(Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .possible_values(&["json", "csv"])
+                    .requires_if("csv", "flat")
+                    .help("Output format (csv needs --flat)"))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .takes_value(true)
+                    .help("File to write to instead of stdout"))))
+        .subcommand(SubCommand::with_name("sbom")
+            .about("Generate a software bill of materials for a build")
+            .arg(Arg::with_name("lockfile")
+                .help("Lockfile path or component=version (default OUTPUT/lockfile.json)"))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["cyclonedx", "spdx"])
+                .help("Document format (default cyclonedx)"))
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("File to write to instead of stdout")))
+        .subcommand(SubCommand::with_name("promote")
+            .about("Promote a published version to a release channel without rebuilding")
+            .arg(Arg::with_name("component")
+                .required(true)
+                .help("Published component=version to promote"))
+            .arg(Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .required(true)
+                .help("Release channel to promote to (e.g. stable)"))
+            .arg(Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+                .help("Release channel the version must already be in")))
+        .subcommand(SubCommand::with_name("import")
+            .about("Import a prebuilt tarball from outside lal as a published component")
+            .arg(Arg::with_name("tarball")
+                .required(true)
+                .help("Gzipped tarball to import"))
+            .arg(Arg::with_name("name")
+                .long("name")
+                .takes_value(true)
+                .required(true)
+                .help("Component name to import as"))
+            .arg(Arg::with_name("version")
+                .long("version")
+                .takes_value(true)
+                .required(true)
+                .validator(is_integer)
+                .help("Version to import as"))
+            .arg(Arg::with_name("env")
+                .long("env")
+                .takes_value(true)
+                .help("Environment to import into (defaults to the global -e)"))
+            .arg(Arg::with_name("publish")
+                .long("publish")
+                .help("Publish the imported component as well as caching it")))
         .subcommand(SubCommand::with_name("env")
             .about("Manages environment configurations")
             .subcommand(SubCommand::with_name("set")
@@ -439,16 +1187,55 @@ fn main() {
                 .arg(Arg::with_name("environment")
                     .required(true)
                     .help("Name of the environment to use")))
-            .subcommand(SubCommand::with_name("update").about("Update the current environment"))
+            .subcommand(SubCommand::with_name("list")
+                .alias("ls")
+                .about("List configured environments and their containers"))
+            .subcommand(SubCommand::with_name("sync")
+                .about("Pull the container of the current environment"))
+            .subcommand(SubCommand::with_name("update")
+                .about("Pull the container of the current environment if it was updated"))
             .subcommand(SubCommand::with_name("reset").about("Return to the default environment")))
+        .subcommand(SubCommand::with_name("package")
+            .about("Packs OUTPUT into the component tarball in ARTIFACT and prints its digest")
+            .arg(Arg::with_name("check-reproducible")
+                .long("check-reproducible")
+                .help("Pack OUTPUT twice and fail unless both tarballs are identical"))
+            .arg(normalize_mtimes_arg()))
         .subcommand(SubCommand::with_name("stash")
             .about("Stashes current build OUTPUT in cache for later reuse")
             .alias("save")
+            .setting(AppSettings::SubcommandsNegateReqs)
             .arg(Arg::with_name("name")
                 .required(true)
-                .help("Name used for current build")))
+                .help("Name used for current build"))
+            .subcommand(SubCommand::with_name("list")
+                .about("List stashed builds with their sizes, environments and ages")
+                .arg(Arg::with_name("component")
+                    .help("Only list stashes of this component")))
+            .subcommand(SubCommand::with_name("rm")
+                .alias("remove")
+                .about("Remove stashed builds from the cache")
+                .arg(Arg::with_name("all")
+                    .long("all")
+                    .conflicts_with("component")
+                    .help("Remove every stashed build"))
+                .arg(Arg::with_name("component")
+                    .required_unless("all")
+                    .help("Component to remove stashes of"))
+                .arg(Arg::with_name("name")
+                    .help("Stash to remove (all of the component's stashes if omitted)")))
+            .subcommand(SubCommand::with_name("push")
+                .about("Share a stash of this component with others through the backend")
+                .arg(Arg::with_name("name")
+                    .required(true)
+                    .help("Name of the local stash to share")))
+            .subcommand(SubCommand::with_name("pull")
+                .about("Fetch a stash shared by someone else into the local stash")
+                .arg(Arg::with_name("stash")
+                    .required(true)
+                    .help("Shared stash as <user>/<name>")))
+            .arg(normalize_mtimes_arg()))
         .subcommand(SubCommand::with_name("remove")
-            .alias("rm")
             .about("Remove specific dependencies from INPUT")
             .arg(Arg::with_name("components")
                 .help("Remove specific components")
@@ -464,24 +1251,99 @@ fn main() {
                 .long("save-dev")
                 .conflicts_with("save")
                 .help("Save removal of devDependencies in the manifest")))
+        .subcommand(SubCommand::with_name("rm")
+            .about("Remove dependencies from the manifest and INPUT")
+            .arg(Arg::with_name("components")
+                .help("Components to remove from dependencies or devDependencies")
+                .required(true)
+                .multiple(true)))
+        .subcommand(SubCommand::with_name("logs")
+            .about("Show the captured output of earlier builds")
+            .arg(Arg::with_name("log").help("Name of a log in .lal/logs to print"))
+            .arg(Arg::with_name("last")
+                .long("last")
+                .conflicts_with("log")
+                .help("Print the log of the last build")))
         .subcommand(SubCommand::with_name("clean")
             .about("Clean old artifacts in the cache directory to save space")
             .arg(Arg::with_name("days")
                 .short("d")
                 .long("days")
                 .takes_value(true)
-                .default_value("14")
                 .validator(is_integer)
-                .help("Number of days to serve as cutoff")))
+                .help("Number of days to serve as cutoff (default 14)"))
+            .arg(Arg::with_name("stash")
+                .long("stash")
+                .help("Also remove all stashed builds regardless of age"))
+            .arg(Arg::with_name("all")
+                .long("all")
+                .conflicts_with_all(&["days", "stash"])
+                .help("Remove everything in the cache"))
+            .arg(Arg::with_name("dry-run")
+                .long("dry-run")
+                .short("n")
+                .help("Only print what would be removed and the space it would reclaim")))
+        .subcommand(SubCommand::with_name("cache")
+            .about("Manages the artifact cache")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("move")
+                .about("Move the cache to a new directory and update the config")
+                .arg(Arg::with_name("directory")
+                    .required(true)
+                    .help("New cache directory")))
+            .subcommand(SubCommand::with_name("serve")
+                .about("Serve the cache to other machines as a read-only artifactory backend")
+                .arg(Arg::with_name("address")
+                    .long("address")
+                    .short("a")
+                    .takes_value(true)
+                    .default_value("0.0.0.0:8080")
+                    .help("Address and port to listen on"))))
         .subcommand(SubCommand::with_name("query")
             .about("Query for available versions on artifactory")
             .arg(Arg::with_name("latest")
                 .long("latest")
                 .short("l")
                 .help("Return latest version only"))
+            .arg(json_arg())
+            .arg(Arg::with_name("component")
+                .required(true)
+                .help("Component name to search for"))
+            .setting(AppSettings::SubcommandsNegateReqs)
+            .subcommand(SubCommand::with_name("meta")
+                .about("Show the properties recorded on a published version")
+                .arg(json_arg())
+                .arg(Arg::with_name("component")
+                    .required(true)
+                    .help("Published component=version"))))
+        .subcommand(SubCommand::with_name("search")
+            .about("Search for published components (in every environment without -e)")
+            .arg(Arg::with_name("regex")
+                .long("regex")
+                .short("r")
+                .help("Treat the search term as a regular expression"))
+            .arg(json_arg())
+            .arg(Arg::with_name("term")
+                .required(true)
+                .help("Substring (or regex) of component names to find")))
+        .subcommand(SubCommand::with_name("list-versions")
+            .about("List available versions of a component (in every environment without -e)")
+            .arg(Arg::with_name("last")
+                .long("last")
+                .short("l")
+                .takes_value(true)
+                .validator(is_integer)
+                .help("Only show the newest N versions"))
+            .arg(json_arg())
             .arg(Arg::with_name("component")
                 .required(true)
-                .help("Component name to search for")))
+                .help("Component name to list versions of")))
+        .subcommand(SubCommand::with_name("outdated")
+            .about("Compare manifest dependencies against the latest published versions")
+            .arg(Arg::with_name("json")
+                .short("j")
+                .long("json")
+                .help("Produce a machine readable list of all dependencies")))
         .subcommand(SubCommand::with_name("propagate")
             .about("Show steps to propagate a version fully through the tree")
             .arg(Arg::with_name("component")
@@ -500,12 +1362,41 @@ fn main() {
             .arg(Arg::with_name("save")
                 .short("S")
                 .long("save")
-                .help("Save updated versions in the right object in the manifest")))
+                .help("Save updated versions in the right object in the manifest"))
+            .arg(Arg::with_name("policy")
+                .long("policy")
+                .takes_value(true)
+                .possible_values(&["latest", "newer"])
+                .default_value("latest")
+                .help("Move pins to latest, or only when newer than the current pin"))
+            .arg(partial_arg().requires("save"))
+            .arg(stashed_arg())
+            .arg(keep_stashed_arg())
+            .arg(fetch_target_arg())
+            .arg(deny_deprecated_arg()))
         .subcommand(SubCommand::with_name("publish")
             .setting(AppSettings::Hidden)
             .arg(Arg::with_name("component")
                 .required(true)
                 .help("Component name to publish"))
+            .arg(Arg::with_name("channel")
+                .long("channel")
+                .takes_value(true)
+                .help("Release channel to put the published version in (e.g. staging)"))
+            .arg(Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Check publish preconditions and show what would be uploaded"))
+            .arg(Arg::with_name("bump")
+                .long("bump")
+                .conflicts_with("dry-run")
+                .help("Publish as the version after the latest published one"))
+            .arg(Arg::with_name("meta")
+                .long("meta")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Property to record on the published version as key=value"))
+            .arg(normalize_mtimes_arg())
             .about("Publish a release build to the default artifactory location"))
         .subcommand(SubCommand::with_name("list-components")
             .setting(AppSettings::Hidden)
@@ -522,6 +1413,15 @@ fn main() {
                 .required(true)
                 .help("Component name to look for in the manifest"))
             .about("list configurations for a given component"))
+        .subcommand(SubCommand::with_name("list-targets")
+            .setting(AppSettings::Hidden)
+            .arg(Arg::with_name("component")
+                .required(true)
+                .help("Component name to look for in the manifest"))
+            .about("list targets for a given component"))
+        .subcommand(SubCommand::with_name("list-scripts")
+            .setting(AppSettings::Hidden)
+            .about("list scripts in the manifest"))
         .subcommand(SubCommand::with_name("list-dependencies")
             .setting(AppSettings::Hidden)
             .arg(Arg::with_name("core")
@@ -535,6 +1435,7 @@ fn main() {
                                  .about("Attempts to upgrade lal from artifactory"));
     }
 
+    let builtins = app.clone();
     let args = app.get_matches();
 
     // by default, always show INFO messages for now (+1)
@@ -548,7 +1449,10 @@ fn main() {
     // Allow lal configure without assumptions
     if let Some(a) = args.subcommand_matches("configure") {
         result_exit("configure",
-                    lal::configure(true, true, a.value_of("file").unwrap()));
+                    lal::configure(true,
+                                   true,
+                                   a.value_of("file").unwrap(),
+                                   a.is_present("project")));
     }
 
     // Force config to exists before allowing remaining actions
@@ -559,35 +1463,73 @@ fn main() {
             println!("If you just got upgraded use `lal configure <site-config>`");
             println!("Site configs are found in {{install_prefix}}/share/lal/configs/ \
                       and should auto-complete");
-            process::exit(1);
+            process::exit(e.code());
         })
         .unwrap();
 
-    // Create a storage backend (something that implements storage/traits.rs)
-    let backend: Box<Backend> = match &config.backend {
-        &BackendConfiguration::Artifactory(ref art_cfg) => {
-            Box::new(ArtifactoryBackend::new(&art_cfg, &config.cache))
-        }
-        &BackendConfiguration::Local(ref local_cfg) => {
-            Box::new(LocalBackend::new(&local_cfg, &config.cache))
+    handle_plugin(&args, &builtins, &config);
+
+    // `--normalize-mtimes` covers the tarballs the backend creates (stashes and restamps)
+    let mut compression = config.compression_options();
+    compression.normalize_mtimes = match args.subcommand() {
+        ("stash", Some(a)) | ("publish", Some(a)) | ("package", Some(a)) => {
+            a.is_present("normalize-mtimes")
         }
+        _ => false,
     };
 
+    // Create a storage backend (something that implements storage/traits.rs)
+    let backend = make_backend(&config, compression);
+
     // Ensure SSL is initialized before using the backend
     openssl_probe::init_ssl_cert_env_vars();
 
     // Do upgrade checks or handle explicit `lal upgrade` here
     #[cfg(feature = "upgrade")] handle_upgrade(&args, &config);
 
-    // Allow lal init / clean without manifest existing in PWD
+    // Allow lal init / clean / validate / logs without a valid manifest in PWD
     if let Some(a) = args.subcommand_matches("init") {
         result_exit("init",
                     lal::init(&config,
                               a.is_present("force"),
-                              a.value_of("environment").unwrap()));
+                              a.value_of("environment").unwrap(),
+                              a.value_of("template")));
     } else if let Some(a) = args.subcommand_matches("clean") {
-        let days = a.value_of("days").unwrap().parse().unwrap();
-        result_exit("clean", lal::clean(&config.cache, days));
+        let mut opts = CleanOptions {
+            stash: a.is_present("stash"),
+            all: a.is_present("all"),
+            dry_run: a.is_present("dry-run"),
+            ..CleanOptions::default()
+        };
+        if let Some(days) = a.value_of("days") {
+            opts.days = days.parse().unwrap();
+        }
+        let res = lal::clean(&config.cache, &opts).map(|summary| {
+            if opts.dry_run {
+                for &(ref pth, size) in &summary.folders {
+                    println!("{} ({} kB)", pth.display(), size / 1024);
+                }
+                println!("Would reclaim {} MB from {} cache folders",
+                         summary.reclaimed / (1024 * 1024),
+                         summary.folders.len());
+            }
+        });
+        result_exit("clean", res);
+    } else if args.subcommand_matches("validate").is_some() {
+        result_exit("validate", lal::validate());
+    } else if let Some(a) = args.subcommand_matches("logs") {
+        result_exit("logs", lal::logs(a.value_of("log"), a.is_present("last")));
+    } else if let Some(a) = args.subcommand_matches("cache") {
+        if let Some(ma) = a.subcommand_matches("move") {
+            result_exit("cache",
+                        lal::cache::relocate(&config, ma.value_of("directory").unwrap()));
+        } else if let Some(ma) = a.subcommand_matches("serve") {
+            // every worker gets a backend of its own
+            let served = config.clone();
+            let res = lal::cache::bind(ma.value_of("address").unwrap())
+                .and_then(|l| lal::cache::serve(l, move || make_backend(&served, compression)));
+            result_exit("cache", res);
+        }
     }
 
     // Read .lal/opts if it exists
@@ -596,7 +1538,7 @@ fn main() {
             // Should not happen unless people are mucking with it manually
             error!("Options error: {}", e);
             println!(".lal/opts must be valid json");
-            process::exit(1);
+            process::exit(e.code());
         })
         .unwrap(); // we get a default empty options here otherwise
 
@@ -607,10 +1549,12 @@ fn main() {
             .get_container(env.into())
             .map_err(|e| {
                 error!("Environment error: {}", e);
-                process::exit(1)
+                process::exit(e.code())
             })
             .unwrap();
     }
+    let _locks = acquire_locks(&args, &config);
+    handle_workspace_cmds(&args, &config, backend.deref(), explicit_env);
     handle_manifest_agnostic_cmds(&args, &config, backend.deref(), explicit_env);
 
     // Force manifest to exist before allowing remaining actions
@@ -618,12 +1562,12 @@ fn main() {
         .map_err(|e| {
             error!("Manifest error: {}", e);
             println!("Ensure manifest.json is valid json or run `lal init`");
-            process::exit(1);
+            process::exit(e.code());
         })
         .unwrap();
 
     // Subcommands that are environment agnostic
-    handle_environment_agnostic_cmds(&args, &manifest, backend.deref());
+    handle_environment_agnostic_cmds(&args, &config, &manifest, backend.deref());
 
     // Force a valid container key configured in manifest and corr. value in config
     // NB: --env overrides sticky env overrides manifest.env
@@ -646,8 +1590,8 @@ fn main() {
     }
 
     // Main subcommands
-    handle_network_cmds(&args, &manifest, backend.deref(), &env);
-    handle_docker_cmds(&args, &manifest, &config, &env, &container);
+    handle_network_cmds(&args, &config, &manifest, backend.deref(), &env);
+    handle_docker_cmds(&args, &manifest, &config, &env, &container, backend.deref());
 
     unreachable!("Subcommand valid, but not implemented");
 }
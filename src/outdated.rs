@@ -0,0 +1,100 @@
+use ansi_term::Colour;
+use chrono::UTC;
+use serde_json;
+
+use storage::Backend;
+use super::{CliError, LalResult, Manifest};
+
+/// A manifest dependency compared against the latest published version
+#[derive(Serialize, Clone, Debug)]
+pub struct OutdatedEntry {
+    /// Component name
+    pub name: String,
+    /// Version (or version range) in the manifest
+    pub current: String,
+    /// Latest version published in the environment
+    pub latest: u32,
+    /// Whether the manifest does not allow the latest version
+    pub outdated: bool,
+    /// Days since the latest version was published, if it is not allowed
+    pub age_days: Option<i64>,
+    /// Deprecation notice for the manifest version (if any)
+    pub deprecated: Option<String>,
+}
+
+/// Compare every dependency in the manifest against the latest version in an environment
+pub fn outdated_dependencies(
+    manifest: &Manifest,
+    backend: &Backend,
+    env: &str,
+) -> LalResult<Vec<OutdatedEntry>> {
+    let deprecations = backend.get_deprecations()?;
+    let mut res = vec![];
+    for (name, &version) in &manifest.all_dependencies() {
+        // channel pins are only behind the latest version in their channel
+        let channel = manifest.version_range(name).and_then(|r| r.channel.clone());
+        let latest = match channel {
+            Some(ref c) => {
+                let versions = backend.get_channel_versions(name, c, env)?;
+                versions.into_iter()
+                    .max()
+                    .ok_or_else(|| CliError::NoMatchingVersion(name.clone(), format!("@{}", c)))?
+            }
+            None => backend.get_latest_version(name, env)?,
+        };
+        let (current, outdated) = match manifest.version_range(name) {
+            Some(range) => (range.to_string(), !range.matches(latest)),
+            None => (version.to_string(), latest > version),
+        };
+        let age_days = if outdated {
+            backend.get_publish_time(name, latest, env)?
+                .map(|t| (UTC::now() - t).num_days())
+        } else {
+            None
+        };
+        debug!("{} at {} has latest {} in {}", name, current, latest, env);
+        res.push(OutdatedEntry {
+            name: name.clone(),
+            current: current,
+            latest: latest,
+            outdated: outdated,
+            age_days: age_days,
+            deprecated: deprecations.notice(name, version),
+        });
+    }
+    Ok(res)
+}
+
+/// Print how far the manifest dependencies are behind the latest published versions
+///
+/// The age is how long the latest version has been available without being used.
+/// The `json` output lists every dependency for use in CI.
+pub fn outdated(manifest: &Manifest, backend: &Backend, env: &str, json: bool) -> LalResult<()> {
+    let entries = outdated_dependencies(manifest, backend, env)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+    let width = entries.iter().map(|e| e.name.len()).max().unwrap_or(0).max(4);
+    println!("{:w$}  {:>10}  {:>8}  {:>6}", "name", "current", "latest", "age", w = width);
+    for e in &entries {
+        let age = e.age_days.map(|d| format!("{}d", d)).unwrap_or_else(|| "-".into());
+        let line = format!("{:w$}  {:>10}  {:>8}  {:>6}",
+                           e.name,
+                           e.current,
+                           e.latest,
+                           age,
+                           w = width);
+        if e.outdated {
+            println!("{}", Colour::Yellow.paint(line));
+        } else {
+            println!("{}", line);
+        }
+        if let Some(ref d) = e.deprecated {
+            println!("  {}", Colour::Red.paint(d.clone()));
+        }
+    }
+    let behind = entries.iter().filter(|e| e.outdated).count();
+    info!("{} of {} dependencies are outdated in {}", behind, entries.len(), env);
+    Ok(())
+}
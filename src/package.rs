@@ -0,0 +1,54 @@
+use std::fs;
+use std::path::Path;
+
+// Need both the struct and the trait
+use storage::{Backend, sha256_file};
+use super::{LalResult, CliError, IoResultExt, Lockfile, Manifest, COMPRESSIONS, output};
+
+/// Pack OUTPUT into the component tarball in ARTIFACT and return its sha256 digest
+///
+/// Only the tarball is replaced (tarballs of other compressions are removed), so a
+/// release build in ARTIFACT keeps its lockfile. With `check_reproducible` OUTPUT is
+/// packed a second time, and `UnreproducibleArchive` is returned unless both tarballs
+/// have the same digest.
+pub fn package<T: Backend + ?Sized>(
+    backend: &T,
+    manifest: &Manifest,
+    check_reproducible: bool,
+) -> LalResult<String> {
+    let lfpth = Path::new("./OUTPUT/lockfile.json");
+    if !lfpth.is_file() {
+        return Err(CliError::MissingBuild);
+    }
+    // OUTPUT may be a build of another component in the manifest
+    let name = Lockfile::from_path(lfpth, &manifest.name)?.name;
+
+    let artdir = Path::new("./ARTIFACT");
+    fs::create_dir_all(artdir).context("Failed to create ARTIFACT")?;
+    for c in &COMPRESSIONS {
+        let old = artdir.join(c.tarball_name(&name));
+        if old.exists() {
+            fs::remove_file(&old).context(format!("Failed to remove {}", old.display()))?;
+        }
+    }
+    let tarball = artdir.join(backend.get_compression().tarball_name(&name));
+    output::tar(&tarball, backend.get_compression())?;
+    let digest = format!("sha256:{}", sha256_file(&tarball)?);
+    info!("Packed {} ({})", tarball.display(), digest);
+
+    if check_reproducible {
+        let checkdir = artdir.join(".reproducible");
+        fs::create_dir_all(&checkdir).context("Failed to create ARTIFACT/.reproducible")?;
+        let again = checkdir.join(tarball.file_name().unwrap());
+        let res = output::tar(&again, backend.get_compression())
+            .and_then(|_| sha256_file(&again))
+            .map(|d| format!("sha256:{}", d));
+        let _ = fs::remove_dir_all(&checkdir);
+        let second = res?;
+        if second != digest {
+            return Err(CliError::UnreproducibleArchive(digest, second));
+        }
+        info!("Packing {} again gave the same tarball", name);
+    }
+    Ok(digest)
+}
@@ -0,0 +1,81 @@
+use std::env;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use serde_json;
+
+use super::{CliError, LalResult, IoResultExt, Config, Manifest, exit_code};
+
+/// Global lal flags forwarded to plugins
+#[derive(Default)]
+pub struct PluginGlobals {
+    /// Number of `-v` flags
+    pub verbosity: u64,
+    /// Whether `-d` was passed
+    pub debug: bool,
+    /// Environment passed with `-e`
+    pub environment: Option<String>,
+}
+
+/// What a plugin receives as json on stdin
+#[derive(Serialize)]
+struct PluginInput<'a> {
+    config: &'a Config,
+    manifest: Option<Manifest>,
+}
+
+/// Find the executable `lal-<name>` on the `PATH`
+pub fn find_plugin(name: &str) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+    let exe = format!("lal-{}", name);
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(&exe))
+            .find(|pth| {
+                pth.metadata().map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                    .unwrap_or(false)
+            })
+    })
+}
+
+/// Run an external `lal-<name>` subcommand
+///
+/// Like git, unknown subcommands run an executable called `lal-<name>` from the `PATH`
+/// with the remaining arguments, so teams can extend lal without forking it.
+/// The plugin gets a json object with the `config` and the `manifest` (null outside
+/// of a component) on stdin, and the global flags as `LAL_VERBOSE`, `LAL_DEBUG` and
+/// `LAL_ENVIRONMENT` environment variables.
+pub fn run_plugin(name: &str, args: &[&str], cfg: &Config, globals: &PluginGlobals)
+    -> LalResult<()> {
+    let exe = find_plugin(name).ok_or_else(|| CliError::MissingPlugin(name.into()))?;
+    debug!("Running plugin {}", exe.display());
+    let input = PluginInput {
+        config: cfg,
+        manifest: Manifest::read().ok(),
+    };
+    let data = serde_json::to_string(&input)?;
+
+    let mut cmd = Command::new(&exe);
+    cmd.args(args)
+        .stdin(Stdio::piped())
+        .env("LAL_VERBOSE", globals.verbosity.to_string());
+    if globals.debug {
+        cmd.env("LAL_DEBUG", "1");
+    }
+    if let Some(ref e) = globals.environment {
+        cmd.env("LAL_ENVIRONMENT", e);
+    }
+    let mut child = cmd.spawn().context(format!("Failed to run plugin {}", exe.display()))?;
+    // plugins that ignore stdin must not block on a full pipe
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || {
+        let _ = stdin.write_all(data.as_bytes());
+    });
+    let s = child.wait().context(format!("Failed to wait for plugin {}", exe.display()))?;
+    let _ = writer.join();
+    if !s.success() {
+        return Err(CliError::SubprocessFailure(exit_code(&s), None));
+    }
+    Ok(())
+}
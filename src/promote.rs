@@ -0,0 +1,46 @@
+// Need both the struct and the trait
+use storage::Backend;
+use super::{LalResult, CliError, is_valid_channel};
+
+/// Parse a `component=version` reference to a published component
+pub fn parse_component_version(reference: &str) -> LalResult<(String, u32)> {
+    let pair = reference.splitn(2, '=').collect::<Vec<_>>();
+    if pair.len() != 2 || pair[0].is_empty() {
+        return Err(CliError::InvalidVersion(reference.into()));
+    }
+    let version = pair[1].parse::<u32>().map_err(|_| CliError::InvalidVersion(reference.into()))?;
+    Ok((pair[0].into(), version))
+}
+
+/// Promote a published version of a component to a release channel
+///
+/// The artifact is tagged in the backend rather than rebuilt or uploaded again, so
+/// dependencies pinned to the channel (like `"@stable"`) resolve to it on `lal fetch`.
+/// With `from`, the version must already be in that channel (e.g. `staging` before `stable`).
+pub fn promote<T: Backend + ?Sized>(
+    backend: &T,
+    component: &str,
+    version: u32,
+    env: &str,
+    to: &str,
+    from: Option<&str>,
+) -> LalResult<()> {
+    for c in Some(to).iter().chain(from.iter()) {
+        if !is_valid_channel(c) {
+            return Err(CliError::InvalidChannel(c.to_string()));
+        }
+    }
+    if !backend.get_versions(component, env)?.contains(&version) {
+        let published = format!("{}={}", component, version);
+        return Err(CliError::NoMatchingVersion(component.into(), published));
+    }
+    if let Some(src) = from {
+        if !backend.get_channel_versions(component, src, env)?.contains(&version) {
+            let reference = format!("{}={}", component, version);
+            return Err(CliError::NotInChannel(reference, src.into()));
+        }
+    }
+    backend.tag_channel(component, version, to, env)?;
+    info!("Promoted {}={} to {} in {}", component, version, to, env);
+    Ok(())
+}
@@ -1,22 +1,38 @@
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use ansi_term::Colour;
 
 // Need both the struct and the trait
 use storage::Backend;
-use super::{LalResult, CliError, Lockfile};
+use hooks::{run_hook, HookContext};
+use sbom::{sbom_document, SbomFormat};
+use super::{LalResult, CliError, CompressionOptions, IoResultExt, Lockfile, Manifest,
+            DependencyGraph, input, output, find_tarball, is_valid_channel, sign_file,
+            signature_path};
 
-/// Publish a release build to the storage backend
-///
-/// Meant to be done after a `lal build -r <component>`
-/// and requires publish credentials in the local `Config`.
-pub fn publish<T: Backend + ?Sized>(name: &str, backend: &T) -> LalResult<()> {
+// release tarballs bigger than this are reported in `--dry-run`
+const LARGE_TARBALL_BYTES: u64 = 1024 * 1024 * 1024;
+
+// how many times `--bump` allocates a new version when racing other publishes
+const BUMP_ATTEMPTS: u32 = 5;
+
+// the release build in ARTIFACT with its lockfile
+fn release_build(name: &str) -> LalResult<(PathBuf, Lockfile)> {
     let artdir = Path::new("./ARTIFACT");
-    let tarball = artdir.join(format!("{}.tar.gz", name));
+    let tarball = find_tarball(artdir, name);
     if !artdir.is_dir() || !tarball.exists() {
         warn!("Missing: {}", tarball.display());
         return Err(CliError::MissingReleaseBuild);
     }
+    Ok((tarball, Lockfile::release_build()?))
+}
 
-    let lock = Lockfile::release_build()?;
+// the release build in ARTIFACT with the version it was built with
+fn release_artifact(name: &str) -> LalResult<(PathBuf, Lockfile, u32)> {
+    let (tarball, lock) = release_build(name)?;
 
     let version = lock.version
         .parse::<u32>()
@@ -25,16 +41,394 @@ pub fn publish<T: Backend + ?Sized>(name: &str, backend: &T) -> LalResult<()> {
             debug!("Error: {}", e);
             CliError::MissingReleaseBuild
         })?;
+    Ok((tarball, lock, version))
+}
+
+/// Options for `lal publish`
+#[derive(Clone, Debug, Default)]
+pub struct PublishOptions {
+    /// Release channel to put the published version in (e.g. `staging`)
+    pub channel: Option<String>,
+    /// Properties to record on the published version
+    pub metadata: BTreeMap<String, String>,
+    /// Run the `pre-publish` and `post-publish` hooks (the `runHooks` config setting)
+    pub hooks: bool,
+}
+
+impl PublishOptions {
+    // everything is checked before anything is uploaded
+    fn check(&self) -> LalResult<()> {
+        if let Some(ref c) = self.channel {
+            if !is_valid_channel(c) {
+                return Err(CliError::InvalidChannel(c.clone()));
+            }
+        }
+        for k in self.metadata.keys() {
+            if !is_valid_metadata_key(k) {
+                return Err(CliError::InvalidMetadata(k.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+// property keys are plain identifiers, and `lal.` ones are set by lal itself
+fn is_valid_metadata_key(key: &str) -> bool {
+    !key.is_empty() && !key.starts_with("lal.") &&
+    key.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+}
+
+/// Properties to publish from the `publishMetadata` manifest section and `key=value` flags
+///
+/// Manifest values starting with `$` are read from that environment variable,
+/// and are left out when it is unset. Flags take precedence over the manifest.
+pub fn publish_metadata(
+    manifest: Option<&Manifest>,
+    flags: &[&str],
+) -> LalResult<BTreeMap<String, String>> {
+    let mut meta = BTreeMap::new();
+    if let Some(mf) = manifest {
+        for (k, v) in &mf.publishMetadata {
+            if v.starts_with('$') {
+                match env::var(&v[1..]) {
+                    Ok(val) => meta.insert(k.clone(), val),
+                    Err(_) => {
+                        debug!("Not publishing {} as {} is not set", k, v);
+                        continue;
+                    }
+                };
+            } else {
+                meta.insert(k.clone(), v.clone());
+            }
+        }
+    }
+    for f in flags {
+        let mut parts = f.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(k), Some(v)) if !k.is_empty() => meta.insert(k.into(), v.into()),
+            _ => return Err(CliError::InvalidMetadata(f.to_string())),
+        };
+    }
+    Ok(meta)
+}
+
+/// Publish a release build to the storage backend
+///
+/// Meant to be done after a `lal build -r <component>`
+/// and requires publish credentials in the local `Config`.
+/// The `pre-publish` and `post-publish` hooks run around the upload.
+/// With a `signingKey` in the config, the tarball is signed with gpg and the
+/// signature is uploaded next to it.
+/// With a `channel`, the published version is also put in that release channel
+/// so that it can be promoted from there with `lal promote`.
+/// The `metadata` is recorded as properties on the published version, along with
+/// the `lal.environment`, `lal.target` and `lal.sha` of the build.
+///
+/// Builds for a target are published in the location of that target next to the
+/// environment (see `target_location`), so each target has its own versions.
+pub fn publish<T: Backend + ?Sized>(
+    name: &str,
+    backend: &T,
+    opts: &PublishOptions,
+) -> LalResult<()> {
+    opts.check()?;
+    let (tarball, lock, version) = release_artifact(name)?;
 
     if lock.sha.is_none() {
         warn!("Release build not done --with-sha=$(git rev-parse HEAD)");
     }
 
-    // always publish to the environment in the lockfile
-    let env = lock.environment;
+    // always publish to the environment (and target) in the lockfile
+    upload(name, version, &lock, backend, opts, &tarball, false)
+}
 
-    info!("Publishing {}={} to {}", name, version, env);
-    backend.publish_artifact(name, version, &env)?;
+/// Publish a release build as the next version of the component
+///
+/// Like `publish`, but the release build does not need a version. The latest published
+/// version in the environment of the build is looked up, and the build is stamped and
+/// published as the version after it. Nothing is replaced: when another publish takes
+/// the version first, the next free version is allocated and tried instead.
+///
+/// Returns the version that was published.
+pub fn publish_bump<T: Backend + ?Sized>(
+    name: &str,
+    backend: &T,
+    opts: &PublishOptions,
+) -> LalResult<u32> {
+    opts.check()?;
+    let (tarball, mut lock) = release_build(name)?;
+    if lock.sha.is_none() {
+        warn!("Release build not done --with-sha=$(git rev-parse HEAD)");
+    }
+    let loc = lock.location();
+
+    for _ in 0..BUMP_ATTEMPTS {
+        // a component that was never published starts at version 1
+        let latest = backend.get_versions(name, &loc)?.into_iter().max().unwrap_or(0);
+        let version = latest + 1;
+        stamp_version(name, &tarball, &mut lock, version, backend.get_compression())?;
+        match upload(name, version, &lock, backend, opts, &tarball, true) {
+            Err(CliError::VersionTaken(..)) => {
+                warn!("{}={} was published concurrently - allocating another version",
+                      name,
+                      version);
+            }
+            res => return res.map(|_| version),
+        }
+    }
+    Err(CliError::VersionAllocationFailure(name.into()))
+}
+
+// rewrite the release build in ARTIFACT as a different version
+fn stamp_version(
+    name: &str,
+    tarball: &Path,
+    lock: &mut Lockfile,
+    version: u32,
+    opts: &CompressionOptions,
+) -> LalResult<()> {
+    if !Path::new("./OUTPUT").is_dir() {
+        return Err(CliError::MissingBuild);
+    }
+    lock.version = version.to_string();
+    debug!("Stamping release build of {} as version {}", name, version);
+    // the tarball carries the lockfile from OUTPUT
+    lock.write(Path::new("./OUTPUT/lockfile.json"))?;
+    lock.write(Path::new("./ARTIFACT/lockfile.json"))?;
+    let bompth = Path::new("./ARTIFACT/sbom.cdx.json");
+    if bompth.is_file() {
+        let bom = sbom_document(lock, SbomFormat::CycloneDx)?;
+        fs::File::create(bompth)
+            .and_then(|mut f| f.write_all(bom.as_bytes()))
+            .context(format!("Failed to write {}", bompth.display()))?;
+    }
+    output::tar(tarball, opts)
+}
+
+/// Sign and upload the release build in ARTIFACT between the publish hooks
+///
+/// Shared by `publish` and `import`, which assemble ARTIFACT themselves.
+pub fn upload<T: Backend + ?Sized>(
+    name: &str,
+    version: u32,
+    lock: &Lockfile,
+    backend: &T,
+    opts: &PublishOptions,
+    tarball: &Path,
+    new_version: bool,
+) -> LalResult<()> {
+    let ctx = HookContext {
+        enabled: opts.hooks,
+        component: name.into(),
+        version: Some(version.to_string()),
+        environment: Some(lock.environment.clone()),
+    };
+    let loc = lock.location();
+    run_hook("pre-publish", &ctx)?;
+    if let Some(ref key) = backend.get_signature_policy().key {
+        sign_file(key, tarball)?;
+    }
+    info!("Publishing {}={} to {}", name, version, loc);
+    if new_version {
+        backend.publish_new_artifact(name, version, &loc)?;
+    } else {
+        backend.publish_artifact(name, version, &loc)?;
+    }
+    if let Some(ref c) = opts.channel {
+        backend.tag_channel(name, version, c, &loc)?;
+        info!("Added {}={} to the {} channel", name, version, c);
+    }
+    backend.set_metadata(name, version, &loc, &recorded_metadata(lock, opts))?;
+    run_hook("post-publish", &ctx)?;
 
     Ok(())
 }
+
+// the properties lal records on a published version
+fn recorded_metadata(lock: &Lockfile, opts: &PublishOptions) -> BTreeMap<String, String> {
+    let mut meta = opts.metadata.clone();
+    meta.insert("lal.environment".into(), lock.environment.clone());
+    if let Some(ref t) = lock.target {
+        meta.insert("lal.target".into(), t.clone());
+    }
+    if let Some(ref sha) = lock.sha {
+        meta.insert("lal.sha".into(), sha.clone());
+    }
+    meta
+}
+
+/// Outcome of a publish precondition
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PreflightStatus {
+    /// The precondition holds
+    Ok,
+    /// Publishing would work, but something looks off
+    Warning,
+    /// Publishing would fail
+    Failed,
+}
+
+/// A publish precondition checked by `lal publish --dry-run`
+#[derive(Clone, Debug)]
+pub struct PreflightCheck {
+    /// What was checked
+    pub name: &'static str,
+    /// Whether it holds
+    pub status: PreflightStatus,
+    /// What was found
+    pub detail: String,
+}
+
+/// Everything a publish would check and do, without doing it
+#[derive(Default, Debug)]
+pub struct PreflightReport {
+    /// Preconditions in the order publish relies on them
+    pub checks: Vec<PreflightCheck>,
+    /// Files that would be uploaded with their sizes in bytes
+    pub uploads: Vec<(PathBuf, u64)>,
+    /// Where the files would end up
+    pub destination: Option<String>,
+    /// The first failed precondition
+    pub error: Option<CliError>,
+}
+
+impl PreflightReport {
+    fn record(&mut self, name: &'static str, res: LalResult<String>) {
+        let (status, detail) = match res {
+            Ok(d) => (PreflightStatus::Ok, d),
+            Err(e) => {
+                let detail = e.to_string();
+                if self.error.is_none() {
+                    self.error = Some(e);
+                }
+                (PreflightStatus::Failed, detail)
+            }
+        };
+        self.checks.push(PreflightCheck {
+            name: name,
+            status: status,
+            detail: detail,
+        });
+    }
+
+    fn warn(&mut self, name: &'static str, detail: String) {
+        self.checks.push(PreflightCheck {
+            name: name,
+            status: PreflightStatus::Warning,
+            detail: detail,
+        });
+    }
+}
+
+/// Check every publish precondition without uploading anything
+///
+/// The backend is only queried: the release build must be present with a version,
+/// its lockfile tree must verify, the version must not be published already,
+/// the credentials must be accepted, and the tarball must not be empty.
+pub fn publish_preflight<T: Backend + ?Sized>(
+    name: &str,
+    backend: &T,
+    opts: &PublishOptions,
+) -> PreflightReport {
+    let mut report = PreflightReport::default();
+    let channel = opts.channel.clone().unwrap_or_else(|| "none".into());
+    report.record("options", opts.check().map(|_| format!("channel {}", channel)));
+    let (tarball, lock, version) = match release_artifact(name) {
+        Ok(r) => r,
+        Err(e) => {
+            report.record("release build", Err(e));
+            return report;
+        }
+    };
+    report.record("release build", Ok(format!("{}={} for {}", name, version, lock.environment)));
+    if lock.sha.is_none() {
+        report.warn("revision", "not built --with-sha".into());
+    }
+    let env = lock.environment.clone();
+    let keys = recorded_metadata(&lock, opts).keys().cloned().collect::<Vec<_>>();
+    report.record("metadata", Ok(keys.join(", ")));
+
+    // the same tree checks verify did when the release was built
+    let graph = DependencyGraph::new(&lock);
+    let verified = input::verify_acyclic(&graph)
+        .and_then(|_| match graph.conflicts().into_iter().next() {
+            Some(c) => Err(CliError::MultipleVersions(graph.describe_conflict(&c))),
+            None => Ok(()),
+        })
+        .and_then(|_| input::verify_environment_consistency(&lock, &env))
+        .map(|_| format!("{} dependencies, {} levels deep", graph_size(&lock), graph.max_depth()));
+    report.record("verify", verified);
+
+    let loc = lock.location();
+    let published = backend.get_versions(name, &loc).and_then(|versions| {
+        if versions.contains(&version) {
+            Err(CliError::VersionTaken(name.into(), version))
+        } else {
+            Ok(format!("{} is not published in {}", version, loc))
+        }
+    });
+    report.record("version", published);
+    report.record("credentials", backend.check_publish_access().map(|_| "accepted".into()));
+
+    let size = fs::metadata(&tarball).map(|m| m.len()).unwrap_or(0);
+    if size == 0 {
+        report.record("tarball", Err(CliError::MissingReleaseBuild));
+    } else if size > LARGE_TARBALL_BYTES {
+        report.warn("tarball", format!("{} MB is unusually large", size / (1024 * 1024)));
+    } else {
+        report.record("tarball", Ok(format!("{} KB", size / 1024)));
+    }
+
+    let mut uploads = vec![tarball.clone(), Path::new("./ARTIFACT").join("lockfile.json")];
+    match backend.get_signature_policy().key {
+        Some(ref key) => {
+            report.record("signing", Ok(format!("would sign with {}", key)));
+            uploads.push(signature_path(&tarball));
+        }
+        None => report.record("signing", Ok("not signed".into())),
+    }
+    report.uploads = uploads.into_iter()
+        .map(|f| {
+            let len = fs::metadata(&f).map(|m| m.len()).unwrap_or(0);
+            (f, len)
+        })
+        .collect();
+    report.destination = backend.get_publish_location(name, version, &loc).ok();
+    report
+}
+
+// number of distinct components in a lockfile tree
+fn graph_size(lf: &Lockfile) -> usize { lf.find_all_dependency_versions().len() }
+
+/// Print what `lal publish` would check and upload, without touching the backend
+///
+/// Fails with the first failed precondition, so it can gate a real publish in CI.
+pub fn publish_dry_run<T: Backend + ?Sized>(
+    name: &str,
+    backend: &T,
+    opts: &PublishOptions,
+) -> LalResult<()> {
+    let report = publish_preflight(name, backend, opts);
+    for c in &report.checks {
+        let status = match c.status {
+            PreflightStatus::Ok => Colour::Green.paint("ok"),
+            PreflightStatus::Warning => Colour::Yellow.paint("warn"),
+            PreflightStatus::Failed => Colour::Red.paint("fail"),
+        };
+        println!("{:<14} {:<5} {}", c.name, status.to_string(), c.detail);
+    }
+    if let Some(ref dest) = report.destination {
+        println!("");
+        println!("Would upload to {}", dest);
+        for &(ref f, len) in &report.uploads {
+            println!("  {} ({} bytes)", f.display(), len);
+        }
+    }
+    match report.error {
+        Some(e) => Err(e),
+        None => {
+            info!("Publish preflight passed - nothing was uploaded");
+            Ok(())
+        }
+    }
+}
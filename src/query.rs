@@ -1,10 +1,21 @@
+use std::collections::BTreeMap;
 use std::io::{self, Write};
+use regex::Regex;
+use serde_json;
 
 use storage::Backend;
-use super::{LalResult, CliError};
+use super::{LalResult, CliError, parse_component_version};
 
 /// Prints a list of versions associated with a component
-pub fn query(backend: &Backend, _env: Option<&str>, component: &str, last: bool) -> LalResult<()> {
+///
+/// The `json` flag prints the versions as a json array.
+pub fn query(
+    backend: &Backend,
+    _env: Option<&str>,
+    component: &str,
+    last: bool,
+    json: bool,
+) -> LalResult<()> {
     if component.to_lowercase() != component {
         return Err(CliError::InvalidComponentName(component.into()));
     }
@@ -19,6 +30,9 @@ pub fn query(backend: &Backend, _env: Option<&str>, component: &str, last: bool)
     if last {
         let ver = backend.get_latest_version(component, env)?;
         println!("{}", ver);
+    } else if json {
+        let vers = backend.get_versions(component, env)?;
+        println!("{}", serde_json::to_string(&vers)?);
     } else {
         let vers = backend.get_versions(component, env)?;
         for v in vers {
@@ -33,3 +47,114 @@ pub fn query(backend: &Backend, _env: Option<&str>, component: &str, last: bool)
     }
     Ok(())
 }
+
+/// Prints the properties recorded on a published `component=version`
+///
+/// One `key=value` line is printed per property, or a json object with `json`.
+pub fn query_metadata(
+    backend: &Backend,
+    env: Option<&str>,
+    component_version: &str,
+    json: bool,
+) -> LalResult<()> {
+    let env = env.ok_or(CliError::EnvironmentUnspecified)?;
+    let (component, version) = parse_component_version(component_version)?;
+    let meta = backend.get_metadata(&component, version, env)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&meta)?);
+    } else {
+        for (k, v) in meta {
+            println!("{}={}", k, v);
+        }
+    }
+    Ok(())
+}
+
+/// Prints the versions of a component available in each of the given environments
+///
+/// With a single environment this prints one version per line like `query`.
+/// Otherwise every environment gets a line with its versions. Only the `last`
+/// newest versions are shown if set. The `json` flag prints an object mapping
+/// every environment to its versions.
+pub fn list_versions(
+    backend: &Backend,
+    envs: &[String],
+    component: &str,
+    last: Option<usize>,
+    json: bool,
+) -> LalResult<()> {
+    if component.to_lowercase() != component {
+        return Err(CliError::InvalidComponentName(component.into()));
+    }
+    let mut res = BTreeMap::new();
+    for env in envs {
+        let mut vers = match backend.get_versions(component, env) {
+            Ok(v) => v,
+            Err(e) => {
+                debug!("No versions of {} in {} ({})", component, env, e);
+                vec![]
+            }
+        };
+        vers.sort_by(|a, b| b.cmp(a));
+        if let Some(n) = last {
+            vers.truncate(n);
+        }
+        if json {
+            res.insert(env.clone(), vers);
+        } else if envs.len() == 1 {
+            for v in vers {
+                println!("{}", v);
+                if io::stdout().flush().is_err() {
+                    return Ok(());
+                }
+            }
+        } else {
+            let vs = vers.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+            println!("{}: {}", env, vs.join(" "));
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&res)?);
+    }
+    Ok(())
+}
+
+/// Prints components published in the given environments matching a search term
+///
+/// The term is a substring unless `regex` is set. Components are listed with
+/// the environments they are available in. The `json` flag prints an object mapping
+/// every matching component to its environments.
+pub fn search(
+    backend: &Backend,
+    envs: &[String],
+    term: &str,
+    regex: bool,
+    json: bool,
+) -> LalResult<()> {
+    let re = if regex {
+        Some(Regex::new(term).map_err(|e| CliError::InvalidSearchPattern(e.to_string()))?)
+    } else {
+        None
+    };
+    let matches = |name: &str| match re {
+        Some(ref r) => r.is_match(name),
+        None => name.contains(term),
+    };
+    let mut found: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for env in envs {
+        for name in backend.get_components(env)?.into_iter().filter(|n| matches(n)) {
+            found.entry(name).or_insert_with(Vec::new).push(env.clone());
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&found)?);
+        return Ok(());
+    }
+    if found.is_empty() {
+        info!("No components matching {} found", term);
+    }
+    for (name, es) in found {
+        println!("{} ({})", name, es.join(", "));
+    }
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use std::fs;
 use std::path::Path;
 
-use super::{CliError, LalResult, Manifest};
+use super::{CliError, LalResult, Manifest, forget_source};
 
 /// Remove specific components from `./INPUT` and the manifest.
 ///
@@ -39,18 +39,72 @@ pub fn remove(manifest: &Manifest, xs: Vec<String>, save: bool, savedev: bool) -
         info!("Updating manifest with removed dependencies");
         mf.write()?;
     }
+    remove_from_input(&xs)
+}
 
-    // delete the folder (ignore if the folder does not exist)
+// delete the folders in INPUT (ignore if the folder does not exist)
+fn remove_from_input(xs: &[String]) -> LalResult<()> {
     let input = Path::new("./INPUT");
     if !input.is_dir() {
         return Ok(());
     }
     for component in xs {
-        let pth = Path::new(&input).join(&component);
+        let pth = Path::new(&input).join(component);
         if pth.is_dir() {
             debug!("Deleting INPUT/{}", component);
             fs::remove_dir_all(&pth)?;
+            forget_source(component)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove dependencies from the manifest and `./INPUT`
+///
+/// Unlike `remove`, this finds the map each component is in by itself, so
+/// dependencies and devDependencies can be removed together. Components are also
+/// dropped from dependency groups and environment overrides. Every component must
+/// be in the manifest, or nothing is changed.
+pub fn rm(manifest: &Manifest, xs: Vec<String>) -> LalResult<()> {
+    let mut mf = manifest.clone();
+    let mut removed = vec![];
+    for component in &xs {
+        let kind = if mf.dependencies.remove(component).is_some() {
+            "dependencies"
+        } else if mf.devDependencies.remove(component).is_some() {
+            "devDependencies"
+        } else {
+            return Err(CliError::MissingComponent(component.clone()));
+        };
+        mf.ranges.remove(component);
+        for members in mf.groups.values_mut() {
+            members.retain(|m| m != component);
+        }
+        for overrides in mf.environmentOverrides.values_mut() {
+            overrides.remove(component);
         }
+        removed.push((component, kind));
+    }
+    let empty_groups = mf.groups
+        .iter()
+        .filter(|&(_, m)| m.is_empty())
+        .map(|(g, _)| g.clone())
+        .collect::<Vec<_>>();
+    for g in empty_groups {
+        mf.groups.remove(&g);
+    }
+    let empty_envs = mf.environmentOverrides
+        .iter()
+        .filter(|&(_, o)| o.is_empty())
+        .map(|(e, _)| e.clone())
+        .collect::<Vec<_>>();
+    for e in empty_envs {
+        mf.environmentOverrides.remove(&e);
+    }
+    mf.write()?;
+    remove_from_input(&xs)?;
+    for (component, kind) in removed {
+        info!("Removed {} from {}", component, kind);
     }
     Ok(())
 }
@@ -0,0 +1,128 @@
+use std::collections::BTreeSet;
+
+// Need both the struct and the trait
+use storage::Backend;
+use super::{LalResult, RetentionPolicy};
+
+/// What a retention policy does to the published versions in one location
+#[derive(Default, Debug)]
+pub struct RetentionPlan {
+    /// The environment, or one of its `<env>+<target>` locations
+    pub location: String,
+    /// Versions kept as they are among the newest of their component, or in a kept channel
+    pub kept: BTreeSet<(String, u32)>,
+    /// Older versions kept as kept releases depend on them
+    pub referenced: BTreeSet<(String, u32)>,
+    /// Versions to delete
+    pub expired: BTreeSet<(String, u32)>,
+}
+
+/// Work out which published versions in an environment a retention policy removes
+///
+/// Plans the environment and each of its `<env>+<target>` locations. The newest
+/// `keepLast` versions of every component are kept, as are versions promoted to one of
+/// the `keepChannels`, along with every version that appears anywhere in the lockfiles of
+/// those kept versions. Releases for a target use target independent components from the
+/// environment, so the dependencies of kept versions are kept in every one of these
+/// locations. Kept versions without a readable lockfile are warned about and skipped.
+pub fn retention_plan<T: Backend + ?Sized>(
+    backend: &T,
+    policy: &RetentionPolicy,
+    env: &str,
+) -> LalResult<Vec<RetentionPlan>> {
+    let mut locations = vec![env.to_string()];
+    locations.extend(backend.get_target_locations(env)?);
+
+    let mut plans = vec![];
+    let mut older = vec![];
+    for loc in locations {
+        let mut plan = RetentionPlan { location: loc, ..RetentionPlan::default() };
+        let mut old = BTreeSet::new();
+        for name in backend.get_components(&plan.location)? {
+            let mut versions = backend.get_versions(&name, &plan.location)?;
+            versions.sort_by(|a, b| b.cmp(a));
+            let mut promoted = BTreeSet::new();
+            for c in &policy.keepChannels {
+                promoted.extend(backend.get_channel_versions(&name, c, &plan.location)?);
+            }
+            for (i, v) in versions.into_iter().enumerate() {
+                if i < policy.keepLast || promoted.contains(&v) {
+                    plan.kept.insert((name.clone(), v));
+                } else {
+                    old.insert((name.clone(), v));
+                }
+            }
+        }
+        plans.push(plan);
+        older.push(old);
+    }
+
+    // releases must stay fetchable with their whole dependency tree
+    let mut needed = BTreeSet::new();
+    for plan in &plans {
+        for &(ref name, v) in &plan.kept {
+            let lf = match backend.get_lockfile(name, v, &plan.location) {
+                Ok(lf) => lf,
+                Err(e) => {
+                    warn!("Ignoring dependencies of {}={} in {} ({})", name, v, plan.location, e);
+                    continue;
+                }
+            };
+            for (dep, vers) in lf.find_all_dependency_versions() {
+                for dv in vers.iter().filter_map(|s| s.parse::<u32>().ok()) {
+                    needed.insert((dep.clone(), dv));
+                }
+            }
+        }
+    }
+    for (plan, old) in plans.iter_mut().zip(older) {
+        for entry in old {
+            if needed.contains(&entry) {
+                plan.referenced.insert(entry);
+            } else {
+                plan.expired.insert(entry);
+            }
+        }
+    }
+    Ok(plans)
+}
+
+/// Delete published versions that fall outside the retention policy
+///
+/// Applies `retention_plan` in each of the environments (and their target locations).
+/// A `dry_run` only prints the versions that would be deleted.
+pub fn retention_apply<T: Backend + ?Sized>(
+    backend: &T,
+    policy: &RetentionPolicy,
+    envs: &[String],
+    dry_run: bool,
+) -> LalResult<()> {
+    for env in envs {
+        for plan in retention_plan(backend, policy, env)? {
+            let loc = &plan.location;
+            for &(ref name, v) in &plan.referenced {
+                debug!("Keeping {}={} in {} as a kept release depends on it", name, v, loc);
+            }
+            for &(ref name, v) in &plan.expired {
+                if dry_run {
+                    println!("{}={} in {}", name, v, loc);
+                } else {
+                    debug!("Deleting {}={} from {}", name, v, loc);
+                    backend.delete_version(name, v, loc)?;
+                }
+            }
+            if dry_run {
+                info!("Would delete {} versions from {} (keeping {})",
+                      plan.expired.len(),
+                      loc,
+                      plan.kept.len() + plan.referenced.len());
+            } else {
+                info!("Deleted {} versions from {} (kept {})",
+                      plan.expired.len(),
+                      loc,
+                      plan.kept.len() + plan.referenced.len());
+            }
+        }
+    }
+    Ok(())
+}
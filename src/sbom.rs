@@ -0,0 +1,323 @@
+use std::collections::{BTreeMap, BTreeSet};
+use chrono::UTC;
+use rand;
+use serde_json;
+
+use storage::CachedBackend;
+use lockdiff::{read_lock_reference, write_output};
+use super::{LalResult, Lockfile, ProgressReporter};
+
+/// Software bill of materials formats for `lal sbom`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SbomFormat {
+    /// CycloneDX 1.4 json
+    CycloneDx,
+    /// SPDX 2.3 json
+    Spdx,
+}
+
+// a unique component version in the tree
+struct SbomComponent {
+    name: String,
+    version: String,
+    license: Option<String>,
+    // hex sha256 of the contents, without the algorithm prefix
+    sha256: Option<String>,
+}
+
+impl SbomComponent {
+    fn from_lockfile(lf: &Lockfile) -> Self {
+        SbomComponent {
+            name: lf.name.clone(),
+            version: lf.version.clone(),
+            license: lf.license.clone(),
+            sha256: lf.contents.as_ref().map(|c| c.trim_left_matches("sha256:").to_string()),
+        }
+    }
+
+    fn reference(&self) -> String { format!("{}@{}", self.name, self.version) }
+
+    // spdx ids only allow letters, numbers, `.` and `-`
+    fn spdx_id(&self) -> String {
+        let id = format!("SPDXRef-Package-{}-{}", self.name, self.version);
+        id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '-' })
+            .collect()
+    }
+}
+
+// every component version in the tree, and the component versions each depends on
+struct SbomTree {
+    root: SbomComponent,
+    components: BTreeMap<String, SbomComponent>,
+    edges: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl SbomTree {
+    fn new(lf: &Lockfile) -> Self {
+        let mut tree = SbomTree {
+            root: SbomComponent::from_lockfile(lf),
+            components: BTreeMap::new(),
+            edges: BTreeMap::new(),
+        };
+        let root = tree.root.reference();
+        tree.walk(&root, lf);
+        tree
+    }
+
+    fn walk(&mut self, parent: &str, lf: &Lockfile) {
+        for (name, dep) in &lf.dependencies {
+            // dependency lockfiles are keyed by the name they were fetched as
+            let mut c = SbomComponent::from_lockfile(dep);
+            c.name = name.clone();
+            let r = c.reference();
+            self.edges.entry(parent.into()).or_insert_with(BTreeSet::new).insert(r.clone());
+            if !self.components.contains_key(&r) {
+                self.components.insert(r.clone(), c);
+                self.walk(&r, dep);
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CdxLicense {
+    expression: String,
+}
+
+#[derive(Serialize)]
+struct CdxHash {
+    alg: &'static str,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct CdxComponent {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(rename = "bom-ref")]
+    bom_ref: String,
+    name: String,
+    version: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    licenses: Vec<CdxLicense>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hashes: Vec<CdxHash>,
+}
+
+#[derive(Serialize)]
+struct CdxTool {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+struct CdxMetadata {
+    timestamp: String,
+    tools: Vec<CdxTool>,
+    component: CdxComponent,
+}
+
+#[derive(Serialize)]
+struct CdxDependency {
+    #[serde(rename = "ref")]
+    reference: String,
+    #[serde(rename = "dependsOn")]
+    depends_on: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct CycloneDx {
+    bomFormat: &'static str,
+    specVersion: &'static str,
+    serialNumber: String,
+    version: u32,
+    metadata: CdxMetadata,
+    components: Vec<CdxComponent>,
+    dependencies: Vec<CdxDependency>,
+}
+
+fn cdx_component(c: &SbomComponent, kind: &'static str) -> CdxComponent {
+    CdxComponent {
+        kind: kind,
+        bom_ref: c.reference(),
+        name: c.name.clone(),
+        version: c.version.clone(),
+        licenses: c.license.iter().map(|l| CdxLicense { expression: l.clone() }).collect(),
+        hashes: c.sha256
+            .iter()
+            .map(|h| CdxHash { alg: "SHA-256", content: h.clone() })
+            .collect(),
+    }
+}
+
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct SpdxChecksum {
+    algorithm: &'static str,
+    checksumValue: String,
+}
+
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct SpdxPackage {
+    name: String,
+    SPDXID: String,
+    versionInfo: String,
+    downloadLocation: &'static str,
+    filesAnalyzed: bool,
+    licenseConcluded: &'static str,
+    licenseDeclared: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    checksums: Vec<SpdxChecksum>,
+}
+
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct SpdxRelationship {
+    spdxElementId: String,
+    relationshipType: &'static str,
+    relatedSpdxElement: String,
+}
+
+#[derive(Serialize)]
+struct SpdxCreationInfo {
+    created: String,
+    creators: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct Spdx {
+    spdxVersion: &'static str,
+    dataLicense: &'static str,
+    SPDXID: &'static str,
+    name: String,
+    documentNamespace: String,
+    creationInfo: SpdxCreationInfo,
+    packages: Vec<SpdxPackage>,
+    relationships: Vec<SpdxRelationship>,
+}
+
+fn spdx_package(c: &SbomComponent) -> SpdxPackage {
+    SpdxPackage {
+        name: c.name.clone(),
+        SPDXID: c.spdx_id(),
+        versionInfo: c.version.clone(),
+        downloadLocation: "NOASSERTION",
+        filesAnalyzed: false,
+        licenseConcluded: "NOASSERTION",
+        licenseDeclared: c.license.clone().unwrap_or_else(|| "NOASSERTION".into()),
+        checksums: c.sha256
+            .iter()
+            .map(|h| SpdxChecksum { algorithm: "SHA256", checksumValue: h.clone() })
+            .collect(),
+    }
+}
+
+// random version 4 uuid
+fn uuid() -> String {
+    let (a, b) = (rand::random::<u64>(), rand::random::<u64>());
+    format!("{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+            a >> 32,
+            (a >> 16) & 0xffff,
+            a & 0xfff,
+            0x8000 | ((b >> 48) & 0x3fff),
+            b & 0xffff_ffff_ffff)
+}
+
+/// Generate a software bill of materials for a lockfile tree
+///
+/// Every component version in the tree is listed once, with its license (from the
+/// `license` in its manifest at build time) and contents digest when recorded, along
+/// with the dependency relations between them.
+pub fn sbom_document(lf: &Lockfile, format: SbomFormat) -> LalResult<String> {
+    let tree = SbomTree::new(lf);
+    let now = UTC::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let doc = match format {
+        SbomFormat::CycloneDx => {
+            let mut dependencies = vec![];
+            for (r, deps) in &tree.edges {
+                dependencies.push(CdxDependency {
+                    reference: r.clone(),
+                    depends_on: deps.iter().cloned().collect(),
+                });
+            }
+            let bom = CycloneDx {
+                bomFormat: "CycloneDX",
+                specVersion: "1.4",
+                serialNumber: format!("urn:uuid:{}", uuid()),
+                version: 1,
+                metadata: CdxMetadata {
+                    timestamp: now,
+                    tools: vec![CdxTool { name: "lal", version: env!("CARGO_PKG_VERSION") }],
+                    component: cdx_component(&tree.root, "application"),
+                },
+                components: tree.components.values().map(|c| cdx_component(c, "library")).collect(),
+                dependencies: dependencies,
+            };
+            serde_json::to_string_pretty(&bom)?
+        }
+        SbomFormat::Spdx => {
+            let ids = tree.components
+                .iter()
+                .map(|(r, c)| (r.clone(), c.spdx_id()))
+                .chain(Some((tree.root.reference(), tree.root.spdx_id())))
+                .collect::<BTreeMap<_, _>>();
+            let mut relationships = vec![SpdxRelationship {
+                spdxElementId: "SPDXRef-DOCUMENT".into(),
+                relationshipType: "DESCRIBES",
+                relatedSpdxElement: tree.root.spdx_id(),
+            }];
+            for (r, deps) in &tree.edges {
+                for d in deps {
+                    relationships.push(SpdxRelationship {
+                        spdxElementId: ids[r].clone(),
+                        relationshipType: "DEPENDS_ON",
+                        relatedSpdxElement: ids[d].clone(),
+                    });
+                }
+            }
+            let mut packages = vec![spdx_package(&tree.root)];
+            packages.extend(tree.components.values().map(spdx_package));
+            let doc = Spdx {
+                spdxVersion: "SPDX-2.3",
+                dataLicense: "CC0-1.0",
+                SPDXID: "SPDXRef-DOCUMENT",
+                name: tree.root.reference(),
+                documentNamespace: format!("urn:lal:spdx:{}:{}", tree.root.reference(), uuid()),
+                creationInfo: SpdxCreationInfo {
+                    created: now,
+                    creators: vec![format!("Tool: lal-{}", env!("CARGO_PKG_VERSION"))],
+                },
+                packages: packages,
+                relationships: relationships,
+            };
+            serde_json::to_string_pretty(&doc)?
+        }
+    };
+    Ok(doc)
+}
+
+/// Write a software bill of materials for a built artifact
+///
+/// The lockfile tree is read from a reference as in `lal lock diff`, defaulting to
+/// the lockfile of the last build in `./OUTPUT`. The document is written to `output`
+/// if given, otherwise to stdout.
+pub fn sbom<T: CachedBackend + ?Sized>(
+    backend: &T,
+    reference: Option<&str>,
+    env: Option<&str>,
+    format: SbomFormat,
+    output: Option<&str>,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    let reference = reference.unwrap_or("OUTPUT/lockfile.json");
+    let lf = read_lock_reference(backend, reference, env, reporter)?;
+    let doc = sbom_document(&lf, format)?;
+    write_output(&format!("{}\n", doc), output)?;
+    if let Some(pth) = output {
+        info!("Wrote software bill of materials for {} to {}", lf.name, pth);
+    }
+    Ok(())
+}
@@ -1,9 +1,14 @@
-use std::process::Command;
+use std::process::{Command, ExitStatus, Stdio};
 use std::env;
-use std::path::Path;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::vec::Vec;
+use libc;
 
-use super::{Config, Container, CliError, LalResult};
+use super::{Config, Container, CliError, LalResult, Manifest, exit_code, home_dir};
 
 /// Verifies that `id -u` and `id -g` are both 1000
 ///
@@ -35,10 +40,10 @@ fn permission_sanity_check() -> LalResult<()> {
 /// Will return a trimmed String containing the image ID requested, wrapped in
 /// a Result::Ok, or CliError::DockerImageNotFound wrapped in a Result::Err if
 /// docker images returns no output.
-fn get_docker_image_id(container: &Container) -> LalResult<String> {
-    trace!("Using docker images to find ID of container {}", container);
+fn get_docker_image_id(runtime: &str, container: &Container) -> LalResult<String> {
+    trace!("Using {} images to find ID of container {}", runtime, container);
     let image_id_output =
-        Command::new("docker").arg("images").arg("-q").arg(container.to_string()).output()?;
+        Command::new(runtime).arg("images").arg("-q").arg(container.to_string()).output()?;
     let image_id_str: String = String::from_utf8_lossy(&image_id_output.stdout).trim().into();
     match image_id_str.len() {
         0 => {
@@ -58,17 +63,30 @@ fn get_docker_image_id(container: &Container) -> LalResult<String> {
 /// Returns Ok(()) if the command is successful, Err(CliError::SubprocessFailure)
 /// if `docker pull` fails or is interrupted by a signal, Err(CliError::Io) if the
 /// command status() call fails for a different reason.
-fn pull_docker_image(container: &Container) -> LalResult<()> {
+fn pull_docker_image(runtime: &str, container: &Container) -> LalResult<()> {
     trace!("Pulling container {}", container);
-    let s = Command::new("docker").arg("pull").arg(container.to_string()).status()?;
+    let s = Command::new(runtime).arg("pull").arg(container.to_string()).status()?;
     if !s.success() {
         trace!("Pull failed");
-        return Err(CliError::SubprocessFailure(s.code().unwrap_or(1001)));
+        return Err(CliError::SubprocessFailure(exit_code(&s), None));
     };
     trace!("Pull succeeded");
     Ok(())
 }
 
+/// Pulls a container unless its image is already present
+///
+/// `docker_run` pulls missing images itself, but doing it up front lets the pull be
+/// told apart from the command that runs in the container (e.g. in build timings).
+pub fn ensure_docker_image(cfg: &Config, container: &Container) -> LalResult<()> {
+    let runtime = cfg.runtime();
+    if get_docker_image_id(runtime, container).is_err() {
+        info!("Pulling {}", container);
+        pull_docker_image(runtime, container)?;
+    }
+    Ok(())
+}
+
 /// Builds a docker container
 ///
 /// Uses `docker build` to build a docker container with the specified
@@ -76,7 +94,11 @@ fn pull_docker_image(container: &Container) -> LalResult<()> {
 /// Returns Ok(()) if the command is successful, Err(CliError::SubprocessFailure)
 /// if `bash -c` fails or is interrupted by a signal, Err(CliError::Io) if the
 /// command status() call fails for a different reason.
-fn build_docker_image(container: &Container, instructions: Vec<String>) -> LalResult<()> {
+fn build_docker_image(
+    runtime: &str,
+    container: &Container,
+    instructions: Vec<String>,
+) -> LalResult<()> {
     trace!("Building docker image for {}", container);
     let instruction_strings = instructions.join("\\n");
     trace!("Build instructions: \n{}", instruction_strings);
@@ -84,13 +106,14 @@ fn build_docker_image(container: &Container, instructions: Vec<String>) -> LalRe
     let instruction_strings = instruction_strings.replace("'", "'\\''");
     let s = Command::new("bash")
         .arg("-c")
-        .arg(format!("echo -e '{}' | docker build --tag {} -",
+        .arg(format!("echo -e '{}' | {} build --tag {} -",
                      instruction_strings,
+                     runtime,
                      container))
         .status()?;
     if !s.success() {
         trace!("Build failed");
-        return Err(CliError::SubprocessFailure(s.code().unwrap_or(1001)));
+        return Err(CliError::SubprocessFailure(exit_code(&s), None));
     };
     trace!("Build succeeded");
     Ok(())
@@ -108,47 +131,97 @@ pub struct DockerRunFlags {
     pub interactive: bool,
     /// Pass --privileged (situational)
     pub privileged: bool,
+    /// Do not allocate a TTY (for one-off commands outside a terminal)
+    pub no_tty: bool,
+    /// Also capture the output of the command into this file
+    pub log: Option<PathBuf>,
+}
+
+// copy output of a command to the terminal and a shared log as it arrives
+fn tee<R: Read + Send + 'static>(
+    mut src: R,
+    stderr: bool,
+    log: Arc<Mutex<fs::File>>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        while let Ok(n) = src.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let _ = if stderr {
+                io::stderr().write_all(&buf[..n])
+            } else {
+                let mut out = io::stdout();
+                out.write_all(&buf[..n]).and_then(|_| out.flush())
+            };
+            if let Ok(mut f) = log.lock() {
+                let _ = f.write_all(&buf[..n]);
+            }
+        }
+    })
+}
+
+// run a command streaming its output while capturing it into a log file
+fn run_logged(cmd: &mut Command, log: &Path) -> LalResult<ExitStatus> {
+    let file = Arc::new(Mutex::new(fs::File::create(log)?));
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let out = tee(child.stdout.take().unwrap(), false, file.clone());
+    let err = tee(child.stderr.take().unwrap(), true, file);
+    let status = child.wait()?;
+    let _ = out.join();
+    let _ = err.join();
+    Ok(status)
 }
 
+// whether both stdin and stdout are terminals
+fn on_terminal() -> bool { unsafe { libc::isatty(0) == 1 && libc::isatty(1) == 1 } }
+
 /// Fixes up docker container for use with given uid and gid
 ///
 /// Returns a container derived from the one passed as an argument, with the `lal`
 /// user having its uid and gid modified to match the ones passed.
 /// The container is built if necessary (e.g. new base container from upstream)
-fn fixup_docker_container(container: &Container, u: u32, g: u32) -> LalResult<Container> {
+fn fixup_docker_container(
+    runtime: &str,
+    container: &Container,
+    u: u32,
+    g: u32,
+) -> LalResult<Container> {
     info!("Using appropriate container for user {}:{}", u, g);
     // Find image id of regular docker container
     // We might have to pull it
-    let image_id = get_docker_image_id(container)
+    let image_id = get_docker_image_id(runtime, container)
         .or_else(|_| {
-            pull_docker_image(container)?;
-            get_docker_image_id(container)
+            pull_docker_image(runtime, container)?;
+            get_docker_image_id(runtime, container)
         })?;
 
     // Produce name and tag of modified container
     let modified_container = Container {
         name: format!("{}-u{}_g{}", container.name, u, g),
         tag: format!("from_{}", image_id),
+        digest: None,
     };
 
     info!("Using container {}", modified_container);
 
     // Try to find image id of modified container
     // If we fail we need to build it
-    match get_docker_image_id(&modified_container) {
+    match get_docker_image_id(runtime, &modified_container) {
         Ok(id) => {
             info!("Found container {}, image id is {}", modified_container, id);
         }
         Err(_) => {
             let instructions: Vec<String> =
                 vec![
-                    format!("FROM {}", container),
+                    format!("FROM {}", container.pinned().unwrap_or_else(|| container.to_string())),
                     "USER root".into(),
                     format!("RUN groupmod -g {} lal && usermod -u {} lal", g, u),
                     "USER lal".into(),
                 ];
             info!("Attempting to build container {}...", modified_container);
-            build_docker_image(&modified_container, instructions)?;
+            build_docker_image(runtime, &modified_container, instructions)?;
         }
     };
     trace!("Fixup for user {}:{} succeeded", u, g);
@@ -161,6 +234,11 @@ fn fixup_docker_container(container: &Container, u: u32, g: u32) -> LalResult<Co
 /// and absorb the `Stdio` supplied by this `Command`.
 ///
 /// This is the most general function, used by both `lal build` and `lal shell`.
+/// With a `log` in the flags the output still streams to the terminal, but is also
+/// captured into that file, which failures then point at.
+/// The configured `runtime` is invoked instead of docker when set. Rootless podman
+/// maps the invoking user onto the `lal` user through a user namespace, so no
+/// container with a modified `lal` user is built for it.
 pub fn docker_run(
     cfg: &Config,
     container: &Container,
@@ -170,6 +248,7 @@ pub fn docker_run(
 ) -> LalResult<()> {
 
     let mut modified_container_option: Option<Container> = None;
+    let runtime = cfg.runtime();
 
     trace!("Performing docker permission sanity check");
     if let Err(e) = permission_sanity_check() {
@@ -182,7 +261,10 @@ pub fn docker_run(
                                                                 u,
                                                                 g));
                 }
-                modified_container_option = Some(fixup_docker_container(container, u, g)?);
+                if !cfg.is_podman() {
+                    modified_container_option =
+                        Some(fixup_docker_container(runtime, container, u, g)?);
+                }
             }
             x => {
                 return Err(x);
@@ -194,7 +276,7 @@ pub fn docker_run(
     let container = modified_container_option.as_ref().unwrap_or(container);
 
     trace!("Finding home and cwd");
-    let home = env::home_dir().unwrap(); // crash if no $HOME
+    let home = home_dir()?;
     let pwd = env::current_dir().unwrap();
 
     // construct arguments vector
@@ -231,6 +313,13 @@ pub fn docker_run(
     for var in modes.env_vars.clone() {
         args.push(format!("--env={}", var));
     }
+    // forward whitelisted host variables by name so values stay out of printed commands
+    for var in &cfg.envPassthrough {
+        if env::var_os(var).is_some() {
+            trace!(" - passing through {}", var);
+            args.push(format!("--env={}", var));
+        }
+    }
 
     if flags.privileged {
         args.push("--privileged".into())
@@ -240,6 +329,10 @@ pub fn docker_run(
     args.push("/home/lal/volume".into());
     args.push("--user".into());
     args.push("lal".into());
+    if cfg.is_podman() {
+        // the invoking user becomes lal (1000:1000) so the volume stays writable
+        args.push("--userns=keep-id:uid=1000,gid=1000".into());
+    }
 
     // If no command, then override entrypoint to /bin/bash
     // This happens when we use `lal shell` without args
@@ -247,16 +340,22 @@ pub fn docker_run(
         args.push("--entrypoint".into());
         args.push("/bin/bash".into());
     }
-    args.push((if flags.interactive { "-it" } else { "-t" }).into());
+    match (flags.interactive, flags.no_tty) {
+        (true, false) => args.push("-it".into()),
+        (false, false) => args.push("-t".into()),
+        (true, true) => args.push("-i".into()),
+        (false, true) => {}
+    }
 
-    args.push(format!("{}:{}", container.name, container.tag));
+    // run the exact image a build recorded when its digest is known
+    args.push(container.pinned().unwrap_or_else(|| container.to_string()));
     for c in command {
         args.push(c);
     }
 
     // run or print docker command
     if modes.printonly {
-        print!("docker");
+        print!("{}", runtime);
         for arg in args {
             if arg.contains(' ') {
                 // leave quoted args quoted
@@ -267,11 +366,20 @@ pub fn docker_run(
         }
         println!("");
     } else {
-        trace!("Entering docker");
-        let s = Command::new("docker").args(&args).status()?;
-        trace!("Exited docker");
+        trace!("Entering {}", runtime);
+        let mut cmd = Command::new(runtime);
+        cmd.args(&args);
+        let s = match flags.log {
+            Some(ref log) => {
+                debug!("Logging output to {}", log.display());
+                run_logged(&mut cmd, log)?
+            }
+            None => cmd.status()?,
+        };
+        trace!("Exited {}", runtime);
         if !s.success() {
-            return Err(CliError::SubprocessFailure(s.code().unwrap_or(1001)));
+            let log = flags.log.as_ref().map(|l| l.display().to_string());
+            return Err(CliError::SubprocessFailure(exit_code(&s), log));
         }
     }
     Ok(())
@@ -296,6 +404,8 @@ pub struct ShellModes {
 ///
 /// If a command vector is given, this is called non-interactively instead of /bin/bash
 /// You can thus do `lal shell ./BUILD target` or ``lal shell bash -c "cmd1; cmd2"`
+/// (or `lal shell -- cmd --with-flags`). Such one-off commands only get a TTY when
+/// lal runs in a terminal, so they can be used from CI steps and scripts.
 pub fn shell(
     cfg: &Config,
     container: &Container,
@@ -310,6 +420,8 @@ pub fn shell(
     let flags = DockerRunFlags {
         interactive: cmd.is_none() || cfg.interactive,
         privileged: privileged,
+        no_tty: cmd.is_some() && !on_terminal(),
+        log: None,
     };
     let mut bash = vec![];
     if let Some(cmdu) = cmd {
@@ -320,33 +432,60 @@ pub fn shell(
     docker_run(cfg, container, bash, &flags, modes)
 }
 
-/// Runs a script in `.lal/scripts/` with supplied arguments in a docker shell
+// whether a script command refers to its positional parameters itself
+fn uses_arguments(command: &str) -> bool {
+    ["$@", "${@", "$*", "${*", "$#"].iter().any(|p| command.contains(p)) ||
+    (1..10).any(|i| command.contains(&format!("${}", i)) || command.contains(&format!("${{{}", i)))
+}
+
+/// Runs a named script from the manifest with supplied arguments in a docker shell
 ///
 /// This is a convenience helper for running things that aren't builds.
-/// E.g. `lal run my-large-test RUNONLY=foo`
+/// Scripts are commands in the `scripts` of the manifest, run with `bash -c` with the
+/// arguments as positional parameters. Unless the command uses them itself (`$@`, `$1`..),
+/// they are appended, so `lal run test -R unit` with `"test": "ctest"` runs `ctest -R unit`.
+/// Appending only reaches the last command of a list like `make && ctest`.
+///
+/// Scripts in `.lal/scripts/` are still sourced and have their `main` called with the
+/// arguments when the manifest has no script of that name, but they are deprecated.
 pub fn script(
     cfg: &Config,
     container: &Container,
+    manifest: &Manifest,
     name: &str,
     args: Vec<&str>,
     modes: &ShellModes,
     privileged: bool,
 ) -> LalResult<()> {
-    let pth = Path::new(".").join(".lal").join("scripts").join(&name);
-    if !pth.exists() {
-        return Err(CliError::MissingScript(name.into()));
-    }
+    let cmd = if let Some(command) = manifest.scripts.get(name) {
+        // arguments become the positional parameters, so they are passed on unmangled
+        let command = if uses_arguments(command) {
+            command.clone()
+        } else {
+            format!("{} \"$@\"", command)
+        };
+        let mut cmd = vec!["bash".into(), "-c".into(), command, name.into()];
+        cmd.extend(args.iter().map(|a| a.to_string()));
+        cmd
+    } else {
+        let pth = Path::new(".").join(".lal").join("scripts").join(&name);
+        if !pth.exists() {
+            return Err(CliError::MissingScript(name.into()));
+        }
+        warn!("Scripts in .lal/scripts are deprecated - move {} to the scripts of the manifest",
+              name);
+        vec![
+            "bash".into(),
+            "-c".into(),
+            format!("source {}; main {}", pth.display(), args.join(" ")),
+        ]
+    };
 
     let flags = DockerRunFlags {
         interactive: cfg.interactive,
         privileged: privileged,
+        no_tty: false,
+        log: None,
     };
-
-    // Simply run the script by adding on the arguments
-    let cmd = vec![
-        "bash".into(),
-        "-c".into(),
-        format!("source {}; main {}", pth.display(), args.join(" ")),
-    ];
     Ok(docker_run(cfg, container, cmd, &flags, modes)?)
 }
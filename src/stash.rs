@@ -1,8 +1,59 @@
-use std::path::Path;
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use chrono::UTC;
+use serde_json;
+use walkdir::WalkDir;
 
-use storage::CachedBackend;
-use super::{CliError, LalResult, Manifest, Lockfile};
+use storage::{Backend, CachedBackend};
+use super::{CliError, LalResult, IoResultExt, Lockfile, Manifest, ProgressReporter, COMPRESSIONS,
+            find_tarball, output};
+use clean::clean_orphaned_blobs;
+use hooks::{run_hook, HookContext};
 
+/// Representation of the `stash.json` stored next to a stashed tarball
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StashMetadata {
+    /// Name of the stashed component
+    pub component: String,
+    /// Name it was stashed under
+    pub name: String,
+    /// When it was stashed
+    pub stashed: String,
+    /// Environment the stashed build was made in
+    pub environment: String,
+    /// Build configuration used
+    pub config: String,
+    /// Version of lal that stashed it
+    pub tool: String,
+}
+
+impl StashMetadata {
+    /// Read the metadata of a stash directory (if it was recorded)
+    pub fn read(stashdir: &Path) -> Option<StashMetadata> {
+        let mut data = String::new();
+        File::open(stashdir.join("stash.json"))
+            .and_then(|mut f| f.read_to_string(&mut data))
+            .ok()
+            .and_then(|_| serde_json::from_str(&data).ok())
+    }
+
+    fn write(&self, stashdir: &Path) -> LalResult<()> {
+        let pth = stashdir.join("stash.json");
+        let encoded = serde_json::to_string_pretty(self)?;
+        File::create(&pth)
+            .and_then(|mut f| write!(f, "{}\n", encoded))
+            .context(format!("Failed to write stash metadata {}", pth.display()))?;
+        Ok(())
+    }
+}
+
+// whether a name can be used as a single directory name in the stash
+fn valid_stash_label(name: &str) -> bool {
+    !name.is_empty() && !name.contains('/') && !name.starts_with('.')
+}
 
 /// Saves current build `./OUTPUT` to the local cache under a specific name
 ///
@@ -10,30 +61,268 @@ use super::{CliError, LalResult, Manifest, Lockfile};
 /// then copies this to `~/.lal/cache/stash/${name}/`.
 ///
 /// This file can then be installed via `update` using a component=${name} argument.
-pub fn stash<T: CachedBackend + ?Sized>(backend: &T, mf: &Manifest, name: &str) -> LalResult<()> {
+/// The time, environment and lal version are recorded in a `stash.json` next to it.
+/// The `pre-stash` and `post-stash` hooks run around it with the name as the version
+/// when `hooks` is set.
+pub fn stash<T: CachedBackend + Backend + ?Sized>(
+    backend: &T,
+    mf: &Manifest,
+    name: &str,
+    hooks: bool,
+) -> LalResult<()> {
     info!("Stashing OUTPUT into cache under {}/{}", mf.name, name);
     // sanity: verify name does NOT parse as a u32
     if let Ok(n) = name.parse::<u32>() {
         return Err(CliError::InvalidStashName(n));
     }
+    if !valid_stash_label(name) {
+        return Err(CliError::InvalidStashLabel(name.into()));
+    }
 
     let outputdir = Path::new("./OUTPUT");
     if !outputdir.is_dir() {
         return Err(CliError::MissingBuild);
     }
 
-    // convenience edit for lal status here:
-    // we edit the lockfile's version key to be "${stashname}"
-    // rather than the ugly colony default of "EXPERIMENTAL-${hex}"
-    // stashed builds are only used locally so this allows easier inspection
-    // full version list is available in `lal ls -f`
     let lf_path = Path::new("OUTPUT").join("lockfile.json");
-    let mut lf = Lockfile::from_path(&lf_path, &mf.name)?;
-    lf.version = name.to_string();
-    lf.write(&lf_path)?;
+    if !lf_path.is_file() {
+        return Err(CliError::MissingLockfile(mf.name.clone()));
+    }
+    // a lockfile on its own is not a build
+    let built = fs::read_dir(outputdir)
+        .context("Failed to read OUTPUT")?
+        .filter_map(|e| e.ok())
+        .any(|e| e.file_name().to_string_lossy() != "lockfile.json");
+    if !built {
+        return Err(CliError::MissingBuild);
+    }
+    let lf = Lockfile::from_path(&lf_path, &mf.name)?;
+
+    if let Some(ref contract) = mf.artifactContract {
+        output::verify_contract(contract)?;
+    }
+
+    let ctx = HookContext {
+        enabled: hooks,
+        component: mf.name.clone(),
+        version: Some(name.into()),
+        environment: Some(lf.environment.clone()),
+    };
+    run_hook("pre-stash", &ctx)?;
 
     // main operation:
+    // NB: the stash name is written into the lockfile version when unpacking
+    // stashed builds are only used locally so this allows easier inspection
+    // full version list is available in `lal ls -f`
     backend.stash_output(&mf.name, name)?;
 
+    let stashdir = Path::new(&backend.get_cache_dir()).join("stash").join(&mf.name).join(name);
+    let meta = StashMetadata {
+        component: mf.name.clone(),
+        name: name.into(),
+        stashed: UTC::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        environment: lf.environment,
+        config: lf.config,
+        tool: env!("CARGO_PKG_VERSION").into(),
+    };
+    meta.write(&stashdir)?;
+
+    run_hook("post-stash", &ctx)?;
+    Ok(())
+}
+
+fn human_size(bytes: u64) -> String {
+    let mb = bytes as f64 / (1024.0 * 1024.0);
+    if mb >= 1024.0 { format!("{:.1}G", mb / 1024.0) } else { format!("{:.1}M", mb) }
+}
+
+// a stashed build found in the cache
+struct StashEntry {
+    dir: PathBuf,
+    size: u64,
+    inode: (u64, u64),
+    environment: String,
+    age_days: i64,
+}
+
+// stashed builds in the cache keyed by `component/name`
+fn find_stashes(cachedir: &str, component: Option<&str>) -> BTreeMap<String, StashEntry> {
+    use std::os::unix::fs::MetadataExt;
+
+    let stashdir = Path::new(cachedir).join("stash");
+    let mut stashes = BTreeMap::new();
+    let dirs = WalkDir::new(&stashdir)
+        .min_depth(2)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir());
+    for d in dirs {
+        let pth = d.path().strip_prefix(&stashdir).unwrap().to_path_buf();
+        let comp = pth.parent().unwrap().to_string_lossy().into_owned();
+        if component.map_or(false, |c| c != comp) {
+            continue;
+        }
+        let tarball = find_tarball(d.path(), &comp);
+        if let Ok(meta) = tarball.metadata() {
+            // environment comes from the lockfile stashed along with the build
+            let environment = Lockfile::from_path_shallow(&d.path().join("lockfile.json"), &comp)
+                .map(|lf| lf.environment)
+                .unwrap_or_else(|_| "unknown".into());
+            let age = UTC::now().timestamp() - meta.mtime();
+            stashes.insert(pth.to_string_lossy().into_owned(),
+                           StashEntry {
+                               dir: d.path().to_path_buf(),
+                               size: meta.len(),
+                               inode: (meta.dev(), meta.ino()),
+                               environment: environment,
+                               age_days: age / (60 * 60 * 24),
+                           });
+        }
+    }
+    stashes
+}
+
+/// Print all stashed builds in the cache along with their sizes
+///
+/// Every stash is listed with the environment it was built in and its age,
+/// optionally only for a single `component`.
+/// Identical stashed tarballs are hardlinked to a shared blob, so the total
+/// apparent size can be much larger than what is actually used on disk.
+pub fn stash_list(cachedir: &str, component: Option<&str>) -> LalResult<()> {
+    let mut apparent = 0;
+    let mut used = 0;
+    let mut inodes = BTreeSet::new();
+    for (name, e) in find_stashes(cachedir, component) {
+        apparent += e.size;
+        if inodes.insert(e.inode) {
+            used += e.size;
+        }
+        println!("{} ({}, {}, {}d old)",
+                 name,
+                 human_size(e.size),
+                 e.environment,
+                 e.age_days);
+    }
+    println!("Total: {} apparent, {} deduplicated",
+             human_size(apparent),
+             human_size(used));
     Ok(())
 }
+
+/// Remove stashed builds from the cache
+///
+/// Removes the stash `name` of `component`, every stash of `component` if no name
+/// is given, or every stash in the cache without a `component`. Blobs no longer
+/// shared with any stash are removed as well.
+pub fn stash_remove(cachedir: &str, component: Option<&str>, name: Option<&str>) -> LalResult<()> {
+    let targets = find_stashes(cachedir, component)
+        .into_iter()
+        .filter(|&(ref key, _)| name.map_or(true, |n| key.ends_with(&format!("/{}", n))))
+        .map(|(key, e)| (key, e.dir))
+        .collect::<Vec<_>>();
+    if targets.is_empty() {
+        return match component {
+            Some(c) => {
+                let what = name.map(|n| format!("{}/{}", c, n)).unwrap_or_else(|| c.into());
+                Err(CliError::MissingStashArtifact(what))
+            }
+            None => {
+                info!("No stashed builds in the cache");
+                Ok(())
+            }
+        };
+    }
+    for (key, dir) in targets {
+        info!("Removing stash {}", key);
+        fs::remove_dir_all(&dir).context(format!("Failed to remove {}", dir.display()))?;
+        // drop the component directory once it has no stashes left
+        if let Some(parent) = dir.parent() {
+            let _ = fs::remove_dir(parent);
+        }
+    }
+    clean_orphaned_blobs(&Path::new(cachedir).join("stash").join(".blobs"))
+}
+
+// user name that pushed stashes are shared under
+fn stash_user() -> String { env::var("USER").unwrap_or_else(|_| "unknown".into()) }
+
+/// Share a stashed build of a component through the backend
+///
+/// The stash `name` is uploaded under the current `$USER`, so colleagues can
+/// `lal stash pull <user>/<name>` it without a version being published.
+pub fn stash_push<T: Backend + ?Sized>(backend: &T, component: &str, name: &str) -> LalResult<()> {
+    let dir = Path::new(&backend.get_cache_dir()).join("stash").join(component).join(name);
+    if !find_tarball(&dir, component).is_file() {
+        return Err(CliError::MissingStashArtifact(format!("{}/{}", component, name)));
+    }
+    let user = stash_user();
+    info!("Sharing stash {}/{} as {}/{}", component, name, user, name);
+    backend.upload_stash(component, &dir, &user, name)
+}
+
+/// Fetch a stash shared by another user into the local stash
+///
+/// `shared` is given as `<user>/<name>`. The component is read from the shared
+/// lockfile, and the build is stashed locally as `<user>-<name>` so it can be
+/// used with `lal update <component>=<user>-<name>`. Returns the component name.
+pub fn stash_pull<T: Backend + ?Sized>(
+    backend: &T,
+    shared: &str,
+    reporter: &ProgressReporter,
+) -> LalResult<String> {
+    let (user, name) = match shared.find('/') {
+        Some(i) => (&shared[..i], &shared[i + 1..]),
+        None => return Err(CliError::InvalidStashLabel(shared.into())),
+    };
+    if !valid_stash_label(user) || !valid_stash_label(name) {
+        return Err(CliError::InvalidStashLabel(shared.into()));
+    }
+    let local = format!("{}-{}", user, name);
+
+    // the lockfile tells us what component was shared
+    let stashdir = Path::new(&backend.get_cache_dir()).join("stash");
+    let incoming = stashdir.join(".incoming");
+    fs::create_dir_all(&incoming).context(format!("Failed to create {}", incoming.display()))?;
+    let lf_path = incoming.join("lockfile.json");
+    let _ = fs::remove_file(&lf_path);
+    backend.raw_fetch(&backend.get_stash_location(user, name, "lockfile.json")?,
+                   &lf_path,
+                   reporter)?;
+    let component = Lockfile::from_path_shallow(&lf_path, shared)?.name;
+    if !valid_stash_label(&component) {
+        return Err(CliError::InvalidStashLabel(component));
+    }
+
+    let dest = stashdir.join(&component).join(&local);
+    fs::create_dir_all(&dest).context(format!("Failed to create {}", dest.display()))?;
+    // the shared tarball can have any compression
+    let mut fetched = false;
+    for c in &COMPRESSIONS {
+        let tarname = c.tarball_name(&component);
+        let tarball = dest.join(&tarname);
+        let _ = fs::remove_file(&tarball); // may be a shared hardlink
+        match backend.get_stash_location(user, name, &tarname)
+            .and_then(|loc| backend.raw_fetch(&loc, &tarball, reporter)) {
+            Ok(_) => {
+                fetched = true;
+                break;
+            }
+            Err(e) => {
+                debug!("No {} shared as {} ({})", tarname, shared, e);
+                let _ = fs::remove_file(&tarball);
+            }
+        }
+    }
+    if !fetched {
+        return Err(CliError::MissingStashArtifact(shared.into()));
+    }
+    fs::rename(&lf_path, dest.join("lockfile.json"))
+        .context(format!("Failed to move lockfile into {}", dest.display()))?;
+    info!("Stashed {} from {} - use `lal update {}={}`",
+          component,
+          shared,
+          component,
+          local);
+    Ok(component)
+}
@@ -1,6 +1,34 @@
+use std::collections::BTreeMap;
 use ansi_term::{Colour, ANSIString};
+use serde_json;
 use core::input;
-use super::{Lockfile, CliError, LalResult, Manifest};
+use storage::Backend;
+use super::{Lockfile, CliError, LalResult, Manifest, InputSource, InputSources, StickyOptions};
+
+/// A first level dependency in `lal status --json`
+#[derive(Serialize)]
+struct StatusEntry<'a> {
+    name: String,
+    version: Option<&'a str>,
+    environment: Option<&'a str>,
+    built: Option<&'a str>,
+    missing: bool,
+    extraneous: bool,
+    development: bool,
+    requested: bool,
+    group: Option<String>,
+    source: Option<&'a InputSource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dependencies: Option<&'a BTreeMap<String, Lockfile>>,
+}
+
+/// Output of `lal status --json`
+#[derive(Serialize)]
+struct StatusReport<'a> {
+    name: &'a str,
+    dependencies: Vec<StatusEntry<'a>>,
+    error: Option<String>,
+}
 
 fn version_string(lf: Option<&Lockfile>, show_ver: bool, show_time: bool) -> ANSIString<'static> {
     if let Some(lock) = lf {
@@ -60,26 +88,61 @@ fn status_recurse(
     }
 }
 
+/// Options for `lal status`
+#[derive(Clone, Default, Debug)]
+pub struct StatusOptions {
+    /// Splice in the full dependency tree from the lockfiles
+    pub full: bool,
+    /// Show the version of each component
+    pub versions: bool,
+    /// Show when each component was built
+    pub time: bool,
+    /// Show the cached tarball each first level dependency was extracted from
+    pub paths: bool,
+    /// Print a json object instead of the tree
+    pub json: bool,
+}
+
 /// Prints a fancy dependency tree of `./INPUT` to stdout.
 ///
 /// This is the quick version information of what you currently have in `./INPUT`.
 /// It prints the tree and highlights versions, as well as both missing and extraneous
 /// dependencies in `./INPUT`.
 ///
-/// If the full flag is given, then the full dependency tree is also spliced in
-/// from lockfile data.
+/// If the full option is given, then the full dependency tree is also spliced in
+/// from lockfile data. If the paths option is given, the cached tarball each first level
+/// dependency was extracted from is shown along with its digest.
 ///
 /// It is not intended as a verifier, but will nevertheless produce a summary at the end.
-pub fn status(manifest: &Manifest, full: bool, show_ver: bool, show_time: bool) -> LalResult<()> {
+/// The `json` option prints the same information (always with versions and sources)
+/// as a single json object instead of the tree.
+pub fn status(manifest: &Manifest, opts: &StatusOptions) -> LalResult<()> {
+    let StatusOptions { full, versions: show_ver, time: show_time, paths: show_paths, json } =
+        *opts;
     let mut error = None;
 
-    let lf = Lockfile::default().populate_from_input()?;
+    // only the full tree needs the dependencies of INPUT components
+    let lf = if full {
+        Lockfile::default().populate_from_input()?
+    } else {
+        Lockfile::default().populate_from_input_shallow()?
+    };
+    let sources = InputSources::read()?;
+    let optional = manifest.excluded_group_members(&StickyOptions::read()?.groups, false);
 
-    println!("{}", manifest.name);
-    let deps = input::analyze_full(manifest)?;
+    if !json {
+        println!("{}", manifest.name);
+    }
+    let deps = input::analyze_full(manifest, &lf)?;
+    let mut entries = vec![];
     let len = deps.len();
     for (i, (d, dep)) in deps.iter().enumerate() {
-        let notes = if dep.missing && !dep.development {
+        let group = manifest.group_of(&dep.name)
+            .map(|g| format!(" (group {})", g))
+            .unwrap_or_default();
+        let notes = if dep.missing && optional.contains(&dep.name) {
+            Colour::Yellow.paint("(not requested)").to_string()
+        } else if dep.missing && !dep.development {
             error = Some(CliError::MissingDependencies);
             Colour::Red.paint("(missing)").to_string()
         } else if dep.missing {
@@ -92,6 +155,23 @@ pub fn status(manifest: &Manifest, full: bool, show_ver: bool, show_time: bool)
         } else {
             "".to_string()
         };
+        if json {
+            let lock = lf.dependencies.get(&dep.name);
+            entries.push(StatusEntry {
+                name: dep.name.clone(),
+                version: lock.map(|l| l.version.as_str()),
+                environment: lock.map(|l| l.environment.as_str()),
+                built: lock.and_then(|l| l.built.as_ref().map(|b| b.as_str())),
+                missing: dep.missing,
+                extraneous: dep.extraneous,
+                development: dep.development,
+                requested: !optional.contains(&dep.name),
+                group: manifest.group_of(&dep.name).map(String::from),
+                source: sources.components.get(&dep.name),
+                dependencies: if full { lock.map(|l| &l.dependencies) } else { None },
+            });
+            continue;
+        }
         // list children in --full mode
         // NB: missing deps will not be populatable
         let has_children = full && !dep.missing &&
@@ -101,9 +181,16 @@ pub fn status(manifest: &Manifest, full: bool, show_ver: bool, show_time: bool)
         let turn_char = if is_last { "└" } else { "├" };
 
         // first level deps are formatted with more metadata
-        let level1 = format!("{} {}", d, notes);
+        let level1 = format!("{} {}{}", d, notes, group);
         let ver_str = version_string(lf.dependencies.get(&dep.name), show_ver, show_time);
-        println!("{}─{} {} {}", turn_char, fork_char, level1, ver_str);
+        let src_str = if !show_paths || dep.missing {
+            "".to_string()
+        } else if let Some(src) = sources.components.get(&dep.name) {
+            format!("[{} sha1:{}]", src.path, src.sha1)
+        } else {
+            "[unknown source]".to_string()
+        };
+        println!("{}─{} {} {} {}", turn_char, fork_char, level1, ver_str, src_str);
 
         if has_children {
             trace!("Attempting to get {} out of lockfile deps {:?}",
@@ -115,9 +202,197 @@ pub fn status(manifest: &Manifest, full: bool, show_ver: bool, show_time: bool)
         }
     }
 
+    if json {
+        let report = StatusReport {
+            name: &manifest.name,
+            dependencies: entries,
+            error: error.as_ref().map(|e: &CliError| e.to_string()),
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+
     // Return one of the errors as the main one (no need to vectorize these..)
     if let Some(e) = error {
         return Err(e);
     }
     Ok(())
 }
+
+/// A first level dependency in `lal status --table`
+#[derive(Debug)]
+pub struct TableRow {
+    /// Component name
+    pub name: String,
+    /// Version (or version range) required by the manifest, or `-`
+    pub manifest: String,
+    /// Version in INPUT, or `-`
+    pub input: String,
+    /// Environment of the component in INPUT, or `-`
+    pub environment: String,
+    /// Latest version published in the environment, or `-`
+    pub latest: String,
+    /// Drift against the manifest, like `missing` or `outdated` (empty when ok)
+    pub states: Vec<&'static str>,
+}
+
+impl TableRow {
+    fn cells(&self) -> Vec<String> {
+        let state = if self.states.is_empty() { "ok".into() } else { self.states.join(",") };
+        vec![self.name.clone(),
+             self.manifest.clone(),
+             self.input.clone(),
+             self.environment.clone(),
+             self.latest.clone(),
+             state]
+    }
+
+    fn colour(&self) -> Option<Colour> {
+        if self.states.iter().any(|s| *s == "missing" || *s == "mismatch") {
+            Some(Colour::Red)
+        } else if self.states.iter().any(|s| ["outdated", "stashed", "environment"].contains(s)) {
+            Some(Colour::Yellow)
+        } else if self.states.contains(&"extraneous") {
+            Some(Colour::Green)
+        } else {
+            None
+        }
+    }
+}
+
+const TABLE_HEADER: &'static [&'static str] = &["name", "manifest", "input", "environment",
+                                                "latest", "status"];
+
+/// The rows of `lal status --table`: first level dependencies with drift against the manifest
+///
+/// Every dependency in the manifest or in `./INPUT` gets a row with the version
+/// required by the manifest (for this environment), the version and environment
+/// in INPUT, and the latest version published in the environment. The states
+/// flag components that are missing, extraneous, stashed, development only, built
+/// in another environment, not matching the manifest, or behind the latest version.
+pub fn status_rows(manifest: &Manifest, backend: &Backend, env: &str) -> LalResult<Vec<TableRow>> {
+    let m = manifest.for_environment(env);
+    let lf = Lockfile::default().populate_from_input_shallow()?;
+    let deps = input::analyze_full(&m, &lf)?;
+    let optional = m.excluded_group_members(&StickyOptions::read()?.groups, false);
+    let names = deps.keys().cloned().collect::<Vec<_>>();
+    let mut latests = backend.get_latest_versions_of(&names, env);
+
+    let mut rows = vec![];
+    for dep in deps.values() {
+        let lock = lf.dependencies.get(&dep.name);
+        let required = m.all_dependencies().get(&dep.name).cloned();
+        let range = m.version_range(&dep.name);
+        let input_ver = lock.and_then(|l| l.version.parse::<u32>().ok());
+        let latest = match latests.remove(&dep.name) {
+            Some(Ok(v)) => Some(v),
+            Some(Err(e)) => {
+                debug!("No latest version of {} in {}: {}", dep.name, env, e);
+                None
+            }
+            None => None,
+        };
+
+        let mut states = vec![];
+        if dep.missing && optional.contains(&dep.name) {
+            states.push("not-requested");
+        } else if dep.missing {
+            states.push("missing");
+        }
+        if dep.extraneous {
+            states.push("extraneous");
+        }
+        if dep.development {
+            states.push("dev");
+        }
+        if let Some(l) = lock {
+            if input_ver.is_none() {
+                states.push("stashed");
+            }
+            if l.environment != env {
+                states.push("environment");
+            }
+        }
+        if let (Some(v), Some(req)) = (input_ver, required) {
+            let satisfied = range.map_or(v == req, |r| r.matches(v));
+            if !satisfied {
+                states.push("mismatch");
+            }
+        }
+        if let (Some(v), Some(l)) = (input_ver, latest) {
+            if l > v {
+                states.push("outdated");
+            }
+        }
+        rows.push(TableRow {
+            name: dep.name.clone(),
+            manifest: range.map(|r| r.to_string())
+                .or_else(|| required.map(|r| r.to_string()))
+                .unwrap_or_else(|| "-".into()),
+            input: lock.map(|l| l.version.clone()).unwrap_or_else(|| "-".into()),
+            environment: lock.map(|l| l.environment.clone()).unwrap_or_else(|| "-".into()),
+            latest: latest.map(|l| l.to_string()).unwrap_or_else(|| "-".into()),
+            states: states,
+        });
+    }
+    Ok(rows)
+}
+
+/// Prints the first level dependencies as a table with drift against the manifest
+///
+/// See `status_rows` for what the columns hold.
+///
+/// The `machine` flag prints tab separated values without colour for scripts.
+/// Like `status`, this fails on missing or extraneous dependencies.
+pub fn status_table(
+    manifest: &Manifest,
+    backend: &Backend,
+    env: &str,
+    machine: bool,
+) -> LalResult<()> {
+    let rows = status_rows(manifest, backend, env)?;
+    let mut error = None;
+    for r in &rows {
+        if r.states.contains(&"missing") && !r.states.contains(&"dev") {
+            error = Some(CliError::MissingDependencies);
+        }
+        if r.states.contains(&"extraneous") {
+            error = Some(CliError::ExtraneousDependencies(r.name.clone()));
+        }
+    }
+
+    if machine {
+        println!("{}", TABLE_HEADER.join("\t"));
+        for r in &rows {
+            println!("{}", r.cells().join("\t"));
+        }
+    } else {
+        let mut widths = TABLE_HEADER.iter().map(|h| h.len()).collect::<Vec<_>>();
+        for r in &rows {
+            for (w, c) in widths.iter_mut().zip(r.cells()) {
+                *w = (*w).max(c.len());
+            }
+        }
+        let align = |cells: Vec<String>| {
+            cells.iter()
+                .zip(&widths)
+                .map(|(c, w)| format!("{:w$}", c, w = *w))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_right()
+                .to_string()
+        };
+        println!("{}", align(TABLE_HEADER.iter().map(|h| h.to_string()).collect()));
+        for r in &rows {
+            let line = align(r.cells());
+            match r.colour() {
+                Some(c) => println!("{}", c.paint(line)),
+                None => println!("{}", line),
+            }
+        }
+    }
+
+    if let Some(e) = error {
+        return Err(e);
+    }
+    Ok(())
+}
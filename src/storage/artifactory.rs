@@ -1,31 +1,101 @@
 #![allow(missing_docs)]
 
+use std::cell::RefCell;
+use std::cmp;
+use std::error::Error;
+use std::collections::{BTreeMap, BTreeSet};
 use std::vec::Vec;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::fs::File;
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
 
 #[cfg(feature = "upgrade")]
 use semver::Version;
 
+use chrono::{DateTime, UTC};
+
+use rand;
 use serde_json;
 use sha1;
 use hyper::{self, Client};
-use hyper::net::HttpsConnector;
-use hyper::header::{Authorization, Basic};
+use hyper::net::{HttpConnector, HttpsConnector, HttpsStream, NetworkConnector, SslClient};
+use hyper::header::{Authorization, Basic, Headers, Host, ProxyAuthorization};
+use hyper::method::Method;
 use hyper::status::StatusCode;
+use hyper::client::{ProxyConfig, RequestBuilder};
 use hyper_native_tls::NativeTlsClient;
+use native_tls::{TlsConnector, TlsConnectorBuilder};
 
-use core::{CliError, LalResult};
+use core::{CliError, Compression, CompressionOptions, IoResultExt, LalResult, Deprecations,
+           Lockfile, ProgressReporter, SignaturePolicy, find_tarball, signature_path};
+use configure::CredentialProvider;
 
 
 /// Artifactory credentials
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
 pub struct Credentials {
-    /// Upload username
+    /// Username for basic auth
+    #[serde(default)]
     pub username: String,
-    /// Upload password
+    /// Password for basic auth
+    #[serde(default)]
     pub password: String,
+    /// API key sent instead of basic auth when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+}
+
+impl Credentials {
+    /// Credentials from `LAL_ARTIFACTORY_USERNAME`/`LAL_ARTIFACTORY_PASSWORD` or
+    /// `LAL_ARTIFACTORY_API_KEY`
+    pub fn from_env() -> Option<Credentials> {
+        use std::env;
+        let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+        let api_key = var("LAL_ARTIFACTORY_API_KEY");
+        match (var("LAL_ARTIFACTORY_USERNAME"), var("LAL_ARTIFACTORY_PASSWORD")) {
+            (Some(u), Some(p)) => {
+                Some(Credentials {
+                         username: u,
+                         password: p,
+                         api_key: api_key,
+                     })
+            }
+            _ if api_key.is_some() => Some(Credentials { api_key: api_key, ..Default::default() }),
+            _ => None,
+        }
+    }
+}
+
+/// How often and how patiently to retry transient request failures
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total number of attempts per request (including the first)
+    pub attempts: u32,
+    /// Milliseconds to wait before the first retry (doubled for every retry after)
+    pub backoff: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            backoff: 500,
+        }
+    }
+}
+
+/// Transport settings for every request to artifactory
+#[derive(Clone, Default, Debug)]
+pub struct HttpOptions {
+    /// Retry policy for transient failures
+    pub retry: RetryPolicy,
+    /// PEM bundle of extra certificate authorities to trust
+    pub ca_bundle: Option<String>,
+    /// Credentials sent with every request
+    pub credentials: Option<Credentials>,
 }
 
 /// Static Artifactory locations
@@ -39,8 +109,23 @@ pub struct ArtifactoryConfig {
     pub release: String,
     /// Virtual group (for downloads)
     pub vgroup: String,
-    /// Optional publish credentials
+    /// Optional credentials for publishing, and for downloads from private repositories
     pub credentials: Option<Credentials>,
+    /// Retry policy for transient failures (server errors, timeouts, dropped connections)
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Mirrors of `slave` that downloads are tried from first, in order
+    ///
+    /// Only downloads use them. API queries still go to `master`, and publishing to `slave`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+}
+
+impl ArtifactoryConfig {
+    /// Credentials from the environment, falling back to the config
+    pub fn resolve_credentials(&self) -> Option<Credentials> {
+        Credentials::from_env().or_else(|| self.credentials.clone())
+    }
 }
 
 
@@ -55,54 +140,422 @@ struct ArtifactoryVersion {
 struct ArtifactoryStorageResponse {
     children: Vec<ArtifactoryVersion>,
 }
+// The storage api on a file has its checksums (sha256 on newer artifactory versions)
+#[derive(Deserialize)]
+struct ArtifactoryFileInfo {
+    checksums: Checksums,
+}
 
-// simple request body fetcher
-fn hyper_req(url: &str) -> LalResult<String> {
-    let client = Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap()));
-    let mut res = client.get(url).send()?;
-    if res.status != hyper::Ok {
-        return Err(CliError::BackendFailure(format!("GET request with {}", res.status)));
+// The property search api lists matching items by their storage api uri
+#[derive(Deserialize)]
+struct ArtifactorySearchResult {
+    uri: String,
+}
+#[derive(Deserialize)]
+struct ArtifactorySearchResponse {
+    results: Vec<ArtifactorySearchResult>,
+}
+// The properties api on an item has multi-valued properties
+#[derive(Deserialize)]
+struct ArtifactoryProperties {
+    properties: BTreeMap<String, Vec<String>>,
+}
+
+// item property holding the release channels a published version is in
+const CHANNEL_PROPERTY: &'static str = "lal.channel";
+
+// The storage api on a folder has its creation time
+#[derive(Deserialize)]
+struct ArtifactoryFolderInfo {
+    created: String,
+}
+
+// a failed request attempt, and whether it is worth trying again
+struct Failure {
+    err: CliError,
+    retry: bool,
+}
+
+// connection problems and interrupted transfers are always worth retrying
+impl From<hyper::Error> for Failure {
+    fn from(e: hyper::Error) -> Failure {
+        Failure {
+            err: e.into(),
+            retry: true,
+        }
+    }
+}
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Failure {
+        Failure {
+            err: e.into(),
+            retry: true,
+        }
     }
-    let mut body = String::new();
-    res.read_to_string(&mut body)?;
-    Ok(body)
 }
 
-// simple request downloader
-pub fn http_download_to_path(url: &str, save: &PathBuf) -> LalResult<()> {
-    debug!("GET {}", url);
-    let client = Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap()));
-    let mut res = client.get(url).send()?;
-    if res.status != hyper::Ok {
-        return Err(CliError::BackendFailure(format!("GET request with {}", res.status)));
-    }
-
-    if cfg!(feature = "progress") {
-        #[cfg(feature = "progress")]
-        {
-            use indicatif::{ProgressBar, ProgressStyle};
-            let total_size = res.headers.get::<hyper::header::ContentLength>().unwrap().0;
-            let mut downloaded = 0;
-            let mut buffer = [0; 1024 * 64];
-            let mut f = File::create(save)?;
-            let pb = ProgressBar::new(total_size);
-            pb.set_style(ProgressStyle::default_bar()
-                             .template("{bar:40.yellow/black} {bytes}/{total_bytes} ({eta})"));
-
-            while downloaded < total_size {
-                let read = res.read(&mut buffer)?;
-                f.write_all(&buffer[0..read])?;
-                downloaded += read as u64;
-                pb.set_position(downloaded);
+// server errors, timeouts and throttling are transient - anything else (like 404) is not
+fn status_failure(err: CliError, status: StatusCode) -> Failure {
+    let retry = status.is_server_error() || status == StatusCode::RequestTimeout ||
+                status == StatusCode::TooManyRequests;
+    Failure {
+        err: err,
+        retry: retry,
+    }
+}
+
+// a failed GET, telling a missing file apart from every other failure
+fn get_failure(url: &str, status: StatusCode) -> Failure {
+    if status == StatusCode::NotFound {
+        return status_failure(CliError::RemoteNotFound(url.into()), status);
+    }
+    status_failure(CliError::BackendFailure(format!("GET request with {}", status)), status)
+}
+
+// run a request attempt until it succeeds, fails permanently, or runs out of attempts
+fn with_retries<T, F>(policy: &RetryPolicy, what: &str, mut attempt: F) -> LalResult<T>
+where
+    F: FnMut() -> Result<T, Failure>,
+{
+    let mut delay = policy.backoff;
+    let mut tries = 1;
+    loop {
+        match attempt() {
+            Ok(res) => return Ok(res),
+            Err(ref f) if f.retry && tries < policy.attempts => {
+                warn!("{} failed ({}) - retrying in {}ms ({}/{})",
+                      what,
+                      f.err,
+                      delay,
+                      tries,
+                      policy.attempts);
+                thread::sleep(Duration::from_millis(delay));
+                delay *= 2;
+                tries += 1;
             }
-            f.flush()?;
+            Err(f) => return Err(f.err),
+        }
+    }
+}
+
+/// A proxy server picked from the environment for a url
+#[derive(Debug)]
+pub struct Proxy {
+    /// Host of the proxy
+    pub host: String,
+    /// Port of the proxy
+    pub port: u16,
+    /// Basic auth credentials given as userinfo in the proxy url
+    pub credentials: Option<Credentials>,
+}
+
+// decode the %XX escapes that userinfo in a url is written with
+fn percent_decode(s: &str) -> String {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < s.len() {
+        let escaped = s.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match escaped {
+            Some(b) if s.as_bytes()[i] == b'%' => {
+                out.push(b);
+                i += 3;
+            }
+            _ => {
+                out.push(s.as_bytes()[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// The proxy to use for a url from `http_proxy`/`https_proxy`
+///
+/// Hosts matched by `no_proxy` (exactly, or as a subdomain) are not proxied.
+pub fn proxy_for(url: &str) -> Option<Proxy> {
+    use std::env;
+    let var = |name: &str| {
+        env::var(name)
+            .or_else(|_| env::var(name.to_uppercase()))
+            .ok()
+            .filter(|v| !v.is_empty())
+    };
+    let url = hyper::Url::parse(url).ok()?;
+    let host = url.host_str().unwrap_or("");
+    if let Some(np) = var("no_proxy") {
+        let bypassed = np.split(',').map(|h| h.trim().trim_left_matches('.')).any(|h| {
+            h == "*" || (!h.is_empty() && (host == h || host.ends_with(&format!(".{}", h))))
+        });
+        if bypassed {
+            trace!("Not proxying {} (no_proxy)", host);
+            return None;
+        }
+    }
+    let proxy = var(&format!("{}_proxy", url.scheme()))?;
+    // proxies are commonly given without a scheme
+    let proxy = if proxy.contains("://") { proxy } else { format!("http://{}", proxy) };
+    match hyper::Url::parse(&proxy) {
+        Ok(ref p) if p.host_str().is_some() => {
+            let credentials = if p.username().is_empty() {
+                None
+            } else {
+                Some(Credentials {
+                         username: percent_decode(p.username()),
+                         password: percent_decode(p.password().unwrap_or("")),
+                         api_key: None,
+                     })
+            };
+            Some(Proxy {
+                     host: p.host_str().unwrap().into(),
+                     port: p.port_or_known_default().unwrap_or(80),
+                     credentials: credentials,
+                 })
+        }
+        _ => {
+            warn!("Ignoring invalid proxy {}", proxy);
+            None
         }
-    } else {
-        let mut buffer: Vec<u8> = Vec::new();
-        res.read_to_end(&mut buffer)?;
-        let mut f = File::create(save)?;
-        f.write_all(&buffer)?;
     }
+}
+
+// basic auth for a proxy that was given credentials
+fn proxy_authorization(proxy: &Proxy) -> Option<ProxyAuthorization<Basic>> {
+    proxy.credentials.as_ref().map(|c| {
+        ProxyAuthorization(Basic {
+                               username: c.username.clone(),
+                               password: Some(c.password.clone()),
+                           })
+    })
+}
+
+// add the certificate authorities in a PEM bundle to the trusted roots
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn add_ca_bundle(builder: &mut TlsConnectorBuilder, ca: &str) -> Result<(), Box<Error>> {
+    use native_tls::backend::openssl::TlsConnectorBuilderExt;
+    builder.builder_mut().builder_mut().set_ca_file(ca)?;
+    Ok(())
+}
+
+// only the OpenSSL backend of native-tls can read a PEM bundle of several certificates
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "windows"))]
+fn add_ca_bundle(_: &mut TlsConnectorBuilder, _: &str) -> Result<(), Box<Error>> {
+    Err("caBundle is only supported on platforms using OpenSSL".into())
+}
+
+// tls client trusting the system roots, plus the configured CA bundle
+fn tls_client(ca_bundle: Option<&str>) -> LalResult<NativeTlsClient> {
+    let invalid = |e: &Error| {
+        CliError::InvalidCaBundle(format!("{} ({})", ca_bundle.unwrap_or("system"), e))
+    };
+    let mut builder = TlsConnector::builder().map_err(|e| invalid(&e))?;
+    if let Some(ca) = ca_bundle {
+        debug!("Trusting certificate authorities in {}", ca);
+        add_ca_bundle(&mut builder, ca).map_err(|e| invalid(&*e))?;
+    }
+    Ok(NativeTlsClient::from(builder.build().map_err(|e| invalid(&e))?))
+}
+
+// connects through a CONNECT tunnel, authenticating with the proxy if it has credentials
+//
+// hyper's own proxy support sends CONNECT without any way to add a Proxy-Authorization.
+struct TunnelConnector {
+    proxy: Proxy,
+    tls: NativeTlsClient,
+}
+
+impl NetworkConnector for TunnelConnector {
+    type Stream = HttpsStream<<NativeTlsClient as SslClient>::Stream>;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<Self::Stream> {
+        let mut stream = HttpConnector.connect(&self.proxy.host, self.proxy.port, "http")?;
+        let mut headers = Headers::new();
+        headers.set(Host {
+                        hostname: host.into(),
+                        port: Some(port),
+                    });
+        if let Some(auth) = proxy_authorization(&self.proxy) {
+            headers.set(auth);
+        }
+        write!(stream, "CONNECT {}:{} HTTP/1.1\r\n{}\r\n", host, port, headers)?;
+        stream.flush()?;
+        // read the response head a byte at a time to leave the tunnelled stream untouched
+        let mut head = vec![];
+        let mut byte = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") && head.len() < 8192 {
+            if stream.read(&mut byte)? == 0 {
+                break;
+            }
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let status = head.lines().next().unwrap_or("").trim();
+        if status.split_whitespace().nth(1) != Some("200") {
+            let msg = format!("proxy refused CONNECT to {}:{} ({})", host, port, status);
+            return Err(io::Error::new(io::ErrorKind::Other, msg).into());
+        }
+        match scheme {
+            "https" => self.tls.wrap_client(stream, host).map(HttpsStream::Https),
+            _ => Ok(HttpsStream::Http(stream)),
+        }
+    }
+}
+
+// a hyper client that adds proxy credentials to requests sent to a plain http proxy
+struct HttpClient {
+    client: Client,
+    proxy_auth: Option<ProxyAuthorization<Basic>>,
+}
+
+impl HttpClient {
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let req = self.client.request(method, url);
+        match self.proxy_auth {
+            Some(ref auth) => req.header(auth.clone()),
+            None => req,
+        }
+    }
+    fn get(&self, url: &str) -> RequestBuilder { self.request(Method::Get, url) }
+    fn put(&self, url: &str) -> RequestBuilder { self.request(Method::Put, url) }
+    fn post(&self, url: &str) -> RequestBuilder { self.request(Method::Post, url) }
+    fn delete(&self, url: &str) -> RequestBuilder { self.request(Method::Delete, url) }
+}
+
+// a hyper client for a url, going through a proxy if the environment asks for one
+//
+// https goes through a CONNECT tunnel, plain http through hyper's forwarding proxy.
+fn http_client(url: &str, http: &HttpOptions) -> LalResult<HttpClient> {
+    let tls = tls_client(http.ca_bundle.as_ref().map(|s| s.as_str()))?;
+    let parsed = hyper::Url::parse(url)
+        .map_err(|e| CliError::BackendFailure(format!("Invalid url {} ({})", url, e)))?;
+    let (client, proxy_auth) = match proxy_for(url) {
+        Some(proxy) => {
+            debug!("Using proxy {}:{} for {}", proxy.host, proxy.port, url);
+            if parsed.scheme() == "https" {
+                let tunnel = TunnelConnector {
+                    proxy: proxy,
+                    tls: tls,
+                };
+                (Client::with_connector(tunnel), None)
+            } else {
+                let auth = proxy_authorization(&proxy);
+                let cfg = ProxyConfig::new("http", proxy.host, proxy.port, HttpConnector, tls);
+                (Client::with_proxy_config(cfg), auth)
+            }
+        }
+        None => (Client::with_connector(HttpsConnector::new(tls)), None),
+    };
+    Ok(HttpClient {
+           client: client,
+           proxy_auth: proxy_auth,
+       })
+}
+
+// attach credentials to a request, preferring an api key over basic auth
+fn authorized<'a>(req: RequestBuilder<'a>, creds: &Option<Credentials>) -> RequestBuilder<'a> {
+    match *creds {
+        Some(Credentials { api_key: Some(ref key), .. }) => req.header(XJFrogArtApi(key.clone())),
+        Some(ref c) => {
+            req.header(Authorization(Basic {
+                                         username: c.username.clone(),
+                                         password: Some(c.password.clone()),
+                                     }))
+        }
+        None => req,
+    }
+}
+
+// simple request body fetcher
+fn hyper_req(url: &str, http: &HttpOptions) -> LalResult<String> {
+    let client = http_client(url, http)?;
+    with_retries(&http.retry, &format!("GET {}", url), || {
+        let mut res = authorized(client.get(url), &http.credentials).send()?;
+        if res.status != hyper::Ok {
+            return Err(get_failure(url, res.status));
+        }
+        let mut body = String::new();
+        res.read_to_string(&mut body)?;
+        Ok(body)
+    })
+}
+
+// like `hyper_req`, but with None for a 404 (and every other failure still an error)
+fn hyper_req_optional(url: &str, http: &HttpOptions) -> LalResult<Option<String>> {
+    let client = http_client(url, http)?;
+    with_retries(&http.retry, &format!("GET {}", url), || {
+        let mut res = authorized(client.get(url), &http.credentials).send()?;
+        if res.status == StatusCode::NotFound {
+            return Ok(None);
+        }
+        if res.status != hyper::Ok {
+            let err = CliError::BackendFailure(format!("GET request with {}", res.status));
+            return Err(status_failure(err, res.status));
+        }
+        let mut body = String::new();
+        res.read_to_string(&mut body)?;
+        Ok(Some(body))
+    })
+}
+
+/// Download a url to a path, retrying transient failures
+///
+/// An existing file at the path is resumed with a HTTP Range request, so an
+/// interrupted download (or a retry) only fetches the remaining bytes.
+pub fn http_download_to_path(
+    url: &str,
+    save: &PathBuf,
+    http: &HttpOptions,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    debug!("GET {}", url);
+    let client = http_client(url, http)?;
+    with_retries(&http.retry,
+                 &format!("GET {}", url),
+                 || download_attempt(&client, url, save, &http.credentials, reporter))
+}
+
+// a single download attempt, resuming from what a previous attempt left behind
+fn download_attempt(
+    client: &HttpClient,
+    url: &str,
+    save: &PathBuf,
+    creds: &Option<Credentials>,
+    reporter: &ProgressReporter,
+) -> Result<(), Failure> {
+    use hyper::header::{ContentLength, Range, ByteRangeSpec};
+    use std::fs::OpenOptions;
+
+    let offset = save.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut req = authorized(client.get(url), creds);
+    if offset > 0 {
+        debug!("Resuming {} from byte {}", url, offset);
+        req = req.header(Range::Bytes(vec![ByteRangeSpec::AllFrom(offset)]));
+    }
+    let mut res = req.send()?;
+    let (mut f, mut downloaded) = match res.status {
+        StatusCode::PartialContent if offset > 0 => {
+            (OpenOptions::new().append(true).open(save)?, offset)
+        }
+        StatusCode::RangeNotSatisfiable if offset > 0 => {
+            // nothing left to fetch - checksum verification decides if it is intact
+            debug!("{} was already completely downloaded", url);
+            return Ok(());
+        }
+        hyper::Ok => (File::create(save)?, 0), // no range support - start over
+        status => return Err(get_failure(url, status)),
+    };
+
+    let total_size = res.headers.get::<ContentLength>().map(|l| l.0 + downloaded);
+    let mut buffer = [0; 1024 * 64];
+    loop {
+        let read = res.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        f.write_all(&buffer[0..read])?;
+        downloaded += read as u64;
+        reporter.bytes(downloaded, total_size);
+    }
+    f.flush()?;
     Ok(())
 }
 
@@ -111,14 +564,18 @@ pub fn http_download_to_path(url: &str, save: &PathBuf) -> LalResult<()> {
 ///
 /// This will get, then parse all results as u32s, and return this list.
 /// This assumes versoning is done via a single integer.
-fn get_storage_versions(uri: &str) -> LalResult<Vec<u32>> {
+fn get_storage_versions(uri: &str, http: &HttpOptions) -> LalResult<Vec<u32>> {
     debug!("GET {}", uri);
 
-    let resp = hyper_req(uri)
-        .map_err(|e| {
+    // a component that was never published has no versions
+    let resp = match hyper_req_optional(uri, http) {
+        Ok(Some(resp)) => resp,
+        Ok(None) => return Ok(vec![]),
+        Err(e) => {
             warn!("Failed to GET {}: {}", uri, e);
-            CliError::BackendFailure("No version information found on API".into())
-        })?;
+            return Err(CliError::BackendFailure("No version information found on API".into()));
+        }
+    };
 
     trace!("Got body {}", resp);
 
@@ -136,36 +593,53 @@ fn get_storage_versions(uri: &str) -> LalResult<Vec<u32>> {
 // artifactory extra headers
 header! {(XCheckSumDeploy, "X-Checksum-Deploy") => [String]}
 header! {(XCheckSumSha1, "X-Checksum-Sha1") => [String]}
+header! {(XJFrogArtApi, "X-JFrog-Art-Api") => [String]}
 
 /// Upload a tarball to artifactory
 ///
-/// This is using a http basic auth PUT to artifactory using config credentials.
-fn upload_artifact(arti: &ArtifactoryConfig, uri: &str, f: &mut File) -> LalResult<()> {
-    if let Some(creds) = arti.credentials.clone() {
-        let client = Client::new();
-
-        let mut buffer: Vec<u8> = Vec::new();
-        f.read_to_end(&mut buffer)?;
+/// This is using an authenticated PUT to artifactory using config credentials.
+/// The file is streamed from disk rather than held in memory.
+fn upload_artifact(
+    arti: &ArtifactoryConfig,
+    http: &HttpOptions,
+    uri: &str,
+    f: &mut File,
+) -> LalResult<()> {
+    use hyper::client::Body;
+    use std::io::{Seek, SeekFrom};
 
+    if http.credentials.is_some() {
         let full_uri = format!("{}/{}/{}", arti.slave, arti.release, uri);
+        let client = http_client(&full_uri, http)?;
 
+        // hash in chunks up front (the checksum PUT needs it)
         let mut sha = sha1::Sha1::new();
-        sha.update(&buffer);
-
-        let auth = Authorization(Basic {
-                                     username: creds.username,
-                                     password: Some(creds.password),
-                                 });
+        let mut buffer = [0; 1024 * 64];
+        let mut len = 0;
+        loop {
+            let read = f.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            sha.update(&buffer[0..read]);
+            len += read as u64;
+        }
 
         // upload the artifact
         info!("PUT {}", full_uri);
-        let resp = client.put(&full_uri[..]).header(auth.clone()).body(&buffer[..]).send()?;
-        debug!("resp={:?}", resp);
-        let respstr = format!("{} from PUT {}", resp.status, full_uri);
-        if resp.status != StatusCode::Created {
-            return Err(CliError::UploadFailure(respstr));
-        }
-        debug!("{}", respstr);
+        with_retries(&http.retry, &format!("PUT {}", full_uri), || {
+            f.seek(SeekFrom::Start(0))?;
+            let resp = authorized(client.put(&full_uri[..]), &http.credentials)
+                .body(Body::SizedBody(&mut *f, len))
+                .send()?;
+            debug!("resp={:?}", resp);
+            let respstr = format!("{} from PUT {}", resp.status, full_uri);
+            if resp.status != StatusCode::Created {
+                return Err(status_failure(CliError::UploadFailure(respstr), resp.status));
+            }
+            debug!("{}", respstr);
+            Ok(())
+        })?;
 
         // do another request to get the hash on artifactory
         // jfrog api does not allow do do both at once - and this also creates the md5 (somehow)
@@ -173,55 +647,256 @@ fn upload_artifact(arti: &ArtifactoryConfig, uri: &str, f: &mut File) -> LalResu
         // This `respsha` can fail if engci-maven becomes inconsistent. NotFound has been seen.
         // And that makes no sense because the above must have returned Created to get here..
         info!("PUT {} (X-Checksum-Sha1)", full_uri);
-        let respsha = client
-            .put(&full_uri[..])
-            .header(XCheckSumDeploy("true".into()))
-            .header(XCheckSumSha1(sha.digest().to_string()))
-            .header(auth)
+        with_retries(&http.retry, &format!("PUT {} (X-Checksum-Sha1)", full_uri), || {
+            let respsha = authorized(client.put(&full_uri[..]), &http.credentials)
+                .header(XCheckSumDeploy("true".into()))
+                .header(XCheckSumSha1(sha.digest().to_string()))
+                .send()?;
+            debug!("respsha={:?}", respsha);
+            let respshastr = format!("{} from PUT {} (X-Checksum-Sha1)",
+                                     respsha.status,
+                                     full_uri);
+            if respsha.status != StatusCode::Created {
+                let err = CliError::UploadFailure(respshastr);
+                return Err(status_failure(err, respsha.status));
+            }
+            debug!("{}", respshastr);
+            Ok(())
+        })
+    } else {
+        Err(CliError::MissingBackendCredentials)
+    }
+}
+
+/// Upload files into a folder and check the checksums artifactory recorded for them
+///
+/// Older artifactory versions do not record sha256, so only sha1 is required to match.
+fn upload_verified(
+    arti: &ArtifactoryConfig,
+    http: &HttpOptions,
+    folder: &str,
+    files: &[PathBuf],
+) -> LalResult<()> {
+    for pth in files {
+        let fname = pth.file_name().unwrap().to_string_lossy().into_owned();
+        let uri = format!("{}/{}", folder, fname);
+        upload_artifact(arti, http, &uri, &mut File::open(pth)?)?;
+
+        let url = format!("{}/api/storage/{}/{}", arti.master, arti.release, uri);
+        debug!("GET {}", url);
+        let recorded = serde_json::from_str::<ArtifactoryFileInfo>(&hyper_req(&url, http)?)?
+            .checksums;
+        let (sha1, sha256) = (sha1_file(pth)?, sha256_file(pth)?);
+        if recorded.sha1.as_ref() != Some(&sha1) ||
+           recorded.sha256.as_ref().map_or(false, |s| s != &sha256) {
+            warn!("{} was uploaded with sha1 {} and sha256 {}", fname, sha1, sha256);
+            warn!("Artifactory recorded {:?}", recorded);
+            return Err(CliError::UnverifiedUpload(fname));
+        }
+        debug!("Verified {} on artifactory", uri);
+    }
+    Ok(())
+}
+
+/// Move a folder within the release repository
+///
+/// Artifactory moves are done server side, so the destination never has partial files.
+fn move_folder(
+    arti: &ArtifactoryConfig,
+    http: &HttpOptions,
+    from: &str,
+    to: &str,
+) -> LalResult<()> {
+    let url = format!("{}/api/move/{}/{}?to=/{}/{}",
+                      arti.master,
+                      arti.release,
+                      from,
+                      arti.release,
+                      to);
+    let client = http_client(&url, http)?;
+    info!("POST {}", url);
+    with_retries(&http.retry, &format!("POST {}", url), || {
+        let resp = authorized(client.post(&url[..]), &http.credentials).send()?;
+        let respstr = format!("{} from POST {}", resp.status, url);
+        if resp.status != StatusCode::Ok {
+            return Err(status_failure(CliError::UploadFailure(respstr), resp.status));
+        }
+        debug!("{}", respstr);
+        Ok(())
+    })
+}
+
+// whether a folder exists in the release repository
+fn folder_exists(arti: &ArtifactoryConfig, http: &HttpOptions, uri: &str) -> LalResult<bool> {
+    let url = format!("{}/api/storage/{}/{}", arti.master, arti.release, uri);
+    let client = http_client(&url, http)?;
+    debug!("GET {}", url);
+    with_retries(&http.retry, &format!("GET {}", url), || {
+        let resp = authorized(client.get(&url[..]), &http.credentials).send()?;
+        match resp.status {
+            StatusCode::Ok => Ok(true),
+            StatusCode::NotFound => Ok(false),
+            s => {
+                let err = CliError::BackendFailure(format!("GET request with {}", s));
+                Err(status_failure(err, s))
+            }
+        }
+    })
+}
+
+// marker left in every version folder published without replacing, claiming the version
+const CLAIM_FILE: &'static str = ".lal-publish";
+
+// claim a version folder for one publish, false when another publish claimed it first
+//
+// The marker is created with `If-None-Match: *`, which artifactory refuses for an existing
+// file, and read back so that a proxy dropping the condition cannot make two claims win.
+fn claim_folder(arti: &ArtifactoryConfig, http: &HttpOptions, uri: &str) -> LalResult<bool> {
+    use hyper::header::IfNoneMatch;
+    let token = format!("{:016x}", rand::random::<u64>());
+    let url = format!("{}/{}/{}/{}", arti.slave, arti.release, uri, CLAIM_FILE);
+    let client = http_client(&url, http)?;
+    info!("PUT {} (claim)", url);
+    let created = with_retries(&http.retry, &format!("PUT {}", url), || {
+        let resp = authorized(client.put(&url[..]), &http.credentials)
+            .header(IfNoneMatch::Any)
+            .body(token.as_bytes())
             .send()?;
-        debug!("respsha={:?}", respsha);
-        let respshastr = format!("{} from PUT {} (X-Checksum-Sha1)", respsha.status, full_uri);
-        if respsha.status != StatusCode::Created {
-            return Err(CliError::UploadFailure(respshastr));
+        match resp.status {
+            StatusCode::Created => Ok(true),
+            StatusCode::PreconditionFailed | StatusCode::Conflict => Ok(false),
+            s => {
+                let err = CliError::UploadFailure(format!("{} from PUT {}", s, url));
+                Err(status_failure(err, s))
+            }
         }
-        debug!("{}", respshastr);
+    })?;
+    Ok(created && hyper_req(&url, http)?.trim() == token)
+}
+
+// best effort removal of a staged upload that will not be promoted
+fn delete_folder(arti: &ArtifactoryConfig, http: &HttpOptions, uri: &str) {
+    let url = format!("{}/{}/{}", arti.slave, arti.release, uri);
+    info!("DELETE {}", url);
+    let res = http_client(&url, http).and_then(|client| {
+        Ok(authorized(client.delete(&url[..]), &http.credentials).send()?)
+    });
+    match res {
+        Ok(ref resp) if resp.status.is_success() => debug!("Removed {}", url),
+        Ok(resp) => warn!("Failed to remove {} ({})", url, resp.status),
+        Err(e) => warn!("Failed to remove {} ({})", url, e),
+    }
+}
 
+/// Set a property on an item in the release repository
+///
+/// Existing values of the property are replaced (other properties are kept).
+fn set_property(
+    arti: &ArtifactoryConfig,
+    http: &HttpOptions,
+    path: &str,
+    key: &str,
+    values: &[String],
+) -> LalResult<()> {
+    let url = format!("{}/api/storage/{}/{}?properties={}={}&recursive=0",
+                      arti.master,
+                      arti.release,
+                      path,
+                      key,
+                      values.join(","));
+    let client = http_client(&url, http)?;
+    info!("PUT {}", url);
+    with_retries(&http.retry, &format!("PUT {}", url), || {
+        let resp = authorized(client.put(&url[..]), &http.credentials).send()?;
+        let respstr = format!("{} from PUT {}", resp.status, url);
+        if !resp.status.is_success() {
+            return Err(status_failure(CliError::UploadFailure(respstr), resp.status));
+        }
+        debug!("{}", respstr);
         Ok(())
-    } else {
-        Err(CliError::MissingBackendCredentials)
+    })
+}
+
+/// Escape a property value for the matrix parameters of the properties api
+///
+/// Artifactory separators (`,|=;\`) are escaped with a backslash, and everything
+/// but unreserved characters is percent encoded.
+pub fn property_escape(value: &str) -> String {
+    let mut res = String::new();
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            res.push(b as char);
+        } else if b",|=;\\".contains(&b) {
+            // artifactory separators are escaped with a backslash
+            res.push_str(&format!("%5C%{:02X}", b));
+        } else {
+            res.push_str(&format!("%{:02X}", b));
+        }
     }
+    res
 }
 
 /// Get the maximal version number from the storage api
-fn get_storage_as_u32(uri: &str) -> LalResult<u32> {
-    if let Some(&latest) = get_storage_versions(uri)?.iter().max() {
+fn get_storage_as_u32(uri: &str, http: &HttpOptions) -> LalResult<u32> {
+    if let Some(&latest) = get_storage_versions(uri, http)?.iter().max() {
         Ok(latest)
     } else {
         Err(CliError::BackendFailure("No version information found on API".into()))
     }
 }
 
+// The tarball name of a published version, by the compression found in its folder
+//
+// Listed in the repository tarballs are downloaded from.
+fn get_tarball_name(
+    art_cfg: &ArtifactoryConfig,
+    http: &HttpOptions,
+    name: &str,
+    version: u32,
+    env: &str,
+) -> LalResult<String> {
+    let url = format!("{}/api/storage/{}/env/{}/{}/{}",
+                      art_cfg.slave,
+                      art_cfg.vgroup,
+                      env,
+                      name,
+                      version);
+    debug!("GET {}", url);
+    let body = hyper_req(&url, http)?;
+    let res: ArtifactoryStorageResponse = serde_json::from_str(&body)?;
+    let compression = res.children
+        .iter()
+        .filter_map(|c| Compression::of_tarball(c.uri.trim_matches('/'), name))
+        .next()
+        .unwrap_or_default();
+    Ok(compression.tarball_name(name))
+}
+
 // The URL for a component tarball under the one of the environment trees
 fn get_dependency_env_url(
     art_cfg: &ArtifactoryConfig,
+    tarball: &str,
     name: &str,
     version: u32,
     env: &str,
 ) -> String {
-    let tar_url = format!("{}/{}/env/{}/{}/{}/{}.tar.gz",
+    let tar_url = format!("{}/{}/env/{}/{}/{}/{}",
                           art_cfg.slave,
                           art_cfg.vgroup,
                           env,
                           name,
                           version.to_string(),
-                          name);
+                          tarball);
 
     trace!("Inferring tarball location as {}", tar_url);
     tar_url
 }
 
+// The latest version of a component, assuming it was published with `compression`
 fn get_dependency_url_latest(
     art_cfg: &ArtifactoryConfig,
+    http: &HttpOptions,
+    compression: Compression,
     name: &str,
     env: &str,
 ) -> LalResult<Component> {
@@ -231,11 +906,12 @@ fn get_dependency_url_latest(
                       "env",
                       env,
                       name);
-    let v = get_storage_as_u32(&url)?;
+    let v = get_storage_as_u32(&url, http)?;
 
     debug!("Found latest version as {}", v);
+    let tarball = compression.tarball_name(name);
     Ok(Component {
-           location: get_dependency_env_url(art_cfg, name, v, env),
+           location: get_dependency_env_url(art_cfg, &tarball, name, v, env),
            version: v,
            name: name.into(),
        })
@@ -243,7 +919,12 @@ fn get_dependency_url_latest(
 
 // This queries the API for the default location
 // if a default exists, then all our current multi-builds must exist
-fn get_latest_versions(art_cfg: &ArtifactoryConfig, name: &str, env: &str) -> LalResult<Vec<u32>> {
+fn get_latest_versions(
+    art_cfg: &ArtifactoryConfig,
+    http: &HttpOptions,
+    name: &str,
+    env: &str,
+) -> LalResult<Vec<u32>> {
     let url = format!("{}/api/storage/{}/{}/{}/{}",
                       art_cfg.master,
                       art_cfg.release,
@@ -251,24 +932,27 @@ fn get_latest_versions(art_cfg: &ArtifactoryConfig, name: &str, env: &str) -> La
                       env,
                       name);
 
-    get_storage_versions(&url)
+    get_storage_versions(&url, http)
 }
 
 /// Main entry point for install
 fn get_tarball_uri(
     art_cfg: &ArtifactoryConfig,
+    http: &HttpOptions,
+    compression: Compression,
     name: &str,
     version: Option<u32>,
     env: &str,
 ) -> LalResult<Component> {
     if let Some(v) = version {
+        let tarball = compression.tarball_name(name);
         Ok(Component {
-               location: get_dependency_env_url(art_cfg, name, v, env),
+               location: get_dependency_env_url(art_cfg, &tarball, name, v, env),
                version: v,
                name: name.into(),
            })
     } else {
-        get_dependency_url_latest(art_cfg, name, env)
+        get_dependency_url_latest(art_cfg, http, compression, name, env)
     }
 }
 
@@ -288,11 +972,11 @@ pub struct LatestLal {
 /// This is used regardless of your used backend because we want people to use our
 /// main release of lal on CME-release on cisco artifactory at the moment.
 #[cfg(feature = "upgrade")]
-pub fn get_latest_lal_version() -> LalResult<LatestLal> {
+pub fn get_latest_lal_version(http: &HttpOptions) -> LalResult<LatestLal> {
     // canonical latest url
     let uri = "https://engci-maven-master.cisco.com/artifactory/api/storage/CME-release/lal";
     debug!("GET {}", uri);
-    let resp = hyper_req(uri)
+    let resp = hyper_req(uri, http)
         .map_err(|e| {
             warn!("Failed to GET {}: {}", uri, e);
             CliError::BackendFailure("No version information found on API".into())
@@ -319,7 +1003,10 @@ pub fn get_latest_lal_version() -> LalResult<LatestLal> {
     }
 }
 
-use super::{Backend, Component};
+use super::{Backend, Component, Checksums, sha1_file, sha256_file};
+
+// how many latest version queries run at once
+const QUERY_THREADS: usize = 8;
 
 /// Everything we need for Artifactory to implement the Backend trait
 pub struct ArtifactoryBackend {
@@ -327,6 +1014,20 @@ pub struct ArtifactoryBackend {
     pub config: ArtifactoryConfig,
     /// Cache directory
     pub cache: String,
+    /// Size cap of cached components in bytes (unbounded if unset)
+    pub cache_max_size: Option<u64>,
+    /// Signing key and trusted keys for published artifacts
+    pub signatures: SignaturePolicy,
+    /// How stashed and imported tarballs are compressed
+    pub compression: CompressionOptions,
+    /// Extra certificate authorities to trust (PEM bundle)
+    pub ca_bundle: Option<String>,
+    /// Credential helper or keychain to ask before the config credentials
+    pub credential_provider: Option<Box<CredentialProvider>>,
+    // credentials from the provider (asked once)
+    provided: RefCell<Option<Option<Credentials>>>,
+    // mirrors that failed a download, and are skipped for the rest of the run
+    unhealthy: RefCell<BTreeSet<String>>,
 }
 
 impl ArtifactoryBackend {
@@ -335,6 +1036,80 @@ impl ArtifactoryBackend {
         ArtifactoryBackend {
             config: cfg.clone(),
             cache: cache.into(),
+            cache_max_size: None,
+            signatures: SignaturePolicy::default(),
+            compression: CompressionOptions::default(),
+            ca_bundle: None,
+            credential_provider: None,
+            provided: RefCell::new(None),
+            unhealthy: RefCell::new(BTreeSet::new()),
+        }
+    }
+
+    // credentials from the environment, then the credential provider, then the config
+    fn credentials(&self) -> Option<Credentials> {
+        if let Some(c) = Credentials::from_env() {
+            return Some(c);
+        }
+        if let Some(ref provider) = self.credential_provider {
+            let mut provided = self.provided.borrow_mut();
+            if provided.is_none() {
+                *provided = Some(provider.credentials(&self.config.slave).unwrap_or_else(|e| {
+                    warn!("{} - falling back to config credentials", e);
+                    None
+                }));
+            }
+            if let Some(Some(ref c)) = *provided {
+                return Some(c.clone());
+            }
+        }
+        self.config.credentials.clone()
+    }
+
+    // publish the ARTIFACT dir, optionally replacing an existing version
+    fn publish_staged(&self, name: &str, version: u32, env: &str, replace: bool) -> LalResult<()> {
+        // this fn basically assumes all the sanity checks have been performed
+        // files must exist and lockfile must be sensible
+        let artdir = Path::new("./ARTIFACT");
+        let tarball = find_tarball(artdir, name);
+        let mut files = vec![tarball.clone(), artdir.join("lockfile.json")];
+        if signature_path(&tarball).is_file() {
+            files.push(signature_path(&tarball));
+        }
+
+        // upload to a unique staging folder, and only move it into place once verified
+        // so that an interrupted publish never leaves a partial version behind
+        let staging = format!("staging/{}/{}/{}-{:08x}", env, name, version, rand::random::<u32>());
+        let dest = format!("env/{}/{}/{}", env, name, version);
+        let http = self.http();
+        let mut claimed = false;
+        let res = upload_verified(&self.config, &http, &staging, &files).and_then(|_| {
+            // artifactory merges moved folders, so a new version is claimed before moving
+            if !replace {
+                if folder_exists(&self.config, &http, &dest)? ||
+                    !claim_folder(&self.config, &http, &dest)?
+                {
+                    return Err(CliError::VersionTaken(name.into(), version));
+                }
+                claimed = true;
+            }
+            move_folder(&self.config, &http, &staging, &dest)
+        });
+        if res.is_err() {
+            delete_folder(&self.config, &http, &staging);
+            if claimed {
+                delete_folder(&self.config, &http, &dest); // give the version back
+            }
+        }
+        res
+    }
+
+    // transport settings for requests made by this backend
+    fn http(&self) -> HttpOptions {
+        HttpOptions {
+            retry: self.config.retry.clone(),
+            ca_bundle: self.ca_bundle.clone(),
+            credentials: self.credentials(),
         }
     }
 }
@@ -344,47 +1119,378 @@ impl ArtifactoryBackend {
 /// This is intended to be used by the caching trait `CachedBackend`, but for
 /// specific low-level use cases, these methods can be used directly.
 impl Backend for ArtifactoryBackend {
+    fn get_components(&self, loc: &str) -> LalResult<Vec<String>> {
+        let url = format!("{}/api/storage/{}/env/{}",
+                          self.config.master,
+                          self.config.release,
+                          loc);
+        debug!("GET {}", url);
+        // nothing was published in a location artifactory does not know
+        let body = match hyper_req_optional(&url, &self.http())? {
+            Some(body) => body,
+            None => return Ok(vec![]),
+        };
+        let res: ArtifactoryStorageResponse = serde_json::from_str(&body)?;
+        let mut names = res.children
+            .iter()
+            .map(|c| c.uri.trim_matches('/').to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+
+    fn get_target_locations(&self, env: &str) -> LalResult<Vec<String>> {
+        let url = format!("{}/api/storage/{}/env", self.config.master, self.config.release);
+        debug!("GET {}", url);
+        let body = hyper_req(&url, &self.http())?;
+        let res: ArtifactoryStorageResponse = serde_json::from_str(&body)?;
+        let prefix = format!("{}+", env);
+        let mut locs = res.children
+            .iter()
+            .map(|c| c.uri.trim_matches('/').to_string())
+            .filter(|l| l.starts_with(&prefix))
+            .collect::<Vec<_>>();
+        locs.sort();
+        Ok(locs)
+    }
+
     fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<u32>> {
-        get_latest_versions(&self.config, name, loc)
+        get_latest_versions(&self.config, &self.http(), name, loc)
     }
 
     fn get_latest_version(&self, name: &str, loc: &str) -> LalResult<u32> {
-        let latest = get_dependency_url_latest(&self.config, name, loc)?;
+        let compression = self.compression.compression;
+        let latest = get_dependency_url_latest(&self.config, &self.http(), compression, name, loc)?;
         Ok(latest.version)
     }
 
+    fn get_latest_versions_of(
+        &self,
+        names: &[String],
+        loc: &str,
+    ) -> BTreeMap<String, LalResult<u32>> {
+        let compression = self.compression.compression;
+        let http = self.http(); // credentials are resolved once, before spreading out
+        let chunk_size = cmp::max(1, (names.len() + QUERY_THREADS - 1) / QUERY_THREADS);
+        let handles = names.chunks(chunk_size)
+            .map(|chunk| {
+                let (chunk, cfg, http, loc) =
+                    (chunk.to_vec(), self.config.clone(), http.clone(), loc.to_string());
+                thread::spawn(move || {
+                    chunk.into_iter()
+                        .map(|name| {
+                            let latest =
+                                get_dependency_url_latest(&cfg, &http, compression, &name, &loc);
+                            (name, latest.map(|c| c.version))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        handles.into_iter()
+            .flat_map(|h| h.join().expect("version query thread panicked"))
+            .collect()
+    }
+
     fn get_component_info(
         &self,
         name: &str,
         version: Option<u32>,
         loc: &str,
     ) -> LalResult<Component> {
-        get_tarball_uri(&self.config, name, version, loc)
+        // tarballs are assumed to be compressed as configured, see `get_tarball_location`
+        let compression = self.compression.compression;
+        get_tarball_uri(&self.config, &self.http(), compression, name, version, loc)
+    }
+
+    fn get_tarball_location(&self, name: &str, version: u32, loc: &str) -> LalResult<String> {
+        let tarball = get_tarball_name(&self.config, &self.http(), name, version, loc)?;
+        Ok(get_dependency_env_url(&self.config, &tarball, name, version, loc))
     }
 
     fn publish_artifact(&self, name: &str, version: u32, env: &str) -> LalResult<()> {
-        // this fn basically assumes all the sanity checks have been performed
-        // files must exist and lockfile must be sensible
-        let artdir = Path::new("./ARTIFACT");
-        let tarball = artdir.join(format!("{}.tar.gz", name));
-        let lockfile = artdir.join("lockfile.json");
+        self.publish_staged(name, version, env, true)
+    }
+
+    fn publish_new_artifact(&self, name: &str, version: u32, env: &str) -> LalResult<()> {
+        self.publish_staged(name, version, env, false)
+    }
+
+    fn get_publish_location(&self, name: &str, version: u32, env: &str) -> LalResult<String> {
+        Ok(format!("{}/{}/env/{}/{}/{}/",
+                   self.config.slave,
+                   self.config.release,
+                   env,
+                   name,
+                   version))
+    }
+
+    fn check_publish_access(&self) -> LalResult<()> {
+        let http = self.http();
+        if http.credentials.is_none() {
+            return Err(CliError::MissingBackendCredentials);
+        }
+        // artifactory rejects invalid credentials even where anonymous reads are allowed
+        let url = format!("{}/api/storage/{}", self.config.master, self.config.release);
+        debug!("GET {}", url);
+        hyper_req(&url, &http).map(|_| ())
+    }
 
-        // uri prefix if specific env upload
-        let prefix = format!("env/{}/", env);
+    fn get_channel_versions(&self, name: &str, channel: &str, loc: &str) -> LalResult<Vec<u32>> {
+        let url = format!("{}/api/search/prop?{}={}&repos={}",
+                          self.config.master,
+                          CHANNEL_PROPERTY,
+                          channel,
+                          self.config.release);
+        debug!("GET {}", url);
+        let res: ArtifactorySearchResponse = serde_json::from_str(&hyper_req(&url, &self.http())?)?;
+        // results are for every component and environment in the channel
+        let prefix = format!("/{}/env/{}/{}/", self.config.release, loc, name);
+        let mut versions = res.results
+            .iter()
+            .filter_map(|r| {
+                let i = r.uri.find(&prefix)?;
+                r.uri[i + prefix.len()..].trim_matches('/').parse::<u32>().ok()
+            })
+            .collect::<Vec<_>>();
+        versions.sort_by(|a, b| b.cmp(a)); // descending like get_versions
+        Ok(versions)
+    }
+
+    fn tag_channel(&self, name: &str, version: u32, channel: &str, loc: &str) -> LalResult<()> {
+        let path = format!("env/{}/{}/{}", loc, name, version);
+        let url = format!("{}/api/storage/{}/{}?properties={}",
+                          self.config.master,
+                          self.config.release,
+                          path,
+                          CHANNEL_PROPERTY);
+        // setting a property replaces its values, so keep the channels it is already in
+        // (artifactory answers 404 when none are set, any other failure must not lose them)
+        let mut channels = match hyper_req_optional(&url, &self.http())? {
+            Some(body) => {
+                let mut props: ArtifactoryProperties = serde_json::from_str(&body)?;
+                props.properties.remove(CHANNEL_PROPERTY).unwrap_or_default()
+            }
+            None => {
+                debug!("No channels set on {}", path);
+                vec![]
+            }
+        };
+        if !channels.iter().any(|c| c == channel) {
+            channels.push(channel.into());
+        }
+        set_property(&self.config, &self.http(), &path, CHANNEL_PROPERTY, &channels)
+    }
+
+    fn get_lockfile(&self, name: &str, version: u32, loc: &str) -> LalResult<Lockfile> {
+        let url = format!("{}/{}/env/{}/{}/{}/lockfile.json",
+                          self.config.slave,
+                          self.config.release,
+                          loc,
+                          name,
+                          version);
+        debug!("GET {}", url);
+        Ok(serde_json::from_str(&hyper_req(&url, &self.http())?)?)
+    }
 
-        let tar_uri = format!("{}{}/{}/{}.tar.gz", prefix, name, version, name);
-        let mut tarf = File::open(tarball)?;
-        upload_artifact(&self.config, &tar_uri, &mut tarf)?;
+    fn delete_version(&self, name: &str, version: u32, loc: &str) -> LalResult<()> {
+        let url = format!("{}/{}/env/{}/{}/{}",
+                          self.config.slave,
+                          self.config.release,
+                          loc,
+                          name,
+                          version);
+        let http = self.http();
+        let client = http_client(&url, &http)?;
+        info!("DELETE {}", url);
+        with_retries(&http.retry, &format!("DELETE {}", url), || {
+            let resp = authorized(client.delete(&url[..]), &http.credentials).send()?;
+            let respstr = format!("{} from DELETE {}", resp.status, url);
+            if !resp.status.is_success() {
+                return Err(status_failure(CliError::BackendFailure(respstr), resp.status));
+            }
+            debug!("{}", respstr);
+            Ok(())
+        })
+    }
 
-        let mut lockf = File::open(lockfile)?;
-        let lf_uri = format!("{}{}/{}/lockfile.json", prefix, name, version);
-        upload_artifact(&self.config, &lf_uri, &mut lockf)?;
+    fn set_metadata(
+        &self,
+        name: &str,
+        version: u32,
+        loc: &str,
+        meta: &BTreeMap<String, String>,
+    ) -> LalResult<()> {
+        let path = format!("env/{}/{}/{}", loc, name, version);
+        let http = self.http();
+        for (k, v) in meta {
+            set_property(&self.config, &http, &path, k, &[property_escape(v)])?;
+        }
         Ok(())
     }
 
+    fn get_metadata(
+        &self,
+        name: &str,
+        version: u32,
+        loc: &str,
+    ) -> LalResult<BTreeMap<String, String>> {
+        let path = format!("env/{}/{}/{}", loc, name, version);
+        let url = format!("{}/api/storage/{}/{}?properties",
+                          self.config.master,
+                          self.config.release,
+                          path);
+        debug!("GET {}", url);
+        match hyper_req(&url, &self.http()) {
+            Ok(body) => {
+                let props: ArtifactoryProperties = serde_json::from_str(&body)?;
+                Ok(props.properties.into_iter().map(|(k, v)| (k, v.join(","))).collect())
+            }
+            Err(CliError::RemoteNotFound(e)) => {
+                // artifactory has no properties response for items without any
+                debug!("No properties set on {} ({})", path, e);
+                Ok(BTreeMap::new())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn upload_stash(&self, name: &str, dir: &Path, user: &str, code: &str) -> LalResult<()> {
+        let prefix = format!("stash/{}/{}/", user, code);
+
+        let tarball = find_tarball(dir, name);
+        let tar_uri = format!("{}{}", prefix, tarball.file_name().unwrap().to_string_lossy());
+        let mut tarf = File::open(&tarball)
+            .context(format!("Failed to open stashed tarball {}", tarball.display()))?;
+        upload_artifact(&self.config, &self.http(), &tar_uri, &mut tarf)?;
+
+        let lf_path = dir.join("lockfile.json");
+        let mut lockf = File::open(&lf_path)
+            .context(format!("Failed to open stashed lockfile {}", lf_path.display()))?;
+        let lf_uri = format!("{}lockfile.json", prefix);
+        upload_artifact(&self.config, &self.http(), &lf_uri, &mut lockf)?;
+        Ok(())
+    }
+
+    fn get_stash_location(&self, user: &str, code: &str, file: &str) -> LalResult<String> {
+        Ok(format!("{}/{}/stash/{}/{}/{}",
+                   self.config.slave,
+                   self.config.vgroup,
+                   user,
+                   code,
+                   file))
+    }
+
+    fn upload_build_output(&self, key: &str, tarball: &Path) -> LalResult<()> {
+        let uri = format!("buildcache/{}/{}", key, tarball.file_name().unwrap().to_string_lossy());
+        let mut f = File::open(tarball)?;
+        upload_artifact(&self.config, &self.http(), &uri, &mut f)
+    }
+
+    fn get_build_output_location(&self, key: &str, file: &str) -> LalResult<String> {
+        Ok(format!("{}/{}/buildcache/{}/{}",
+                   self.config.slave,
+                   self.config.vgroup,
+                   key,
+                   file))
+    }
+
+    fn get_checksums(
+        &self,
+        name: &str,
+        version: u32,
+        loc: &str,
+        tarball: &str,
+    ) -> LalResult<Checksums> {
+        let url = format!("{}/api/storage/{}/env/{}/{}/{}/{}",
+                          self.config.master,
+                          self.config.release,
+                          loc,
+                          name,
+                          version,
+                          tarball);
+        debug!("GET {}", url);
+        match hyper_req(&url, &self.http()) {
+            Ok(body) => Ok(serde_json::from_str::<ArtifactoryFileInfo>(&body)?.checksums),
+            Err(CliError::RemoteNotFound(e)) => {
+                warn!("No checksums found for {} {} ({})", name, version, e);
+                Ok(Checksums::default())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_publish_time(
+        &self,
+        name: &str,
+        version: u32,
+        loc: &str,
+    ) -> LalResult<Option<DateTime<UTC>>> {
+        let url = format!("{}/api/storage/{}/env/{}/{}/{}",
+                          self.config.master,
+                          self.config.release,
+                          loc,
+                          name,
+                          version);
+        match hyper_req(&url, &self.http()) {
+            Ok(body) => {
+                let info: ArtifactoryFolderInfo = serde_json::from_str(&body)?;
+                Ok(DateTime::parse_from_rfc3339(&info.created).ok().map(|t| t.with_timezone(&UTC)))
+            }
+            Err(CliError::RemoteNotFound(e)) => {
+                debug!("No publish time for {} {} ({})", name, version, e);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_deprecations(&self) -> LalResult<Deprecations> {
+        let url = format!("{}/{}/deprecations.json", self.config.slave, self.config.vgroup);
+        match hyper_req(&url, &self.http()) {
+            Ok(body) => Ok(serde_json::from_str(&body)?),
+            Err(CliError::RemoteNotFound(e)) => {
+                debug!("No deprecations published at {} ({})", url, e);
+                Ok(Deprecations::default())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     fn get_cache_dir(&self) -> String { self.cache.clone() }
 
-    fn raw_fetch(&self, url: &str, dest: &PathBuf) -> LalResult<()> {
-        http_download_to_path(url, dest)
+    fn get_cache_max_size(&self) -> Option<u64> { self.cache_max_size }
+
+    fn get_signature_policy(&self) -> &SignaturePolicy { &self.signatures }
+
+    fn get_compression(&self) -> &CompressionOptions { &self.compression }
+
+    fn raw_fetch(&self, url: &str, dest: &PathBuf, reporter: &ProgressReporter) -> LalResult<()> {
+        let http = self.http();
+        let prefix = format!("{}/", self.config.slave);
+        if url.starts_with(&prefix) {
+            for mirror in &self.config.mirrors {
+                if self.unhealthy.borrow().contains(mirror) {
+                    continue;
+                }
+                let mirrored = format!("{}/{}", mirror, &url[prefix.len()..]);
+                match http_download_to_path(&mirrored, dest, &http, reporter) {
+                    Ok(()) => {
+                        debug!("Downloaded from mirror {}", mirror);
+                        return Ok(());
+                    }
+                    Err(CliError::Hype(e)) => {
+                        warn!("Mirror {} is unreachable ({}) - skipping it from now on", mirror, e);
+                        self.unhealthy.borrow_mut().insert(mirror.clone());
+                    }
+                    // e.g. not replicated yet, so the mirror is still used for other downloads
+                    Err(e) => warn!("Mirror {} failed ({}) - trying the next one", mirror, e),
+                }
+            }
+            if !self.config.mirrors.is_empty() {
+                info!("Downloading from {} instead of mirrors", self.config.slave);
+            }
+        }
+        http_download_to_path(url, dest, &http, reporter)
     }
 }
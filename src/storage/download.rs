@@ -1,70 +1,412 @@
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use serde_json;
 
-use storage::{Backend, CachedBackend, Component};
-use core::{CliError, LalResult, output};
+use storage::{Backend, CachedBackend, CacheEntry, CacheLookup, Component, Checksums};
+use core::{CliError, Compression, COMPRESSIONS, LalResult, IoResultExt, Lockfile, InputSources,
+           ProgressReporter, SignaturePolicy, SilentReporter, input, output, cache_entry_users,
+           find_tarball, signature_path, verify_signature};
+use super::objects;
+
+/// Lockfile inside a component tarball, if it has a parseable one
+pub fn tarball_lockfile(tarball: &Path) -> Option<Lockfile> {
+    use std::io::Read;
+    use tar::Archive;
+    let data = fs::File::open(tarball).ok()?;
+    let decoder = Compression::from_path(tarball).decoder(data).ok()?;
+    let mut archive = Archive::new(decoder);
+    for entry in archive.entries().ok()? {
+        let mut entry = entry.ok()?;
+        if entry.path().ok()?.ends_with("lockfile.json") {
+            let mut data = String::new();
+            entry.read_to_string(&mut data).ok()?;
+            return serde_json::from_str(&data).ok();
+        }
+    }
+    None
+}
+
+// move published components from the old environment-less `globals/<name>/<version>`
+//
+// Entries are keyed by the environment in their lockfile, and entries without
+// one are dropped (they are refetched when needed) so environments never alias.
+fn migrate_legacy_cache(cache: &Path) -> LalResult<()> {
+    use walkdir::WalkDir;
+    let globals = cache.join("globals");
+    if !globals.is_dir() {
+        return Ok(());
+    }
+    info!("Migrating cached components in {} to per environment folders",
+          globals.display());
+    let entries = WalkDir::new(&globals)
+        .min_depth(2)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_dir());
+    for e in entries {
+        let rel = e.path().strip_prefix(&globals).unwrap().to_path_buf();
+        let name = rel.parent().unwrap().to_string_lossy().into_owned();
+        let tarball = find_tarball(e.path(), &name);
+        match tarball_lockfile(&tarball).map(|lf| lf.environment) {
+            Some(env) => {
+                let dest = cache.join("environments").join(env).join(&rel);
+                if dest.exists() {
+                    debug!("{} is already cached per environment", rel.display());
+                    continue;
+                }
+                debug!("Migrating {} to {}", rel.display(), dest.display());
+                fs::create_dir_all(dest.parent().unwrap())
+                    .context(format!("Failed to create {}", dest.display()))?;
+                fs::rename(e.path(), &dest)
+                    .context(format!("Failed to migrate {}", e.path().display()))?;
+            }
+            None => debug!("Dropping {} without a known environment", rel.display()),
+        }
+    }
+    fs::remove_dir_all(&globals).context(format!("Failed to remove {}", globals.display()))?;
+    Ok(())
+}
+
+// create the cache root and its subdirectories lazily on first use
+fn ensure_cache_dirs<T: Backend + ?Sized>(backend: &T) -> LalResult<()> {
+    let cache = Path::new(&backend.get_cache_dir()).to_path_buf();
+    if cache.exists() && !cache.is_dir() {
+        return Err(CliError::CacheIsFile(cache.display().to_string()));
+    }
+    for sub in &["environments", "stash"] {
+        let dir = cache.join(sub);
+        if !dir.is_dir() {
+            debug!("Creating cache directory {}", dir.display());
+            fs::create_dir_all(&dir).map_err(|e| {
+                    warn!("Failed to create {}: {}", dir.display(), e);
+                    CliError::UnwritableCache(dir.display().to_string())
+                })?;
+        }
+    }
+    migrate_legacy_cache(&cache)
+}
 
 fn is_cached<T: Backend + ?Sized>(backend: &T, name: &str, version: u32, env: &str) -> bool {
     get_cache_dir(backend, name, version, env).is_dir()
 }
 
+// mark a cache entry as recently used (its mtime is what eviction and `lal clean` look at)
+fn touch_cache_entry(entry: &Path) -> LalResult<()> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use filetime::{self, FileTime};
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let now = FileTime::from_seconds_since_1970(secs, 0);
+    filetime::set_file_times(entry, now, now)
+        .context(format!("Failed to update access time of {}", entry.display()))?;
+    Ok(())
+}
+
+// evict least recently used components until the cache fits under its size cap
+//
+// Only published components count towards the cap, and `keep` is never evicted.
+fn evict_lru<T: Backend + ?Sized>(backend: &T, keep: &Path) -> LalResult<()> {
+    use walkdir::WalkDir;
+    use filetime::FileTime;
+    let max = match backend.get_cache_max_size() {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let edir = Path::new(&backend.get_cache_dir()).join("environments");
+    let mut entries = vec![];
+    let mut total = 0;
+    for e in WalkDir::new(&edir).min_depth(3).max_depth(3).into_iter().filter_map(|e| e.ok()) {
+        if !e.file_type().is_dir() {
+            continue;
+        }
+        let size: u64 = WalkDir::new(e.path())
+            .into_iter()
+            .filter_map(|f| f.ok())
+            .filter_map(|f| f.metadata().ok())
+            .filter(|m| m.is_file())
+            .map(|m| m.len())
+            .sum();
+        let used = FileTime::from_last_modification_time(&e.metadata().unwrap())
+            .seconds_relative_to_1970();
+        total += size;
+        entries.push((used, size, e.path().to_path_buf()));
+    }
+    entries.sort();
+    for (_, size, pth) in entries {
+        if total <= max {
+            break;
+        }
+        if pth == keep {
+            continue;
+        }
+        for user in cache_entry_users(&pth)? {
+            warn!("Evicting {} which still backs {}", pth.display(), user);
+        }
+        info!("Evicting {} from the cache ({} MB)", pth.display(), size / (1024 * 1024));
+        fs::remove_dir_all(&pth).context(format!("Failed to evict {}", pth.display()))?;
+        total -= size;
+    }
+    if total > max {
+        warn!("Cache is above its {} GB cap even after evicting everything else",
+              max / (1024 * 1024 * 1024));
+    }
+    Ok(())
+}
+
 fn get_cache_dir<T: Backend + ?Sized>(backend: &T, name: &str, version: u32, env: &str) -> PathBuf {
     let cache = backend.get_cache_dir();
     Path::new(&cache).join("environments").join(env).join(name).join(version.to_string())
 }
 
+// partial download next to the cache entry it will become (kept across runs to resume)
+fn get_partial_path<T: Backend + ?Sized>(
+    backend: &T,
+    name: &str,
+    version: u32,
+    env: &str,
+    compression: Compression,
+) -> PathBuf {
+    let entry = get_cache_dir(backend, name, version, env);
+    entry.with_file_name(format!("{}{}.part", version, compression.extension()))
+}
+
+// download a component to a .part file in the cache, to be moved into place once verified
+fn download_part<T: Backend + ?Sized>(
+    backend: &T,
+    component: &Component,
+    env: &str,
+    reporter: &ProgressReporter,
+) -> LalResult<PathBuf> {
+    let compression = Compression::from_path(Path::new(&component.location));
+    let part = get_partial_path(backend, &component.name, component.version, env, compression);
+    let partdir = part.parent().unwrap();
+    fs::create_dir_all(partdir)
+        .context(format!("Failed to create cache directory {}", partdir.display()))?;
+    if part.is_file() {
+        info!("Resuming partial download of {} {}", component.name, component.version);
+    }
+    backend.raw_fetch(&component.location, &part, reporter)?;
+    Ok(part)
+}
+
 fn store_tarball<T: Backend + ?Sized>(
     backend: &T,
     name: &str,
     version: u32,
     env: &str,
+    src: &Path,
+    sha256: &str,
+    compression: Compression,
 ) -> Result<(), CliError> {
     // 1. mkdir -p cacheDir/$name/$version
     let destdir = get_cache_dir(backend, name, version, env);
     if !destdir.is_dir() {
-        fs::create_dir_all(&destdir)?;
+        fs::create_dir_all(&destdir)
+            .context(format!("Failed to create cache directory {}", destdir.display()))?;
     }
-    // 2. stuff $PWD/$name.tar.gz in there
-    let tarname = [name, ".tar.gz"].concat();
-    let dest = Path::new(&destdir).join(&tarname);
-    let src = Path::new(".").join(&tarname);
+    // 2. move the verified download in there
+    let dest = Path::new(&destdir).join(compression.tarball_name(name));
     if !src.is_file() {
         return Err(CliError::MissingTarball);
     }
     debug!("Move {:?} -> {:?}", src, dest);
-    fs::copy(&src, &dest)?;
-    fs::remove_file(&src)?;
+    fs::rename(&src, &dest)
+        .context(format!("Failed to move {} to {}", src.display(), dest.display()))?;
+    // 3. keep the checksum next to it so corruption can be detected later
+    let sumfile = checksum_path(&dest);
+    fs::File::create(&sumfile)
+        .and_then(|mut f| write!(f, "{}", sha256))
+        .context(format!("Failed to write checksum {}", sumfile.display()))?;
+    // 4. make room for it if the cache is capped
+    evict_lru(backend, &destdir)?;
 
     Ok(())
 }
 
-// helper for the unpack_ functions
-fn extract_tarball_to_input(tarname: PathBuf, component: &str) -> LalResult<()> {
-    use tar::Archive;
-    use flate2::read::GzDecoder;
-
-    let extract_path = Path::new("./INPUT").join(component);
-    let _ = fs::remove_dir_all(&extract_path); // remove current dir if exists
-    fs::create_dir_all(&extract_path)?;
-
-    // Open file, conditionally wrap a progress bar around the file reading
-    if cfg!(feature = "progress") {
-        #[cfg(feature = "progress")]
-        {
-            use super::progress::ProgressReader;
-            let data = fs::File::open(tarname)?;
-            let progdata = ProgressReader::new(data)?;
-            let decompressed = GzDecoder::new(progdata)?; // decoder reads data (proxied)
-            let mut archive = Archive::new(decompressed); // Archive reads decoded
-            archive.unpack(&extract_path)?;
-        }
+fn checksum_path(tarball: &Path) -> PathBuf {
+    let mut name = tarball.file_name().unwrap().to_os_string();
+    name.push(".sha256");
+    tarball.with_file_name(name)
+}
+
+// check a downloaded tarball against the backend checksums and return its sha256
+fn verify_download(sums: &Checksums, tarball: &Path, name: &str) -> LalResult<String> {
+    let sha256 = sha256_file(tarball)?;
+    let matches = if let Some(ref expected) = sums.sha256 {
+        expected.to_lowercase() == sha256
+    } else if let Some(ref expected) = sums.sha1 {
+        expected.to_lowercase() == sha1_file(tarball)?
     } else {
-        let data = fs::File::open(tarname)?;
-        let decompressed = GzDecoder::new(data)?; // decoder reads data
-        let mut archive = Archive::new(decompressed); // Archive reads decoded
-        archive.unpack(&extract_path)?;
+        warn!("No checksum available for {} - unable to verify download", name);
+        true
     };
+    if !matches {
+        let _ = fs::remove_file(tarball);
+        return Err(CliError::ChecksumMismatch(name.into()));
+    }
+    Ok(sha256)
+}
+
+// check a downloaded tarball against the signature published next to it
+//
+// Unsigned artifacts are only rejected when signatures are required. Failing to
+// fetch a signature that may exist is an error either way.
+fn verify_download_signature<T: Backend + ?Sized>(
+    backend: &T,
+    policy: &SignaturePolicy,
+    component: &Component,
+    tarball: &Path,
+) -> LalResult<()> {
+    let sig = signature_path(tarball);
+    let _ = fs::remove_file(&sig);
+    let location = format!("{}.asc", component.location);
+    if let Err(e) = backend.raw_fetch(&location, &sig, &SilentReporter) {
+        let _ = fs::remove_file(&sig);
+        if !e.is_missing_file() {
+            return Err(e);
+        }
+        if policy.required {
+            let _ = fs::remove_file(tarball);
+            return Err(CliError::UnsignedArtifact(component.name.clone()));
+        }
+        warn!("No signature found for {} {} ({})", component.name, component.version, e);
+        return Ok(());
+    }
+    let res = verify_signature(&policy.public_keys, tarball, &sig)
+        .map_err(|e| match e {
+            CliError::InvalidSignature(_) => CliError::InvalidSignature(component.name.clone()),
+            e => e,
+        });
+    let _ = fs::remove_file(&sig);
+    if res.is_err() {
+        let _ = fs::remove_file(tarball);
+    }
+    res
+}
+
+// whether a cached tarball still matches the checksum stored with it
+fn cache_intact(tarball: &Path) -> LalResult<bool> {
+    use std::io::Read;
+    let sumfile = checksum_path(tarball);
+    if !sumfile.is_file() {
+        return Ok(true); // cached before checksums were stored
+    }
+    let mut expected = String::new();
+    fs::File::open(&sumfile)
+        .and_then(|mut f| f.read_to_string(&mut expected))
+        .context(format!("Failed to read checksum {}", sumfile.display()))?;
+    Ok(tarball.is_file() && expected.trim() == sha256_file(tarball)?)
+}
+
+// validate a cached published component before it is used
+fn lookup<T: Backend + ?Sized>(
+    backend: &T,
+    name: &str,
+    version: u32,
+    env: &str,
+) -> LalResult<CacheLookup> {
+    let dir = get_cache_dir(backend, name, version, env);
+    if !dir.is_dir() {
+        return Ok(CacheLookup::Missing);
+    }
+    let tarball = find_tarball(&dir, name);
+    if !tarball.is_file() {
+        return Ok(CacheLookup::Corrupt("tarball is missing".into()));
+    }
+    if !cache_intact(&tarball)? {
+        return Ok(CacheLookup::Corrupt("checksum mismatch".into()));
+    }
+    match tarball_lockfile(&tarball) {
+        Some(lf) => {
+            Ok(CacheLookup::Valid(CacheEntry {
+                tarball: tarball,
+                lockfile: lf,
+            }))
+        }
+        None => Ok(CacheLookup::Corrupt("lockfile is missing or unparseable".into())),
+    }
+}
+
+/// SHA256 hex digest of a file read in chunks
+pub fn sha256_file(pth: &Path) -> LalResult<String> {
+    use std::io::Read;
+    use sha2::{Digest, Sha256};
+    let ctx = || format!("Failed to read {}", pth.display());
+    let mut f = fs::File::open(pth).context(ctx())?;
+    let mut sha = Sha256::default();
+    let mut buffer = [0; 1024 * 64];
+    loop {
+        let read = f.read(&mut buffer).context(ctx())?;
+        if read == 0 {
+            break;
+        }
+        sha.input(&buffer[0..read]);
+    }
+    Ok(format!("{:x}", sha.result()))
+}
+
+/// SHA1 hex digest of a file read in chunks
+pub fn sha1_file(pth: &Path) -> LalResult<String> {
+    use std::io::Read;
+    use sha1;
+    let ctx = || format!("Failed to read {}", pth.display());
+    let mut f = fs::File::open(pth).context(ctx())?;
+    let mut sha = sha1::Sha1::new();
+    let mut buffer = [0; 1024 * 64];
+    loop {
+        let read = f.read(&mut buffer).context(ctx())?;
+        if read == 0 {
+            break;
+        }
+        sha.update(&buffer[0..read]);
+    }
+    Ok(sha.digest().to_string())
+}
+
+// hardlink a stashed tarball against an identical blob in `stash/.blobs`
+//
+// Every stash name keeps its own hardlink, so deleting one stash folder never
+// affects another sharing the same blob. Blobs with a single link left are
+// orphans that `lal clean` removes.
+fn dedup_stashed_tarball(cache: &str, tarball: &Path) -> LalResult<()> {
+    let blobdir = Path::new(cache).join("stash").join(".blobs");
+    fs::create_dir_all(&blobdir)
+        .context(format!("Failed to create stash blob directory {}", blobdir.display()))?;
+    let digest = sha1_file(tarball)?;
+    let blob = blobdir.join(format!("{}{}", digest, Compression::from_path(tarball).extension()));
+    if blob.is_file() {
+        debug!("Reusing identical stash blob {}", digest);
+        fs::remove_file(tarball)
+            .context(format!("Failed to remove {}", tarball.display()))?;
+        if let Err(e) = fs::hard_link(&blob, tarball) {
+            debug!("Failed to hardlink stash blob ({}) - copying", e);
+            fs::copy(&blob, tarball)
+                .context(format!("Failed to copy {} to {}", blob.display(), tarball.display()))?;
+        }
+    } else if let Err(e) = fs::hard_link(tarball, &blob) {
+        debug!("Failed to hardlink stash blob ({}) - not deduplicating", e);
+    }
+    Ok(())
+}
+
+// helper for the unpack_ functions
+fn extract_tarball_to_input(
+    cache: &str,
+    tarname: PathBuf,
+    component: &str,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    // assemble it next to INPUT so an interrupted fetch never leaves half a component
+    let staging = input::staging_dir(component)?;
 
+    // files are hardlinked from the object store rather than unpacked in place
+    let digest = sha1_file(&tarname)?;
+    objects::materialize(cache, &tarname, &digest, &staging, reporter)?;
+    input::commit_staged(component)?;
+
+    // Remember where this came from for `lal status --paths` and `lal clean`
+    InputSources::record(component, &tarname, &digest)?;
     Ok(())
 }
 
@@ -112,24 +454,66 @@ where
         name: &str,
         version: Option<u32>,
         env: &str,
+        reporter: &ProgressReporter,
     ) -> LalResult<(PathBuf, Component)> {
         trace!("Locate component {}", name);
 
-        let component = self.get_component_info(name, version, env)?;
+        ensure_cache_dirs(self)?;
+        reporter.start_phase("resolving");
+        let mut component = match version {
+            // a cached pinned version needs no lookup of where it was published
+            Some(v) if is_cached(self, name, v, env) => {
+                let dir = get_cache_dir(self, name, v, env);
+                Component {
+                    name: name.into(),
+                    version: v,
+                    location: find_tarball(&dir, name).to_string_lossy().into(),
+                }
+            }
+            _ => self.get_component_info(name, version, env)?,
+        };
+        reporter.finish_phase("resolving");
 
+        let cachedir = get_cache_dir(self, &component.name, component.version, env);
+        if let CacheLookup::Corrupt(reason) = lookup(self, name, component.version, env)? {
+            reporter.warn(&format!("Cached {} {} is corrupt ({}) - fetching it again",
+                                   name,
+                                   component.version,
+                                   reason));
+            fs::remove_dir_all(&cachedir)
+                .context(format!("Failed to remove corrupt cache entry {}", cachedir.display()))?;
+            component = self.get_component_info(name, Some(component.version), env)?;
+        }
         if !is_cached(self, &component.name, component.version, env) {
-            // download to PWD then move it to stash immediately
-            let local_tarball = Path::new(".").join(format!("{}.tar.gz", name));
-            self.raw_fetch(&component.location, &local_tarball)?;
-            store_tarball(self, name, component.version, env)?;
+            reporter.start_phase("downloading");
+            let part = match download_part(self, &component, env, reporter) {
+                Err(CliError::RemoteNotFound(_)) => {
+                    // published with another compression than was assumed
+                    let v = component.version;
+                    component.location = self.get_tarball_location(name, v, env)?;
+                    download_part(self, &component, env, reporter)?
+                }
+                res => res?,
+            };
+            reporter.finish_phase("downloading");
+            let compression = Compression::from_path(Path::new(&component.location));
+            let tarname = compression.tarball_name(name);
+            let sums = self.get_checksums(name, component.version, env, &tarname)?;
+            let sha256 = verify_download(&sums, &part, name)?;
+            let policy = self.get_signature_policy();
+            if policy.verifies() {
+                verify_download_signature(self, policy, &component, &part)?;
+            }
+            reporter.start_phase("storing in cache");
+            store_tarball(self, name, component.version, env, &part, &sha256, compression)?;
+            reporter.finish_phase("storing in cache");
         }
         assert!(is_cached(self, &component.name, component.version, env),
                 "cached component");
+        touch_cache_entry(&cachedir)?;
 
         trace!("Fetching {} from cache", name);
-        let tarname = get_cache_dir(self, &component.name, component.version, env)
-            .join(format!("{}.tar.gz", name));
-        Ok((tarname, component))
+        Ok((find_tarball(&cachedir, name), component))
     }
 
     // basic functionality for `fetch`/`update`
@@ -138,32 +522,87 @@ where
         name: &str,
         version: Option<u32>,
         env: &str,
+        reporter: &ProgressReporter,
     ) -> LalResult<Component> {
-        let (tarname, component) = self.retrieve_published_component(name, version, env)?;
+        let (tarname, component) =
+            self.retrieve_published_component(name, version, env, reporter)?;
 
         debug!("Unpacking tarball {} for {}",
                tarname.to_str().unwrap(),
                component.name);
-        extract_tarball_to_input(tarname, name)?;
+        reporter.start_phase("unpacking");
+        extract_tarball_to_input(&self.get_cache_dir(), tarname, name, reporter)?;
+        reporter.finish_phase("unpacking");
 
         Ok(component)
     }
 
     /// helper for `update`
-    fn unpack_stashed_component(&self, name: &str, code: &str) -> LalResult<()> {
+    fn unpack_stashed_component(
+        &self,
+        name: &str,
+        code: &str,
+        reporter: &ProgressReporter,
+    ) -> LalResult<()> {
         let tarpath = self.retrieve_stashed_component(name, code)?;
 
-        extract_tarball_to_input(tarpath, name)?;
+        extract_tarball_to_input(&self.get_cache_dir(), tarpath, name, reporter)?;
+
+        // convenience edit for lal status here:
+        // we edit the lockfile's version key to be "${stashname}"
+        // rather than the ugly colony default of "EXPERIMENTAL-${hex}"
+        // this is done on unpack so identical stashes share one tarball
+        let lf_path = Path::new("./INPUT").join(name).join("lockfile.json");
+        let mut lf = Lockfile::from_path(&lf_path, name)?;
+        lf.version = code.to_string();
+        // shared with the object store
+        fs::remove_file(&lf_path).context(format!("Failed to remove {}", lf_path.display()))?;
+        lf.write(&lf_path)?;
         Ok(())
     }
 
+    fn is_published_cached(&self, name: &str, version: u32, env: &str) -> bool {
+        is_cached(self, name, version, env)
+    }
+
+    fn lookup_published_component(
+        &self,
+        name: &str,
+        version: u32,
+        env: &str,
+    ) -> LalResult<CacheLookup> {
+        lookup(self, name, version, env)
+    }
+
+    /// helper for `import`
+    fn store_published_component(
+        &self,
+        name: &str,
+        version: u32,
+        env: &str,
+        tarball: &Path,
+    ) -> LalResult<PathBuf> {
+        ensure_cache_dirs(self)?;
+        if tarball_lockfile(tarball).is_none() {
+            return Err(CliError::MissingLockfile(name.into()));
+        }
+        let cachedir = get_cache_dir(self, name, version, env);
+        if cachedir.is_dir() {
+            warn!("Replacing cached {} {} in {}", name, version, env);
+            fs::remove_dir_all(&cachedir)
+                .context(format!("Failed to remove cache entry {}", cachedir.display()))?;
+        }
+        let sha256 = sha256_file(tarball)?;
+        let compression = Compression::from_path(tarball);
+        store_tarball(self, name, version, env, tarball, &sha256, compression)?;
+        Ok(cachedir.join(compression.tarball_name(name)))
+    }
+
     /// helper for unpack_, `export`
     fn retrieve_stashed_component(&self, name: &str, code: &str) -> LalResult<PathBuf> {
-        let tarpath = Path::new(&self.get_cache_dir())
-            .join("stash")
-            .join(name)
-            .join(code)
-            .join(format!("{}.tar.gz", name));
+        ensure_cache_dirs(self)?;
+        let stashdir = Path::new(&self.get_cache_dir()).join("stash").join(name).join(code);
+        let tarpath = find_tarball(&stashdir, name);
         if !tarpath.is_file() {
             return Err(CliError::MissingStashArtifact(format!("{}/{}", name, code)));
         }
@@ -172,16 +611,30 @@ where
 
     // helper for `stash`
     fn stash_output(&self, name: &str, code: &str) -> LalResult<()> {
+        ensure_cache_dirs(self)?;
         let destdir = Path::new(&self.get_cache_dir()).join("stash").join(name).join(code);
         debug!("Creating {:?}", destdir);
-        fs::create_dir_all(&destdir)?;
+        fs::create_dir_all(&destdir)
+            .context(format!("Failed to create stash directory {}", destdir.display()))?;
 
         // Tar it straight into destination
-        output::tar(&destdir.join(format!("{}.tar.gz", name)))?;
+        // NB: never overwrite in place - the old tarball may be a shared hardlink
+        for c in &COMPRESSIONS {
+            let old = destdir.join(c.tarball_name(name));
+            if old.exists() {
+                fs::remove_file(&old)
+                    .context(format!("Failed to remove old stash {}", old.display()))?;
+            }
+        }
+        let tarball = destdir.join(self.get_compression().tarball_name(name));
+        output::tar(&tarball, self.get_compression())?;
+        dedup_stashed_tarball(&self.get_cache_dir(), &tarball)?;
 
         // Copy the lockfile there for users inspecting the stashed folder
         // NB: this is not really needed, as it's included in the tarball anyway
-        fs::copy("./OUTPUT/lockfile.json", destdir.join("lockfile.json"))?;
+        let mut lf = Lockfile::from_path(&Path::new("./OUTPUT").join("lockfile.json"), name)?;
+        lf.version = code.to_string();
+        lf.write(&destdir.join("lockfile.json"))?;
         Ok(())
     }
 }
@@ -1,18 +1,32 @@
 #![allow(missing_docs)]
 
+use std::collections::BTreeMap;
 use std::fs;
+use std::io::Write;
 use std::str::FromStr;
 use std::vec::Vec;
 use std::path::{Path, PathBuf};
+use chrono::{DateTime, UTC, TimeZone};
+use filetime::FileTime;
+use serde_json;
 
-use core::{CliError, LalResult, config_dir, ensure_dir_exists_fresh};
+use core::{CliError, CompressionOptions, IoResultExt, LalResult, Deprecations, Lockfile,
+           ProgressReporter, SignaturePolicy, config_dir, ensure_dir_exists_fresh, find_tarball,
+           signature_path};
 
 
-/// LocalBackend configuration options (currently none)
+/// LocalBackend configuration options
 #[derive(Serialize, Deserialize, Clone, Default)]
-pub struct LocalConfig {}
+pub struct LocalConfig {
+    /// Directory tree with artifacts laid out as `<env>/<name>/<version>/`
+    ///
+    /// This is typically a shared mount on machines without network access.
+    /// When unset, artifacts are kept in `environments` inside the lal cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
 
-use super::{Backend, Component};
+use super::{Backend, Component, Checksums, sha256_file};
 
 /// Artifact storage on the local machine
 pub struct LocalBackend {
@@ -20,6 +34,12 @@ pub struct LocalBackend {
     pub config: LocalConfig,
     /// Cache directory
     pub cache: String,
+    /// Size cap of cached components in bytes (unbounded if unset)
+    pub cache_max_size: Option<u64>,
+    /// Signing key and trusted keys for published artifacts
+    pub signatures: SignaturePolicy,
+    /// How stashed and imported tarballs are compressed
+    pub compression: CompressionOptions,
 }
 
 impl LocalBackend {
@@ -27,8 +47,120 @@ impl LocalBackend {
         LocalBackend {
             config: cfg.clone(),
             cache: cache.into(),
+            cache_max_size: None,
+            signatures: SignaturePolicy::default(),
+            compression: CompressionOptions::default(),
+        }
+    }
+
+    // root of the environment trees (configured directory or inside the cache)
+    fn env_root(&self) -> LalResult<PathBuf> {
+        match self.config.path {
+            Some(ref p) => Ok(PathBuf::from(p)),
+            None => Ok(config_dir()?.join(&self.cache).join("environments")),
+        }
+    }
+
+    // root of the stashes shared through the backend
+    fn stash_root(&self) -> LalResult<PathBuf> {
+        match self.config.path {
+            Some(ref p) => Ok(PathBuf::from(p).join("stash")),
+            None => Ok(config_dir()?.join(&self.cache).join("shared-stash")),
+        }
+    }
+
+    // root of the remote build cache (configured directory or inside the cache)
+    fn build_cache_root(&self) -> LalResult<PathBuf> {
+        match self.config.path {
+            Some(ref p) => Ok(PathBuf::from(p).join("buildcache")),
+            None => Ok(config_dir()?.join(&self.cache).join("shared-buildcache")),
+        }
+    }
+
+    // directory for a specific published artifact
+    fn artifact_dir(&self, env: &str, name: &str, version: u32) -> LalResult<PathBuf> {
+        Ok(self.env_root()?.join(env).join(name).join(version.to_string()))
+    }
+
+    // publish the ARTIFACT dir, optionally replacing an existing version
+    fn publish_into_place(
+        &self,
+        name: &str,
+        version: u32,
+        env: &str,
+        replace: bool,
+    ) -> LalResult<()> {
+        // this fn basically assumes all the sanity checks have been performed
+        // files must exist and lockfile must be sensible
+        let artifactdir = Path::new("./ARTIFACT");
+        let tarball = find_tarball(artifactdir, name);
+        let tarname = tarball.file_name().unwrap().to_string_lossy().into_owned();
+        let lockfile = artifactdir.join("lockfile.json");
+
+        // assemble on the same filesystem, then rename into place once verified
+        // so that an interrupted publish never leaves a partial version behind
+        let staging = self.env_root()?
+            .join(".staging")
+            .join(format!("{}-{}-{}-{:08x}", env, name, version, ::rand::random::<u32>()));
+        fs::create_dir_all(&staging)?;
+        let res = stage_artifact(&staging, &tarname, &tarball, &lockfile).and_then(|_| {
+            let tar_dir = self.artifact_dir(env, name, version)?;
+            if tar_dir.exists() {
+                if !replace {
+                    return Err(CliError::VersionTaken(name.into(), version));
+                }
+                // a replaced version stays in the channels it was promoted to
+                if tar_dir.join("channels").is_file() {
+                    fs::copy(tar_dir.join("channels"), staging.join("channels"))?;
+                }
+                fs::remove_dir_all(&tar_dir)?;
+            }
+            fs::create_dir_all(tar_dir.parent().unwrap())?;
+            // renaming onto a version that appeared in the meantime fails as it is not empty
+            fs::rename(&staging, &tar_dir).map_err(|e| if !replace && tar_dir.exists() {
+                CliError::VersionTaken(name.into(), version)
+            } else {
+                e.into()
+            })
+        });
+        if res.is_err() {
+            let _ = fs::remove_dir_all(&staging);
         }
+        res
+    }
+}
+
+// copy a release into a staging directory and check the copies are intact
+fn stage_artifact(staging: &Path, tarname: &str, tarball: &Path, lockfile: &Path) -> LalResult<()> {
+    let tar_path = staging.join(tarname);
+    fs::copy(tarball, &tar_path)?;
+    fs::copy(lockfile, staging.join("lockfile.json"))?;
+    let sha = sha256_file(tarball)?;
+    if sha256_file(&tar_path)? != sha {
+        return Err(CliError::UnverifiedUpload(tarname.into()));
+    }
+    if sha256_file(&staging.join("lockfile.json"))? != sha256_file(lockfile)? {
+        return Err(CliError::UnverifiedUpload("lockfile.json".into()));
+    }
+    if signature_path(tarball).is_file() {
+        fs::copy(signature_path(tarball), signature_path(&tar_path))?;
     }
+    // record the checksum next to the tarball for verification on fetch
+    let mut f = fs::File::create(staging.join(format!("{}.sha256", tarname)))?;
+    write!(f, "{}", sha)?;
+    Ok(())
+}
+
+// channels a published version has been promoted to (one per line in `channels`)
+fn read_channels(dir: &Path) -> LalResult<Vec<String>> {
+    use std::io::Read;
+    let pth = dir.join("channels");
+    if !pth.is_file() {
+        return Ok(vec![]);
+    }
+    let mut data = String::new();
+    fs::File::open(&pth)?.read_to_string(&mut data)?;
+    Ok(data.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).map(String::from).collect())
 }
 
 /// Artifact backend trait for `LocalBackend`
@@ -36,11 +168,45 @@ impl LocalBackend {
 /// This is intended to be used by the caching trait `CachedBackend`, but for
 /// specific low-level use cases, these methods can be used directly.
 impl Backend for LocalBackend {
+    fn get_components(&self, loc: &str) -> LalResult<Vec<String>> {
+        let env_dir = self.env_root()?.join(loc);
+        let mut names = vec![];
+        if env_dir.is_dir() {
+            for entry in fs::read_dir(env_dir)? {
+                let path = entry?;
+                if path.path().is_dir() {
+                    names.push(path.file_name().to_string_lossy().into_owned());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn get_target_locations(&self, env: &str) -> LalResult<Vec<String>> {
+        let root = self.env_root()?;
+        let prefix = format!("{}+", env);
+        let mut locs = vec![];
+        if root.is_dir() {
+            for entry in fs::read_dir(root)? {
+                let path = entry?;
+                let loc = path.file_name().to_string_lossy().into_owned();
+                if loc.starts_with(&prefix) && path.path().is_dir() {
+                    locs.push(loc);
+                }
+            }
+        }
+        locs.sort();
+        Ok(locs)
+    }
+
     fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<u32>> {
-        let tar_dir = format!("{}/environments/{}/{}/", self.cache, loc, name);
-        let dentries = fs::read_dir(config_dir().join(tar_dir));
+        let tar_dir = self.env_root()?.join(loc).join(name);
         let mut versions = vec![];
-        for entry in dentries? {
+        if !tar_dir.is_dir() {
+            return Ok(versions); // never published
+        }
+        for entry in fs::read_dir(tar_dir)? {
             let path = entry?;
             if let Some(filename) = path.file_name().to_str() {
                 if let Ok(version) = u32::from_str(filename) {
@@ -48,6 +214,7 @@ impl Backend for LocalBackend {
                 }
             }
         }
+        versions.sort();
         Ok(versions)
     }
 
@@ -58,6 +225,14 @@ impl Backend for LocalBackend {
         Err(CliError::BackendFailure("No versions found on local storage".into()))
     }
 
+    fn get_latest_versions_of(
+        &self,
+        names: &[String],
+        loc: &str,
+    ) -> BTreeMap<String, LalResult<u32>> {
+        names.iter().map(|n| (n.clone(), self.get_latest_version(n, loc))).collect()
+    }
+
     fn get_component_info(
         &self,
         name: &str,
@@ -71,41 +246,215 @@ impl Backend for LocalBackend {
         } else {
             self.get_latest_version(name, loc)?
         };
-        let loc = format!("{}/environments/{}/{}/{}/{}.tar.gz", self.cache, loc, name, v, name);
+        let tarball = find_tarball(&self.artifact_dir(loc, name, v)?, name);
         Ok(Component {
             name: name.into(),
             version: v,
-            location: loc,
+            location: tarball.to_string_lossy().into(),
         })
     }
 
+    fn get_tarball_location(&self, name: &str, version: u32, loc: &str) -> LalResult<String> {
+        Ok(find_tarball(&self.artifact_dir(loc, name, version)?, name).to_string_lossy().into())
+    }
+
     fn publish_artifact(&self, name: &str, version: u32, env: &str) -> LalResult<()> {
-        // this fn basically assumes all the sanity checks have been performed
-        // files must exist and lockfile must be sensible
-        let artifactdir = Path::new("./ARTIFACT");
-        let tarball = artifactdir.join(format!("{}.tar.gz", name));
-        let lockfile = artifactdir.join("lockfile.json");
+        self.publish_into_place(name, version, env, true)
+    }
+
+    fn publish_new_artifact(&self, name: &str, version: u32, env: &str) -> LalResult<()> {
+        self.publish_into_place(name, version, env, false)
+    }
+
+    fn get_publish_location(&self, name: &str, version: u32, env: &str) -> LalResult<String> {
+        Ok(self.artifact_dir(env, name, version)?.to_string_lossy().into())
+    }
 
-        // prefix with environment
-        let tar_dir = format!("{}/environments/{}/{}/{}/", self.cache, env, name, version);
-        let tar_path = format!("{}/environments/{}/{}/{}/{}.tar.gz", self.cache, env, name, version, name);
-        let lock_path = format!("{}/environments/{}/{}/{}/lockfile.json", self.cache, env, name, version);
+    fn check_publish_access(&self) -> LalResult<()> {
+        // the closest existing parent of the environment trees has to be writable
+        let root = self.env_root()?;
+        let mut dir = root.as_path();
+        while !dir.exists() {
+            dir = match dir.parent() {
+                Some(p) => p,
+                None => break,
+            };
+        }
+        let readonly = dir.metadata().map(|m| m.permissions().readonly()).unwrap_or(true);
+        if readonly {
+            let reason = format!("{} is not writable", dir.display());
+            return Err(CliError::UploadFailure(reason));
+        }
+        Ok(())
+    }
 
-        if let Some(full_tar_dir) = config_dir().join(tar_dir).to_str() {
-            ensure_dir_exists_fresh(full_tar_dir)?;
+    fn get_channel_versions(&self, name: &str, channel: &str, loc: &str) -> LalResult<Vec<u32>> {
+        let mut versions = vec![];
+        for v in self.get_versions(name, loc)? {
+            if read_channels(&self.artifact_dir(loc, name, v)?)?.iter().any(|c| c == channel) {
+                versions.push(v);
+            }
         }
+        Ok(versions)
+    }
 
-        fs::copy(tarball, config_dir().join(tar_path))?;
-        fs::copy(lockfile, config_dir().join(lock_path))?;
+    fn tag_channel(&self, name: &str, version: u32, channel: &str, loc: &str) -> LalResult<()> {
+        let dir = self.artifact_dir(loc, name, version)?;
+        let mut channels = read_channels(&dir)?;
+        if !channels.iter().any(|c| c == channel) {
+            channels.push(channel.into());
+            let mut f = fs::File::create(dir.join("channels"))?;
+            write!(f, "{}\n", channels.join("\n"))?;
+        }
+        Ok(())
+    }
+
+    fn get_lockfile(&self, name: &str, version: u32, loc: &str) -> LalResult<Lockfile> {
+        let pth = self.artifact_dir(loc, name, version)?.join("lockfile.json");
+        Lockfile::from_path(&pth, name)
+    }
+
+    fn delete_version(&self, name: &str, version: u32, loc: &str) -> LalResult<()> {
+        let dir = self.artifact_dir(loc, name, version)?;
+        if !dir.is_dir() {
+            return Err(CliError::BackendFailure(format!("{}={} is not published", name, version)));
+        }
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
 
+    fn set_metadata(
+        &self,
+        name: &str,
+        version: u32,
+        loc: &str,
+        meta: &BTreeMap<String, String>,
+    ) -> LalResult<()> {
+        let mut props = self.get_metadata(name, version, loc)?;
+        props.extend(meta.iter().map(|(k, v)| (k.clone(), v.clone())));
+        let pth = self.artifact_dir(loc, name, version)?.join("metadata.json");
+        let mut f = fs::File::create(&pth)?;
+        write!(f, "{}\n", serde_json::to_string_pretty(&props)?)?;
         Ok(())
     }
 
+    fn get_metadata(
+        &self,
+        name: &str,
+        version: u32,
+        loc: &str,
+    ) -> LalResult<BTreeMap<String, String>> {
+        let dir = self.artifact_dir(loc, name, version)?;
+        if !dir.is_dir() {
+            return Err(CliError::BackendFailure(format!("{}={} is not published", name, version)));
+        }
+        let pth = dir.join("metadata.json");
+        if !pth.is_file() {
+            return Ok(BTreeMap::new());
+        }
+        Ok(serde_json::from_reader(fs::File::open(&pth)?)?)
+    }
+
+    fn upload_stash(&self, name: &str, dir: &Path, user: &str, code: &str) -> LalResult<()> {
+        let dest = self.stash_root()?.join(user).join(code);
+        if let Some(d) = dest.to_str() {
+            ensure_dir_exists_fresh(d)?;
+        }
+        let tarball = find_tarball(dir, name);
+        fs::copy(&tarball, dest.join(tarball.file_name().unwrap()))
+            .context(format!("Failed to share stashed tarball {}", tarball.display()))?;
+        let lf_path = dir.join("lockfile.json");
+        fs::copy(&lf_path, dest.join("lockfile.json"))
+            .context(format!("Failed to share stashed lockfile {}", lf_path.display()))?;
+        Ok(())
+    }
+
+    fn get_stash_location(&self, user: &str, code: &str, file: &str) -> LalResult<String> {
+        let pth = self.stash_root()?.join(user).join(code).join(file);
+        if !pth.is_file() {
+            return Err(CliError::MissingStashArtifact(format!("{}/{}", user, code)));
+        }
+        Ok(pth.to_string_lossy().into())
+    }
+
+    fn upload_build_output(&self, key: &str, tarball: &Path) -> LalResult<()> {
+        let dest = self.build_cache_root()?.join(key);
+        fs::create_dir_all(&dest)?;
+        // copy next to it first so a concurrent fetch never sees a partial tarball
+        let file = tarball.file_name().unwrap().to_string_lossy().into_owned();
+        let partial = dest.join(format!("{}.part", file));
+        fs::copy(tarball, &partial)?;
+        fs::rename(&partial, dest.join(file))?;
+        Ok(())
+    }
+
+    fn get_build_output_location(&self, key: &str, file: &str) -> LalResult<String> {
+        let pth = self.build_cache_root()?.join(key).join(file);
+        if !pth.is_file() {
+            return Err(CliError::MissingBuildOutput(key.into()));
+        }
+        Ok(pth.to_string_lossy().into())
+    }
+
+    fn get_checksums(
+        &self,
+        name: &str,
+        version: u32,
+        loc: &str,
+        tarball: &str,
+    ) -> LalResult<Checksums> {
+        use std::io::Read;
+        let tarball = self.artifact_dir(loc, name, version)?.join(tarball);
+        let pth = PathBuf::from(format!("{}.sha256", tarball.display()));
+        let mut sums = Checksums::default();
+        if pth.is_file() {
+            let mut data = String::new();
+            fs::File::open(&pth)?.read_to_string(&mut data)?;
+            sums.sha256 = Some(data.trim().into());
+        }
+        Ok(sums)
+    }
+
+    fn get_deprecations(&self) -> LalResult<Deprecations> {
+        use std::io::Read;
+        let pth = match self.config.path {
+            Some(ref p) => Path::new(p).join("deprecations.json"),
+            None => config_dir()?.join(format!("{}/deprecations.json", self.cache)),
+        };
+        if !pth.is_file() {
+            return Ok(Deprecations::default());
+        }
+        let mut data = String::new();
+        fs::File::open(&pth)?.read_to_string(&mut data)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    fn get_publish_time(
+        &self,
+        name: &str,
+        version: u32,
+        loc: &str,
+    ) -> LalResult<Option<DateTime<UTC>>> {
+        let dir = self.artifact_dir(loc, name, version)?;
+        if !dir.is_dir() {
+            return Ok(None);
+        }
+        let mtime = FileTime::from_last_modification_time(&dir.metadata()?);
+        Ok(Some(UTC.timestamp(mtime.seconds_relative_to_1970() as i64, 0)))
+    }
+
     fn get_cache_dir(&self) -> String { self.cache.clone() }
 
-    fn raw_fetch(&self, src: &str, dest: &PathBuf) -> LalResult<()> {
+    fn get_cache_max_size(&self) -> Option<u64> { self.cache_max_size }
+
+    fn get_signature_policy(&self) -> &SignaturePolicy { &self.signatures }
+
+    fn get_compression(&self) -> &CompressionOptions { &self.compression }
+
+    fn raw_fetch(&self, src: &str, dest: &PathBuf, reporter: &ProgressReporter) -> LalResult<()> {
         debug!("raw fetch {} -> {}", src, dest.display());
-        fs::copy(src, dest)?;
+        let copied = fs::copy(src, dest)?;
+        reporter.bytes(copied, Some(copied));
         Ok(())
     }
 }
@@ -1,7 +1,11 @@
-pub use self::traits::{BackendConfiguration, Backend, CachedBackend, Component};
+pub use self::traits::{BackendConfiguration, Backend, CachedBackend, CacheEntry, CacheLookup,
+                       Component, Checksums};
 
-pub use self::artifactory::{ArtifactoryConfig, Credentials, ArtifactoryBackend};
+pub use self::artifactory::{ArtifactoryConfig, Credentials, ArtifactoryBackend, RetryPolicy,
+                              HttpOptions, Proxy, property_escape, proxy_for};
 pub use self::local::{LocalConfig, LocalBackend};
+pub use self::download::{sha1_file, sha256_file, tarball_lockfile};
+pub use self::objects::clean_orphaned_objects;
 
 // Some special exports for lal upgrade - canonical releases are on artifactory atm
 #[cfg(feature = "upgrade")]
@@ -11,6 +15,5 @@ mod traits;
 mod artifactory;
 mod local;
 mod download;
-
-#[cfg(feature = "progress")]
 mod progress;
+mod objects;
@@ -0,0 +1,242 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use filetime::{self, FileTime};
+use serde_json;
+use sha1;
+
+use core::{Compression, EntryGuard, LalResult, ProgressReporter};
+use super::progress::ProgressReader;
+
+/// A single extracted path recorded in a `Tree`
+#[derive(Serialize, Deserialize, Debug)]
+enum TreeEntry {
+    /// A directory
+    Dir(String),
+    /// A regular file with the object name it is stored under
+    File(String, String),
+    /// A symlink and its target
+    Symlink(String, String),
+}
+
+/// Everything a tarball extracts to, so it can be materialized again without unpacking
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Tree {
+    /// Layout of the objects the tree refers to (trees of older layouts are unpacked again)
+    #[serde(default)]
+    version: u32,
+    entries: Vec<TreeEntry>,
+}
+
+// objects are named by content, permission bits and mtime since version 1
+const TREE_VERSION: u32 = 1;
+
+// root of the content addressed store inside the cache
+fn objects_dir(cache: &str) -> PathBuf { Path::new(cache).join("objects") }
+
+fn object_path(cache: &str, object: &str) -> PathBuf {
+    objects_dir(cache).join(&object[0..2]).join(object)
+}
+
+fn tree_path(cache: &str, tarball_sha: &str) -> PathBuf {
+    objects_dir(cache).join("trees").join(format!("{}.json", tarball_sha))
+}
+
+// hardlink an object into place, copying when linking is impossible (e.g. across devices)
+fn link_object(obj: &Path, dest: &Path) -> LalResult<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let _ = fs::remove_file(dest); // repeated tar entries replace earlier ones
+    if let Err(e) = fs::hard_link(obj, dest) {
+        trace!("Failed to hardlink {} ({}) - copying", obj.display(), e);
+        fs::copy(obj, dest)?; // keeps the mode, but not the mtime
+        let mtime = FileTime::from_last_modification_time(&obj.metadata()?);
+        filetime::set_file_times(dest, mtime, mtime)?;
+    }
+    Ok(())
+}
+
+// write a file entry into the store and return its object name
+//
+// Objects are read-only since every INPUT folder using them shares the same inode.
+// The read and execute bits and the mtime of the entry are kept, so identical files
+// only share an object if those match too (as they do in tarballs with normalized mtimes).
+fn store_object<R: Read>(cache: &str, rdr: &mut R, mode: u32, mtime: u64) -> LalResult<String> {
+    let tmpdir = objects_dir(cache).join("tmp");
+    fs::create_dir_all(&tmpdir)?;
+    let tmp = tmpdir.join(format!("{}", ::rand::random::<u64>()));
+    let mut sha = sha1::Sha1::new();
+    {
+        let mut f = fs::File::create(&tmp)?;
+        let mut buffer = [0; 1024 * 64];
+        loop {
+            let read = rdr.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            sha.update(&buffer[0..read]);
+            f.write_all(&buffer[0..read])?;
+        }
+    }
+    let perms = mode & 0o555;
+    let object = format!("{}-{:o}-{}", sha.digest(), perms, mtime);
+    let dest = object_path(cache, &object);
+    if dest.is_file() {
+        fs::remove_file(&tmp)?;
+    } else {
+        fs::create_dir_all(dest.parent().unwrap())?;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(perms))?;
+        let mtime = FileTime::from_seconds_since_1970(mtime, 0);
+        filetime::set_file_times(&tmp, mtime, mtime)?;
+        fs::rename(&tmp, &dest)?;
+    }
+    Ok(object)
+}
+
+// materialize a previously recorded tree (false if any object has since been cleaned)
+fn link_tree(cache: &str, tree: &Tree, dest: &Path) -> LalResult<bool> {
+    let complete = tree.entries.iter().all(|e| match *e {
+        TreeEntry::File(_, ref obj) => object_path(cache, obj).is_file(),
+        _ => true,
+    });
+    if !complete {
+        return Ok(false);
+    }
+    for e in &tree.entries {
+        match *e {
+            TreeEntry::Dir(ref p) => fs::create_dir_all(dest.join(p))?,
+            TreeEntry::File(ref p, ref obj) => {
+                link_object(&object_path(cache, obj), &dest.join(p))?
+            }
+            TreeEntry::Symlink(ref p, ref target) => symlink(target, dest.join(p))?,
+        }
+    }
+    Ok(true)
+}
+
+// unpack a tarball through the store, recording the tree it produced
+fn unpack_tree(
+    cache: &str,
+    tarball: &Path,
+    dest: &Path,
+    reporter: &ProgressReporter,
+) -> LalResult<Tree> {
+    use tar::{Archive, EntryType};
+
+    // Open file, and report progress on the file reading
+    let data = fs::File::open(tarball)?;
+    let total = data.metadata()?.len();
+    let progdata = ProgressReader::new(data, Some(total), reporter);
+    // decoder reads data (proxied)
+    let decompressed = Compression::from_path(tarball).decoder(progdata)?;
+    let mut archive = Archive::new(decompressed); // Archive reads decoded
+
+    let mut tree = Tree { version: TREE_VERSION, ..Tree::default() };
+    let mut guard = EntryGuard::default();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        guard.check(&entry)?;
+        let pth = entry.path()?.into_owned();
+        let rel = pth.to_string_lossy().into_owned();
+        let target = dest.join(&pth);
+        match entry.header().entry_type() {
+            EntryType::Directory => {
+                fs::create_dir_all(&target)?;
+                tree.entries.push(TreeEntry::Dir(rel));
+            }
+            EntryType::Symlink => {
+                let link = entry.link_name()?.map(|l| l.into_owned()).unwrap_or_default();
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                symlink(&link, &target)?;
+                tree.entries.push(TreeEntry::Symlink(rel, link.to_string_lossy().into_owned()));
+            }
+            EntryType::Link => {
+                // hardlink to an earlier entry - reuse its object
+                let link = entry.link_name()?.map(|l| l.to_string_lossy().into_owned());
+                let obj = tree.entries.iter().filter_map(|e| match *e {
+                    TreeEntry::File(ref p, ref o) if Some(p) == link.as_ref() => Some(o.clone()),
+                    _ => None,
+                }).next();
+                if let Some(o) = obj {
+                    link_object(&object_path(cache, &o), &target)?;
+                    tree.entries.push(TreeEntry::File(rel, o));
+                } else {
+                    warn!("Skipping hardlink {} to unknown {:?}", rel, link);
+                }
+            }
+            EntryType::Regular | EntryType::Continuous => {
+                let mode = entry.header().mode()?;
+                let mtime = entry.header().mtime()?;
+                let obj = store_object(cache, &mut entry, mode, mtime)?;
+                link_object(&object_path(cache, &obj), &target)?;
+                tree.entries.push(TreeEntry::File(rel, obj));
+            }
+            other => debug!("Skipping unsupported tar entry {} ({:?})", rel, other),
+        }
+    }
+    Ok(tree)
+}
+
+/// Extract a tarball into a directory through the content addressed store
+///
+/// Every file is stored once under `objects` in the cache by its SHA1, mode and mtime,
+/// and hardlinked into place, so identical files across components and versions share
+/// disk space. The resulting tree is recorded by the tarball's SHA1, so materializing
+/// the same tarball again only creates links. Objects with no links left are removed
+/// by `lal clean`.
+pub fn materialize(
+    cache: &str,
+    tarball: &Path,
+    tarball_sha: &str,
+    dest: &Path,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    let treefile = tree_path(cache, tarball_sha);
+    if treefile.is_file() {
+        let mut data = String::new();
+        fs::File::open(&treefile)?.read_to_string(&mut data)?;
+        if let Ok(tree) = serde_json::from_str::<Tree>(&data) {
+            if tree.version != TREE_VERSION {
+                debug!("Tree for {} predates the object layout - unpacking again", dest.display());
+            } else if link_tree(cache, &tree, dest)? {
+                debug!("Materialized {} from known tree", dest.display());
+                return Ok(());
+            } else {
+                debug!("Objects for {} were cleaned - unpacking again", dest.display());
+            }
+        }
+    }
+    let tree = unpack_tree(cache, tarball, dest, reporter)?;
+    fs::create_dir_all(treefile.parent().unwrap())?;
+    let mut f = fs::File::create(&treefile)?;
+    write!(f, "{}", serde_json::to_string(&tree)?)?;
+    Ok(())
+}
+
+/// Remove objects that are no longer linked from any INPUT folder or cache entry
+pub fn clean_orphaned_objects(cache: &str) -> LalResult<()> {
+    use std::os::unix::fs::MetadataExt;
+    let objdir = objects_dir(cache);
+    if !objdir.is_dir() {
+        return Ok(());
+    }
+    for sub in fs::read_dir(&objdir)? {
+        let sub = sub?.path();
+        let name = sub.file_name().unwrap().to_string_lossy().into_owned();
+        if !sub.is_dir() || name == "trees" {
+            continue;
+        }
+        for entry in fs::read_dir(&sub)? {
+            let pth = entry?.path();
+            if name == "tmp" || pth.metadata()?.nlink() == 1 {
+                trace!("Cleaning orphaned object {}", pth.display());
+                fs::remove_file(&pth)?;
+            }
+        }
+    }
+    Ok(())
+}
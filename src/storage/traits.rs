@@ -1,6 +1,9 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, UTC};
 
-use core::LalResult;
+use core::{LalResult, CompressionOptions, Deprecations, Lockfile, ProgressReporter,
+           SignaturePolicy};
 use super::{ArtifactoryConfig, LocalConfig};
 
 /// An enum struct for the currently configured `Backend`
@@ -38,36 +41,200 @@ pub struct Component {
     pub location: String,
 }
 
+/// Checksums a backend has recorded for a component tarball
+///
+/// Backends may know none, some, or all of these.
+#[derive(Deserialize, Default, Debug)]
+pub struct Checksums {
+    /// SHA256 hex digest
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// SHA1 hex digest (always recorded by artifactory on publish)
+    #[serde(default)]
+    pub sha1: Option<String>,
+}
+
 /// Properties a storage backend of artifacts should have
 ///
 /// We are not really relying on Artifactory specific quirks in our default usage
 /// so that in case it fails it can be switched over.
 /// We do rely on there being a basic API that can implement this trait though.
 pub trait Backend {
+    /// Get the locations of components built in an environment for a target
+    ///
+    /// These are the `<env>+<target>` locations next to the environment (see
+    /// `target_location`), for every target something was published for.
+    fn get_target_locations(&self, env: &str) -> LalResult<Vec<String>>;
     /// Get a list of versions for a component in descending order
     fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<u32>>;
+    /// Get the names of all components published in an environment
+    fn get_components(&self, loc: &str) -> LalResult<Vec<String>>;
     /// Get the latest version of a component
     fn get_latest_version(&self, name: &str, loc: &str) -> LalResult<u32>;
+    /// Get the latest version of several components
+    ///
+    /// Remote backends query the components concurrently.
+    fn get_latest_versions_of(
+        &self,
+        names: &[String],
+        loc: &str,
+    ) -> BTreeMap<String, LalResult<u32>>;
 
     /// Get the version and location information of a component
     ///
-    /// If no version is given, figure out what latest is
+    /// If no version is given, figure out what latest is. The location may assume the
+    /// configured compression without looking, see `get_tarball_location`.
     fn get_component_info(&self, name: &str, ver: Option<u32>, loc: &str) -> LalResult<Component>;
 
+    /// Location of the tarball of a published version, by what was actually published
+    ///
+    /// For components published with another compression than `get_component_info` assumed.
+    fn get_tarball_location(&self, name: &str, version: u32, loc: &str) -> LalResult<String>;
+
     /// Publish a release build's ARTIFACT to a specific location
     ///
     /// This will publish everything inside the ARTIFACT dir created by `lal build -r`
     fn publish_artifact(&self, name: &str, version: u32, env: &str) -> LalResult<()>;
 
+    /// Publish the ARTIFACT dir as a new version of a component
+    ///
+    /// Unlike `publish_artifact` this never replaces an existing version, and fails
+    /// with `VersionTaken` when the version was published by someone else first.
+    fn publish_new_artifact(&self, name: &str, version: u32, env: &str) -> LalResult<()>;
+
+    /// Where `publish_artifact` puts a version of a component (for reporting)
+    fn get_publish_location(&self, name: &str, version: u32, env: &str) -> LalResult<String>;
+
+    /// Check that publishing is possible without uploading anything
+    ///
+    /// This checks credentials (or permissions) the way a publish would use them.
+    fn check_publish_access(&self) -> LalResult<()>;
+
+    /// Versions of a component promoted to a release channel
+    fn get_channel_versions(&self, name: &str, channel: &str, loc: &str) -> LalResult<Vec<u32>>;
+
+    /// Add a published version of a component to a release channel
+    ///
+    /// Channels are tags on published versions, so the artifact is not copied or rebuilt.
+    /// A version can be in several channels at once.
+    fn tag_channel(&self, name: &str, version: u32, channel: &str, loc: &str) -> LalResult<()>;
+
+    /// The lockfile published next to a version of a component
+    fn get_lockfile(&self, name: &str, version: u32, loc: &str) -> LalResult<Lockfile>;
+
+    /// Remove a published version of a component from the backend
+    fn delete_version(&self, name: &str, version: u32, loc: &str) -> LalResult<()>;
+
+    /// Record properties on a published version of a component
+    ///
+    /// Existing properties with the same keys are replaced, others are kept.
+    fn set_metadata(
+        &self,
+        name: &str,
+        version: u32,
+        loc: &str,
+        meta: &BTreeMap<String, String>,
+    ) -> LalResult<()>;
+
+    /// Properties recorded on a published version of a component
+    fn get_metadata(
+        &self,
+        name: &str,
+        version: u32,
+        loc: &str,
+    ) -> LalResult<BTreeMap<String, String>>;
+
+    /// Upload a stashed build to an area shared with other users of the backend
+    ///
+    /// The tarball and lockfile of the component `name` in `dir` are stored under
+    /// `stash/<user>/<code>/` so that they can be fetched with `get_stash_location`.
+    fn upload_stash(&self, name: &str, dir: &Path, user: &str, code: &str) -> LalResult<()>;
+
+    /// Location of a file in a shared stash, for use with `raw_fetch`
+    fn get_stash_location(&self, user: &str, code: &str, file: &str) -> LalResult<String>;
+
+    /// Upload the OUTPUT tarball of a build to the remote build cache
+    ///
+    /// The tarball is stored under `buildcache/<key>/` with its file name, so that
+    /// identical builds elsewhere can fetch it with `get_build_output_location`.
+    fn upload_build_output(&self, key: &str, tarball: &Path) -> LalResult<()>;
+
+    /// Location of a file in the remote build cache, for use with `raw_fetch`
+    fn get_build_output_location(&self, key: &str, file: &str) -> LalResult<String>;
+
     /// Raw fetch of location to a destination
     ///
     /// location can be a HTTPS url / a system path / etc (depending on the backend)
-    fn raw_fetch(&self, location: &str, dest: &PathBuf) -> LalResult<()>;
+    /// A partial file at the destination may be resumed rather than fetched again.
+    fn raw_fetch(
+        &self,
+        location: &str,
+        dest: &PathBuf,
+        reporter: &ProgressReporter,
+    ) -> LalResult<()>;
+
+    /// Expected checksums of a published component tarball
+    ///
+    /// Downloads are verified against these before they are stored in the cache.
+    /// The tarball is the file name the component was downloaded as.
+    fn get_checksums(
+        &self,
+        name: &str,
+        version: u32,
+        loc: &str,
+        tarball: &str,
+    ) -> LalResult<Checksums>;
+
+    /// Deprecation notices published at the root of the backend
+    ///
+    /// Backends without any published notices return an empty set.
+    fn get_deprecations(&self) -> LalResult<Deprecations>;
+
+    /// When a component was published at a version, if the backend knows
+    fn get_publish_time(
+        &self,
+        name: &str,
+        version: u32,
+        loc: &str,
+    ) -> LalResult<Option<DateTime<UTC>>>;
 
     /// Return the base directory to be used to dump cached downloads
     ///
     /// This has to be in here for `CachedBackend` to have a straight dependency
     fn get_cache_dir(&self) -> String;
+
+    /// Return the maximum size in bytes of cached components (if capped)
+    ///
+    /// Least recently used components are evicted when storing new ones exceeds it.
+    fn get_cache_max_size(&self) -> Option<u64>;
+
+    /// Signing key and trusted keys for published artifacts
+    ///
+    /// Downloads are verified against the trusted keys before they are stored in the cache.
+    fn get_signature_policy(&self) -> &SignaturePolicy;
+
+    /// How tarballs created for the cache (stashes and imports) are compressed
+    fn get_compression(&self) -> &CompressionOptions;
+}
+
+/// A published component found intact in the cache
+#[derive(Debug)]
+pub struct CacheEntry {
+    /// Path to the cached tarball
+    pub tarball: PathBuf,
+    /// The lockfile stored inside the tarball
+    pub lockfile: Lockfile,
+}
+
+/// The state of a published component in the cache
+#[derive(Debug)]
+pub enum CacheLookup {
+    /// Not in the cache
+    Missing,
+    /// In the cache, but unusable for the given reason
+    Corrupt(String),
+    /// In the cache with a matching checksum and a valid lockfile
+    Valid(CacheEntry),
 }
 
 /// A secondary trait that builds upon the Backend trait
@@ -87,8 +254,35 @@ pub trait CachedBackend {
         name: &str,
         version: Option<u32>,
         env: &str,
+        reporter: &ProgressReporter,
     ) -> LalResult<(PathBuf, Component)>;
 
+    /// Whether a published component is already in the cache
+    fn is_published_cached(&self, name: &str, version: u32, env: &str) -> bool;
+
+    /// Look up a published component in the cache and validate it
+    ///
+    /// Unlike `is_published_cached` this verifies the tarball against its stored
+    /// checksum, and that it contains a parseable lockfile.
+    fn lookup_published_component(
+        &self,
+        name: &str,
+        version: u32,
+        env: &str,
+    ) -> LalResult<CacheLookup>;
+
+    /// Put a component tarball in the cache as a published version
+    ///
+    /// The tarball is moved into place (replacing any cached copy of that version),
+    /// so it should be on the same filesystem as the cache.
+    fn store_published_component(
+        &self,
+        name: &str,
+        version: u32,
+        env: &str,
+        tarball: &Path,
+    ) -> LalResult<PathBuf>;
+
     /// Retrieve the location to a stashed component
     fn retrieve_stashed_component(&self, name: &str, code: &str) -> LalResult<PathBuf>;
 
@@ -98,10 +292,16 @@ pub trait CachedBackend {
         name: &str,
         version: Option<u32>,
         env: &str,
+        reporter: &ProgressReporter,
     ) -> LalResult<Component>;
 
     /// Retrieve and unpack a stashed component to INPUT
-    fn unpack_stashed_component(&self, name: &str, code: &str) -> LalResult<()>;
+    fn unpack_stashed_component(
+        &self,
+        name: &str,
+        code: &str,
+        reporter: &ProgressReporter,
+    ) -> LalResult<()>;
 
     /// Add a stashed component from a folder
     fn stash_output(&self, name: &str, code: &str) -> LalResult<()>;
@@ -0,0 +1,170 @@
+use std::fs;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use walkdir::WalkDir;
+use xml::attribute::OwnedAttribute;
+use xml::reader::{Error as XmlError, EventReader, XmlEvent};
+
+use shell;
+use super::{CliError, Config, Container, LalResult, Manifest, ShellModes, IoResultExt,
+            ensure_dir_exists_fresh};
+
+/// Where test scripts are expected to write JUnit XML reports (given as `TEST_RESULTS`)
+pub const TEST_RESULTS: &'static str = "OUTPUT/test-results";
+
+/// Outcome of the test cases in a directory of JUnit XML reports
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TestSummary {
+    /// Number of XML reports read
+    pub reports: u32,
+    /// Test cases that passed
+    pub passed: u32,
+    /// Test cases that were skipped
+    pub skipped: u32,
+    /// Names (`classname.name`) of test cases that failed or errored
+    pub failed: Vec<String>,
+}
+
+impl TestSummary {
+    /// Print how many tests passed, and which failed
+    pub fn print(&self) {
+        for name in &self.failed {
+            println!("FAILED {}", name);
+        }
+        println!("{} passed, {} failed, {} skipped ({} reports in {})",
+                 self.passed,
+                 self.failed.len(),
+                 self.skipped,
+                 self.reports,
+                 TEST_RESULTS);
+    }
+}
+
+// a test case being read from a report
+struct Case {
+    name: String,
+    failed: bool,
+    skipped: bool,
+}
+
+fn attribute(attributes: &[OwnedAttribute], name: &str) -> Option<String> {
+    attributes.iter().find(|a| a.name.local_name == name).map(|a| a.value.clone())
+}
+
+// add the test cases of one JUnit XML report to a summary
+//
+// Only `<failure>`, `<error>` and `<skipped>` elements directly inside a `<testcase>`
+// count, so the same tags in captured output or properties are left alone.
+fn summarize_report<R: Read>(xml: R, summary: &mut TestSummary) -> Result<(), XmlError> {
+    let mut parents: Vec<String> = vec![];
+    let mut case: Option<Case> = None;
+    for event in EventReader::new(xml) {
+        match event? {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                let tag = name.local_name;
+                if tag == "testcase" {
+                    let name = attribute(&attributes, "name").unwrap_or_default();
+                    let full = match attribute(&attributes, "classname") {
+                        Some(ref class) if !class.is_empty() => format!("{}.{}", class, name),
+                        _ => name,
+                    };
+                    case = Some(Case {
+                        name: full,
+                        failed: false,
+                        skipped: false,
+                    });
+                } else if parents.last().map_or(false, |p| p == "testcase") {
+                    if let Some(ref mut c) = case {
+                        match tag.as_str() {
+                            "failure" | "error" => c.failed = true,
+                            "skipped" => c.skipped = true,
+                            _ => {}
+                        }
+                    }
+                }
+                parents.push(tag);
+            }
+            XmlEvent::EndElement { .. } => {
+                if parents.pop().map_or(false, |p| p == "testcase") {
+                    match case.take() {
+                        Some(ref c) if c.failed => summary.failed.push(c.name.clone()),
+                        Some(ref c) if c.skipped => summary.skipped += 1,
+                        Some(_) => summary.passed += 1,
+                        None => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Summarize every JUnit XML report under a directory
+///
+/// Test cases read before a report turns out to be malformed (e.g. cut short by a
+/// crashing test) are still counted.
+pub fn collect_results(dir: &Path) -> LalResult<TestSummary> {
+    let mut summary = TestSummary::default();
+    if !dir.is_dir() {
+        return Ok(summary);
+    }
+    let reports = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| e.path().extension().map_or(false, |x| x == "xml"));
+    for e in reports {
+        debug!("Reading test report {}", e.path().display());
+        let f = fs::File::open(e.path())
+            .context(format!("Failed to read test report {}", e.path().display()))?;
+        if let Err(err) = summarize_report(BufReader::new(f), &mut summary) {
+            warn!("Ignoring the rest of {} ({})", e.path().display(), err);
+        }
+        summary.reports += 1;
+    }
+    Ok(summary)
+}
+
+/// Runs the test script of the component and reports the results
+///
+/// The `test` script of the manifest runs in the container like `lal run test`, with
+/// `TEST_RESULTS` set to `OUTPUT/test-results`, which is emptied first. JUnit XML reports
+/// written there are summarized once the script is done, failing with `TestFailures`
+/// when any test case failed. Without reports, the exit code of the script decides.
+pub fn test(
+    cfg: &Config,
+    container: &Container,
+    manifest: &Manifest,
+    args: Vec<&str>,
+    modes: &ShellModes,
+    privileged: bool,
+) -> LalResult<()> {
+    let mut modes = modes.clone();
+    modes.env_vars.push(format!("TEST_RESULTS={}", TEST_RESULTS));
+    if modes.printonly {
+        return shell::script(cfg, container, manifest, "test", args, &modes, privileged);
+    }
+    let legacy = Path::new(".").join(".lal").join("scripts").join("test");
+    if !manifest.scripts.contains_key("test") && !legacy.exists() {
+        return Err(CliError::MissingScript("test".into()));
+    }
+    let results = format!("./{}", TEST_RESULTS);
+    ensure_dir_exists_fresh(&results)?;
+
+    info!("Running tests in {}", container);
+    let res = shell::script(cfg, container, manifest, "test", args, &modes, privileged);
+    let summary = collect_results(Path::new(&results))?;
+    if summary.reports == 0 {
+        warn!("No JUnit XML reports were written to {}", TEST_RESULTS);
+        return res;
+    }
+    summary.print();
+    if !summary.failed.is_empty() {
+        return Err(CliError::TestFailures(summary.failed.len() as u32));
+    }
+    if res.is_err() {
+        warn!("Every test passed, but the test script failed");
+    }
+    res
+}
@@ -1,5 +1,19 @@
-use storage::CachedBackend;
-use super::{LalResult, Manifest, CliError};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use storage::{Backend, CachedBackend, Component};
+use fetch::{FetchOptions, StashedPolicy, stashed_in_input, resolve_stashed, check_deprecations,
+            component_location, fetch_target, target_components};
+use super::{LalResult, Manifest, CliError, ProgressReporter};
+
+// name of a component in an update string
+fn component_name(comp: &str) -> &str { comp.split('=').next().unwrap() }
+
+// whether an update string pulls a published version rather than a stash
+fn is_published(comp: &str) -> bool {
+    comp.split('=').nth(1).map(|v| v.parse::<u32>().is_ok()).unwrap_or(true)
+}
 
 /// Update specific dependencies outside the manifest
 ///
@@ -9,117 +23,298 @@ use super::{LalResult, Manifest, CliError};
 /// If installation was successful, the fetched tarballs are unpacked into `./INPUT`.
 /// If one `save` or `savedev` was set, the fetched versions are also updated in the
 /// manifest. This provides an easy way to not have to deal with strict JSON manually.
-pub fn update<T: CachedBackend + ?Sized>(
+///
+/// Published versions are fetched for the target from `fetch_target` when they are
+/// published for it. Stashed components in INPUT that would be replaced by published
+/// versions are handled according to the `stashed` option, and kept components are skipped.
+/// Deprecated versions are warned about, or denied with `deny_deprecated`.
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+pub fn update<T: CachedBackend + Backend + ?Sized>(
     manifest: &Manifest,
     backend: &T,
     components: Vec<String>,
     save: bool,
     savedev: bool,
+    opts: &FetchOptions,
     env: &str,
+    reporter: &ProgressReporter,
 ) -> LalResult<()> {
     debug!("Update specific deps: {:?}", components);
+    let start = Instant::now();
+    let target = fetch_target(manifest, opts)?;
+    let target = target.as_ref().map(|t| t.as_str());
+    let published = target_components(backend, env, target)?;
+
+    let current = stashed_in_input()?;
+    let conflicts: BTreeMap<String, String> = components
+        .iter()
+        .filter(|c| is_published(c))
+        .filter_map(|c| {
+            let name = component_name(c);
+            current.get(name).map(|v| (name.to_string(), v.clone()))
+        })
+        .collect();
+    let kept = resolve_stashed(&conflicts, opts.stashed)?;
+    let components = components
+        .into_iter()
+        .filter(|c| !kept.contains(component_name(c)))
+        .collect::<Vec<_>>();
+
+    // resolve bare names to the latest version first so deprecations can be checked
+    let mut pinned = Vec::with_capacity(components.len());
+    for comp in components {
+        if comp.contains('=') {
+            pinned.push(comp);
+            continue;
+        }
+        if comp.to_lowercase() != comp {
+            return Err(CliError::InvalidComponentName(comp.clone()));
+        }
+        // Since this potentially goes in the manifest
+        // make sure the version is found for all supported environments:
+        let ver = backend
+            .get_latest_supported_versions(&comp, manifest.supportedEnvironments.clone())?
+            .into_iter()
+            .max()
+            .ok_or(CliError::NoIntersectedVersion(comp.clone()))?;
+        pinned.push(format!("{}={}", comp, ver));
+    }
+    let resolved = pinned
+        .iter()
+        .filter_map(|c| {
+            c.split('=').nth(1).and_then(|v| v.parse::<u32>().ok()).map(|v| {
+                (component_name(c).to_string(), v)
+            })
+        })
+        .collect::<Vec<_>>();
+    check_deprecations(backend, &resolved, opts.deny_deprecated, reporter)?;
 
     let mut error = None;
-    let mut updated = Vec::with_capacity(components.len());
-    for comp in &components {
+    let mut updated = Vec::with_capacity(pinned.len());
+    for comp in &pinned {
         info!("Fetch {} {}", env, comp);
-        if comp.contains('=') {
-            let pair: Vec<&str> = comp.split('=').collect();
-            if let Ok(n) = pair[1].parse::<u32>() {
-                if pair[0].to_lowercase() != pair[0] {
-                    return Err(CliError::InvalidComponentName(pair[0].into()));
-                }
-                // standard fetch with an integer version
-                match backend.unpack_published_component(pair[0], Some(n), env) {
-                    Ok(c) => updated.push(c),
-                    Err(e) => {
-                        warn!("Failed to update {} ({})", pair[0], e);
-                        error = Some(e);
-                    }
-                }
-            } else {
-                // fetch from stash - this does not go into `updated` it it succeeds
-                // because we wont and cannot save stashed versions in the manifest
-                let _ = backend.unpack_stashed_component(pair[0], pair[1]).map_err(|e| {
-                    warn!("Failed to update {} from stash ({})", pair[0], e);
-                    error = Some(e);
-                });
-            }
-        } else {
-            if &comp.to_lowercase() != comp {
-                return Err(CliError::InvalidComponentName(comp.clone()));
+        let pair: Vec<&str> = comp.split('=').collect();
+        if let Ok(n) = pair[1].parse::<u32>() {
+            if pair[0].to_lowercase() != pair[0] {
+                return Err(CliError::InvalidComponentName(pair[0].into()));
             }
-            // fetch without a specific version (latest)
-
-            // First, since this potentially goes in the manifest
-            // make sure the version is found for all supported environments:
-            let ver = backend
-                .get_latest_supported_versions(comp, manifest.supportedEnvironments.clone())?
-                .into_iter()
-                .max()
-                .ok_or(CliError::NoIntersectedVersion(comp.clone()))?;
-            info!("Fetch {} {}={}", env, comp, ver);
-
-            match backend.unpack_published_component(comp, Some(ver), env) {
+            // standard fetch with an integer version
+            reporter.start_component(pair[0], pair[1]);
+            let loc = component_location(&published, pair[0], env, target);
+            match backend.unpack_published_component(pair[0], Some(n), &loc, reporter) {
                 Ok(c) => updated.push(c),
                 Err(e) => {
-                    warn!("Failed to update {} ({})", &comp, e);
+                    reporter.warn(&format!("Failed to update {} ({})", pair[0], e));
                     error = Some(e);
                 }
             }
+            reporter.finish_component(pair[0]);
+        } else {
+            // fetch from stash - this does not go into `updated` it it succeeds
+            // because we wont and cannot save stashed versions in the manifest
+            reporter.start_component(pair[0], pair[1]);
+            let _ = backend.unpack_stashed_component(pair[0], pair[1], reporter).map_err(|e| {
+                reporter.warn(&format!("Failed to update {} from stash ({})", pair[0], e));
+                error = Some(e);
+            });
+            reporter.finish_component(pair[0]);
         }
     }
+    // nothing is saved unless everything succeeded (or partial saves were asked for)
     if let Some(e) = error {
+        if !opts.partial || updated.is_empty() || !(save || savedev) {
+            return Err(e);
+        }
+        warn!("Saving the {} successfully updated components in the manifest", updated.len());
+        save_updates(manifest, &updated, save, opts)?;
         return Err(e);
     }
 
     // Update manifest if saving in any way
     if save || savedev {
-        let mut mf = manifest.clone();
-        // find reference to correct list
-        let mut hmap = if save { mf.dependencies.clone() } else { mf.devDependencies.clone() };
-        for c in &updated {
-            debug!("Successfully updated {} at version {}", &c.name, c.version);
-            if hmap.contains_key(&c.name) {
-                let val = hmap.get_mut(&c.name).unwrap();
-                if c.version < *val {
-                    warn!("Downgrading {} from {} to {}", c.name, *val, c.version);
-                } else if c.version > *val {
-                    info!("Upgrading {} from {} to {}", c.name, *val, c.version);
-                } else {
-                    info!("Maintaining {} at version {}", c.name, c.version);
-                }
-                *val = c.version;
+        save_updates(manifest, &updated, save, opts)?;
+    }
+    let took = start.elapsed();
+    info!("Updated {} components into INPUT in {}.{:01}s",
+          pinned.len(),
+          took.as_secs(),
+          took.subsec_nanos() / 100_000_000);
+    Ok(())
+}
+
+// pin updated components in dependencies (or devDependencies unless `save`)
+fn save_updates(
+    manifest: &Manifest,
+    updated: &[Component],
+    save: bool,
+    opts: &FetchOptions,
+) -> LalResult<()> {
+    let mut mf = manifest.clone();
+    // find reference to correct list
+    let mut hmap = if save { mf.dependencies.clone() } else { mf.devDependencies.clone() };
+    for c in updated {
+        debug!("Successfully updated {} at version {}", &c.name, c.version);
+        if hmap.contains_key(&c.name) {
+            let val = hmap.get_mut(&c.name).unwrap();
+            if c.version < *val {
+                warn!("Downgrading {} from {} to {}", c.name, *val, c.version);
+            } else if c.version > *val {
+                info!("Upgrading {} from {} to {}", c.name, *val, c.version);
             } else {
-                hmap.insert(c.name.clone(), c.version);
+                info!("Maintaining {} at version {}", c.name, c.version);
             }
-        }
-        if save {
-            mf.dependencies = hmap;
+            *val = c.version;
+            mf.ranges.remove(&c.name); // saving pins an exact version
         } else {
-            mf.devDependencies = hmap;
+            hmap.insert(c.name.clone(), c.version);
+        }
+    }
+    if save {
+        mf.dependencies = hmap;
+    } else {
+        mf.devDependencies = hmap;
+    }
+    for g in &opts.groups {
+        let members = mf.groups.entry(g.clone()).or_insert_with(Vec::new);
+        for c in updated {
+            if !members.contains(&c.name) {
+                info!("Adding {} to group {}", c.name, g);
+                members.push(c.name.clone());
+            }
+        }
+    }
+    mf.write()
+}
+
+/// Add new dependencies to the manifest and `./INPUT`
+///
+/// This is `update` with stricter semantics for new dependencies. Every component
+/// must be absent from the manifest (`update --save` changes existing versions), and
+/// only published versions can be added. Versions default to the latest available in
+/// all supported environments. Components are saved in `devDependencies` with `dev`,
+/// and in `dependencies` otherwise.
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+pub fn add<T: CachedBackend + Backend + ?Sized>(
+    manifest: &Manifest,
+    backend: &T,
+    components: Vec<String>,
+    dev: bool,
+    opts: &FetchOptions,
+    env: &str,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    for comp in &components {
+        let name = component_name(comp);
+        if manifest.dependencies.contains_key(name) {
+            return Err(CliError::DependencyExists(name.into(), "dependencies".into()));
+        }
+        if manifest.devDependencies.contains_key(name) {
+            return Err(CliError::DependencyExists(name.into(), "devDependencies".into()));
+        }
+        if !is_published(comp) {
+            warn!("Stashed versions cannot be saved in the manifest - use `lal update`");
+            return Err(CliError::InvalidVersion(comp.clone()));
+        }
+    }
+    let names = components.iter().map(|c| component_name(c).to_string()).collect::<Vec<_>>();
+    update(manifest, backend, components, !dev, dev, opts, env, reporter)?;
+
+    let mf = Manifest::read()?;
+    let (kind, pins) = if dev {
+        ("devDependencies", &mf.devDependencies)
+    } else {
+        ("dependencies", &mf.dependencies)
+    };
+    for name in names {
+        if let Some(v) = pins.get(&name) {
+            info!("Added {} {} to {}", name, v, kind);
         }
-        mf.write()?;
     }
     Ok(())
 }
 
+/// Policy deciding how `update_all` moves the manifest pins
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UpdatePolicy {
+    /// Move every pin to the latest version available in all supported environments
+    Latest,
+    /// Only move a pin when the latest version is newer than the current pin
+    Newer,
+}
+
+impl Default for UpdatePolicy {
+    fn default() -> Self { UpdatePolicy::Latest }
+}
+
 /// Wrapper around update that updates all components
 ///
-/// This will pass all dependencies or devDependencies to update.
+/// This will pass all dependencies or devDependencies to update at the version
+/// chosen by the `UpdatePolicy`, then print a per-component summary.
 /// If the save flag is set, then the manifest will be updated correctly.
 /// I.e. dev updates will update only the dev portions of the manifest.
-pub fn update_all<T: CachedBackend + ?Sized>(
+///
+/// Stashed components are resolved up front via the `stashed` option, and the summary
+/// marks which ones were kept rather than refreshed.
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+pub fn update_all<T: CachedBackend + Backend + ?Sized>(
     manifest: &Manifest,
     backend: &T,
     save: bool,
     dev: bool,
+    policy: UpdatePolicy,
+    opts: &FetchOptions,
     env: &str,
+    reporter: &ProgressReporter,
 ) -> LalResult<()> {
-    let deps: Vec<String> = if dev {
-        manifest.devDependencies.keys().cloned().collect()
-    } else {
-        manifest.dependencies.keys().cloned().collect()
-    };
-    update(manifest, backend, deps, save && !dev, save && dev, env)
+    let pins = if dev { &manifest.devDependencies } else { &manifest.dependencies };
+
+    let conflicts: BTreeMap<String, String> = stashed_in_input()?
+        .into_iter()
+        .filter(|&(ref n, _)| pins.contains_key(n))
+        .collect();
+    let kept = resolve_stashed(&conflicts, opts.stashed)?;
+
+    let mut deps = Vec::with_capacity(pins.len());
+    let mut summary = Vec::with_capacity(pins.len());
+    for (name, &current) in pins {
+        if kept.contains(name) {
+            continue;
+        }
+        let latest = backend
+            .get_latest_supported_versions(name, manifest.supportedEnvironments.clone())?
+            .into_iter()
+            .max()
+            .ok_or_else(|| CliError::NoIntersectedVersion(name.clone()))?;
+        let target = match policy {
+            UpdatePolicy::Latest => latest,
+            UpdatePolicy::Newer => cmp::max(latest, current),
+        };
+        debug!("Policy {:?} resolved {} from {} to {}", policy, name, current, target);
+        deps.push(format!("{}={}", name, target));
+        summary.push((name.clone(), current, target));
+    }
+    // conflicts are resolved already - everything left may be overwritten
+    let resolved_opts = FetchOptions { stashed: StashedPolicy::Overwrite, ..opts.clone() };
+    update(manifest,
+           backend,
+           deps,
+           save && !dev,
+           save && dev,
+           &resolved_opts,
+           env,
+           reporter)?;
+
+    for name in &kept {
+        info!("{}: kept stashed ({})", name, conflicts[name]);
+    }
+    for (name, old, new) in summary {
+        if let Some(stash) = conflicts.get(&name) {
+            info!("{}: refreshed stashed {} -> {}", name, stash, new);
+        } else if old == new {
+            info!("{}: unchanged ({})", name, old);
+        } else {
+            info!("{}: {} -> {}", name, old, new);
+        }
+    }
+    Ok(())
 }
@@ -14,8 +14,8 @@ use std::path::{Path, PathBuf};
 use std::fs;
 use std::process::Command;
 
-use super::{LalResult, CliError};
-use super::{http_download_to_path, get_latest_lal_version, LatestLal};
+use super::{LalResult, CliError, TerminalReporter, exit_code, unpack_safely};
+use super::{http_download_to_path, get_latest_lal_version, LatestLal, HttpOptions};
 
 struct ExeInfo {
     /// Whether ldd things its a dynamic executable
@@ -62,8 +62,7 @@ fn extract_tarball(input: PathBuf, output: &PathBuf) -> LalResult<()> {
     let decompressed = GzDecoder::new(data)?; // decoder reads data
     let mut archive = Archive::new(decompressed); // Archive reads decoded
 
-    archive.unpack(&output)?;
-    Ok(())
+    unpack_safely(&mut archive, output)
 }
 
 fn verify_permissions(exe: &ExeInfo) -> LalResult<()> {
@@ -72,7 +71,7 @@ fn verify_permissions(exe: &ExeInfo) -> LalResult<()> {
     // would sufficiently check that we have write permissions
     let s = Command::new("touch").arg(&exe.path).status()?;
     if !s.success() {
-        return Err(CliError::SubprocessFailure(s.code().unwrap_or(1001)));
+        return Err(CliError::SubprocessFailure(exit_code(&s), None));
     }
     Ok(())
 }
@@ -97,7 +96,7 @@ fn validate_exe(latest: &LatestLal, exe: &ExeInfo) -> LalResult<()> {
     Ok(())
 }
 
-fn upgrade_exe(latest: &LatestLal, exe: &ExeInfo) -> LalResult<()> {
+fn upgrade_exe(latest: &LatestLal, exe: &ExeInfo, http: &HttpOptions) -> LalResult<()> {
     let prefix = exe.prefix.clone().unwrap();
     // 0. sanity - could we actually upgrade if we tried?
     verify_permissions(exe)
@@ -112,8 +111,14 @@ fn upgrade_exe(latest: &LatestLal, exe: &ExeInfo) -> LalResult<()> {
     }
     // 2. make sure we can download the tarball before starting
     let tar_dest = prefix.join("lal.tar.gz");
+    if tar_dest.is_file() {
+        fs::remove_file(&tar_dest)?; // never resume a download of an older release
+    }
     info!("Downloading tarball to {}", tar_dest.display());
-    http_download_to_path(&latest.url, &tar_dest)?;
+    http_download_to_path(&latest.url,
+                          &tar_dest,
+                          http,
+                          &TerminalReporter::default())?;
     info!("Backing up {} to {}", exe.path, old_file.display());
     fs::rename(&exe.path, &old_file)?; // need to undo this if we fail
     // NB: DO NOT INSERT CALLS THAT CAN FAIL HERE BEFORE THE OVERWRITE
@@ -141,8 +146,8 @@ fn upgrade_exe(latest: &LatestLal, exe: &ExeInfo) -> LalResult<()> {
 /// This will query for the latest version, and upgrade in the one possible case.
 /// If a newer version found (> in semver), and it's a static executable,
 /// then an executable upgrade is attempted from the new release url.
-pub fn upgrade(silent: bool) -> LalResult<bool> {
-    let latest = get_latest_lal_version()?;
+pub fn upgrade(silent: bool, http: &HttpOptions) -> LalResult<bool> {
+    let latest = get_latest_lal_version(http)?;
     let exe = identify_exe()?;
 
     if latest.version > exe.version {
@@ -159,7 +164,7 @@ pub fn upgrade(silent: bool) -> LalResult<bool> {
         } else if exe.prefix.is_some() {
             // install lal in the prefix it's normally in
             info!("Upgrading...");
-            upgrade_exe(&latest, &exe)?;
+            upgrade_exe(&latest, &exe, http)?;
             info!("lal upgraded successfully to {} at {}",
                   latest.version,
                   exe.path);
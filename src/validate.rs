@@ -0,0 +1,312 @@
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use serde_json::{self, Map, Value};
+
+use super::{CliError, LalResult, ManifestLocation, VersionRange};
+
+// top level keys of manifest.json and whether they are required
+const MANIFEST_KEYS: &'static [(&'static str, bool)] = &[
+    ("name", true),
+    ("environment", true),
+    ("supportedEnvironments", true),
+    ("components", true),
+    ("dependencies", true),
+    ("devDependencies", true),
+    ("groups", false),
+    ("pruneExtraneous", false),
+    ("artifactContract", false),
+    ("environmentOverrides", false),
+    ("license", false),
+    ("publishMetadata", false),
+    ("scripts", false),
+];
+
+/// Whether a component name is canonical: lowercase and safe to use in paths and urls
+pub fn is_canonical_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') &&
+    name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-_.".contains(c))
+}
+
+// configuration names end up in artifact paths
+fn is_valid_configuration(name: &str) -> bool {
+    !name.is_empty() &&
+    name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-_".contains(c))
+}
+
+// strings in an array value, recording a problem for anything else
+fn string_array<'a>(v: &'a Value, key: &str, problems: &mut Vec<String>) -> Vec<&'a str> {
+    match v.as_array() {
+        Some(xs) => {
+            let strs = xs.iter().filter_map(|x| x.as_str()).collect::<Vec<_>>();
+            if strs.len() != xs.len() {
+                problems.push(format!("{} must only contain strings", key));
+            }
+            strs
+        }
+        None => {
+            problems.push(format!("{} must be an array of strings", key));
+            vec![]
+        }
+    }
+}
+
+// an object of string values, recording a problem for anything else
+fn check_string_map(v: &Value, key: &str, problems: &mut Vec<String>) {
+    match v.as_object() {
+        Some(m) => {
+            for (k, x) in m.iter().filter(|&(_, x)| !x.is_string()) {
+                problems.push(format!("{}.{} must be a string, not {}", key, k, x));
+            }
+        }
+        None => problems.push(format!("{} must be an object of strings", key)),
+    }
+}
+
+fn unknown_keys(obj: &Map<String, Value>, known: &[&str], key: &str, problems: &mut Vec<String>) {
+    for k in obj.keys().filter(|k| !known.contains(&&k[..])) {
+        problems.push(format!("{}: unknown key '{}'", key, k));
+    }
+}
+
+fn check_components(v: &Value, problems: &mut Vec<String>) {
+    let comps = match v.as_object() {
+        Some(c) => c,
+        None => {
+            problems.push("components must be an object".into());
+            return;
+        }
+    };
+    for (name, conf) in comps {
+        let key = format!("components.{}", name);
+        if !is_canonical_name(name) {
+            problems.push(format!("{}: non-canonical component name '{}'", key, name));
+        }
+        let conf = match conf.as_object() {
+            Some(c) => c,
+            None => {
+                problems.push(format!("{} must be an object", key));
+                continue;
+            }
+        };
+        let known = ["defaultConfig", "configurations", "targets", "defaultTarget"];
+        unknown_keys(conf, &known, &key, problems);
+        let configs = match conf.get("configurations") {
+            Some(c) => string_array(c, &format!("{}.configurations", key), problems),
+            None => {
+                problems.push(format!("{}: missing required key 'configurations'", key));
+                vec![]
+            }
+        };
+        for c in configs.iter().filter(|c| !is_valid_configuration(c)) {
+            problems.push(format!("{}: bad configuration name '{}'", key, c));
+        }
+        match conf.get("defaultConfig").map(|d| d.as_str()) {
+            Some(Some(d)) if !configs.contains(&d) => {
+                problems.push(format!("{}: defaultConfig '{}' is not in configurations", key, d));
+            }
+            Some(Some(_)) => {}
+            Some(None) => problems.push(format!("{}.defaultConfig must be a string", key)),
+            None => problems.push(format!("{}: missing required key 'defaultConfig'", key)),
+        }
+        let targets = conf.get("targets")
+            .map(|t| string_array(t, &format!("{}.targets", key), problems))
+            .unwrap_or_default();
+        match conf.get("defaultTarget").map(|d| d.as_str()) {
+            Some(Some(d)) if !targets.contains(&d) => {
+                problems.push(format!("{}: defaultTarget '{}' is not in targets", key, d));
+            }
+            Some(None) => problems.push(format!("{}.defaultTarget must be a string", key)),
+            _ => {}
+        }
+    }
+}
+
+fn check_dependencies<'a>(v: &'a Value, key: &str, problems: &mut Vec<String>) -> Vec<&'a str> {
+    let deps = match v.as_object() {
+        Some(d) => d,
+        None => {
+            problems.push(format!("{} must be an object", key));
+            return vec![];
+        }
+    };
+    for (name, ver) in deps {
+        if !is_canonical_name(name) {
+            problems.push(format!("{}: non-canonical component name '{}'", key, name));
+        }
+        let valid = match *ver {
+            Value::Number(ref n) => n.as_u64().map_or(false, |n| n <= u64::from(u32::max_value())),
+            Value::String(ref s) => {
+                s.trim().parse::<u32>().is_ok() || VersionRange::parse(s).is_ok()
+            }
+            _ => false,
+        };
+        if !valid {
+            problems.push(format!("{}.{}: invalid version or range {}", key, name, ver));
+        }
+    }
+    deps.keys().map(|k| &k[..]).collect()
+}
+
+// overrides are exact versions of existing dependencies
+fn check_overrides(v: &Value,
+                   key: &str,
+                   deps: &[&str],
+                   dev_deps: &[&str],
+                   problems: &mut Vec<String>) {
+    let comps = match v.as_object() {
+        Some(c) => c,
+        None => {
+            problems.push(format!("{} must be an object", key));
+            return;
+        }
+    };
+    for (name, ver) in comps {
+        if !deps.contains(&&name[..]) && !dev_deps.contains(&&name[..]) {
+            problems.push(format!("{}: '{}' is not a dependency", key, name));
+        }
+        if ver.as_u64().map_or(true, |n| n > u64::from(u32::max_value())) {
+            problems.push(format!("{}.{}: invalid version {}", key, name, ver));
+        }
+    }
+}
+
+/// Validate the contents of a manifest against the strict manifest schema
+///
+/// Returns every problem found rather than stopping at the first one. This catches
+/// things the lenient parser accepts or reports one at a time: unknown keys, missing
+/// required keys, wrong types, non-canonical component names, components in both
+/// `dependencies` and `devDependencies`, and bad configuration names.
+pub fn validate_manifest(data: &str) -> Vec<String> {
+    let value: Value = match serde_json::from_str(data) {
+        Ok(v) => v,
+        Err(e) => return vec![format!("invalid json: {}", e)],
+    };
+    let root = match value.as_object() {
+        Some(r) => r,
+        None => return vec!["manifest must be a json object".into()],
+    };
+    let mut problems = vec![];
+    let known = MANIFEST_KEYS.iter().map(|&(k, _)| k).collect::<Vec<_>>();
+    for k in root.keys().filter(|k| !known.contains(&&k[..])) {
+        problems.push(format!("unknown key '{}'", k));
+    }
+    for &(k, _) in MANIFEST_KEYS.iter().filter(|&&(k, req)| req && !root.contains_key(k)) {
+        problems.push(format!("missing required key '{}'", k));
+    }
+
+    if let Some(name) = root.get("name") {
+        match name.as_str() {
+            Some(n) if !is_canonical_name(n) => {
+                problems.push(format!("name: non-canonical component name '{}'", n))
+            }
+            Some(_) => {}
+            None => problems.push("name must be a string".into()),
+        }
+    }
+    let supported = root.get("supportedEnvironments")
+        .map(|s| string_array(s, "supportedEnvironments", &mut problems))
+        .unwrap_or_default();
+    if root.contains_key("supportedEnvironments") && supported.is_empty() {
+        problems.push("supportedEnvironments must not be empty".into());
+    }
+    if let Some(env) = root.get("environment") {
+        match env.as_str() {
+            Some(e) if !supported.is_empty() && !supported.contains(&e) => {
+                problems.push(format!("environment '{}' is not in supportedEnvironments", e))
+            }
+            Some(_) => {}
+            None => problems.push("environment must be a string".into()),
+        }
+    }
+    if let Some(c) = root.get("components") {
+        check_components(c, &mut problems);
+    }
+
+    let deps = root.get("dependencies")
+        .map(|d| check_dependencies(d, "dependencies", &mut problems))
+        .unwrap_or_default();
+    let dev_deps = root.get("devDependencies")
+        .map(|d| check_dependencies(d, "devDependencies", &mut problems))
+        .unwrap_or_default();
+    for d in deps.iter().filter(|d| dev_deps.contains(*d)) {
+        problems.push(format!("'{}' is in both dependencies and devDependencies", d));
+    }
+
+    if let Some(groups) = root.get("groups") {
+        match groups.as_object() {
+            Some(gs) => {
+                for (g, members) in gs {
+                    let key = format!("groups.{}", g);
+                    for m in string_array(members, &key, &mut problems) {
+                        if !deps.contains(&m) && !dev_deps.contains(&m) {
+                            problems.push(format!("{}: '{}' is not a dependency", key, m));
+                        }
+                    }
+                }
+            }
+            None => problems.push("groups must be an object".into()),
+        }
+    }
+    if let Some(overrides) = root.get("environmentOverrides") {
+        match overrides.as_object() {
+            Some(os) => {
+                for (env, comps) in os {
+                    let key = format!("environmentOverrides.{}", env);
+                    if !supported.is_empty() && !supported.contains(&&env[..]) {
+                        problems.push(format!("{}: unsupported environment '{}'", key, env));
+                    }
+                    check_overrides(comps, &key, &deps, &dev_deps, &mut problems);
+                }
+            }
+            None => problems.push("environmentOverrides must be an object".into()),
+        }
+    }
+    if root.get("license").map_or(false, |l| l.as_str().map_or(true, |l| l.trim().is_empty())) {
+        problems.push("license must be a non-empty string".into());
+    }
+    if let Some(meta) = root.get("publishMetadata") {
+        check_string_map(meta, "publishMetadata", &mut problems);
+    }
+    if let Some(scripts) = root.get("scripts") {
+        check_string_map(scripts, "scripts", &mut problems);
+    }
+    if root.get("pruneExtraneous").map_or(false, |p| !p.is_boolean()) {
+        problems.push("pruneExtraneous must be a boolean".into());
+    }
+    if let Some(contract) = root.get("artifactContract") {
+        match contract.as_object() {
+            Some(c) => {
+                unknown_keys(c, &["require", "forbid"], "artifactContract", &mut problems);
+                for k in &["require", "forbid"] {
+                    if let Some(v) = c.get(*k) {
+                        string_array(v, &format!("artifactContract.{}", k), &mut problems);
+                    }
+                }
+            }
+            None => problems.push("artifactContract must be an object".into()),
+        }
+    }
+    problems
+}
+
+/// Validates the manifest in the current directory
+///
+/// Prints every problem found by `validate_manifest`, and fails with the number of
+/// problems if there were any.
+pub fn validate() -> LalResult<()> {
+    let pwd = env::current_dir()?;
+    let mpath = ManifestLocation::identify(&pwd)?.as_path(&pwd);
+    let mut data = String::new();
+    File::open(&mpath)?.read_to_string(&mut data)?;
+
+    let problems = validate_manifest(&data);
+    for p in &problems {
+        println!("{}: {}", mpath.display(), p);
+    }
+    if !problems.is_empty() {
+        return Err(CliError::InvalidManifest(problems.len()));
+    }
+    info!("{} is valid", mpath.display());
+    Ok(())
+}
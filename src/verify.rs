@@ -1,5 +1,54 @@
-use super::{Lockfile, Manifest, LalResult};
+use serde_json;
+
+use super::{Lockfile, DependencyGraph, Manifest, LalResult, StickyOptions, VerifyCheck};
 use input;
+use output;
+
+/// Output of `lal verify --json`
+#[derive(Serialize)]
+struct VerifyReport<'a> {
+    environment: &'a str,
+    simple: bool,
+    strict: bool,
+    verified: bool,
+    error: Option<String>,
+    warnings: Vec<String>,
+    depth: Option<usize>,
+}
+
+// what a verify found besides its outcome
+#[derive(Default)]
+struct VerifyNotes {
+    // failed checks allowed by the policy
+    warnings: Vec<String>,
+    // levels in the dependency tree of INPUT
+    depth: Option<usize>,
+}
+
+// downgrade a failed check to a warning if the policy allows it
+fn check(res: LalResult<()>, allowed: &[VerifyCheck], warnings: &mut Vec<String>) -> LalResult<()> {
+    match res {
+        Err(ref e) if allowed.iter().any(|c| c.covers(e)) => {
+            warn!("{} (allowed by verify policy)", e);
+            warnings.push(e.to_string());
+            Ok(())
+        }
+        res => res,
+    }
+}
+
+/// Options for `lal verify`
+#[derive(Clone, Default, Debug)]
+pub struct VerifyOptions {
+    /// Only check what matters for stashed components (see `verify`)
+    pub simple: bool,
+    /// Also check container digests and the contents of INPUT
+    pub strict: bool,
+    /// Print the outcome as a json object
+    pub json: bool,
+    /// Checks whose failures are only warnings
+    pub allowed: Vec<VerifyCheck>,
+}
 
 /// Verifies that `./INPUT` satisfies all strictness conditions.
 ///
@@ -8,7 +57,8 @@ use input;
 ///
 /// Once this is done, `INPUT` is analysed thoroughly via each components lockfiles.
 /// Missing dependencies, or multiple versions dependend on implicitly are both
-/// considered errors for verify, as are having custom versions in `./INPUT`.
+/// considered errors for verify, as are having custom versions in `./INPUT`, and
+/// dependencies built for different targets.
 ///
 /// This function is meant to be a helper for when we want official builds, but also
 /// a way to tell developers that they are using things that differ from what jenkins
@@ -18,9 +68,47 @@ use input;
 /// Users can use `lal verify --simple` or `lal build -s` aka. `--simple-verify`,
 /// instead of having to use `lal build --force` when just using stashed components.
 /// This avoids problems with different environments going undetected.
-pub fn verify(m: &Manifest, env: &str, simple: bool) -> LalResult<()> {
+///
+/// A strict verify additionally requires that dependencies built with the same
+/// container tag were built with the same image digest, since tags move, and that
+/// the contents of `INPUT` match the digests recorded in their lockfiles at build time.
+///
+/// The `json` option prints the outcome as a json object in addition to returning it.
+///
+/// Failures of the `allowed` checks are logged as warnings (and listed in the json
+/// output) instead of failing verify. Release builds should allow no checks.
+pub fn verify(m: &Manifest, env: &str, opts: &VerifyOptions) -> LalResult<()> {
+    let (simple, strict, json) = (opts.simple, opts.strict, opts.json);
+    let mut notes = VerifyNotes::default();
+    let res = verify_input(m, env, simple, strict, &opts.allowed, &mut notes);
+    if json {
+        let report = VerifyReport {
+            environment: env,
+            simple: simple,
+            strict: strict,
+            verified: res.is_ok(),
+            error: res.as_ref().err().map(|e| e.to_string()),
+            warnings: notes.warnings,
+            depth: notes.depth,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+    res
+}
+
+fn verify_input(
+    m: &Manifest,
+    env: &str,
+    simple: bool,
+    strict: bool,
+    allowed: &[VerifyCheck],
+    notes: &mut VerifyNotes,
+) -> LalResult<()> {
     // 1. Verify that the manifest is sane
     m.verify()?;
+    // dependencies are expected at their versions for this environment
+    let env_manifest = m.for_environment(env);
+    let m = &env_manifest;
 
     // 2. dependencies in `INPUT` match `manifest.json`.
     if m.dependencies.is_empty() && !input::present() {
@@ -28,24 +116,65 @@ pub fn verify(m: &Manifest, env: &str, simple: bool) -> LalResult<()> {
         // nothing needs to be verified in this case, so allow missing INPUT
         return Ok(());
     }
-    input::verify_dependencies_present(m)?;
+    // members of groups not requested by the last fetch may be absent
+    let groups = StickyOptions::read()?.groups;
+    input::verify_dependencies_present(m, &m.excluded_group_members(&groups, false))?;
 
     // get data for big verify steps
-    let lf = Lockfile::default().populate_from_input()?;
+    let lf = Lockfile::default().set_name(&m.name).populate_from_input()?;
+    let graph = DependencyGraph::new(&lf);
+    notes.depth = Some(graph.max_depth());
+    debug!("Dependency tree depth is {} via {}",
+           graph.max_depth(),
+           graph.deepest_path().join(" -> "));
 
     // 3. verify the root level dependencies match the manifest
     if !simple {
-        input::verify_global_versions(&lf, m)?;
+        check(input::verify_global_versions(&lf, m), allowed, &mut notes.warnings)?;
     }
 
-    // 4. the dependency tree is flat, and deps use only global deps
+    // 4. no component depends on itself
+    input::verify_acyclic(&graph)?;
+
+    // 5. the dependency tree is flat, and deps use only global deps
     if !simple {
-        input::verify_consistent_dependency_versions(&lf, m)?;
+        let res = input::verify_consistent_dependency_versions(&graph, m);
+        check(res, allowed, &mut notes.warnings)?;
+    }
+
+    // 6. verify all components are built in the same environment
+    check(input::verify_environment_consistency(&lf, env), allowed, &mut notes.warnings)?;
+
+    // 7. all components built for a target are built for the same one
+    check(input::verify_target_consistency(&lf, None), allowed, &mut notes.warnings)?;
+
+    // 8. the same container tags resolved to the same images
+    if strict {
+        input::verify_container_digests(&lf)?;
     }
 
-    // 5. verify all components are built in the same environment
-    input::verify_environment_consistency(&lf, env)?;
+    // 9. INPUT contents are what their builds produced
+    if strict {
+        input::verify_contents(&lf)?;
+    }
 
-    info!("Dependencies fully verified");
+    if notes.warnings.is_empty() {
+        info!("Dependencies fully verified ({} levels deep)", graph.max_depth());
+    } else {
+        warn!("Dependencies verified with {} allowed failure(s)", notes.warnings.len());
+    }
+    Ok(())
+}
+
+/// Verifies that `./OUTPUT` satisfies the `artifactContract` in the manifest
+///
+/// This is the same check that runs before release packaging and stashing.
+pub fn verify_output(m: &Manifest) -> LalResult<()> {
+    if let Some(ref contract) = m.artifactContract {
+        output::verify_contract(contract)?;
+        info!("OUTPUT satisfies the artifact contract");
+    } else {
+        info!("No artifactContract in the manifest - nothing to verify");
+    }
     Ok(())
 }
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use libc::{self, c_char, c_int};
+use walkdir::WalkDir;
+
+use super::LalResult;
+use super::output::SOURCE_EXCLUDES;
+
+// inotify is declared here as older libc releases do not bind it
+extern "C" {
+    fn inotify_init1(flags: c_int) -> c_int;
+    fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+}
+
+const IN_NONBLOCK: c_int = 0o4000;
+const IN_CLOEXEC: c_int = 0o2000000;
+// modify, attrib, close_write, moved_from, moved_to, create, delete
+const IN_CHANGES: u32 = 0x2 | 0x4 | 0x8 | 0x40 | 0x80 | 0x100 | 0x200;
+
+// how often the source tree is scanned when inotify is unavailable
+const POLL_INTERVAL_MS: u64 = 1000;
+
+// size and mtime of every file in the source tree, enough to tell that something changed
+// (directories only by presence, as writing the ignored paths touches their parents)
+type Snapshot = BTreeMap<PathBuf, (u64, Option<SystemTime>)>;
+
+fn is_ignored(root: &Path, pth: &Path) -> bool {
+    pth.strip_prefix(root)
+        .map(|rel| SOURCE_EXCLUDES.iter().any(|i| rel.starts_with(i)))
+        .unwrap_or(false)
+}
+
+fn snapshot(root: &Path) -> Snapshot {
+    WalkDir::new(root)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| !is_ignored(root, e.path()))
+        .filter_map(|e| e.ok())
+        .filter_map(|e| match fs::symlink_metadata(e.path()) {
+            Ok(m) => {
+                let rel = e.path().strip_prefix(root).unwrap_or(e.path()).to_path_buf();
+                if m.is_dir() {
+                    Some((rel, (0, None)))
+                } else {
+                    Some((rel, (m.len(), m.modified().ok())))
+                }
+            }
+            Err(_) => None, // removed while walking
+        })
+        .collect()
+}
+
+// paths added, removed or modified between two snapshots
+fn changes(before: &Snapshot, after: &Snapshot) -> Vec<PathBuf> {
+    let mut changed = after.iter()
+        .filter(|&(p, v)| before.get(p) != Some(v))
+        .map(|(p, _)| p.clone())
+        .collect::<Vec<_>>();
+    changed.extend(before.keys().filter(|p| !after.contains_key(*p)).cloned());
+    changed.sort();
+    changed
+}
+
+/// Waits for changes to the sources of a component
+///
+/// Every directory of the source tree (everything but `INPUT`, `OUTPUT`, `ARTIFACT` and
+/// `.git`) is watched with inotify, falling back to scanning the tree every second where
+/// inotify is unavailable. Changes are confirmed against sizes and mtimes of the tree, so
+/// events that leave the sources as they were do not count.
+pub struct SourceWatcher {
+    root: PathBuf,
+    fd: c_int,
+    last: Snapshot,
+}
+
+impl SourceWatcher {
+    /// Start watching the sources under a directory as they are now
+    pub fn new(root: &Path) -> SourceWatcher {
+        let fd = unsafe { inotify_init1(IN_NONBLOCK | IN_CLOEXEC) };
+        if fd < 0 {
+            warn!("inotify is unavailable - scanning {} for changes instead", root.display());
+        }
+        let watcher = SourceWatcher {
+            root: root.to_path_buf(),
+            fd: fd,
+            last: snapshot(root),
+        };
+        watcher.add_watches();
+        watcher
+    }
+
+    // watch every directory (adding a watch again for the same directory is a no-op)
+    fn add_watches(&self) {
+        if self.fd < 0 {
+            return;
+        }
+        let dirs = self.last.keys().map(|p| self.root.join(p)).filter(|p| p.is_dir());
+        for dir in Some(self.root.clone()).into_iter().chain(dirs) {
+            if let Ok(cpth) = CString::new(dir.as_os_str().as_bytes()) {
+                if unsafe { inotify_add_watch(self.fd, cpth.as_ptr(), IN_CHANGES) } < 0 {
+                    debug!("Failed to watch {}", dir.display());
+                }
+            }
+        }
+    }
+
+    // discard queued events
+    fn drain(&self) {
+        let mut buf = [0u8; 4096];
+        loop {
+            let read = unsafe {
+                libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+            };
+            if read <= 0 {
+                break;
+            }
+        }
+    }
+
+    // block until something may have changed (false when nothing happened in time)
+    fn wait(&self, timeout: Duration) -> bool {
+        if self.fd < 0 {
+            thread::sleep(timeout);
+            return true;
+        }
+        let ms = timeout.as_secs() * 1000 + u64::from(timeout.subsec_nanos() / 1_000_000);
+        let mut pfd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut pfd, 1, ms as c_int) };
+        if ready > 0 {
+            self.drain();
+        }
+        ready > 0
+    }
+
+    /// Block until the sources change, returning the changed paths
+    ///
+    /// Changes are only returned once the tree has been left alone for `debounce`, so an
+    /// editor saving several files (or a `git checkout`) is reported once.
+    pub fn wait_for_change(&mut self, debounce: Duration) -> Vec<PathBuf> {
+        loop {
+            if !self.wait(Duration::from_millis(POLL_INTERVAL_MS)) {
+                continue;
+            }
+            let mut current = snapshot(&self.root);
+            if current == self.last {
+                continue;
+            }
+            loop {
+                thread::sleep(debounce);
+                if self.fd >= 0 {
+                    self.drain();
+                }
+                let settled = snapshot(&self.root);
+                if settled == current {
+                    break;
+                }
+                current = settled;
+            }
+            let changed = changes(&self.last, &current);
+            self.last = current;
+            self.add_watches(); // new directories
+            if !changed.is_empty() {
+                return changed;
+            }
+        }
+    }
+}
+
+impl Drop for SourceWatcher {
+    fn drop(&mut self) {
+        if self.fd >= 0 {
+            unsafe { libc::close(self.fd) };
+        }
+    }
+}
+
+/// Rebuild whenever the sources in the current directory change
+///
+/// Runs `rebuild` once straight away, and again every time `SourceWatcher` reports changes.
+/// Failed builds are logged rather than returned, so that the next save can fix them; the
+/// output of the build streams to the terminal as usual. Keeps going until lal is
+/// interrupted.
+pub fn watch<F>(debounce: Duration, mut rebuild: F) -> LalResult<()>
+    where F: FnMut() -> LalResult<()>
+{
+    let mut watcher = SourceWatcher::new(Path::new("."));
+    loop {
+        match rebuild() {
+            Ok(_) => info!("Build succeeded - waiting for changes"),
+            Err(e) => error!("Build failed: {} - waiting for changes", e),
+        }
+        let changed = watcher.wait_for_change(debounce);
+        let mut shown = changed.iter().take(3).map(|p| p.display().to_string()).collect::<Vec<_>>();
+        if changed.len() > 3 {
+            shown.push(format!("{} more", changed.len() - 3));
+        }
+        info!("Rebuilding after changes to {}", shown.join(", "));
+    }
+}
@@ -0,0 +1,223 @@
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use serde_json;
+
+use storage::{Backend, CachedBackend};
+use input;
+use fetch::{fetch, link_tree, FetchOptions};
+use build::{build, BuildOptions};
+use shell::ShellModes;
+use status::{status, StatusOptions};
+use super::{Config, CliError, LalResult, LalLock, Manifest, ProgressReporter, StickyOptions,
+            forget_source};
+
+/// Representation of `workspace.json` at the root of a multi-component repository
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Workspace {
+    /// Member directories relative to the workspace root
+    pub members: Vec<String>,
+
+    /// Directory containing `workspace.json`
+    #[serde(skip_serializing, skip_deserializing)]
+    pub root: PathBuf,
+}
+
+/// A workspace member directory along with its manifest
+pub struct WorkspaceMember {
+    /// Absolute path of the member directory
+    pub dir: PathBuf,
+    /// Manifest of the member
+    pub manifest: Manifest,
+}
+
+impl Workspace {
+    /// Find and read `workspace.json` in the current directory or its closest parent
+    pub fn find() -> LalResult<Workspace> {
+        let mut dir = Some(env::current_dir()?);
+        while let Some(d) = dir {
+            if d.join("workspace.json").is_file() {
+                return Workspace::read_from(&d);
+            }
+            dir = d.parent().map(Path::to_path_buf);
+        }
+        Err(CliError::MissingWorkspace)
+    }
+
+    /// Read `workspace.json` in a workspace root
+    pub fn read_from(root: &Path) -> LalResult<Workspace> {
+        let pth = root.join("workspace.json");
+        trace!("Using workspace in {}", pth.display());
+        let mut res: Workspace = serde_json::from_reader(File::open(&pth)?)?;
+        res.root = root.to_path_buf();
+        Ok(res)
+    }
+
+    /// Read the members of the workspace in dependency order
+    ///
+    /// Every member comes after the members it depends on, and otherwise in the order
+    /// listed in `workspace.json`.
+    pub fn members(&self) -> LalResult<Vec<WorkspaceMember>> {
+        let mut remaining = vec![];
+        for m in &self.members {
+            let dir = self.root.join(m);
+            let manifest = Manifest::read_from(&dir)?;
+            remaining.push(WorkspaceMember { dir: dir, manifest: manifest });
+        }
+        let names = remaining.iter().map(|m| m.manifest.name.clone()).collect::<Vec<_>>();
+        let mut ordered: Vec<WorkspaceMember> = vec![];
+        while !remaining.is_empty() {
+            let next = remaining.iter().position(|m| {
+                m.manifest.all_dependencies().keys().all(|d| {
+                    !names.contains(d) || ordered.iter().any(|o| &o.manifest.name == d)
+                })
+            });
+            match next {
+                Some(i) => ordered.push(remaining.remove(i)),
+                None => {
+                    let cycle = remaining.iter()
+                        .map(|m| &m.manifest.name[..])
+                        .collect::<Vec<_>>();
+                    return Err(CliError::WorkspaceCycle(cycle.join(", ")));
+                }
+            }
+        }
+        Ok(ordered)
+    }
+}
+
+// OUTPUT directories of workspace members by component name
+fn sibling_outputs(members: &[WorkspaceMember]) -> BTreeMap<String, PathBuf> {
+    members.iter().map(|m| (m.manifest.name.clone(), m.dir.join("OUTPUT"))).collect()
+}
+
+/// Link the OUTPUT of sibling workspace members into INPUT
+///
+/// Each named component is replaced in INPUT by whatever its member built last.
+/// Components whose member has not been built yet are skipped with a warning.
+/// Returns the names of the components that were linked.
+pub fn link_siblings(names: &[String],
+                     siblings: &BTreeMap<String, PathBuf>)
+                     -> LalResult<Vec<String>> {
+    let mut linked = vec![];
+    for name in names {
+        let out = match siblings.get(name) {
+            Some(o) => o,
+            None => continue,
+        };
+        if !out.join("lockfile.json").is_file() {
+            warn!("Workspace member {} has not been built - not linking it into INPUT", name);
+            continue;
+        }
+        fs::create_dir_all("./INPUT")?;
+        let staging = input::staging_dir(name)?;
+        if let Err(e) = link_tree(out, &staging).and_then(|_| input::commit_staged(name)) {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+        forget_source(name)?; // not from the cache
+        info!("Link {} from {}", name, out.display());
+        linked.push(name.clone());
+    }
+    Ok(linked)
+}
+
+// run an action inside every member directory, returning to where we were afterwards
+//
+// Unless `stop_on_error` is set, every member is visited and the first error is returned.
+fn for_each_member<F>(members: &[WorkspaceMember], stop_on_error: bool, mut f: F) -> LalResult<()>
+where
+    F: FnMut(&WorkspaceMember) -> LalResult<()>,
+{
+    let pwd = env::current_dir()?;
+    let mut err = None;
+    for m in members {
+        env::set_current_dir(&m.dir)?;
+        let res = f(m);
+        env::set_current_dir(&pwd)?;
+        if let Err(e) = res {
+            warn!("{} failed in {} ({})", m.manifest.name, m.dir.display(), e);
+            err = err.or(Some(e));
+            if stop_on_error {
+                break;
+            }
+        }
+    }
+    match err {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// environment of a member: explicit, then sticky, then its manifest default
+fn member_env(m: &WorkspaceMember, explicit: Option<&str>) -> LalResult<String> {
+    if let Some(e) = explicit {
+        return Ok(e.into());
+    }
+    Ok(StickyOptions::read()?.env.unwrap_or_else(|| m.manifest.environment.clone()))
+}
+
+/// Fetch dependencies of every workspace member
+///
+/// Dependencies on other members are linked from their OUTPUT rather than fetched
+/// from the backend, so members should be built in order with `build_all` afterwards.
+pub fn fetch_all<T: CachedBackend + Backend + ?Sized>(
+    ws: &Workspace,
+    backend: &T,
+    opts: &FetchOptions,
+    env: Option<&str>,
+    reporter: &ProgressReporter,
+) -> LalResult<()> {
+    let members = ws.members()?;
+    let siblings = sibling_outputs(&members);
+    for_each_member(&members, false, |m| {
+        info!("Fetching workspace member {}", m.manifest.name);
+        let _lock = LalLock::project()?;
+        let mut mopts = opts.clone();
+        mopts.siblings = siblings.clone();
+        mopts.siblings.remove(&m.manifest.name);
+        fetch(&m.manifest, backend, &mopts, &member_env(m, env)?, reporter)
+    })
+}
+
+/// Build every workspace member in dependency order
+///
+/// Before each member is built, the OUTPUT of the members it depends on is linked
+/// into its INPUT. Sibling OUTPUT is unversioned, so members are verified with the
+/// simple verify algorithm. Building stops at the first member that fails.
+pub fn build_all(
+    cfg: &Config,
+    ws: &Workspace,
+    opts: &BuildOptions,
+    env: Option<&str>,
+    modes: &ShellModes,
+) -> LalResult<()> {
+    let members = ws.members()?;
+    let siblings = sibling_outputs(&members);
+    for_each_member(&members, true, |m| {
+        info!("Building workspace member {}", m.manifest.name);
+        let _lock = LalLock::project()?;
+        let deps = m.manifest.all_dependencies().keys().cloned().collect::<Vec<_>>();
+        link_siblings(&deps, &siblings)?;
+        let envname = member_env(m, env)?;
+        let mut mopts = opts.clone();
+        mopts.container = cfg.get_container(envname.clone())?;
+        mopts.simple_verify = true;
+        build(cfg, &m.manifest, &mopts, envname, modes.clone())
+    })
+}
+
+/// Print the status of every workspace member
+///
+/// The json option is ignored, every member is printed as a tree.
+pub fn status_all(ws: &Workspace, opts: &StatusOptions) -> LalResult<()> {
+    let members = ws.members()?;
+    let opts = StatusOptions { json: false, ..opts.clone() };
+    for_each_member(&members, false, |m| {
+        println!("{}:", m.dir.strip_prefix(&ws.root).unwrap_or(&m.dir).display());
+        let res = status(&m.manifest, &opts);
+        println!("");
+        res
+    })
+}